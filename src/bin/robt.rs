@@ -1,28 +1,789 @@
-use mkit::{cbor::FromCbor, db::Bloom, nobitmap::NoBitmap};
+use mkit::{
+    cbor::{Cbor, FromCbor, IntoCbor},
+    db::{self, BuildIndex},
+    nobitmap::NoBitmap,
+};
+use serde_json as json;
 use structopt::StructOpt;
 
-use std::{ffi, fmt};
+use std::{
+    error, ffi, fmt, fs,
+    hash::Hash,
+    io::{self, BufRead, Write},
+    ops::Bound,
+    result,
+    str::FromStr,
+};
 
-use robt::db::Index;
+use robt::{
+    db::{Builder, Change, Index},
+    Config, Dyn, SalvageReport, VBLOCKSIZE,
+};
+
+type Result<T> = result::Result<T, Box<dyn error::Error>>;
 
 /// Command line options.
 #[derive(Clone, StructOpt)]
 pub struct Opt {
-    index_file: ffi::OsString,
+    /// Key type, one of u64, i64, string, bytes, dyn.
+    #[structopt(long = "key-type", default_value = "u64")]
+    key_type: Type,
+
+    /// Value type, one of u64, i64, string, bytes, dyn.
+    #[structopt(long = "value-type", default_value = "u64")]
+    value_type: Type,
+
+    #[structopt(subcommand)]
+    subcmd: SubCommand,
+}
+
+#[derive(Clone, StructOpt)]
+pub enum SubCommand {
+    /// Print index statistics.
+    Stats { index_file: ffi::OsString },
+    /// Get entry for key.
+    Get {
+        index_file: ffi::OsString,
+        key: String,
+    },
+    /// Iterate over entries within [from, to], missing bounds are unbounded.
+    Range {
+        index_file: ffi::OsString,
+        from: Option<String>,
+        to: Option<String>,
+        /// Iterate in reverse order.
+        #[structopt(long = "reverse")]
+        reverse: bool,
+        /// Include older versions of each entry.
+        #[structopt(long = "versions")]
+        versions: bool,
+    },
+    /// Validate index.
+    Validate {
+        index_file: ffi::OsString,
+        /// Walk every block and report all structural problems found.
+        #[structopt(long = "deep")]
+        deep: bool,
+    },
+    /// Print application metadata as hex and CBOR.
+    Meta { index_file: ffi::OsString },
+    /// Print the btree, block by block.
+    Tree { index_file: ffi::OsString },
+    /// Export entries to stdout, one record per entry.
+    Export {
+        index_file: ffi::OsString,
+        /// Output format, one of jsonl, csv.
+        #[structopt(long = "format", default_value = "jsonl")]
+        format: Format,
+    },
+    /// Build a fresh index from records read from stdin.
+    Import {
+        /// Directory to create the index in.
+        #[structopt(long = "dir")]
+        dir: ffi::OsString,
+        /// Name of the index.
+        #[structopt(long = "name")]
+        name: String,
+        /// Input format, one of jsonl, csv.
+        #[structopt(long = "format", default_value = "jsonl")]
+        format: Format,
+        /// Leaf block size.
+        #[structopt(long = "z-blocksize", default_value = "4096")]
+        z_blocksize: usize,
+        /// Intermediate block size.
+        #[structopt(long = "m-blocksize", default_value = "4096")]
+        m_blocksize: usize,
+        /// Value-log block size.
+        #[structopt(long = "v-blocksize", default_value = "4096")]
+        v_blocksize: usize,
+        /// Persist values in a separate value-log file.
+        #[structopt(long = "vlog")]
+        vlog: bool,
+        /// Disable delta persistence.
+        #[structopt(long = "no-delta")]
+        no_delta: bool,
+        /// Bytes of records buffered in memory while sorting the input,
+        /// larger inputs are spilled to temporary files under `dir`.
+        #[structopt(long = "mem-budget", default_value = "67108864")]
+        mem_budget: usize,
+    },
+    /// Recover entries from a damaged index into a fresh index.
+    Salvage {
+        index_file: ffi::OsString,
+        /// Directory to create the salvaged index in.
+        #[structopt(long = "dir")]
+        dir: ffi::OsString,
+        /// Name of the salvaged index.
+        #[structopt(long = "name")]
+        name: String,
+        /// Value-log file of the damaged index, if not in the same directory.
+        #[structopt(long = "vlog")]
+        vlog: Option<ffi::OsString>,
+        /// Leaf block size, used when the meta-block is not readable.
+        #[structopt(long = "z-blocksize", default_value = "4096")]
+        z_blocksize: usize,
+        /// Intermediate block size, used when the meta-block is not readable.
+        #[structopt(long = "m-blocksize", default_value = "4096")]
+        m_blocksize: usize,
+    },
+    /// Print key-level changes between an older and a newer snapshot, within
+    /// [from, to], missing bounds are unbounded.
+    Diff {
+        old_file: ffi::OsString,
+        new_file: ffi::OsString,
+        from: Option<String>,
+        to: Option<String>,
+    },
+    /// Rewrite an index file into the current on-disk format.
+    Upgrade {
+        index_file: ffi::OsString,
+        /// Directory to create the upgraded index in.
+        #[structopt(long = "dir")]
+        dir: ffi::OsString,
+        /// Name of the upgraded index.
+        #[structopt(long = "name")]
+        name: String,
+    },
+}
+
+/// Record format for import and export.
+///
+/// * `jsonl`, one JSON object per line, `{"key", "value", "seqno", "deleted"}`.
+/// * `csv`, with header `key,value,seqno,deleted`.
+///
+/// While importing, `seqno` defaults to the record's position in the input
+/// and `deleted` defaults to false. `value` is ignored for deleted records.
+#[derive(Clone, Copy)]
+pub enum Format {
+    Jsonl,
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> result::Result<Format, String> {
+        match s {
+            "jsonl" => Ok(Format::Jsonl),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!("invalid format {:?}", s)),
+        }
+    }
+}
+
+/// Types supported via command line, use `dyn` for type-erased CBOR values.
+#[derive(Clone, Copy)]
+pub enum Type {
+    U64,
+    I64,
+    Str,
+    Bytes,
+    Dyn,
+}
+
+impl FromStr for Type {
+    type Err = String;
+
+    fn from_str(s: &str) -> result::Result<Type, String> {
+        match s {
+            "u64" => Ok(Type::U64),
+            "i64" => Ok(Type::I64),
+            "string" => Ok(Type::Str),
+            "bytes" => Ok(Type::Bytes),
+            "dyn" => Ok(Type::Dyn),
+            _ => Err(format!("invalid type {:?}", s)),
+        }
+    }
+}
+
+/// Convert keys and values to and from command line arguments, JSON and
+/// CSV fields.
+pub trait Arg: Sized {
+    fn parse_arg(s: &str) -> Result<Self>;
+
+    fn to_arg(&self) -> String;
+
+    fn from_json(val: &json::Value) -> Result<Self>;
+
+    fn to_json(&self) -> json::Value;
+}
+
+impl Arg for u64 {
+    fn parse_arg(s: &str) -> Result<u64> {
+        Ok(s.parse()?)
+    }
+
+    fn to_arg(&self) -> String {
+        self.to_string()
+    }
+
+    fn from_json(val: &json::Value) -> Result<u64> {
+        match val.as_u64() {
+            Some(val) => Ok(val),
+            None => Err(format!("invalid u64 {}", val).into()),
+        }
+    }
+
+    fn to_json(&self) -> json::Value {
+        json::Value::from(*self)
+    }
+}
+
+impl Arg for i64 {
+    fn parse_arg(s: &str) -> Result<i64> {
+        Ok(s.parse()?)
+    }
+
+    fn to_arg(&self) -> String {
+        self.to_string()
+    }
+
+    fn from_json(val: &json::Value) -> Result<i64> {
+        match val.as_i64() {
+            Some(val) => Ok(val),
+            None => Err(format!("invalid i64 {}", val).into()),
+        }
+    }
+
+    fn to_json(&self) -> json::Value {
+        json::Value::from(*self)
+    }
+}
+
+impl Arg for String {
+    fn parse_arg(s: &str) -> Result<String> {
+        Ok(s.to_string())
+    }
+
+    fn to_arg(&self) -> String {
+        self.clone()
+    }
+
+    fn from_json(val: &json::Value) -> Result<String> {
+        match val.as_str() {
+            Some(val) => Ok(val.to_string()),
+            None => Err(format!("invalid string {}", val).into()),
+        }
+    }
+
+    fn to_json(&self) -> json::Value {
+        json::Value::from(self.as_str())
+    }
+}
+
+impl Arg for Vec<u8> {
+    // bytes are supplied as hex string, with optional 0x prefix.
+    fn parse_arg(s: &str) -> Result<Vec<u8>> {
+        let s = s.strip_prefix("0x").unwrap_or(s);
+        if !s.is_ascii() || s.len() % 2 != 0 {
+            return Err(format!("invalid hex {:?}", s).into());
+        }
+        let mut bytes = vec![];
+        for i in (0..s.len()).step_by(2) {
+            bytes.push(u8::from_str_radix(&s[i..i + 2], 16)?);
+        }
+        Ok(bytes)
+    }
+
+    fn to_arg(&self) -> String {
+        let hex: String = self.iter().map(|b| format!("{:02x}", b)).collect();
+        format!("0x{}", hex)
+    }
+
+    fn from_json(val: &json::Value) -> Result<Vec<u8>> {
+        match val.as_str() {
+            Some(val) => Self::parse_arg(val),
+            None => Err(format!("invalid bytes {}", val).into()),
+        }
+    }
+
+    fn to_json(&self) -> json::Value {
+        json::Value::from(self.to_arg())
+    }
+}
+
+impl Arg for Dyn {
+    // unsigned integers are parsed as u64, everything else as text.
+    fn parse_arg(s: &str) -> Result<Dyn> {
+        match s.parse::<u64>() {
+            Ok(val) => Ok(Dyn::from(val)),
+            Err(_) => Ok(Dyn::from(s.to_string())),
+        }
+    }
+
+    fn to_arg(&self) -> String {
+        match &self.0 {
+            Cbor::Major3(_, _) => String::from_cbor(self.0.clone()).unwrap_or_default(),
+            _ => self.to_string(),
+        }
+    }
+
+    fn from_json(val: &json::Value) -> Result<Dyn> {
+        let val = match val {
+            json::Value::Bool(val) => into_cbor(*val)?,
+            json::Value::Number(num) => match (num.as_u64(), num.as_i64()) {
+                (Some(num), _) => into_cbor(num)?,
+                (None, Some(num)) => into_cbor(num)?,
+                (None, None) => Err(format!("invalid number {}", num))?,
+            },
+            json::Value::String(val) => into_cbor(val.clone())?,
+            json::Value::Array(items) => {
+                let items: Result<Vec<Dyn>> = items.iter().map(Dyn::from_json).collect();
+                into_cbor(items?)?
+            }
+            val => Err(format!("unsupported json {}", val))?,
+        };
+        Ok(Dyn::new(val)?)
+    }
+
+    // Dyn is rendered as JSON like text, fall back to string otherwise.
+    fn to_json(&self) -> json::Value {
+        let text = self.to_string();
+        json::from_str(&text).unwrap_or(json::Value::String(text))
+    }
+}
+
+fn into_cbor<T: IntoCbor>(val: T) -> Result<Cbor> {
+    Ok(val.into_cbor().map_err(robt::Error::from)?)
+}
+
+macro_rules! dispatch_value {
+    ($key:ty, $opts:expr) => {
+        match $opts.value_type {
+            Type::U64 => run::<$key, u64>($opts),
+            Type::I64 => run::<$key, i64>($opts),
+            Type::Str => run::<$key, String>($opts),
+            Type::Bytes => run::<$key, Vec<u8>>($opts),
+            Type::Dyn => run::<$key, Dyn>($opts),
+        }
+    };
 }
 
 fn main() {
     let opts = Opt::from_args();
-    run::<u64, u64, u64, NoBitmap>(opts)
+
+    let res = match opts.key_type {
+        Type::U64 => dispatch_value!(u64, opts),
+        Type::I64 => dispatch_value!(i64, opts),
+        Type::Str => dispatch_value!(String, opts),
+        Type::Bytes => dispatch_value!(Vec<u8>, opts),
+        Type::Dyn => dispatch_value!(Dyn, opts),
+    };
+
+    if let Err(err) = res {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
 }
 
-fn run<K, V, D, B>(opts: Opt)
+// Older versions of value are always read as type-erased deltas.
+type IndexT<K, V> = Index<K, V, Dyn, NoBitmap>;
+
+fn run<K, V>(opts: Opt) -> Result<()>
+where
+    K: 'static + Clone + Ord + Hash + FromCbor + IntoCbor + fmt::Debug + Arg,
+    V: 'static + Clone + PartialEq + FromCbor + IntoCbor + fmt::Debug + Arg,
+{
+    match opts.subcmd {
+        SubCommand::Stats { index_file } => {
+            let index = IndexT::<K, V>::open_file(&index_file)?;
+            cmd_stats(&index)
+        }
+        SubCommand::Get { index_file, key } => {
+            let mut index = IndexT::<K, V>::open_file(&index_file)?;
+            let entry = index.get_versions(&K::parse_arg(&key)?)?;
+            println!("{:?}", entry);
+            Ok(())
+        }
+        SubCommand::Range { index_file, from, to, reverse, versions } => {
+            let mut index = IndexT::<K, V>::open_file(&index_file)?;
+            let from = match from {
+                Some(from) => Bound::Included(K::parse_arg(&from)?),
+                None => Bound::Unbounded,
+            };
+            let to = match to {
+                Some(to) => Bound::Included(K::parse_arg(&to)?),
+                None => Bound::Unbounded,
+            };
+            let iter = match (reverse, versions) {
+                (false, false) => index.iter((from, to))?,
+                (false, true) => index.iter_versions((from, to))?,
+                (true, false) => index.reverse((from, to))?,
+                (true, true) => index.reverse_versions((from, to))?,
+            };
+            for entry in iter {
+                println!("{:?}", entry?);
+            }
+            Ok(())
+        }
+        SubCommand::Validate { index_file, deep: false } => {
+            let mut index = IndexT::<K, V>::open_file(&index_file)?;
+            let stats = index.validate()?;
+            println!("ok, {} entries, {} deleted", stats.n_count, stats.n_deleted);
+            Ok(())
+        }
+        SubCommand::Validate { index_file, deep: true } => {
+            let mut index = IndexT::<K, V>::open_file(&index_file)?;
+            let report = index.validate_deep()?;
+            println!(
+                "{} m-blocks, {} z-blocks, depth {}, {} entries, {} deleted",
+                report.n_mblocks,
+                report.n_zblocks,
+                report.depth,
+                report.n_count,
+                report.n_deleted
+            );
+            for problem in report.problems.iter() {
+                println!("{}", problem);
+            }
+            match report.problems.len() {
+                0 => Ok(()),
+                n => Err(format!("found {} problems", n).into()),
+            }
+        }
+        SubCommand::Meta { index_file } => {
+            let index = IndexT::<K, V>::open_file(&index_file)?;
+            cmd_meta(&index)
+        }
+        SubCommand::Tree { index_file } => {
+            let mut index = IndexT::<K, V>::open_file(&index_file)?;
+            Ok(index.print()?)
+        }
+        SubCommand::Export { index_file, format } => {
+            let mut index = IndexT::<K, V>::open_file(&index_file)?;
+            cmd_export(&mut index, format)
+        }
+        SubCommand::Import {
+            dir,
+            name,
+            format,
+            z_blocksize,
+            m_blocksize,
+            v_blocksize,
+            vlog,
+            no_delta,
+            mem_budget,
+        } => {
+            let mut config = Config::new(&dir, &name);
+            config
+                .set_blocksize(z_blocksize, v_blocksize, m_blocksize)
+                .set_value_log(vlog)
+                .set_delta(!no_delta);
+            cmd_import::<K, V>(config, format, mem_budget)
+        }
+        SubCommand::Salvage {
+            index_file,
+            dir,
+            name,
+            vlog,
+            z_blocksize,
+            m_blocksize,
+        } => {
+            let mut config = Config::new(&dir, &name);
+            config.set_blocksize(z_blocksize, VBLOCKSIZE, m_blocksize);
+            let vlog = vlog.as_deref();
+            let report = robt::db::salvage::<K, V, Dyn>(&index_file, vlog, config)?;
+            cmd_salvage(report)
+        }
+        SubCommand::Diff { old_file, new_file, from, to } => {
+            let mut old = IndexT::<K, V>::open_file(&old_file)?;
+            let mut new = IndexT::<K, V>::open_file(&new_file)?;
+            let from = match from {
+                Some(from) => Bound::Included(K::parse_arg(&from)?),
+                None => Bound::Unbounded,
+            };
+            let to = match to {
+                Some(to) => Bound::Included(K::parse_arg(&to)?),
+                None => Bound::Unbounded,
+            };
+            cmd_diff(&mut old, &mut new, (from, to))
+        }
+        SubCommand::Upgrade { index_file, dir, name } => {
+            let index = IndexT::<K, V>::open_file(&index_file)?;
+            let version = index.to_format_version();
+            let mut config = Config::from(index.to_stats());
+            config.dir = dir;
+            config.name = name;
+            config.set_merkle(index.to_merkle_root().is_some());
+            let index = index.upgrade(config, NoBitmap)?;
+            let (n, file) = (index.to_format_version(), index.to_name());
+            println!("upgraded format version {} -> {} into {}", version, n, file);
+            Ok(())
+        }
+    }
+}
+
+fn cmd_stats<K, V>(index: &IndexT<K, V>) -> Result<()>
+where
+    K: Clone,
+{
+    println!("name              : {}", index.to_name());
+    println!("app_meta_data     : {}", index.to_app_metadata().len());
+    println!("root block at     : {}", index.to_root());
+    println!("sequence num. at  : {}", index.to_seqno());
+    println!("range tombstones  : {}", index.to_range_tombstones().len());
+    println!("schema            : {:?}", index.to_schema());
+    println!("format version    : {}", index.to_format_version());
+    if let Some(root) = index.to_merkle_root() {
+        let root: Vec<String> = root.iter().map(|b| format!("{:02x}", b)).collect();
+        println!("merkle root       : {}", root.join(""));
+    }
+    println!("stats             : {:#?}", index.to_stats());
+    Ok(())
+}
+
+fn cmd_meta<K, V>(index: &IndexT<K, V>) -> Result<()> {
+    let meta = index.to_app_metadata();
+
+    let hex: String = meta.iter().map(|b| format!("{:02x}", b)).collect();
+    println!("hex  : {}", hex);
+    match Cbor::decode(&mut meta.as_slice()) {
+        Ok((val, n)) if n == meta.len() => println!("cbor : {}", Dyn::new(val)?),
+        Ok((_, n)) => println!("cbor : not a cbor value, decoded {}/{}", n, meta.len()),
+        Err(_) => println!("cbor : not a cbor value"),
+    }
+    for name in index.to_app_metadata_names().into_iter() {
+        if let Some(val) = index.get_app_metadata::<Dyn>(&name)? {
+            println!("{} : {}", name, val);
+        }
+    }
+    Ok(())
+}
+
+fn cmd_export<K, V>(index: &mut IndexT<K, V>, format: Format) -> Result<()>
+where
+    K: Clone + Ord + FromCbor + Arg,
+    V: Clone + FromCbor + Arg,
+{
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+    let iter = index.iter((Bound::<K>::Unbounded, Bound::<K>::Unbounded))?;
+
+    match format {
+        Format::Jsonl => {
+            for entry in iter {
+                let entry = entry?;
+                let (value, seqno, deleted) = match &entry.value {
+                    db::Value::U { value, seqno } => (value.to_json(), *seqno, false),
+                    db::Value::D { seqno } => (json::Value::Null, *seqno, true),
+                };
+                let record = json::json!({
+                    "key": entry.key.to_json(),
+                    "value": value,
+                    "seqno": seqno,
+                    "deleted": deleted,
+                });
+                writeln!(out, "{}", record)?;
+            }
+        }
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(out);
+            writer.write_record(&["key", "value", "seqno", "deleted"])?;
+            for entry in iter {
+                let entry = entry?;
+                let (value, seqno, deleted) = match &entry.value {
+                    db::Value::U { value, seqno } => (value.to_arg(), *seqno, false),
+                    db::Value::D { seqno } => (String::default(), *seqno, true),
+                };
+                let (seqno, deleted) = (seqno.to_string(), deleted.to_string());
+                writer.write_record(&[entry.key.to_arg(), value, seqno, deleted])?;
+            }
+            writer.flush()?;
+            return Ok(());
+        }
+    }
+
+    out.flush()?;
+    Ok(())
+}
+
+// records read from stdin, for import.
+type Records<'a, K, V> = Box<dyn Iterator<Item = Result<db::Entry<K, V, V>>> + 'a>;
+
+fn cmd_import<K, V>(config: Config, format: Format, mem_budget: usize) -> Result<()>
+where
+    K: Clone + Ord + Hash + FromCbor + IntoCbor + Arg,
+    V: Clone + FromCbor + IntoCbor + Arg,
+{
+    let stdin = io::stdin();
+    let records: Records<K, V> = match format {
+        Format::Jsonl => Box::new(read_jsonl::<K, V, _>(stdin.lock())),
+        Format::Csv => Box::new(read_csv::<K, V, _>(stdin.lock())?),
+    };
+
+    // stop at the first bad record, partially built files are removed by
+    // the builder, and the record's error is returned.
+    let iter = records.map(|item| {
+        item.map_err(|err| robt::Error::Invalid("import".to_string(), err.to_string()))
+    });
+
+    // entries are sorted externally, for duplicate keys the latest seqno wins.
+    let index_file = config.to_index_file_location();
+    let mut build = Builder::<K, V, V>::initial(config, vec![])?;
+    build.try_from_unsorted(iter, mem_budget, NoBitmap, None)?;
+
+    let index = IndexT::<K, V>::open_file(&index_file)?;
+    println!("imported {} entries into {:?}", index.len(), index_file);
+    Ok(())
+}
+
+fn read_jsonl<K, V, R>(input: R) -> impl Iterator<Item = Result<db::Entry<K, V, V>>>
 where
-    K: Clone + FromCbor + fmt::Debug,
-    V: Clone + FromCbor + fmt::Debug,
-    D: Clone + FromCbor + fmt::Debug,
-    B: Bloom,
+    K: Arg,
+    V: Arg,
+    R: BufRead,
 {
-    let mut index = Index::<K, V, D, B>::open_file(&opts.index_file).unwrap();
-    index.print().unwrap()
+    input.lines().enumerate().filter_map(|(lineno, line)| match line {
+        Ok(line) if line.trim().is_empty() => None,
+        Ok(line) => Some(parse_jsonl(lineno, &line)),
+        Err(err) => Some(Err(err.into())),
+    })
+}
+
+fn parse_jsonl<K, V>(lineno: usize, line: &str) -> Result<db::Entry<K, V, V>>
+where
+    K: Arg,
+    V: Arg,
+{
+    let record: json::Value = json::from_str(line)?;
+    let key = match record.get("key") {
+        Some(key) => K::from_json(key)?,
+        None => Err(format!("missing key at line {}", lineno + 1))?,
+    };
+    let seqno = match record.get("seqno") {
+        Some(seqno) => u64::from_json(seqno)?,
+        None => (lineno as u64) + 1,
+    };
+    let deleted = record.get("deleted").and_then(|d| d.as_bool()).unwrap_or(false);
+    let value = match (deleted, record.get("value")) {
+        (true, _) => None,
+        (false, Some(value)) => Some(V::from_json(value)?),
+        (false, None) => Err(format!("missing value at line {}", lineno + 1))?,
+    };
+    Ok(to_entry(key, value, seqno))
+}
+
+// column positions for key, value, seqno and deleted fields.
+type Columns = [Option<usize>; 4];
+
+fn read_csv<K, V, R>(input: R) -> Result<impl Iterator<Item = Result<db::Entry<K, V, V>>>>
+where
+    K: Arg,
+    V: Arg,
+    R: io::Read,
+{
+    let mut reader = csv::Reader::from_reader(input);
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| headers.iter().position(|h| h == name);
+    let cols = [column("key"), column("value"), column("seqno"), column("deleted")];
+
+    let iter = reader.into_records().enumerate().map(move |(i, record)| {
+        let record = record?;
+        parse_csv(i, &record, cols)
+    });
+    Ok(iter)
+}
+
+fn parse_csv<K, V>(
+    i: usize,
+    record: &csv::StringRecord,
+    cols: Columns,
+) -> Result<db::Entry<K, V, V>>
+where
+    K: Arg,
+    V: Arg,
+{
+    let [key_col, value_col, seqno_col, deleted_col] = cols;
+    let field = |col: Option<usize>| col.and_then(|col| record.get(col));
+
+    let key = match field(key_col) {
+        Some(key) => K::parse_arg(key)?,
+        None => Err(format!("missing key at record {}", i + 1))?,
+    };
+    let seqno = match field(seqno_col) {
+        Some(seqno) if !seqno.is_empty() => seqno.parse()?,
+        _ => (i as u64) + 1,
+    };
+    let deleted = match field(deleted_col) {
+        Some(deleted) if !deleted.is_empty() => deleted.parse()?,
+        _ => false,
+    };
+    let value = match (deleted, field(value_col)) {
+        (true, _) => None,
+        (false, Some(value)) => Some(V::parse_arg(value)?),
+        (false, None) => Err(format!("missing value at record {}", i + 1))?,
+    };
+    Ok(to_entry(key, value, seqno))
+}
+
+fn to_entry<K, V>(key: K, value: Option<V>, seqno: u64) -> db::Entry<K, V, V> {
+    let value = match value {
+        Some(value) => db::Value::U { value, seqno },
+        None => db::Value::D { seqno },
+    };
+    db::Entry { key, value, deltas: vec![] }
+}
+
+// Added entries are prefixed with `+`, removed entries with `-` and changed
+// entries with `~`, followed by key, seqno and value.
+fn cmd_diff<K, V>(
+    old: &mut IndexT<K, V>,
+    new: &mut IndexT<K, V>,
+    range: (Bound<K>, Bound<K>),
+) -> Result<()>
+where
+    K: Clone + Ord + FromCbor + Arg,
+    V: Clone + PartialEq + FromCbor + Arg,
+{
+    let to_arg = |value: &db::Value<V>| match value {
+        db::Value::U { value, seqno } => format!("{} {}", seqno, value.to_arg()),
+        db::Value::D { seqno } => format!("{} deleted", seqno),
+    };
+
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+    let (mut added, mut removed, mut changed) = (0, 0, 0);
+    for change in robt::db::diff(old, new, range)? {
+        match change? {
+            Change::Added(e) => {
+                writeln!(out, "+ {} {}", e.key.to_arg(), to_arg(&e.value))?;
+                added += 1;
+            }
+            Change::Removed(e) => {
+                writeln!(out, "- {} {}", e.key.to_arg(), to_arg(&e.value))?;
+                removed += 1;
+            }
+            Change::Changed { old, new } => {
+                let (o, n) = (to_arg(&old.value), to_arg(&new.value));
+                writeln!(out, "~ {} {} -> {}", new.key.to_arg(), o, n)?;
+                changed += 1;
+            }
+        }
+    }
+    writeln!(out, "{} added, {} removed, {} changed", added, removed, changed)?;
+
+    out.flush()?;
+    Ok(())
+}
+
+fn cmd_salvage(report: SalvageReport) -> Result<()> {
+    match report.expected {
+        Some(n) => println!("recovered {}/{} entries", report.n_count, n),
+        None => println!("recovered {} entries, meta-block lost", report.n_count),
+    }
+    println!("z-blocks recovered {}", report.n_zblocks);
+    println!("lost entries {}", report.n_lost_entries);
+    println!("lost deltas {}", report.n_lost_deltas);
+    for key in report.lost_keys.iter() {
+        println!("lost key {}", key);
+    }
+    for lost in report.lost_ranges.iter() {
+        let after = lost.after.as_deref().unwrap_or("-");
+        let before = lost.before.as_deref().unwrap_or("-");
+        println!(
+            "lost region {{{},{}}} keys between {} and {}",
+            lost.fpos, lost.length, after, before
+        );
+    }
+    Ok(())
 }
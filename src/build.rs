@@ -3,17 +3,137 @@ use mkit::{
     db,
 };
 
-use std::{cell::RefCell, convert::TryFrom, rc::Rc};
+use std::{
+    cell::RefCell,
+    cmp,
+    collections::VecDeque,
+    convert::TryFrom,
+    marker,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering::SeqCst},
+        Arc,
+    },
+};
 
 use crate::{
-    config::Config, entry::Entry, flush::Flusher, scans::BuildScan, util, Result,
+    checkpoint::Checkpointer,
+    config::Config,
+    entry::Entry,
+    flush::Flusher,
+    merkle::{self, Hashes},
+    scans::{BuildScan, ScanItem, ToBitmap},
+    util, Error, Result,
 };
 
+// block hashes, collected while building the index with merkle tree.
+pub type HashesRef = Option<Rc<RefCell<Hashes>>>;
+
+// checkpoints, recorded while building the index.
+pub type CheckpointRef<K> = Rc<RefCell<Checkpointer<K>>>;
+
+/// Progress of an on-going build, reported after every leaf block, refer
+/// to [Builder::set_observer][crate::db::Builder::set_observer].
+#[derive(Clone, Debug, Default)]
+pub struct Progress {
+    /// Number of entries flushed into leaf blocks.
+    pub n_entries: u64,
+    /// Number of leaf blocks flushed.
+    pub n_blocks: u64,
+    /// Number of bytes written to index file.
+    pub n_ibytes: u64,
+    /// Number of bytes written to value-log file.
+    pub n_vbytes: u64,
+}
+
+// Observe build progress, called after every leaf block is flushed.
+pub type ObserverFn = Rc<dyn Fn(&Progress)>;
+
+// Leaf iterator, yielding the first key and file-position of each z-block,
+// refer to [BuildZZ], and counting the entries flushed so far.
+pub trait LeafIter<K>: Iterator<Item = Result<(K, u64)>> {
+    fn to_entries(&self) -> u64;
+}
+
+impl<'a, K, T> LeafIter<K> for &'a mut T
+where
+    T: LeafIter<K>,
+{
+    fn to_entries(&self) -> u64 {
+        (**self).to_entries()
+    }
+}
+
+// Wrap the leaf iterator, to report progress and to check for cancellation
+// between leaf blocks.
+pub struct Watch<K, I> {
+    iter: I,
+    iflush: Rc<RefCell<Flusher>>,
+    vflush: Rc<RefCell<Flusher>>,
+    observer: Option<ObserverFn>,
+    cancel: Option<Arc<AtomicBool>>,
+    n_blocks: u64,
+
+    _key: marker::PhantomData<K>,
+}
+
+impl<K, I> Watch<K, I> {
+    pub fn new(
+        iter: I,
+        (iflush, vflush): (Rc<RefCell<Flusher>>, Rc<RefCell<Flusher>>),
+        observer: Option<ObserverFn>,
+        cancel: Option<Arc<AtomicBool>>,
+    ) -> Self {
+        Watch {
+            iter,
+            iflush,
+            vflush,
+            observer,
+            cancel,
+            n_blocks: 0,
+
+            _key: marker::PhantomData,
+        }
+    }
+}
+
+impl<K, I> Iterator for Watch<K, I>
+where
+    I: LeafIter<K>,
+{
+    type Item = Result<(K, u64)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(cancel) = self.cancel.as_ref() {
+            if cancel.load(SeqCst) {
+                return Some(err_at!(Cancelled, msg: "after {} blocks", self.n_blocks));
+            }
+        }
+
+        let item = self.iter.next()?;
+        self.n_blocks += u64::from(item.is_ok());
+        if let (Ok(_), Some(observer)) = (&item, self.observer.as_ref()) {
+            let progress = Progress {
+                n_entries: self.iter.to_entries(),
+                n_blocks: self.n_blocks,
+                n_ibytes: self.iflush.borrow().to_fpos().unwrap_or(0),
+                n_vbytes: self.vflush.borrow().to_fpos().unwrap_or(0),
+            };
+            observer(&progress)
+        }
+        Some(item)
+    }
+}
+
 pub struct BuildMM<K, V, D, I> {
     m_blocksize: usize,
+    m_limit: usize,
     iflush: Rc<RefCell<Flusher>>,
     iter: Box<BuildIter<K, V, D, I>>,
-    entry: Option<(K, u64)>,
+    entries: VecDeque<(K, u64)>,
+    hashes: HashesRef,
+    level: usize,
+    checkpoint: Option<CheckpointRef<K>>,
 }
 
 impl<K, V, D, I> BuildMM<K, V, D, I> {
@@ -21,14 +141,33 @@ impl<K, V, D, I> BuildMM<K, V, D, I> {
         config: &Config,
         iflush: Rc<RefCell<Flusher>>,
         iter: BuildIter<K, V, D, I>,
+        hashes: HashesRef,
     ) -> Self {
         BuildMM {
             m_blocksize: config.m_blocksize,
+            m_limit: config.to_m_limit(),
             iflush,
             iter: Box::new(iter),
-            entry: None,
+            entries: VecDeque::default(),
+            hashes,
+            level: 0,
+            checkpoint: None,
         }
     }
+
+    // restore the open block at `level` from checkpoint, and record its
+    // children as they are added, refer to [Checkpointer].
+    pub fn set_checkpoint(
+        &mut self,
+        checkpoint: CheckpointRef<K>,
+        level: usize,
+        children: VecDeque<(K, u64)>,
+    ) -> &mut Self {
+        self.checkpoint = Some(checkpoint);
+        self.level = level;
+        self.entries = children;
+        self
+    }
 }
 
 impl<K, V, D, I> Iterator for BuildMM<K, V, D, I>
@@ -36,7 +175,7 @@ where
     K: Clone + IntoCbor,
     V: Clone + IntoCbor,
     D: Clone + IntoCbor,
-    I: Iterator<Item = db::Entry<K, V, D>>,
+    I: Iterator<Item = Result<(K, u64)>>,
 {
     type Item = Result<(K, u64)>;
 
@@ -47,12 +186,13 @@ where
         let mut first_key: Option<K> = None;
         let mut curr_fpos = None;
         let mut n = 0;
+        let mut children = vec![];
 
         iter_result!(Cbor::Major4(cbor::Info::Indefinite, vec![]).encode(&mut mblock));
 
         loop {
             let entry = {
-                let entry = self.entry.take().map(|e| Some(Ok(e)));
+                let entry = self.entries.pop_front().map(|e| Some(Ok(e)));
                 entry.unwrap_or_else(|| self.iter.next())
             };
             match entry {
@@ -65,11 +205,26 @@ where
                         let e = Entry::<K, V, D>::new_mm(key.clone(), fpos);
                         iter_result!(util::into_cbor_bytes(e))
                     };
-                    if (mblock.len() + ibytes.len()) > block_size {
-                        self.entry = Some((key, fpos));
+                    // an m-block holding a single child is not flushed, hence
+                    // make sure that every m-block holds at least two children.
+                    let limit = match children.len() {
+                        0 | 1 => block_size,
+                        _ => self.m_limit,
+                    };
+                    if (mblock.len() + ibytes.len()) > limit {
+                        if children.len() < 2 {
+                            let n = ibytes.len();
+                            let e = err_at!(Invalid, msg: "entry {} exceeds m-block", n);
+                            return Some(e);
+                        }
+                        self.entries.push_front((key, fpos));
                         break;
                     }
                     mblock.extend_from_slice(&ibytes);
+                    children.push(fpos);
+                    if let Some(checkpoint) = self.checkpoint.as_ref() {
+                        checkpoint.borrow_mut().add_child(self.level, key, fpos);
+                    }
                 }
                 Some(Err(err)) => return Some(Err(err)),
                 None if first_key.is_some() => break,
@@ -82,34 +237,65 @@ where
         mblock.resize(self.m_blocksize, 0);
 
         if n > 1 {
-            curr_fpos = Some(self.iflush.borrow().to_fpos().unwrap_or(0));
+            let fpos = self.iflush.borrow().to_fpos().unwrap_or(0);
+            iter_result!(record_node(&self.hashes, fpos, &children));
+            curr_fpos = Some(fpos);
             iter_result!(self.iflush.borrow_mut().flush(mblock));
         }
+        if let Some(checkpoint) = self.checkpoint.as_ref() {
+            checkpoint.borrow_mut().close_level(self.level);
+        }
 
         Some(Ok((first_key.unwrap(), curr_fpos.unwrap())))
     }
 }
 
+// `I` is the leaf iterator, yielding the first key and file-position of
+// each z-block, refer to [BuildZZ].
 pub struct BuildMZ<K, V, D, I> {
     m_blocksize: usize,
+    m_limit: usize,
     iflush: Rc<RefCell<Flusher>>,
-    iter: BuildZZ<K, V, D, I>,
-    entry: Option<(K, u64)>,
+    iter: I,
+    entries: VecDeque<(K, u64)>,
+    hashes: HashesRef,
+    checkpoint: Option<CheckpointRef<K>>,
+
+    _val: marker::PhantomData<V>,
+    _dff: marker::PhantomData<D>,
 }
 
 impl<K, V, D, I> BuildMZ<K, V, D, I> {
     pub fn new(
         config: &Config,
         iflush: Rc<RefCell<Flusher>>,
-        iter: BuildZZ<K, V, D, I>,
+        iter: I,
+        hashes: HashesRef,
     ) -> Self {
         BuildMZ {
             m_blocksize: config.m_blocksize,
+            m_limit: config.to_m_limit(),
             iflush,
             iter,
-            entry: None,
+            entries: VecDeque::default(),
+            hashes,
+            checkpoint: None,
+            _val: marker::PhantomData,
+            _dff: marker::PhantomData,
         }
     }
+
+    // restore the open block from checkpoint, and record its children as
+    // they are added, refer to [Checkpointer].
+    pub fn set_checkpoint(
+        &mut self,
+        checkpoint: CheckpointRef<K>,
+        children: VecDeque<(K, u64)>,
+    ) -> &mut Self {
+        self.checkpoint = Some(checkpoint);
+        self.entries = children;
+        self
+    }
 }
 
 impl<K, V, D, I> Iterator for BuildMZ<K, V, D, I>
@@ -117,7 +303,7 @@ where
     K: Clone + IntoCbor,
     V: Clone + IntoCbor,
     D: Clone + IntoCbor,
-    I: Iterator<Item = db::Entry<K, V, D>>,
+    I: Iterator<Item = Result<(K, u64)>>,
 {
     type Item = Result<(K, u64)>;
 
@@ -126,12 +312,13 @@ where
         let block_size = self.m_blocksize.saturating_sub(1);
 
         let mut first_key: Option<K> = None;
+        let mut children = vec![];
 
         iter_result!(Cbor::Major4(cbor::Info::Indefinite, vec![]).encode(&mut mblock));
 
         loop {
             let entry = {
-                let entry = self.entry.take().map(|e| Some(Ok(e)));
+                let entry = self.entries.pop_front().map(|e| Some(Ok(e)));
                 entry.unwrap_or_else(|| self.iter.next())
             };
             match entry {
@@ -141,11 +328,24 @@ where
                         let e = Entry::<K, V, D>::new_mz(key.clone(), fpos);
                         iter_result!(util::into_cbor_bytes(e))
                     };
-                    if (mblock.len() + ibytes.len()) > block_size {
-                        self.entry = Some((key, fpos));
+                    let limit = match children.len() {
+                        0 => block_size,
+                        _ => self.m_limit,
+                    };
+                    if (mblock.len() + ibytes.len()) > limit {
+                        if children.is_empty() {
+                            let n = ibytes.len();
+                            let e = err_at!(Invalid, msg: "entry {} exceeds m-block", n);
+                            return Some(e);
+                        }
+                        self.entries.push_front((key, fpos));
                         break;
                     }
                     mblock.extend_from_slice(&ibytes);
+                    children.push(fpos);
+                    if let Some(checkpoint) = self.checkpoint.as_ref() {
+                        checkpoint.borrow_mut().add_child(0, key, fpos);
+                    }
                 }
                 Some(Err(err)) => return Some(Err(err)),
                 None if first_key.is_some() => break,
@@ -158,20 +358,36 @@ where
         mblock.resize(self.m_blocksize, 0);
 
         let fpos = self.iflush.borrow().to_fpos().unwrap_or(0);
+        iter_result!(record_node(&self.hashes, fpos, &children));
 
         iter_result!(self.iflush.borrow_mut().flush(mblock));
+        if let Some(checkpoint) = self.checkpoint.as_ref() {
+            checkpoint.borrow_mut().close_level(0);
+        }
+
         Some(Ok((first_key.unwrap(), fpos)))
     }
 }
 
+// Compute the expiry for an entry, as nanoseconds from UNIX EPOCH.
+pub type ExpiryFn<K, V, D> = Rc<dyn Fn(&db::Entry<K, V, D>) -> Option<u64>>;
+
 pub struct BuildZZ<K, V, D, I> {
     z_blocksize: usize,
+    z_limit: usize,
     v_blocksize: usize,
     value_in_vlog: bool,
     delta_ok: bool,
     iflush: Rc<RefCell<Flusher>>,
     vflush: Rc<RefCell<Flusher>>,
     iter: Rc<RefCell<BuildScan<K, V, D, I>>>,
+    expiry: Option<ExpiryFn<K, V, D>>,
+    hashes: HashesRef,
+    vshift: u64,
+    checkpoint: Option<CheckpointRef<K>>,
+    n_entries: u64,
+    n_deleted: u64,
+    seqno: u64,
 }
 
 impl<K, V, D, I> BuildZZ<K, V, D, I> {
@@ -180,17 +396,41 @@ impl<K, V, D, I> BuildZZ<K, V, D, I> {
         iflush: Rc<RefCell<Flusher>>,
         vflush: Rc<RefCell<Flusher>>,
         iter: Rc<RefCell<BuildScan<K, V, D, I>>>,
+        expiry: Option<ExpiryFn<K, V, D>>,
+        hashes: HashesRef,
     ) -> Self {
         BuildZZ {
             z_blocksize: config.z_blocksize,
+            z_limit: config.to_z_limit(),
             v_blocksize: config.v_blocksize,
             value_in_vlog: config.value_in_vlog,
             delta_ok: config.delta_ok,
             iflush,
             vflush,
             iter,
+            expiry,
+            hashes,
+            vshift: 0,
+            checkpoint: None,
+            n_entries: 0,
+            n_deleted: 0,
+            seqno: 0,
         }
     }
+
+    // value-log references are computed as `vshift` plus the value-log
+    // flusher's file-position, used by parallel build.
+    pub fn set_vshift(&mut self, vshift: u64) -> &mut Self {
+        self.vshift = vshift;
+        self
+    }
+
+    // record flushed leaf blocks, and periodically append a checkpoint,
+    // refer to [Checkpointer].
+    pub fn set_checkpoint(&mut self, checkpoint: CheckpointRef<K>) -> &mut Self {
+        self.checkpoint = Some(checkpoint);
+        self
+    }
 }
 
 impl<K, V, D, I> Iterator for BuildZZ<K, V, D, I>
@@ -198,7 +438,8 @@ where
     K: Clone + IntoCbor,
     V: Clone + IntoCbor,
     D: Clone + IntoCbor,
-    I: Iterator<Item = db::Entry<K, V, D>>,
+    I: Iterator + ToBitmap,
+    I::Item: ScanItem<K, V, D>,
 {
     type Item = Result<(K, u64)>;
 
@@ -208,32 +449,51 @@ where
         let block_size = self.z_blocksize.saturating_sub(1);
 
         let mut first_key: Option<K> = None;
+        let mut last_key: Option<K> = None;
 
         iter_result!(Cbor::Major4(cbor::Info::Indefinite, vec![]).encode(&mut zblock));
 
         let mut iter = self.iter.borrow_mut();
-        let mut vfpos = self.vflush.borrow().to_fpos().unwrap_or(0);
+        let mut vfpos = self.vflush.borrow().to_fpos().unwrap_or(0) + self.vshift;
 
         loop {
             match iter.next() {
-                Some(mut entry) => {
+                Some((mut entry, expiry)) => {
                     if !self.delta_ok {
                         entry.drain_deltas()
                     }
-                    first_key.get_or_insert_with(|| entry.key.clone());
+                    let expiry =
+                        expiry.or_else(|| self.expiry.as_ref().and_then(|f| f(&entry)));
                     let (e, vbytes) = {
-                        let e = Entry::<K, V, D>::from(entry.clone());
+                        let mut e = Entry::<K, V, D>::from(entry.clone());
+                        e.set_expiry(expiry);
                         iter_result!(e.into_reference(vfpos, self.value_in_vlog))
                     };
                     let ibytes = iter_result!(util::into_cbor_bytes(e));
 
-                    if (zblock.len() + ibytes.len()) > block_size {
-                        iter.push(entry);
+                    let limit = match first_key {
+                        Some(_) => self.z_limit,
+                        None => block_size,
+                    };
+                    if (zblock.len() + ibytes.len()) > limit {
+                        if first_key.is_none() {
+                            let n = ibytes.len();
+                            let e = err_at!(Invalid, msg: "entry {} exceeds z-block", n);
+                            return Some(e);
+                        }
+                        iter.push((entry, expiry));
                         break;
                     }
+                    first_key.get_or_insert_with(|| entry.key.clone());
                     zblock.extend_from_slice(&ibytes);
                     vblock.extend_from_slice(&vbytes);
                     vfpos += u64::try_from(vbytes.len()).unwrap();
+                    self.n_entries += 1;
+                    self.n_deleted += u64::from(entry.is_deleted());
+                    self.seqno = cmp::max(self.seqno, entry.to_seqno());
+                    if self.checkpoint.is_some() {
+                        last_key = Some(entry.key);
+                    }
                 }
                 None if first_key.is_some() => break,
                 None => return None,
@@ -245,11 +505,83 @@ where
         zblock.resize(self.z_blocksize, 0);
 
         let fpos = self.iflush.borrow().to_fpos().unwrap_or(0);
+        if let Some(hashes) = self.hashes.as_ref() {
+            let hash = merkle::leaf_hash(&zblock, &vblock);
+            hashes.borrow_mut().insert(fpos, hash);
+        }
 
         iter_result!(self.vflush.borrow_mut().flush(vblock));
         iter_result!(self.iflush.borrow_mut().flush(zblock));
-        Some(Ok((first_key.unwrap(), fpos)))
+
+        let first_key = first_key.unwrap();
+        if let (Some(checkpoint), Some(last_key)) = (self.checkpoint.as_ref(), last_key) {
+            let leaf = (first_key.clone(), fpos);
+            iter_result!(self.to_checkpoint(checkpoint, leaf, last_key, &iter));
+        }
+
+        Some(Ok((first_key, fpos)))
+    }
+}
+
+impl<K, V, D, I> BuildZZ<K, V, D, I>
+where
+    K: Clone + IntoCbor,
+    I: ToBitmap,
+{
+    // count the leaf block, and if a checkpoint is due, sync the flushed
+    // data before persisting the checkpoint, along with block hashes and
+    // the bitmap's state.
+    fn to_checkpoint(
+        &self,
+        checkpoint: &CheckpointRef<K>,
+        leaf: (K, u64),
+        last_key: K,
+        scan: &BuildScan<K, V, D, I>,
+    ) -> Result<()> {
+        let mut checkpoint = checkpoint.borrow_mut();
+        if checkpoint.add_leaf() {
+            let (iflush, vflush) = (self.iflush.borrow(), self.vflush.borrow());
+            iflush.sync()?;
+            vflush.sync()?;
+
+            let ipos = iflush.to_fpos().unwrap_or(0);
+            let vpos = vflush.to_fpos().unwrap_or(0);
+            let stats = (self.n_entries, self.n_deleted, self.seqno);
+            let hashes = match self.hashes.as_ref() {
+                Some(hashes) => hashes.borrow().to_bytes(),
+                None => vec![],
+            };
+            let state = (hashes, scan.to_bitmap()?);
+            checkpoint.flush((last_key, leaf), (ipos, vpos), stats, state)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<K, V, D, I> LeafIter<K> for BuildZZ<K, V, D, I>
+where
+    K: Clone + IntoCbor,
+    V: Clone + IntoCbor,
+    D: Clone + IntoCbor,
+    I: Iterator + ToBitmap,
+    I::Item: ScanItem<K, V, D>,
+{
+    fn to_entries(&self) -> u64 {
+        self.n_entries
+    }
+}
+
+// record hash for intermediate block at `fpos`, over its children.
+fn record_node(hashes: &HashesRef, fpos: u64, children: &[u64]) -> Result<()> {
+    if let Some(hashes) = hashes.as_ref() {
+        let mut hashes = hashes.borrow_mut();
+        let children: Result<Vec<merkle::Hash>> =
+            children.iter().map(|fpos| hashes.get(*fpos)).collect();
+        let hash = merkle::node_hash(&children?);
+        hashes.insert(fpos, hash);
     }
+    Ok(())
 }
 
 pub enum BuildIter<K, V, D, I> {
@@ -274,7 +606,7 @@ where
     K: Clone + IntoCbor,
     V: Clone + IntoCbor,
     D: Clone + IntoCbor,
-    I: Iterator<Item = db::Entry<K, V, D>>,
+    I: Iterator<Item = Result<(K, u64)>>,
 {
     type Item = Result<(K, u64)>;
 
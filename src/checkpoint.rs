@@ -0,0 +1,312 @@
+//! Module implement checkpoints, to resume an interrupted build.
+//!
+//! When enabled via [Config::set_checkpoint][crate::Config::set_checkpoint],
+//! every few leaf blocks the builder syncs the index file and value-log file
+//! and persists a [Checkpoint] record, replacing the previous record. Record
+//! is written to a temporary file and renamed over the checkpoint file, hence
+//! a record that was only partially written, when the build died, is never
+//! loaded.
+//!
+//! Record carries the open intermediate block at each level of the tree,
+//! that is, children of m-blocks that are yet to be flushed, along with the
+//! bitmap's state, block hashes, file offsets and stats. While resuming,
+//! index file and value-log file are truncated to the offsets from the
+//! record, open blocks are restored, refer to [ReplayZZ], and the build
+//! continues with entries after the last flushed key. Intermediate blocks
+//! flushed before the checkpoint are referred by the open blocks, hence they
+//! remain part of the final tree.
+//!
+//! Builder's metadata, that is, named application metadata, range tombstones
+//! and schema-id, is persisted with the record and restored while resuming.
+//! Expiry function, observer and cancel token cannot be persisted, they
+//! shall be set again on the resumed builder.
+
+use mkit::{
+    cbor::{Cbor, FromCbor, IntoCbor},
+    Cborize,
+};
+
+use std::{cmp, collections::VecDeque, ffi, fs, io::Read};
+
+use crate::{
+    build::LeafIter, meta::MetaSection, tombstone::RangeTombstone, util, Error, Result,
+};
+
+const CHILD_VER1: u32 = 0x0001;
+const CHECKPOINT_VER3: u32 = 0x0003;
+
+/// Child block referred by an open intermediate block.
+#[derive(Clone, Debug, Cborize)]
+pub struct Child<K> {
+    /// First key in the child block.
+    pub key: K,
+    /// File position of the child block.
+    pub fpos: u64,
+}
+
+impl<K> Child<K> {
+    const ID: u32 = CHILD_VER1;
+}
+
+/// Record persisted in checkpoint file.
+#[derive(Clone, Debug, Cborize)]
+pub struct Checkpoint<K> {
+    /// Last key flushed into the index.
+    pub last_key: K,
+    /// Last leaf block flushed, yet to be added to its parent block.
+    pub leaf: Child<K>,
+    /// Children of the open intermediate block at each level, starting from
+    /// the level just above the leaf blocks.
+    pub levels: Vec<Vec<Child<K>>>,
+    /// Index file is consistent up to this offset.
+    pub ipos: u64,
+    /// Value-log file is consistent up to this offset.
+    pub vpos: u64,
+    /// Value-log file used by the build, if any.
+    pub vlog_file: Option<ffi::OsString>,
+    /// Older size of value-log file, applicable only in incremental build.
+    pub n_abytes: u64,
+    /// Number of entries flushed so far.
+    pub n_count: u64,
+    /// Number of deleted entries flushed so far.
+    pub n_deleted: u64,
+    /// Sequence number of the latest entry flushed so far.
+    pub seqno: u64,
+    /// Hashes of blocks flushed so far, if the index is built with merkle
+    /// tree, refer to [Hashes][crate::merkle::Hashes].
+    pub hashes: Vec<u8>,
+    /// Bitmap over keys flushed so far, if any.
+    pub bitmap: Vec<u8>,
+    /// Named application metadata, as `{name, data}` sections.
+    pub app_items: Vec<MetaSection>,
+    /// Range tombstones to be persisted with the index.
+    pub tombstones: Vec<RangeTombstone<K>>,
+    /// Application supplied schema-id, if any.
+    pub schema_id: Option<String>,
+}
+
+impl<K> Checkpoint<K> {
+    const ID: u32 = CHECKPOINT_VER3;
+
+    /// Return the children of open intermediate blocks, level by level.
+    pub fn to_levels(&self) -> Vec<VecDeque<(K, u64)>>
+    where
+        K: Clone,
+    {
+        let iter = self.levels.iter();
+        iter.map(|cs| cs.iter().map(|c| (c.key.clone(), c.fpos)).collect()).collect()
+    }
+}
+
+/// Persist checkpoint records while building the index.
+pub struct Checkpointer<K> {
+    file: ffi::OsString,
+    every: usize,
+    n_leaves: usize,
+    levels: Vec<Vec<Child<K>>>,
+    vlog: (Option<ffi::OsString>, u64),
+    // stats upto the checkpoint this build was resumed from.
+    base: (u64, u64, u64),
+    // builder's metadata, persisted along with every record.
+    app_items: Vec<MetaSection>,
+    tombstones: Vec<RangeTombstone<K>>,
+    schema_id: Option<String>,
+}
+
+impl<K> Checkpointer<K> {
+    /// Start afresh, removing checkpoint left behind by an earlier build,
+    /// a record is persisted for every `every` leaf blocks.
+    pub fn create(
+        file: &ffi::OsStr,
+        every: usize,
+        vlog: (Option<ffi::OsString>, u64),
+    ) -> Result<Self> {
+        // NOTE: ignore remove errors.
+        fs::remove_file(file).ok();
+        fs::remove_file(to_temp_file(file)).ok();
+
+        let val = Checkpointer {
+            file: file.to_os_string(),
+            every: cmp::max(every, 1),
+            n_leaves: 0,
+            levels: vec![],
+            vlog,
+            base: (0, 0, 0),
+            app_items: vec![],
+            tombstones: vec![],
+            schema_id: None,
+        };
+
+        Ok(val)
+    }
+
+    /// Load the record from checkpoint file, a partially written record,
+    /// if any, is removed.
+    pub fn load(file: &ffi::OsStr, every: usize) -> Result<(Self, Checkpoint<K>)>
+    where
+        K: FromCbor,
+    {
+        fs::remove_file(to_temp_file(file)).ok(); // NOTE: ignore remove errors.
+
+        let data = {
+            let mut fd = err_at!(IOError, fs::File::open(file))?;
+            let mut data = vec![];
+            err_at!(IOError, fd.read_to_end(&mut data))?;
+            data
+        };
+        let (val, _) = err_at!(InvalidFile, Cbor::decode(&mut data.as_slice()))?;
+        let last = err_at!(InvalidFile, Checkpoint::<K>::from_cbor(val))?;
+
+        let val = Checkpointer {
+            file: file.to_os_string(),
+            every: cmp::max(every, 1),
+            n_leaves: 0,
+            levels: vec![],
+            vlog: (last.vlog_file.clone(), last.n_abytes),
+            base: (last.n_count, last.n_deleted, last.seqno),
+            app_items: vec![],
+            tombstones: vec![],
+            schema_id: None,
+        };
+
+        Ok((val, last))
+    }
+
+    /// Set the builder's metadata, to be persisted along with every record.
+    /// Called before the build starts, after the builder is configured.
+    pub fn set_metadata(
+        &mut self,
+        app_items: Vec<MetaSection>,
+        tombstones: Vec<RangeTombstone<K>>,
+        schema_id: Option<String>,
+    ) {
+        self.app_items = app_items;
+        self.tombstones = tombstones;
+        self.schema_id = schema_id;
+    }
+
+    /// Record a child added to the open intermediate block at `level`.
+    pub fn add_child(&mut self, level: usize, key: K, fpos: u64) {
+        if self.levels.len() <= level {
+            self.levels.resize_with(level + 1, Vec::new)
+        }
+        self.levels[level].push(Child { key, fpos });
+    }
+
+    /// Open intermediate block at `level` is done with, either flushed or
+    /// passed on to its parent.
+    pub fn close_level(&mut self, level: usize) {
+        if let Some(children) = self.levels.get_mut(level) {
+            children.clear()
+        }
+    }
+
+    /// Record a flushed leaf block, return whether a checkpoint is due.
+    pub fn add_leaf(&mut self) -> bool {
+        self.n_leaves += 1;
+        self.n_leaves >= self.every
+    }
+
+    /// Persist a checkpoint record. Caller shall make sure that index file
+    /// and value-log file are synced up to `ipos` and `vpos`, and that
+    /// `leaf` is the last block flushed into the index file. Stats are
+    /// counted from the point this build was started, or resumed.
+    pub fn flush(
+        &mut self,
+        (last_key, leaf): (K, (K, u64)),
+        (ipos, vpos): (u64, u64),
+        (n_count, n_deleted, seqno): (u64, u64, u64),
+        (hashes, bitmap): (Vec<u8>, Vec<u8>),
+    ) -> Result<()>
+    where
+        K: Clone + IntoCbor,
+    {
+        let (b_count, b_deleted, b_seqno) = self.base;
+        let record = Checkpoint {
+            last_key,
+            leaf: Child { key: leaf.0, fpos: leaf.1 },
+            levels: self.levels.clone(),
+            ipos,
+            vpos,
+            vlog_file: self.vlog.0.clone(),
+            n_abytes: self.vlog.1,
+            n_count: b_count + n_count,
+            n_deleted: b_deleted + n_deleted,
+            seqno: cmp::max(b_seqno, seqno),
+            hashes,
+            bitmap,
+            app_items: self.app_items.clone(),
+            tombstones: self.tombstones.clone(),
+            schema_id: self.schema_id.clone(),
+        };
+
+        let data = util::into_cbor_bytes(record)?;
+        let temp = to_temp_file(&self.file);
+        {
+            let mut fd = err_at!(IOError, fs::File::create(&temp))?;
+            write_file!(fd, &data, &temp, "checkpoint")?;
+            err_at!(IOError, fd.sync_all(), "fail sync {:?}", temp)?;
+        }
+        err_at!(IOError, fs::rename(&temp, &self.file), "fail rename {:?}", temp)?;
+        self.n_leaves = 0;
+
+        Ok(())
+    }
+
+    /// Remove checkpoint file, after the build is complete.
+    pub fn purge(&self) {
+        fs::remove_file(&self.file).ok();
+        fs::remove_file(to_temp_file(&self.file)).ok();
+    }
+}
+
+/// Iterator over leaf blocks, replaying the last leaf block flushed before
+/// the checkpoint, followed by leaf blocks from `zz`. Open intermediate
+/// blocks are restored by the upper levels, refer to
+/// [Checkpoint::to_levels].
+pub struct ReplayZZ<K, Z> {
+    leaf: Option<(K, u64)>,
+    n_entries: u64,
+    zz: Z,
+}
+
+impl<K, Z> ReplayZZ<K, Z> {
+    /// `n_entries` is the number of entries flushed before the checkpoint.
+    pub fn new(leaf: (K, u64), n_entries: u64, zz: Z) -> Self {
+        ReplayZZ { leaf: Some(leaf), n_entries, zz }
+    }
+}
+
+impl<K, Z> Iterator for ReplayZZ<K, Z>
+where
+    Z: LeafIter<K>,
+{
+    type Item = Result<(K, u64)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.leaf.take() {
+            Some(leaf) => Some(Ok(leaf)),
+            None => self.zz.next(),
+        }
+    }
+}
+
+impl<K, Z> LeafIter<K> for ReplayZZ<K, Z>
+where
+    Z: LeafIter<K>,
+{
+    fn to_entries(&self) -> u64 {
+        self.n_entries + self.zz.to_entries()
+    }
+}
+
+// record is written to a temporary file, and renamed as checkpoint file.
+fn to_temp_file(file: &ffi::OsStr) -> ffi::OsString {
+    let mut temp = file.to_os_string();
+    temp.push(".temp");
+    temp
+}
+
+#[cfg(test)]
+#[path = "checkpoint_test.rs"]
+mod checkpoint_test;
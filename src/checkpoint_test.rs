@@ -0,0 +1,244 @@
+use mkit::{db::BuildIndex, nobitmap::NoBitmap};
+use rand::{prelude::random, rngs::StdRnd, Rng, SeedableRng};
+
+use std::{ops::Bound, panic, path};
+
+use super::*;
+use crate::{
+    config::to_checkpoint_file,
+    db::{Builder, Index},
+    Config,
+};
+
+#[test]
+fn test_build_index_resume() {
+    let seed: u128 = random();
+    println!("test_build_index_resume {}", seed);
+    let mut rng = StdRnd::from_seed(seed.to_le_bytes());
+
+    let dir = std::env::temp_dir().join("test_build_index_resume");
+    let mut config = Config::new(dir.as_os_str(), "test_build_index_resume");
+    config
+        .set_blocksize(1024, 1024, 1024)
+        .set_value_log(rng.gen())
+        .set_delta(rng.gen())
+        .set_merkle(rng.gen())
+        .set_checkpoint(1 + (rng.gen::<usize>() % 4));
+    println!(
+        "vlog:{} delta:{} merkle:{} checkpoint:{:?}",
+        config.value_in_vlog, config.delta_ok, config.merkle, config.checkpoint
+    );
+
+    let mdb = crate::util::load_index(seed, 5_000, 1_000, 500, 100, None);
+
+    // build dies midway.
+    let n = mdb.len() / 2;
+    let res = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        let mut build =
+            Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+        let iter = mdb.iter().unwrap().enumerate().map(|(i, e)| match i {
+            i if i == n => panic!("build died at {}", i),
+            _ => e,
+        });
+        build.build_index(iter, NoBitmap, None).unwrap();
+    }));
+    assert!(res.is_err());
+
+    // a partially written checkpoint is ignored.
+    let file = to_checkpoint_file(&config.dir, &config.name);
+    fs::write(to_temp_file(&file), b"partial record").unwrap();
+
+    let mut build = Builder::<u16, u64, u64>::resume(config.clone(), vec![]).unwrap();
+    build.build_index_resume(|_| mdb.iter().unwrap(), NoBitmap, None).unwrap();
+    assert!(!path::Path::new(&file).exists());
+    assert!(!path::Path::new(&to_temp_file(&file)).exists());
+
+    let mut index =
+        Index::<u16, u64, u64, NoBitmap>::open(&config.dir, &config.name).unwrap();
+    let stats = index.validate().unwrap();
+    assert_eq!(stats.n_count, mdb.len() as u64);
+    assert_eq!(stats.n_deleted, mdb.deleted_count());
+    assert_eq!(stats.seqno, mdb.to_seqno());
+    assert_eq!(index.to_merkle_root().is_some(), config.merkle);
+
+    let r = (Bound::<u16>::Unbounded, Bound::<u16>::Unbounded);
+    let mut iter = index.iter_versions(r).unwrap();
+    for mut e1 in mdb.iter().unwrap() {
+        if !config.delta_ok {
+            e1.deltas = vec![];
+        }
+        assert_eq!(e1, iter.next().unwrap().unwrap());
+    }
+    assert!(iter.next().is_none());
+
+    // nothing to resume from.
+    match Builder::<u16, u64, u64>::resume(config.clone(), vec![]) {
+        Err(Error::IOError(_, _)) => (),
+        res => panic!("unexpected {:?}", res.err()),
+    }
+}
+
+#[test]
+fn test_build_index_resume_layout() {
+    let seed: u128 = random();
+    println!("test_build_index_resume_layout {}", seed);
+    let mut rng = StdRnd::from_seed(seed.to_le_bytes());
+
+    let dir = std::env::temp_dir().join("test_build_index_resume_layout");
+    let mut config = Config::new(dir.as_os_str(), "test_build_index_resume_layout");
+    config
+        .set_blocksize(1024, 1024, 1024)
+        .set_value_log(rng.gen())
+        .set_merkle(rng.gen())
+        .set_epoch(1)
+        .set_checkpoint(1 + (rng.gen::<usize>() % 4));
+    println!(
+        "vlog:{} merkle:{} checkpoint:{:?}",
+        config.value_in_vlog, config.merkle, config.checkpoint
+    );
+
+    let mdb = crate::util::load_index(seed, 20_000, 0, 0, 0, None);
+    let index_file = config.to_index_file_location();
+
+    // uninterrupted build.
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    build.build_index(mdb.iter().unwrap(), NoBitmap, None).unwrap();
+    let reference = fs::read(&index_file).unwrap();
+
+    // build dies midway.
+    let n = (mdb.len() / 2) + (rng.gen::<usize>() % (mdb.len() / 2));
+    let res = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        let mut build =
+            Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+        let iter = mdb.iter().unwrap().enumerate().map(|(i, e)| match i {
+            i if i == n => panic!("build died at {}", i),
+            _ => e,
+        });
+        build.build_index(iter, NoBitmap, None).unwrap();
+    }));
+    assert!(res.is_err());
+
+    let mut build = Builder::<u16, u64, u64>::resume(config.clone(), vec![]).unwrap();
+    build.build_index_resume(|_| mdb.iter().unwrap(), NoBitmap, None).unwrap();
+
+    // open intermediate blocks are restored from the checkpoint, hence the
+    // resumed build leaves no unreferenced blocks behind.
+    assert!(fs::read(&index_file).unwrap() == reference);
+}
+
+#[test]
+fn test_build_index_resume_bitmap() {
+    use xorfilter::{BuildHasherDefault, Xor8};
+
+    type B = Xor8<BuildHasherDefault>;
+
+    let seed: u128 = random();
+    println!("test_build_index_resume_bitmap {}", seed);
+
+    let dir = std::env::temp_dir().join("test_build_index_resume_bitmap");
+    let mut config = Config::new(dir.as_os_str(), "test_build_index_resume_bitmap");
+    config.set_blocksize(1024, 1024, 1024).set_checkpoint(1);
+
+    let mdb = crate::util::load_index(seed, 10_000, 0, 0, 0, None);
+
+    // build dies midway.
+    let n = mdb.len() / 2;
+    let res = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        let mut build =
+            Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+        let iter = mdb.iter().unwrap().enumerate().map(|(i, e)| match i {
+            i if i == n => panic!("build died at {}", i),
+            _ => e,
+        });
+        build.build_index(iter, B::new(), None).unwrap();
+    }));
+    assert!(res.is_err());
+
+    // keys flushed before the checkpoint are restored into the bitmap.
+    let mut build = Builder::<u16, u64, u64>::resume(config.clone(), vec![]).unwrap();
+    build.build_index_resume(|_| mdb.iter().unwrap(), B::new(), None).unwrap();
+
+    let index = Index::<u16, u64, u64, B>::open(&config.dir, &config.name).unwrap();
+    for entry in mdb.iter().unwrap() {
+        assert!(index.as_bitmap().contains(&entry.key), "{}", entry.key);
+    }
+}
+
+#[test]
+fn test_build_index_resume_metadata() {
+    let seed: u128 = random();
+    println!("test_build_index_resume_metadata {}", seed);
+
+    let dir = std::env::temp_dir().join("test_build_index_resume_metadata");
+    let mut config = Config::new(dir.as_os_str(), "test_build_index_resume_metadata");
+    config.set_blocksize(1024, 1024, 1024).set_checkpoint(1);
+
+    let mdb = crate::util::load_index(seed, 10_000, 0, 0, 0, None);
+
+    // build dies midway.
+    let n = mdb.len() / 2;
+    let res = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        let mut build =
+            Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+        build.put_app_metadata("lsm", 10_u64).unwrap();
+        build.delete_range(10..20, 0).set_schema_id("resume/v1");
+        let iter = mdb.iter().unwrap().enumerate().map(|(i, e)| match i {
+            i if i == n => panic!("build died at {}", i),
+            _ => e,
+        });
+        build.build_index(iter, NoBitmap, None).unwrap();
+    }));
+    assert!(res.is_err());
+
+    // metadata is restored from the checkpoint.
+    let mut build = Builder::<u16, u64, u64>::resume(config.clone(), vec![]).unwrap();
+    build.build_index_resume(|_| mdb.iter().unwrap(), NoBitmap, None).unwrap();
+
+    let index =
+        Index::<u16, u64, u64, NoBitmap>::open(&config.dir, &config.name).unwrap();
+    assert_eq!(index.get_app_metadata::<u64>("lsm").unwrap(), Some(10));
+    assert_eq!(index.to_range_tombstones(), vec![RangeTombstone::new(10..20, 0)]);
+    index.verify_schema_id("resume/v1").unwrap();
+}
+
+#[test]
+fn test_checkpoint_unsupported() {
+    let seed: u128 = random();
+    println!("test_checkpoint_unsupported {}", seed);
+
+    let dir = std::env::temp_dir().join("test_checkpoint_unsupported");
+    let mut config = Config::new(dir.as_os_str(), "test_checkpoint_unsupported");
+    config.set_blocksize(1024, 1024, 1024).set_value_log(true);
+
+    let mdb = crate::util::load_index(seed, 1_000, 0, 0, 0, None);
+    let entries: Vec<_> = mdb.iter().unwrap().collect();
+
+    let prev = {
+        let mut build =
+            Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+        build.build_index(entries.clone().into_iter(), NoBitmap, None).unwrap();
+        Index::<u16, u64, u64, NoBitmap>::open(&config.dir, &config.name).unwrap()
+    };
+
+    config.name = "test_checkpoint_unsupported-ckpt".to_string();
+    config.set_checkpoint(1);
+
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    match build.build_index_par(vec![entries.clone().into_iter()], NoBitmap, None) {
+        Err(Error::Invalid(_, _)) => (),
+        res => panic!("unexpected {:?}", res),
+    }
+
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    match build.concat(std::slice::from_ref(&prev), NoBitmap, None) {
+        Err(Error::Invalid(_, _)) => (),
+        res => panic!("unexpected {:?}", res),
+    }
+
+    let vlog = prev.to_vlog_file_location();
+    let mut build = Builder::<u16, u64, u64>::incremental(config, vlog, vec![]).unwrap();
+    match build.build_index_patch(&prev, entries.into_iter(), NoBitmap, None) {
+        Err(Error::Invalid(_, _)) => (),
+        res => panic!("unexpected {:?}", res),
+    }
+}
@@ -0,0 +1,97 @@
+use std::{cmp, ops::Bound};
+
+/// Comparator to order keys in the index.
+///
+/// By default keys are ordered using their `Ord` implementation, via the
+/// [Natural] comparator. Applications can implement a custom collation
+/// for their keys, say case-insensitive byte-strings, without wrapping
+/// them in a new-type. Note that the same comparator must be used while
+/// building the index and while reading from the index. Comparator's
+/// `NAME` is persisted along with index [Stats][crate::Stats] and opening
+/// an index with a mismatching comparator shall fail.
+///
+/// Comparator is parametrised over `Q`, the type of the borrowed key used
+/// for lookups, typically implemented for both `K` and `Q`.
+pub trait Comparator<Q: ?Sized> {
+    /// Unique name identifying this comparator.
+    const NAME: &'static str;
+
+    /// Compare two keys.
+    fn compare(a: &Q, b: &Q) -> cmp::Ordering;
+}
+
+/// Default comparator, orders keys using their `Ord` implementation.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Natural;
+
+impl<Q> Comparator<Q> for Natural
+where
+    Q: Ord + ?Sized,
+{
+    const NAME: &'static str = "natural";
+
+    #[inline]
+    fn compare(a: &Q, b: &Q) -> cmp::Ordering {
+        a.cmp(b)
+    }
+}
+
+// compare key with the lower bound, unbounded is treated as negative
+// infinity.
+pub fn fcmp<Q, C>(key: &Q, skey: Bound<&Q>) -> cmp::Ordering
+where
+    Q: ?Sized,
+    C: Comparator<Q>,
+{
+    match skey {
+        Bound::Unbounded => cmp::Ordering::Greater,
+        Bound::Included(skey) | Bound::Excluded(skey) => C::compare(key, skey),
+    }
+}
+
+// compare key with the upper bound, unbounded is treated as positive
+// infinity.
+pub fn rcmp<Q, C>(key: &Q, ekey: Bound<&Q>) -> cmp::Ordering
+where
+    Q: ?Sized,
+    C: Comparator<Q>,
+{
+    match ekey {
+        Bound::Unbounded => cmp::Ordering::Less,
+        Bound::Included(ekey) | Bound::Excluded(ekey) => C::compare(key, ekey),
+    }
+}
+
+// return true if `key` is after the lower bound `skey`.
+pub fn after_start<Q, C>(key: &Q, skey: Bound<&Q>) -> bool
+where
+    Q: ?Sized,
+    C: Comparator<Q>,
+{
+    use cmp::Ordering::{Equal, Greater};
+
+    match skey {
+        Bound::Unbounded => true,
+        Bound::Included(skey) => matches!(C::compare(key, skey), Greater | Equal),
+        Bound::Excluded(skey) => matches!(C::compare(key, skey), Greater),
+    }
+}
+
+// return true if `key` is before the upper bound `ekey`.
+pub fn before_end<Q, C>(key: &Q, ekey: Bound<&Q>) -> bool
+where
+    Q: ?Sized,
+    C: Comparator<Q>,
+{
+    use cmp::Ordering::{Equal, Less};
+
+    match ekey {
+        Bound::Unbounded => true,
+        Bound::Included(ekey) => matches!(C::compare(key, ekey), Less | Equal),
+        Bound::Excluded(ekey) => matches!(C::compare(key, ekey), Less),
+    }
+}
+
+#[cfg(test)]
+#[path = "comparator_test.rs"]
+mod comparator_test;
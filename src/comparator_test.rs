@@ -0,0 +1,39 @@
+use super::*;
+
+#[test]
+fn test_natural() {
+    use cmp::Ordering::{Equal, Greater, Less};
+
+    assert_eq!(<Natural as Comparator<u64>>::NAME, "natural");
+    assert_eq!(<Natural as Comparator<str>>::compare("a", "b"), Less);
+    assert_eq!(<Natural as Comparator<u64>>::compare(&10, &10), Equal);
+
+    assert_eq!(fcmp::<u64, Natural>(&10, Bound::Unbounded), Greater);
+    assert_eq!(fcmp::<u64, Natural>(&10, Bound::Included(&11)), Less);
+    assert_eq!(rcmp::<u64, Natural>(&10, Bound::Unbounded), Less);
+    assert_eq!(rcmp::<u64, Natural>(&10, Bound::Excluded(&9)), Greater);
+
+    assert!(after_start::<u64, Natural>(&10, Bound::Unbounded));
+    assert!(after_start::<u64, Natural>(&10, Bound::Included(&10)));
+    assert!(!after_start::<u64, Natural>(&10, Bound::Excluded(&10)));
+    assert!(before_end::<u64, Natural>(&10, Bound::Unbounded));
+    assert!(before_end::<u64, Natural>(&10, Bound::Included(&10)));
+    assert!(!before_end::<u64, Natural>(&10, Bound::Excluded(&10)));
+}
+
+#[test]
+fn test_custom_comparator() {
+    struct CaseInsensitive;
+
+    impl Comparator<str> for CaseInsensitive {
+        const NAME: &'static str = "case-insensitive";
+
+        fn compare(a: &str, b: &str) -> cmp::Ordering {
+            a.to_lowercase().cmp(&b.to_lowercase())
+        }
+    }
+
+    assert_eq!(CaseInsensitive::compare("Hello", "hello"), cmp::Ordering::Equal);
+    assert!(after_start::<str, CaseInsensitive>("b", Bound::Included("B")));
+    assert!(!before_end::<str, CaseInsensitive>("b", Bound::Excluded("B")));
+}
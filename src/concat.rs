@@ -0,0 +1,364 @@
+//! Module implement concatenation of indexes with disjoint key ranges.
+//!
+//! Leaf blocks from source indexes are copied, in source order, into the
+//! new index file, while intermediate blocks are built fresh on top of
+//! them. Every leaf block is decoded, to verify key order across sources
+//! and to populate the bitmap. Value-log files are copied verbatim. Leaf
+//! blocks are copied as is when their source's value-log lands at position
+//! 0, that is, without value-log or for the first source into an empty
+//! value-log. Otherwise leaf blocks are re-encoded with their value-log
+//! references rebased to the new position, split into as many blocks as
+//! needed when they don't fit within the block's fill limit.
+
+use mkit::{
+    cbor::{self, Cbor, FromCbor, IntoCbor},
+    db::Bloom,
+};
+
+use std::{
+    cell::RefCell, cmp, collections::VecDeque, convert::TryFrom, fs, hash, io, marker,
+    rc::Rc,
+};
+
+use crate::{
+    build::{HashesRef, LeafIter},
+    comparator::Comparator,
+    config::Config,
+    entry::Entry,
+    flush::Flusher,
+    merkle,
+    reader::MAX_DEPTH,
+    util, vlog, Error, Result,
+};
+
+// Value-log files are copied in chunks of this size.
+const VLOG_CHUNK: u64 = 1024 * 1024;
+
+/// Source index to concatenate.
+pub struct Source {
+    pub index: fs::File,
+    pub vlog: Option<fs::File>,
+    pub root: u64,
+    pub m_blocksize: usize,
+    /// On-disk format version of the source index file.
+    pub format: u64,
+}
+
+/// Iterator over leaf blocks copied from source indexes, in source order,
+/// yielding the first key and file position of each z-block, same as
+/// [BuildZZ][crate::build::BuildZZ].
+pub struct ConcatZZ<K, V, D, B, C> {
+    z_blocksize: usize,
+    z_limit: usize,
+    iflush: Rc<RefCell<Flusher>>,
+    vflush: Rc<RefCell<Flusher>>,
+    bitmap: Rc<RefCell<B>>,
+    hashes: HashesRef,
+
+    // source along with file-position of its leaf blocks, in key order.
+    sources: VecDeque<(Source, VecDeque<u64>)>,
+    // value-log position in the final file, for the current source.
+    vbase: Option<u64>,
+    last_key: Option<K>,
+    pending: VecDeque<(K, u64)>,
+    n_entries: u64,
+
+    _val: marker::PhantomData<V>,
+    _dff: marker::PhantomData<D>,
+    _cmp: marker::PhantomData<C>,
+}
+
+impl<K, V, D, B, C> ConcatZZ<K, V, D, B, C>
+where
+    K: FromCbor,
+    V: FromCbor,
+    D: FromCbor,
+{
+    pub fn new(
+        config: &Config,
+        iflush: Rc<RefCell<Flusher>>,
+        vflush: Rc<RefCell<Flusher>>,
+        bitmap: Rc<RefCell<B>>,
+        hashes: HashesRef,
+        sources: Vec<Source>,
+    ) -> Result<Self> {
+        let mut items = VecDeque::new();
+        for mut source in sources.into_iter() {
+            let leaves = to_leaves::<K, V, D>(&mut source)?;
+            items.push_back((source, leaves.into_iter().map(|(_, fpos)| fpos).collect()));
+        }
+
+        let val = ConcatZZ {
+            z_blocksize: config.z_blocksize,
+            z_limit: config.to_z_limit(),
+            iflush,
+            vflush,
+            bitmap,
+            hashes,
+
+            sources: items,
+            vbase: None,
+            last_key: None,
+            pending: VecDeque::new(),
+            n_entries: 0,
+
+            _val: marker::PhantomData,
+            _dff: marker::PhantomData,
+            _cmp: marker::PhantomData,
+        };
+
+        Ok(val)
+    }
+}
+
+impl<K, V, D, B, C> ConcatZZ<K, V, D, B, C>
+where
+    K: Clone + hash::Hash + FromCbor + IntoCbor,
+    V: FromCbor + IntoCbor,
+    D: FromCbor + IntoCbor,
+    B: Bloom,
+    C: Comparator<K>,
+{
+    fn concat(&mut self) -> Result<Option<(K, u64)>> {
+        if let Some(item) = self.pending.pop_front() {
+            return Ok(Some(item));
+        }
+
+        loop {
+            match self.sources.front() {
+                Some((_, leaves)) if leaves.is_empty() => {
+                    self.sources.pop_front();
+                    self.vbase = None;
+                }
+                Some(_) => break,
+                None => return Ok(None),
+            }
+        }
+        let vbase = match self.vbase {
+            Some(vbase) => vbase,
+            None => {
+                let vbase = self.copy_vlog()?;
+                self.vbase = Some(vbase);
+                vbase
+            }
+        };
+
+        let (source, leaves) = self.sources.front_mut().unwrap();
+        let fpos = leaves.pop_front().unwrap();
+        let zblock = {
+            let seek = io::SeekFrom::Start(fpos);
+            read_file!(&mut source.index, seek, self.z_blocksize, "read leaf block")?
+        };
+
+        let entries = Entry::<K, V, D>::decode_block(&zblock, source.format)?;
+        let first_key = match entries.first() {
+            Some(entry) => entry.to_key(),
+            None => err_at!(InvalidFile, msg: "empty z-block at {}", fpos)?,
+        };
+        if let Some(last_key) = self.last_key.as_ref() {
+            if C::compare(last_key, &first_key) != cmp::Ordering::Less {
+                err_at!(Invalid, msg: "index overlaps previous index at {}", fpos)?
+            }
+        }
+        self.last_key = entries.last().map(|e| e.to_key());
+        self.n_entries += err_at!(FailConvert, u64::try_from(entries.len()))?;
+
+        {
+            let mut bitmap = self.bitmap.borrow_mut();
+            entries.iter().for_each(|e| bitmap.add_key(e.as_key()));
+        }
+
+        let blocks = match vbase {
+            0 => {
+                let mut vblock = vec![];
+                for entry in entries.iter() {
+                    vblock.extend_from_slice(&self.to_vbytes(entry)?);
+                }
+                vec![(first_key, zblock, vblock)]
+            }
+            vbase => self.rebase_leaf(entries, vbase)?,
+        };
+
+        for (key, zblock, vblock) in blocks.into_iter() {
+            let fpos = self.iflush.borrow().to_fpos().unwrap_or(0);
+            if let Some(hashes) = self.hashes.as_ref() {
+                let hash = merkle::leaf_hash(&zblock, &vblock);
+                hashes.borrow_mut().insert(fpos, hash);
+            }
+            self.iflush.borrow_mut().flush(zblock)?;
+            self.pending.push_back((key, fpos));
+        }
+
+        Ok(self.pending.pop_front())
+    }
+
+    // copy current source's value-log file, return its position in the
+    // final value-log file.
+    fn copy_vlog(&mut self) -> Result<u64> {
+        let vbase = self.vflush.borrow().to_fpos().unwrap_or(0);
+
+        let (source, _) = self.sources.front_mut().unwrap();
+        if let Some(vlog) = source.vlog.as_mut() {
+            copy_vlog(vlog, &mut self.vflush.borrow_mut())?;
+        }
+
+        Ok(vbase)
+    }
+
+    // re-encode leaf with rebased references, into one or more z-blocks.
+    fn rebase_leaf(
+        &mut self,
+        entries: Vec<Entry<K, V, D>>,
+        vbase: u64,
+    ) -> Result<Vec<(K, Vec<u8>, Vec<u8>)>> {
+        let mut blocks: Vec<(K, Vec<u8>, Vec<u8>)> = vec![];
+        for entry in entries.into_iter() {
+            let key = entry.to_key();
+            let vbytes = self.to_vbytes(&entry)?;
+            let ibytes = util::into_cbor_bytes(entry.rebase(0, vbase))?;
+
+            let full = match blocks.last() {
+                Some((_, zblock, _)) => (zblock.len() + ibytes.len()) > self.z_limit,
+                None => true,
+            };
+            if full {
+                let mut zblock = Vec::with_capacity(self.z_blocksize);
+                Cbor::Major4(cbor::Info::Indefinite, vec![]).encode(&mut zblock)?;
+                blocks.push((key, zblock, vec![]));
+            }
+
+            let (_, zblock, vblock) = blocks.last_mut().unwrap();
+            zblock.extend_from_slice(&ibytes);
+            vblock.extend_from_slice(&vbytes);
+        }
+
+        let brk = util::into_cbor_bytes(cbor::SimpleValue::Break)?;
+        for (_, zblock, _) in blocks.iter_mut() {
+            zblock.extend_from_slice(&brk);
+            if zblock.len() > self.z_blocksize {
+                err_at!(Fatal, msg: "rebased z-block {}", zblock.len())?
+            }
+            zblock.resize(self.z_blocksize, 0);
+        }
+
+        Ok(blocks)
+    }
+
+    // values and deltas referred by entry, to compute the leaf hash.
+    fn to_vbytes(&mut self, entry: &Entry<K, V, D>) -> Result<Vec<u8>> {
+        let (source, _) = self.sources.front_mut().unwrap();
+        match (self.hashes.as_ref(), source.vlog.as_mut()) {
+            (Some(_), Some(vlog)) => to_vbytes(vlog, entry),
+            (_, _) => Ok(vec![]),
+        }
+    }
+}
+
+impl<K, V, D, B, C> Iterator for ConcatZZ<K, V, D, B, C>
+where
+    K: Clone + hash::Hash + FromCbor + IntoCbor,
+    V: FromCbor + IntoCbor,
+    D: FromCbor + IntoCbor,
+    B: Bloom,
+    C: Comparator<K>,
+{
+    type Item = Result<(K, u64)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.concat().transpose()
+    }
+}
+
+impl<K, V, D, B, C> LeafIter<K> for ConcatZZ<K, V, D, B, C>
+where
+    K: Clone + hash::Hash + FromCbor + IntoCbor,
+    V: FromCbor + IntoCbor,
+    D: FromCbor + IntoCbor,
+    B: Bloom,
+    C: Comparator<K>,
+{
+    fn to_entries(&self) -> u64 {
+        self.n_entries
+    }
+}
+
+// copy value-log file, in chunks, into `vflush`.
+fn copy_vlog(vlog: &mut fs::File, vflush: &mut Flusher) -> Result<()> {
+    let len = err_at!(IOError, vlog.metadata())?.len();
+    let mut fpos = 0;
+    while fpos < len {
+        let n = cmp::min(len - fpos, VLOG_CHUNK);
+        let seek = io::SeekFrom::Start(fpos);
+        let data = read_file!(vlog, seek, n, "read value-log")?;
+        vflush.flush(data)?;
+        fpos += n;
+    }
+
+    Ok(())
+}
+
+// values and deltas referred by entry from value-log, in the order they are
+// hashed into the leaf hash.
+pub fn to_vbytes<K, V, D>(
+    vlog: &mut fs::File,
+    entry: &Entry<K, V, D>,
+) -> Result<Vec<u8>> {
+    let mut refs = vec![];
+    if let Entry::ZZ { value, deltas, .. } = entry {
+        if let vlog::Value::R { fpos, length } = value {
+            refs.push((*fpos, *length));
+        }
+        for delta in deltas.iter() {
+            if let vlog::Delta::R { fpos, length } = delta {
+                refs.push((*fpos, *length));
+            }
+        }
+    }
+
+    let mut vbytes = vec![];
+    for (fpos, length) in refs.into_iter() {
+        let seek = io::SeekFrom::Start(fpos);
+        vbytes.extend_from_slice(&read_file!(vlog, seek, length, "read value-log")?);
+    }
+
+    Ok(vbytes)
+}
+
+// first key and file-position of leaf blocks, in key order, by walking the
+// tree from root.
+pub fn to_leaves<K, V, D>(source: &mut Source) -> Result<VecDeque<(K, u64)>>
+where
+    K: FromCbor,
+    V: FromCbor,
+    D: FromCbor,
+{
+    let mut leaves = VecDeque::new();
+    let mut stack = vec![(source.root, None, 0)];
+    while let Some((fpos, key, depth)) = stack.pop() {
+        if let Some(key) = key {
+            leaves.push_back((key, fpos));
+            continue;
+        } else if depth > MAX_DEPTH {
+            err_at!(InvalidFile, msg: "depth > {}", MAX_DEPTH)?
+        }
+
+        let block = {
+            let seek = io::SeekFrom::Start(fpos);
+            read_file!(&mut source.index, seek, source.m_blocksize, "read block")?
+        };
+        let entries = Entry::<K, V, D>::decode_block(&block, source.format)?;
+        for entry in entries.into_iter().rev() {
+            match entry {
+                Entry::MM { fpos, .. } => stack.push((fpos, None, depth + 1)),
+                Entry::MZ { key, fpos } => stack.push((fpos, Some(key), depth + 1)),
+                Entry::ZZ { .. } => err_at!(InvalidFile, msg: "leaf entry at {}", fpos)?,
+            }
+        }
+    }
+
+    Ok(leaves)
+}
+
+#[cfg(test)]
+#[path = "concat_test.rs"]
+mod concat_test;
@@ -0,0 +1,142 @@
+use mkit::{db::BuildIndex, nobitmap::NoBitmap};
+use rand::{prelude::random, rngs::StdRnd, Rng, SeedableRng};
+
+use std::ops::Bound;
+
+use super::*;
+use crate::{
+    db::{Builder, Index},
+    Config,
+};
+
+#[test]
+fn test_concat() {
+    let seed: u128 = random();
+    println!("test_concat {}", seed);
+    let mut rng = StdRnd::from_seed(seed.to_le_bytes());
+
+    let dir = std::env::temp_dir().join("test_concat");
+    let mut config = Config::new(dir.as_os_str(), "test_concat");
+    config
+        .set_blocksize(1024, 1024, 1024)
+        .set_value_log(rng.gen())
+        .set_delta(rng.gen())
+        .set_merkle(rng.gen());
+    println!("vlog:{} delta:{}", config.value_in_vlog, config.delta_ok);
+
+    let mdb = crate::util::load_index(seed, 5_000, 1_000, 500, 100, None);
+    let entries: Vec<db::Entry<u16, u64, u64>> = mdb.iter().unwrap().collect();
+
+    // build source indexes over disjoint key ranges.
+    let n = 1 + (rng.gen::<usize>() % 4);
+    let mut indexes = vec![];
+    for (i, chunk) in entries.chunks((entries.len() / n) + 1).enumerate() {
+        let mut config = config.clone();
+        config.name = format!("test_concat-{}", i);
+        let mut build =
+            Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+        build.build_index(chunk.to_vec().into_iter(), NoBitmap, None).unwrap();
+        let index =
+            Index::<u16, u64, u64, NoBitmap>::open(&config.dir, &config.name).unwrap();
+        indexes.push(index);
+    }
+
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    build.concat(&indexes, NoBitmap, None).unwrap();
+
+    let mut index =
+        Index::<u16, u64, u64, NoBitmap>::open(&config.dir, &config.name).unwrap();
+    let stats = index.validate().unwrap();
+    assert_eq!(stats.n_count, mdb.len() as u64);
+    assert_eq!(stats.n_deleted, mdb.deleted_count());
+    assert_eq!(stats.seqno, mdb.to_seqno());
+    assert_eq!(index.to_merkle_root().is_some(), config.merkle);
+
+    let r = (Bound::<u16>::Unbounded, Bound::<u16>::Unbounded);
+    let mut iter = index.iter_versions(r).unwrap();
+    for mut e1 in mdb.iter().unwrap() {
+        if !config.delta_ok {
+            e1.deltas = vec![];
+        }
+        assert_eq!(e1, iter.next().unwrap().unwrap());
+    }
+    assert!(iter.next().is_none());
+
+    // indexes out of order.
+    if indexes.len() > 1 {
+        indexes.reverse();
+        config.name = "test_concat-reverse".to_string();
+        let mut build =
+            Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+        match build.concat(&indexes, NoBitmap, None) {
+            Err(Error::Invalid(_, _)) => (),
+            res => panic!("unexpected {:?}", res),
+        }
+    }
+
+    // mismatched block size.
+    let mut config = config.clone();
+    config.name = "test_concat-blocksize".to_string();
+    config.set_blocksize(2048, 1024, 1024);
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    match build.concat(&indexes, NoBitmap, None) {
+        Err(Error::Invalid(_, _)) => (),
+        res => panic!("unexpected {:?}", res),
+    }
+}
+
+#[test]
+fn test_concat_fill_factor() {
+    use std::io::{Read, Seek};
+
+    let seed: u128 = random();
+    println!("test_concat_fill_factor {}", seed);
+
+    let dir = std::env::temp_dir().join("test_concat_fill_factor");
+    let mut config = Config::new(dir.as_os_str(), "test_concat_fill_factor");
+    config
+        .set_blocksize(1024, 1024, 1024)
+        .set_value_log(true)
+        .set_fill_factor(0.5, 1.0);
+
+    let mdb = crate::util::load_index(seed, 5_000, 0, 0, 0, None);
+    let entries: Vec<db::Entry<u16, u64, u64>> = mdb.iter().unwrap().collect();
+
+    // leaf blocks from every source, except the first, are rebased.
+    let mut indexes = vec![];
+    for (i, chunk) in entries.chunks((entries.len() / 3) + 1).enumerate() {
+        let mut config = config.clone();
+        config.name = format!("test_concat_fill_factor-{}", i);
+        let mut build =
+            Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+        build.build_index(chunk.to_vec().into_iter(), NoBitmap, None).unwrap();
+        let index =
+            Index::<u16, u64, u64, NoBitmap>::open(&config.dir, &config.name).unwrap();
+        indexes.push(index);
+    }
+
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    build.concat(&indexes, NoBitmap, None).unwrap();
+
+    let mut index =
+        Index::<u16, u64, u64, NoBitmap>::open(&config.dir, &config.name).unwrap();
+    assert_eq!(index.validate().unwrap().n_count, mdb.len() as u64);
+
+    let mut source = Source {
+        index: fs::File::open(config.to_index_file_location()).unwrap(),
+        vlog: None,
+        root: index.to_root(),
+        m_blocksize: config.m_blocksize,
+        format: crate::FORMAT_VERSION,
+    };
+    let leaves = to_leaves::<u16, u64, u64>(&mut source).unwrap();
+    assert!(!leaves.is_empty());
+    for (_, fpos) in leaves.into_iter() {
+        let mut block = vec![0; config.z_blocksize];
+        source.index.seek(io::SeekFrom::Start(fpos)).unwrap();
+        source.index.read_exact(&mut block).unwrap();
+        // encoded length includes the break-stop.
+        let (_, n) = Cbor::decode(&mut block.as_slice()).unwrap();
+        assert!(n <= config.to_z_limit() + 1, "{} {}", n, config.to_z_limit());
+    }
+}
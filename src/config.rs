@@ -1,8 +1,12 @@
 use mkit::Cborize;
 
-use std::{ffi, path};
+use std::{cmp, ffi, path};
 
-use crate::files::{IndexFileName, VlogFileName};
+use crate::{
+    comparator::{Comparator, Natural},
+    files::{IndexFileName, VlogFileName},
+    util, Error, Result,
+};
 
 /// Default value for z-block-size, 4 * 1024 bytes.
 pub const ZBLOCKSIZE: usize = 4 * 1024; // 4KB leaf node
@@ -15,7 +19,12 @@ pub const VBLOCKSIZE: usize = 4 * 1024; // 4KB of blobs.
 /// index blocks.
 pub const FLUSH_QUEUE_SIZE: usize = 64;
 
+/// Minimum size for z-block and m-block, 512 bytes. Block sizes shall also
+/// be a multiple of this value.
+pub const MIN_BLOCKSIZE: usize = 512;
+
 const STATS_VER1: u32 = 0x0001;
+const STATS_VER2: u32 = 0x0002;
 
 pub fn to_index_file(dir: &ffi::OsStr, name: &str) -> ffi::OsString {
     let file_path: path::PathBuf =
@@ -25,6 +34,12 @@ pub fn to_index_file(dir: &ffi::OsStr, name: &str) -> ffi::OsString {
     file_path.into_os_string()
 }
 
+pub fn to_checkpoint_file(dir: &ffi::OsStr, name: &str) -> ffi::OsString {
+    let file_path: path::PathBuf =
+        [dir.to_os_string(), format!("{}-robt.ckpt", name).into()].iter().collect();
+    file_path.into_os_string()
+}
+
 pub fn to_vlog_file(dir: &ffi::OsStr, name: &str) -> ffi::OsString {
     let file_path: path::PathBuf =
         [dir.to_os_string(), VlogFileName::from(name.to_string()).into()]
@@ -62,6 +77,25 @@ pub struct Config {
     pub value_in_vlog: bool,
     /// Flush queue size. Default: [FLUSH_QUEUE_SIZE]
     pub flush_queue_size: usize,
+    /// Limit writes, in bytes per second, to index file and value-log file.
+    /// Refer to [Config::set_flush_rate] for details. Default: None
+    pub flush_rate: Option<u64>,
+    /// Fixed timestamp, as nanoseconds from UNIX EPOCH, to be stamped as
+    /// the build's epoch instead of the wall-clock. Refer to
+    /// [Config::set_epoch] for details. Default: None
+    pub epoch: Option<u64>,
+    /// Compute a merkle tree over index blocks, refer to
+    /// [Config::set_merkle] for details. Default: false
+    pub merkle: bool,
+    /// Record a checkpoint every so many leaf blocks, refer to
+    /// [Config::set_checkpoint] for details. Default: None
+    pub checkpoint: Option<usize>,
+    /// Fraction of leaf block to fill with entries, refer to
+    /// [Config::set_fill_factor] for details. Default: 1.0
+    pub z_fill_factor: f64,
+    /// Fraction of intermediate block to fill with entries, refer to
+    /// [Config::set_fill_factor] for details. Default: 1.0
+    pub m_fill_factor: f64,
 }
 
 impl From<Stats> for Config {
@@ -75,6 +109,12 @@ impl From<Stats> for Config {
             delta_ok: val.delta_ok,
             value_in_vlog: val.value_in_vlog,
             flush_queue_size: FLUSH_QUEUE_SIZE,
+            flush_rate: None,
+            epoch: None,
+            merkle: false,
+            checkpoint: None,
+            z_fill_factor: 1.0,
+            m_fill_factor: 1.0,
         }
     }
 }
@@ -92,6 +132,12 @@ impl Config {
             delta_ok: true,
             value_in_vlog: false,
             flush_queue_size: FLUSH_QUEUE_SIZE,
+            flush_rate: None,
+            epoch: None,
+            merkle: false,
+            checkpoint: None,
+            z_fill_factor: 1.0,
+            m_fill_factor: 1.0,
         }
     }
 
@@ -123,6 +169,112 @@ impl Config {
         self.flush_queue_size = size;
         self
     }
+
+    /// Throttle the flusher threads to `rate` bytes per second, to limit
+    /// the disk bandwidth used by the build while serving traffic. Index
+    /// file and value-log file are throttled separately, each allowing a
+    /// burst of upto one second worth of writes.
+    pub fn set_flush_rate(&mut self, rate: u64) -> &mut Self {
+        self.flush_rate = Some(rate);
+        self
+    }
+
+    /// Build the index deterministically, `epoch` is stamped as the build's
+    /// epoch and `build_time` is stamped as ZERO. Building the same input,
+    /// with the same configuration and bitmap, shall produce byte-for-byte
+    /// identical index file and value-log file.
+    pub fn set_epoch(&mut self, epoch: u64) -> &mut Self {
+        self.epoch = Some(epoch);
+        self
+    }
+
+    /// Compute a hash for every block and roll them up into a merkle root,
+    /// persisted along with the index. Replicas can compare the merkle root
+    /// to check whether they hold the same snapshot, refer to
+    /// [Index::diff_ranges][crate::db::Index::diff_ranges] for details.
+    pub fn set_merkle(&mut self, merkle: bool) -> &mut Self {
+        self.merkle = merkle;
+        self
+    }
+
+    /// Record a checkpoint after every `n_blocks` leaf blocks, while
+    /// building the index using `build_index`. Build that died midway can
+    /// be resumed from the last checkpoint, refer to
+    /// [Builder::resume][crate::db::Builder::resume] for details. Parallel
+    /// builds, concat, patch and split don't support checkpoints, and fail
+    /// with [Error::Invalid][crate::Error::Invalid] when configured so.
+    pub fn set_checkpoint(&mut self, n_blocks: usize) -> &mut Self {
+        self.checkpoint = Some(n_blocks);
+        self
+    }
+
+    /// Fill leaf blocks upto `z` fraction of z-blocksize, and intermediate
+    /// blocks upto `m` fraction of m-blocksize. Both shall be in the range
+    /// (0.0, 1.0]. Partially filled blocks make the index larger, but
+    /// leave room for edits, refer to
+    /// [Builder::build_index_patch][crate::db::Builder::build_index_patch].
+    /// A block always holds at least one entry, and an intermediate block
+    /// at least two, irrespective of the fill factor.
+    pub fn set_fill_factor(&mut self, z: f64, m: f64) -> &mut Self {
+        self.z_fill_factor = z;
+        self.m_fill_factor = m;
+        self
+    }
+
+    /// Validate configuration, builder shall fail with `Invalid` error for
+    /// an invalid configuration. For new indexes, z-blocksize and
+    /// m-blocksize shall be a multiple of [MIN_BLOCKSIZE]. Incremental
+    /// builds, and builds resumed from a checkpoint, don't enforce this, so
+    /// that indexes created with unaligned block sizes by older versions
+    /// can still be built incrementally with their original configuration.
+    pub fn validate(&self) -> Result<()> {
+        self.validate_incremental()?;
+
+        let blocks =
+            [("z_blocksize", self.z_blocksize), ("m_blocksize", self.m_blocksize)];
+        for (name, size) in blocks.iter() {
+            if (*size % MIN_BLOCKSIZE) != 0 {
+                let n = MIN_BLOCKSIZE;
+                err_at!(Invalid, msg: "{} {} not aligned to {}", name, size, n)?
+            }
+        }
+
+        Ok(())
+    }
+
+    // same as validate, except that block sizes need not be aligned.
+    pub(crate) fn validate_incremental(&self) -> Result<()> {
+        let blocks =
+            [("z_blocksize", self.z_blocksize), ("m_blocksize", self.m_blocksize)];
+        for (name, size) in blocks.iter() {
+            if *size < MIN_BLOCKSIZE {
+                err_at!(Invalid, msg: "{} {} < {}", name, size, MIN_BLOCKSIZE)?
+            }
+        }
+        if self.v_blocksize == 0 {
+            err_at!(Invalid, msg: "v_blocksize is ZERO")?
+        }
+
+        let fills = [
+            ("z_fill_factor", self.z_fill_factor),
+            ("m_fill_factor", self.m_fill_factor),
+        ];
+        for (name, fill) in fills.iter() {
+            // NOTE: NaN fails the range check.
+            if !(*fill > 0.0 && *fill <= 1.0) {
+                err_at!(Invalid, msg: "{} {} not in (0.0, 1.0]", name, fill)?
+            }
+        }
+
+        if self.flush_queue_size == 0 {
+            err_at!(Invalid, msg: "flush_queue_size is ZERO")?
+        }
+        if self.checkpoint == Some(0) {
+            err_at!(Invalid, msg: "checkpoint is ZERO")?
+        }
+
+        Ok(())
+    }
 }
 
 impl Config {
@@ -133,6 +285,22 @@ impl Config {
     pub fn to_vlog_file_location(&self) -> ffi::OsString {
         to_vlog_file(&self.dir, &self.name)
     }
+
+    // fill limit, in bytes, for leaf blocks.
+    pub(crate) fn to_z_limit(&self) -> usize {
+        to_fill_limit(self.z_blocksize, self.z_fill_factor)
+    }
+
+    // fill limit, in bytes, for intermediate blocks.
+    pub(crate) fn to_m_limit(&self) -> usize {
+        to_fill_limit(self.m_blocksize, self.m_fill_factor)
+    }
+}
+
+// a byte is reserved for the break-stop that ends the block.
+fn to_fill_limit(blocksize: usize, fill: f64) -> usize {
+    let limit = ((blocksize as f64) * fill) as usize;
+    cmp::min(limit, blocksize.saturating_sub(1))
 }
 
 /// Statistic for Read Only BTree index.
@@ -150,6 +318,8 @@ pub struct Stats {
     pub delta_ok: bool,
     /// Comes from [Config] type.
     pub value_in_vlog: bool,
+    /// Name of the [Comparator][crate::Comparator] used to order keys.
+    pub comparator: String,
 
     /// Optional value log file if either `value_in_log` or `delta_ok` is true.
     pub vlog_file: Option<ffi::OsString>,
@@ -170,9 +340,69 @@ pub struct Stats {
 }
 
 impl Stats {
+    const ID: u32 = STATS_VER2;
+
+    /// Decode stats persisted in the meta-block. Stats from format-0 index
+    /// files carry no comparator, keys in such files are ordered by
+    /// [Natural] comparator.
+    pub fn from_bytes(data: &[u8]) -> Result<Stats> {
+        let mut stats = match util::from_cbor_bytes::<Stats>(data) {
+            Ok((stats, _)) => stats,
+            Err(err) => match util::from_cbor_bytes::<StatsV1>(data) {
+                Ok((stats, _)) => stats.into(),
+                Err(_) => return Err(err),
+            },
+        };
+        if stats.comparator.is_empty() {
+            stats.comparator = Natural::NAME.to_string();
+        }
+        Ok(stats)
+    }
+}
+
+// Stats layout used by format-0 index files.
+#[derive(Clone, Default, Debug, Cborize)]
+pub(crate) struct StatsV1 {
+    pub name: String,
+    pub z_blocksize: usize,
+    pub m_blocksize: usize,
+    pub v_blocksize: usize,
+    pub delta_ok: bool,
+    pub value_in_vlog: bool,
+    pub vlog_file: Option<ffi::OsString>,
+    pub n_count: u64,
+    pub n_deleted: usize,
+    pub seqno: u64,
+    pub n_abytes: u64,
+    pub build_time: u64,
+    pub epoch: u64,
+}
+
+impl StatsV1 {
     const ID: u32 = STATS_VER1;
 }
 
+impl From<StatsV1> for Stats {
+    fn from(s: StatsV1) -> Stats {
+        Stats {
+            name: s.name,
+            z_blocksize: s.z_blocksize,
+            m_blocksize: s.m_blocksize,
+            v_blocksize: s.v_blocksize,
+            delta_ok: s.delta_ok,
+            value_in_vlog: s.value_in_vlog,
+            comparator: String::default(),
+            vlog_file: s.vlog_file,
+            n_count: s.n_count,
+            n_deleted: s.n_deleted,
+            seqno: s.seqno,
+            n_abytes: s.n_abytes,
+            build_time: s.build_time,
+            epoch: s.epoch,
+        }
+    }
+}
+
 impl From<Config> for Stats {
     fn from(config: Config) -> Stats {
         Stats {
@@ -183,6 +413,7 @@ impl From<Config> for Stats {
             delta_ok: config.delta_ok,
             vlog_file: Option::default(),
             value_in_vlog: config.value_in_vlog,
+            comparator: String::default(),
             n_count: u64::default(),
             n_deleted: usize::default(),
             seqno: u64::default(),
@@ -192,3 +423,7 @@ impl From<Config> for Stats {
         }
     }
 }
+
+#[cfg(test)]
+#[path = "config_test.rs"]
+mod config_test;
@@ -0,0 +1,163 @@
+use mkit::{db::BuildIndex, nobitmap::NoBitmap};
+use rand::prelude::random;
+
+use std::{fs, ops::Bound};
+
+use super::*;
+use crate::db::{self, Builder, Index};
+
+#[test]
+fn test_config_validate() {
+    let dir = std::env::temp_dir().join("test_config_validate");
+    let config = Config::new(dir.as_os_str(), "test_config_validate");
+    config.validate().unwrap();
+
+    let testcases: Vec<Box<dyn Fn(&mut Config)>> = vec![
+        Box::new(|c| {
+            c.set_blocksize(256, VBLOCKSIZE, MBLOCKSIZE);
+        }),
+        Box::new(|c| {
+            c.set_blocksize(ZBLOCKSIZE, VBLOCKSIZE, 0);
+        }),
+        Box::new(|c| {
+            c.set_blocksize(ZBLOCKSIZE + 1, VBLOCKSIZE, MBLOCKSIZE);
+        }),
+        Box::new(|c| {
+            c.set_blocksize(ZBLOCKSIZE, VBLOCKSIZE, MBLOCKSIZE + 100);
+        }),
+        Box::new(|c| {
+            c.set_blocksize(ZBLOCKSIZE, 0, MBLOCKSIZE);
+        }),
+        Box::new(|c| {
+            c.set_fill_factor(0.0, 1.0);
+        }),
+        Box::new(|c| {
+            c.set_fill_factor(1.0, 1.5);
+        }),
+        Box::new(|c| {
+            c.set_fill_factor(f64::NAN, 1.0);
+        }),
+        Box::new(|c| {
+            c.set_flush_queue_size(0);
+        }),
+        Box::new(|c| {
+            c.set_checkpoint(0);
+        }),
+    ];
+    for (i, tc) in testcases.into_iter().enumerate() {
+        let mut config = config.clone();
+        tc(&mut config);
+        match config.validate() {
+            Err(Error::Invalid(_, _)) => (),
+            res => panic!("testcase {} unexpected {:?}", i, res),
+        }
+        match Builder::<u16, u64, u64>::initial(config, vec![]) {
+            Err(Error::Invalid(_, _)) => (),
+            res => panic!("testcase {} unexpected {:?}", i, res.err()),
+        }
+    }
+
+    // entry larger than z-block.
+    let mut config = config.clone();
+    config.set_blocksize(MIN_BLOCKSIZE, VBLOCKSIZE, MBLOCKSIZE);
+    let mut build = Builder::<String, u64, u64>::initial(config, vec![]).unwrap();
+    let entries = vec![db::Entry::new("a".repeat(MIN_BLOCKSIZE), 10, 1)];
+    match build.build_index(entries.into_iter(), NoBitmap, None) {
+        Err(Error::Invalid(_, _)) => (),
+        res => panic!("unexpected {:?}", res),
+    }
+}
+
+#[test]
+fn test_config_validate_incremental() {
+    let seed: u128 = random();
+    println!("test_config_validate_incremental {}", seed);
+
+    // block sizes of indexes created by older versions need not be aligned.
+    let dir = std::env::temp_dir().join("test_config_validate_incremental");
+    let mut config = Config::new(dir.as_os_str(), "test_config_validate_incremental");
+    config.set_blocksize(1000, 1000, 1000).set_value_log(true);
+    assert!(config.validate().is_err());
+    config.validate_incremental().unwrap();
+
+    let mdb = crate::util::load_index(seed, 1_000, 0, 0, 0, None);
+    let vlog = Some(config.to_vlog_file_location());
+    let mut build =
+        Builder::<u16, u64, u64>::incremental(config.clone(), vlog, vec![]).unwrap();
+    build.build_index(mdb.iter().unwrap(), NoBitmap, None).unwrap();
+
+    let mut index =
+        Index::<u16, u64, u64, NoBitmap>::open(&config.dir, &config.name).unwrap();
+    assert_eq!(index.validate().unwrap().n_count, mdb.len() as u64);
+    assert_eq!(index.to_stats().z_blocksize, 1000);
+
+    // too small block size is rejected, irrespective.
+    config.set_blocksize(256, 1000, 1000);
+    match Builder::<u16, u64, u64>::incremental(config, None, vec![]) {
+        Err(Error::Invalid(_, _)) => (),
+        res => panic!("unexpected {:?}", res.err()),
+    }
+}
+
+#[test]
+fn test_config_fill_factor() {
+    let seed: u128 = random();
+    println!("test_config_fill_factor {}", seed);
+
+    let dir = std::env::temp_dir().join("test_config_fill_factor");
+    let mut config = Config::new(dir.as_os_str(), "test_config_fill_factor");
+    config.set_blocksize(1024, 1024, 1024).set_value_log(true);
+
+    let mdb = crate::util::load_index(seed, 5_000, 1_000, 500, 100, None);
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    build.build_index(mdb.iter().unwrap(), NoBitmap, None).unwrap();
+    let packed = fs::metadata(config.to_index_file_location()).unwrap().len();
+
+    config.name = "test_config_fill_factor-half".to_string();
+    config.set_fill_factor(0.5, 0.1);
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    build.build_index(mdb.iter().unwrap(), NoBitmap, None).unwrap();
+    let half = fs::metadata(config.to_index_file_location()).unwrap().len();
+    println!("packed:{} half:{}", packed, half);
+    assert!(half > packed, "{} {}", half, packed);
+
+    let mut index =
+        Index::<u16, u64, u64, NoBitmap>::open(&config.dir, &config.name).unwrap();
+    let stats = index.validate().unwrap();
+    assert_eq!(stats.n_count, mdb.len() as u64);
+
+    let r = (Bound::<u16>::Unbounded, Bound::<u16>::Unbounded);
+    let mut iter = index.iter_versions(r).unwrap();
+    for e1 in mdb.iter().unwrap() {
+        assert_eq!(e1, iter.next().unwrap().unwrap());
+    }
+    assert!(iter.next().is_none());
+}
+
+#[test]
+fn test_stats_from_bytes() {
+    let config = Config::new(std::env::temp_dir().as_os_str(), "test_stats_from_bytes");
+
+    let stats: Stats = config.clone().into();
+    let data = util::into_cbor_bytes(stats).unwrap();
+    assert_eq!(Stats::from_bytes(&data).unwrap().comparator, Natural::NAME);
+
+    let mut stats: Stats = config.clone().into();
+    stats.comparator = "reverse".to_string();
+    stats.n_count = 10;
+    let data = util::into_cbor_bytes(stats).unwrap();
+    let stats = Stats::from_bytes(&data).unwrap();
+    assert_eq!((stats.comparator.as_str(), stats.n_count), ("reverse", 10));
+
+    let stats = StatsV1 {
+        name: config.name.clone(),
+        n_count: 20,
+        ..StatsV1::default()
+    };
+    let data = util::into_cbor_bytes(stats).unwrap();
+    let stats = Stats::from_bytes(&data).unwrap();
+    assert_eq!(stats.name, config.name);
+    assert_eq!((stats.comparator.as_str(), stats.n_count), (Natural::NAME, 20));
+
+    assert!(Stats::from_bytes(&[0xff, 0xff]).is_err());
+}
@@ -0,0 +1,138 @@
+//! Module implement key-level difference between two index snapshots.
+
+use mkit::{cbor::FromCbor, db};
+
+use std::{borrow::Borrow, cmp, ops::RangeBounds};
+
+use crate::{comparator::Comparator, reader::Iter, robt::Index, Result};
+
+/// Difference for a single key, between an older snapshot and a newer
+/// snapshot.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change<K, V, D> {
+    /// Key is only present in the newer snapshot.
+    Added(db::Entry<K, V, D>),
+    /// Key is only present in the older snapshot.
+    Removed(db::Entry<K, V, D>),
+    /// Key is present in both snapshots, but with different seqno or
+    /// value. A key deleted in the newer snapshot is reported as changed.
+    Changed {
+        old: db::Entry<K, V, D>,
+        new: db::Entry<K, V, D>,
+    },
+}
+
+impl<K, V, D> Change<K, V, D> {
+    pub fn as_key(&self) -> &K {
+        match self {
+            Change::Added(entry) => &entry.key,
+            Change::Removed(entry) => &entry.key,
+            Change::Changed { new, .. } => &new.key,
+        }
+    }
+}
+
+/// Return an iterator over key-level changes between older snapshot `a`
+/// and newer snapshot `b`, within `range`. Changes are ordered by key.
+///
+/// Only the latest version of each key is compared, older versions are
+/// ignored. Entries are compared by key, seqno and value.
+pub fn diff<'a, 'b, K, V, D, B1, B2, C, Q, R>(
+    a: &'a mut Index<K, V, D, B1, C>,
+    b: &'b mut Index<K, V, D, B2, C>,
+    range: R,
+) -> Result<DiffIter<'a, 'b, K, V, D, C>>
+where
+    K: Clone + Ord + Borrow<Q> + FromCbor,
+    V: Clone + FromCbor,
+    D: Clone + FromCbor,
+    Q: Ord + ToOwned<Owned = K>,
+    R: Clone + RangeBounds<Q>,
+    C: Comparator<Q> + Comparator<K>,
+{
+    let iter = DiffIter {
+        a: a.iter(range.clone())?,
+        b: b.iter(range)?,
+        x: None,
+        y: None,
+        done: false,
+    };
+    Ok(iter)
+}
+
+/// Iterator type, returned by [diff].
+pub struct DiffIter<'a, 'b, K, V, D, C> {
+    a: Iter<'a, K, V, D, C>,
+    b: Iter<'b, K, V, D, C>,
+    // look-ahead entries from older and newer snapshot.
+    x: Option<db::Entry<K, V, D>>,
+    y: Option<db::Entry<K, V, D>>,
+    done: bool,
+}
+
+impl<'a, 'b, K, V, D, C> DiffIter<'a, 'b, K, V, D, C>
+where
+    K: Clone + Ord + FromCbor,
+    V: Clone + FromCbor,
+    D: Clone + FromCbor,
+    C: Comparator<K>,
+{
+    fn fill(&mut self) -> Result<()> {
+        if self.x.is_none() {
+            self.x = self.a.next().transpose()?;
+        }
+        if self.y.is_none() {
+            self.y = self.b.next().transpose()?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, 'b, K, V, D, C> Iterator for DiffIter<'a, 'b, K, V, D, C>
+where
+    K: Clone + Ord + FromCbor,
+    V: Clone + PartialEq + FromCbor,
+    D: Clone + FromCbor,
+    C: Comparator<K>,
+{
+    type Item = Result<Change<K, V, D>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.done {
+                break None;
+            }
+            if let Err(err) = self.fill() {
+                self.done = true;
+                break Some(Err(err));
+            }
+
+            match (self.x.take(), self.y.take()) {
+                (None, None) => {
+                    self.done = true;
+                    break None;
+                }
+                (Some(x), None) => break Some(Ok(Change::Removed(x))),
+                (None, Some(y)) => break Some(Ok(Change::Added(y))),
+                (Some(x), Some(y)) => match C::compare(&x.key, &y.key) {
+                    cmp::Ordering::Less => {
+                        self.y = Some(y);
+                        break Some(Ok(Change::Removed(x)));
+                    }
+                    cmp::Ordering::Greater => {
+                        self.x = Some(x);
+                        break Some(Ok(Change::Added(y)));
+                    }
+                    cmp::Ordering::Equal if x.value == y.value => (),
+                    cmp::Ordering::Equal => {
+                        break Some(Ok(Change::Changed { old: x, new: y }));
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "diff_test.rs"]
+mod diff_test;
@@ -0,0 +1,97 @@
+use mkit::{db::BuildIndex, nobitmap::NoBitmap};
+use ppom::mdb::OMap;
+use rand::{prelude::random, rngs::StdRnd, Rng, SeedableRng};
+
+use std::{collections::BTreeMap, ops::Bound};
+
+use super::*;
+use crate::{db::Builder, util, Config};
+
+#[test]
+fn test_diff() {
+    let seed: u128 = random();
+    println!("test_diff {}", seed);
+    let mut rng = StdRnd::from_seed(seed.to_le_bytes());
+
+    let dir = std::env::temp_dir().join("test_diff");
+    let mut config = Config::new(dir.as_os_str(), "test_diff");
+    config.set_blocksize(1024, 1024, 1024).set_value_log(true);
+
+    let build = |name: &str, mdb: &OMap<u16, u64>| {
+        let mut config = config.clone();
+        config.name = name.to_string();
+        let mut b = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+        b.build_index(mdb.iter().unwrap(), NoBitmap, None).unwrap();
+        Index::<u16, u64, u64, NoBitmap>::open(&config.dir, name).unwrap()
+    };
+    let to_values = |mdb: &OMap<u16, u64>| -> BTreeMap<u16, db::Value<u64>> {
+        mdb.iter().unwrap().map(|e| (e.key, e.value)).collect()
+    };
+
+    let mdb = util::load_index(seed, 2_000, 500, 200, 100, None);
+    let mut a = build("test_diff-a", &mdb);
+    let olds = to_values(&mdb);
+
+    for _ in 0..100 {
+        let key: u16 = rng.gen();
+        match rng.gen::<u8>() % 3 {
+            0 => {
+                mdb.set(key, rng.gen()).ok();
+            }
+            1 => {
+                mdb.delete(&key).ok();
+            }
+            _ => {
+                mdb.remove(&key).ok();
+            }
+        }
+    }
+    let mut b = build("test_diff-b", &mdb);
+    let news = to_values(&mdb);
+
+    // no change between identical snapshots.
+    let mut c = build("test_diff-c", &mdb);
+    let r = (Bound::<u16>::Unbounded, Bound::<u16>::Unbounded);
+    assert_eq!(diff(&mut b, &mut c, r).unwrap().count(), 0);
+
+    let mut refs = vec![];
+    for (key, old) in olds.iter() {
+        match news.get(key) {
+            Some(new) if new == old => (),
+            Some(_) => refs.push(*key),
+            None => refs.push(*key),
+        }
+    }
+    refs.extend(news.keys().filter(|key| !olds.contains_key(key)).copied());
+    refs.sort_unstable();
+
+    let mut keys = vec![];
+    for change in diff(&mut a, &mut b, r).unwrap() {
+        let change = change.unwrap();
+        match &change {
+            Change::Added(new) => {
+                assert!(!olds.contains_key(&new.key));
+                assert_eq!(&new.value, news.get(&new.key).unwrap());
+            }
+            Change::Removed(old) => {
+                assert!(!news.contains_key(&old.key));
+                assert_eq!(&old.value, olds.get(&old.key).unwrap());
+            }
+            Change::Changed { old, new } => {
+                assert_eq!(old.key, new.key);
+                assert_ne!(old.value, new.value);
+                assert_eq!(&old.value, olds.get(&old.key).unwrap());
+                assert_eq!(&new.value, news.get(&new.key).unwrap());
+            }
+        }
+        keys.push(*change.as_key());
+    }
+    assert_eq!(keys, refs);
+
+    // within a sub-range.
+    let (lo, hi) = (10_000_u16, 40_000_u16);
+    let changes: Vec<u16> =
+        diff(&mut a, &mut b, lo..hi).unwrap().map(|c| *c.unwrap().as_key()).collect();
+    let refs: Vec<u16> = refs.into_iter().filter(|k| *k >= lo && *k < hi).collect();
+    assert_eq!(changes, refs);
+}
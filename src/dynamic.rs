@@ -0,0 +1,157 @@
+use mkit::{
+    self,
+    cbor::{Cbor, FromCbor, IntoCbor},
+};
+
+use std::{cmp, convert::TryFrom, fmt, hash, result};
+
+use crate::Result;
+
+/// Type-erased key, value or delta, as generic CBOR value.
+///
+/// Since robt indexes are serialized using CBOR, it is possible to read
+/// an index without knowing its key-type, value-type and delta-type, for
+/// example `db::Index<Dyn, Dyn, Dyn, B>`. This is useful for tools that
+/// need to inspect arbitrary robt files.
+///
+/// * Values are rendered as JSON like text, both via `Debug` and `Display`.
+/// * Values are compared using canonical CBOR ordering, that is, bytewise
+///   lexicographic order of their CBOR encoding. Note that this ordering
+///   matches `Ord` for unsigned integers, but may not match for other
+///   types, in which case point lookups and range bounds are not reliable,
+///   use full-table iteration instead.
+#[derive(Clone)]
+pub struct Dyn {
+    val: Cbor,
+    // CBOR encoding of `val`, computed once and used for comparison.
+    data: Vec<u8>,
+}
+
+impl Dyn {
+    /// Create a type-erased value from CBOR value. Fails if `val` cannot
+    /// be encoded.
+    pub fn new(val: Cbor) -> Result<Dyn> {
+        Ok(Dyn::from_cbor(val)?)
+    }
+
+    /// Return the CBOR encoding of this value.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+
+    /// Return the underlying CBOR value.
+    pub fn into_cbor_value(self) -> Cbor {
+        self.val
+    }
+}
+
+impl FromCbor for Dyn {
+    fn from_cbor(val: Cbor) -> mkit::Result<Dyn> {
+        let mut data = vec![];
+        val.clone().encode(&mut data)?;
+        Ok(Dyn { val, data })
+    }
+}
+
+impl IntoCbor for Dyn {
+    fn into_cbor(self) -> mkit::Result<Cbor> {
+        Ok(self.val)
+    }
+}
+
+impl PartialEq for Dyn {
+    fn eq(&self, other: &Dyn) -> bool {
+        self.data == other.data
+    }
+}
+
+impl Eq for Dyn {}
+
+impl PartialOrd for Dyn {
+    fn partial_cmp(&self, other: &Dyn) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Dyn {
+    fn cmp(&self, other: &Dyn) -> cmp::Ordering {
+        self.data.cmp(&other.data)
+    }
+}
+
+impl hash::Hash for Dyn {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        self.data.hash(state)
+    }
+}
+
+impl fmt::Display for Dyn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
+        render(&self.val, f)
+    }
+}
+
+impl fmt::Debug for Dyn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
+        render(&self.val, f)
+    }
+}
+
+impl TryFrom<Dyn> for u64 {
+    type Error = crate::Error;
+
+    fn try_from(val: Dyn) -> Result<u64> {
+        Ok(u64::from_cbor(val.val)?)
+    }
+}
+
+impl From<u64> for Dyn {
+    fn from(val: u64) -> Dyn {
+        // encoding a u64 shall not fail.
+        Dyn::new(val.into_cbor().unwrap()).unwrap()
+    }
+}
+
+impl From<String> for Dyn {
+    fn from(val: String) -> Dyn {
+        // encoding a String shall not fail.
+        Dyn::new(val.into_cbor().unwrap()).unwrap()
+    }
+}
+
+// render CBOR value as JSON like text.
+fn render(val: &Cbor, f: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
+    match val {
+        Cbor::Major0(_, num) => write!(f, "{}", num),
+        Cbor::Major1(_, num) => write!(f, "-{}", (*num as i128) + 1),
+        Cbor::Major2(_, bytes) => write!(f, "\"0x{}\"", to_hex(bytes.as_ref())),
+        Cbor::Major3(_, text) => {
+            let text = String::from_utf8_lossy(AsRef::<[u8]>::as_ref(text));
+            write!(f, "{:?}", text)
+        }
+        Cbor::Major4(_, items) => {
+            write!(f, "[")?;
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                render(item, f)?;
+            }
+            write!(f, "]")
+        }
+        Cbor::Major7(_, sval) => write!(f, "{}", format!("{:?}", sval).to_lowercase()),
+        val => {
+            let mut data = vec![];
+            val.clone().encode(&mut data).map_err(|_| fmt::Error)?;
+            write!(f, "\"cbor:0x{}\"", to_hex(&data))
+        }
+    }
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+#[path = "dynamic_test.rs"]
+mod dynamic_test;
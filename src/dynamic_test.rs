@@ -0,0 +1,25 @@
+use super::*;
+
+#[test]
+fn test_dyn() {
+    let (a, b, c) = (Dyn::from(10), Dyn::from(1000), Dyn::from(1000));
+    assert!(a < b);
+    assert_eq!(b, c);
+    assert_eq!(a.to_bytes(), vec![0x0a]);
+    assert_eq!(u64::try_from(b.clone()).unwrap(), 1000);
+    assert_eq!(format!("{}", a), "10");
+    assert_eq!(format!("{:?}", b), "1000");
+
+    let s = Dyn::from("hello".to_string());
+    assert_eq!(format!("{}", s), "\"hello\"");
+    assert!(u64::try_from(s).is_err());
+
+    let items = Dyn::new(vec![10_u64, 20].into_cbor().unwrap()).unwrap();
+    assert_eq!(format!("{}", items), "[10, 20]");
+}
+
+#[test]
+fn test_to_hex() {
+    assert_eq!(to_hex(&[]), "");
+    assert_eq!(to_hex(&[0x0, 0xab, 0x10]), "00ab10");
+}
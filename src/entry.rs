@@ -8,6 +8,7 @@ use std::{borrow::Borrow, convert::TryFrom, fmt, io};
 use crate::{reader::Reader, util, vlog, Error, Result};
 
 const ENTRY_VER1: u32 = 0x0001;
+const ENTRY_VER2: u32 = 0x0002;
 
 #[derive(Clone, Debug, Eq, PartialEq, Cborize)]
 pub enum Entry<K, V, D> {
@@ -23,35 +24,92 @@ pub enum Entry<K, V, D> {
         key: K,
         value: vlog::Value<V>,
         deltas: Vec<vlog::Delta<D>>,
+        expiry: Option<u64>,
     },
 }
 
+// Entry layout used by format-0 index files, leaf entries carry no expiry.
+#[derive(Clone, Debug, Eq, PartialEq, Cborize)]
+pub(crate) enum EntryV1<K, V, D> {
+    MM {
+        key: K,
+        fpos: u64,
+    },
+    MZ {
+        key: K,
+        fpos: u64,
+    },
+    ZZ {
+        key: K,
+        value: vlog::Value<V>,
+        deltas: Vec<vlog::Delta<D>>,
+    },
+}
+
+impl<K, V, D> EntryV1<K, V, D> {
+    const ID: u32 = ENTRY_VER1;
+}
+
+impl<K, V, D> From<EntryV1<K, V, D>> for Entry<K, V, D> {
+    fn from(e: EntryV1<K, V, D>) -> Self {
+        match e {
+            EntryV1::MM { key, fpos } => Entry::MM { key, fpos },
+            EntryV1::MZ { key, fpos } => Entry::MZ { key, fpos },
+            EntryV1::ZZ { key, value, deltas } => {
+                Entry::ZZ { key, value, deltas, expiry: None }
+            }
+        }
+    }
+}
+
 impl<K, V, D> From<db::Entry<K, V, D>> for Entry<K, V, D> {
     fn from(e: db::Entry<K, V, D>) -> Self {
         Entry::ZZ {
             key: e.key,
             value: e.value.into(),
             deltas: e.deltas.into_iter().map(vlog::Delta::from).collect(),
+            expiry: None,
         }
     }
 }
 
-impl<K, V, D> From<Entry<K, V, D>> for db::Entry<K, V, D> {
-    fn from(e: Entry<K, V, D>) -> Self {
+impl<K, V, D> TryFrom<Entry<K, V, D>> for db::Entry<K, V, D> {
+    type Error = Error;
+
+    fn try_from(e: Entry<K, V, D>) -> Result<Self> {
         match e {
-            Entry::ZZ { key, value, deltas } => db::Entry {
-                key,
-                value: value.into(),
-                deltas: deltas.into_iter().map(db::Delta::from).collect(),
-            },
-            Entry::MZ { .. } => unreachable!(),
-            Entry::MM { .. } => unreachable!(),
+            Entry::ZZ { key, value, deltas, .. } => {
+                let value = db::Value::try_from(value)?;
+                let deltas: Result<Vec<db::Delta<D>>> =
+                    deltas.into_iter().map(db::Delta::try_from).collect();
+                Ok(db::Entry { key, value, deltas: deltas? })
+            }
+            Entry::MZ { .. } => err_at!(InvalidFile, msg: "expected leaf entry, got MZ"),
+            Entry::MM { .. } => err_at!(InvalidFile, msg: "expected leaf entry, got MM"),
         }
     }
 }
 
 impl<K, V, D> Entry<K, V, D> {
-    const ID: u32 = ENTRY_VER1;
+    const ID: u32 = ENTRY_VER2;
+
+    // decode a m-block or z-block, blocks from format-0 files, refer to
+    // [FORMAT_VERSION][crate::FORMAT_VERSION], are decoded as version-1
+    // entries.
+    pub fn decode_block(block: &[u8], format: u64) -> Result<Vec<Self>>
+    where
+        K: FromCbor,
+        V: FromCbor,
+        D: FromCbor,
+    {
+        match format {
+            0 => {
+                let (entries, _) = util::from_cbor_bytes::<Vec<EntryV1<K, V, D>>>(block)?;
+                Ok(entries.into_iter().map(Entry::from).collect())
+            }
+            _ => Ok(util::from_cbor_bytes::<Vec<Entry<K, V, D>>>(block)?.0),
+        }
+    }
 
     pub fn new_mm(key: K, fpos: u64) -> Self {
         Entry::MM { key, fpos }
@@ -69,6 +127,29 @@ impl<K, V, D> Entry<K, V, D> {
             }
         }
     }
+
+    pub fn set_expiry(&mut self, val: Option<u64>) {
+        match self {
+            Entry::MM { .. } | Entry::MZ { .. } => (),
+            Entry::ZZ { expiry, .. } => *expiry = val,
+        }
+    }
+
+    pub fn to_expiry(&self) -> Option<u64> {
+        match self {
+            Entry::MM { .. } | Entry::MZ { .. } => None,
+            Entry::ZZ { expiry, .. } => *expiry,
+        }
+    }
+
+    // return true if this entry has expired with respect to `clock`, when
+    // `clock` is None expiry is ignored.
+    pub fn is_expired(&self, clock: Option<u64>) -> bool {
+        match (self.to_expiry(), clock) {
+            (Some(expiry), Some(clock)) => expiry <= clock,
+            _ => false,
+        }
+    }
 }
 
 impl<K, V, D> Entry<K, V, D> {
@@ -81,7 +162,7 @@ impl<K, V, D> Entry<K, V, D> {
         match self {
             Entry::MM { .. } => Ok((self, vec![])),
             Entry::MZ { .. } => Ok((self, vec![])),
-            Entry::ZZ { key, value, deltas } => {
+            Entry::ZZ { key, value, deltas, expiry } => {
                 let (value, mut vblock) =
                     if vlog { value.into_reference(vfpos)? } else { (value, vec![]) };
 
@@ -100,13 +181,25 @@ impl<K, V, D> Entry<K, V, D> {
                 vblock
                     .extend_from_slice(&util::into_cbor_bytes(cbor::SimpleValue::Break)?);
 
-                let entry = Entry::ZZ { key, value, deltas: drefs };
+                let entry = Entry::ZZ { key, value, deltas: drefs, expiry };
 
                 Ok((entry, vblock))
             }
         }
     }
 
+    // shift value-log references at or beyond `from`, to start at `to`.
+    pub fn rebase(self, from: u64, to: u64) -> Self {
+        match self {
+            Entry::ZZ { key, value, deltas, expiry } => {
+                let value = value.rebase(from, to);
+                let deltas = deltas.into_iter().map(|d| d.rebase(from, to)).collect();
+                Entry::ZZ { key, value, deltas, expiry }
+            }
+            entry => entry,
+        }
+    }
+
     pub fn into_native<F>(self, f: &mut F, versions: bool) -> Result<Self>
     where
         V: FromCbor,
@@ -116,25 +209,25 @@ impl<K, V, D> Entry<K, V, D> {
         match self {
             Entry::MM { .. } => Ok(self),
             Entry::MZ { .. } => Ok(self),
-            Entry::ZZ { key, value, deltas } if versions => {
+            Entry::ZZ { key, value, deltas, expiry } if versions => {
                 let value = value.into_native(f)?;
                 let mut native_deltas = vec![];
                 for delta in deltas.into_iter() {
                     native_deltas.push(delta.into_native(f)?);
                 }
 
-                let entry = Entry::ZZ { key, value, deltas: native_deltas };
+                let entry = Entry::ZZ { key, value, deltas: native_deltas, expiry };
 
                 Ok(entry)
             }
-            Entry::ZZ { key, value, .. } => {
+            Entry::ZZ { key, value, expiry, .. } => {
                 let value = value.into_native(f)?;
-                Ok(Entry::ZZ { key, value, deltas: Vec::default() })
+                Ok(Entry::ZZ { key, value, deltas: Vec::default(), expiry })
             }
         }
     }
 
-    pub fn print(&self, prefix: &str, reader: &mut Reader<K, V, D>) -> Result<()>
+    pub fn print<C>(&self, prefix: &str, reader: &mut Reader<K, V, D, C>) -> Result<()>
     where
         K: fmt::Debug + FromCbor,
         V: fmt::Debug + FromCbor,
@@ -145,19 +238,22 @@ impl<K, V, D> Entry<K, V, D> {
             Entry::MM { key, fpos } => {
                 let off = io::SeekFrom::Start(*fpos);
                 let block = read_file!(fd, off, reader.m_blocksize, "read mm-block")?;
-                let entries = util::from_cbor_bytes::<Vec<Entry<K, V, D>>>(&block)?.0;
+                let entries = Entry::<K, V, D>::decode_block(&block, reader.format)?;
                 println!("{}MM<{:?}@{},{}>", prefix, key, fpos, entries.len());
                 Some(entries)
             }
             Entry::MZ { key, fpos } => {
                 let off = io::SeekFrom::Start(*fpos);
                 let block = read_file!(fd, off, reader.m_blocksize, "read mm-block")?;
-                let entries = util::from_cbor_bytes::<Vec<Entry<K, V, D>>>(&block)?.0;
+                let entries = Entry::<K, V, D>::decode_block(&block, reader.format)?;
                 println!("{}MZ<{:?}@{},{}>", prefix, key, fpos, entries.len());
                 Some(entries)
             }
-            Entry::ZZ { key, value, deltas } => {
-                println!("{}ZZ---- key:{:?}; {:?}; {:?}", prefix, key, value, deltas);
+            Entry::ZZ { key, value, deltas, expiry } => {
+                println!(
+                    "{}ZZ---- key:{:?}; {:?}; {:?}; expiry:{:?}",
+                    prefix, key, value, deltas, expiry
+                );
                 None
             }
         };
@@ -24,7 +24,7 @@ fn test_entry() {
     let mm = Entry::<u64, u64, u64>::new_mm(key, 100);
     let mz = Entry::<u64, u64, u64>::new_mz(key, 200);
 
-    assert_eq!(dbnt, db::Entry::from(Entry::from(dbnt.clone())));
+    assert_eq!(dbnt, db::Entry::try_from(Entry::from(dbnt.clone())).unwrap());
     assert_eq!(zz.as_key(), &key);
     assert_eq!(mz.as_key(), &key);
     assert_eq!(mm.as_key(), &key);
@@ -51,3 +51,27 @@ fn test_entry() {
     let mut data = io::Cursor::new(data);
     assert_eq!(zz_ref.into_native(&mut data, true).unwrap(), zz);
 }
+
+#[test]
+fn test_entry_decode_block_v1() {
+    let dbnt = db::Entry::<u64, u64, u64>::new(10, 100, 1);
+    let zz = EntryV1::ZZ {
+        key: 10,
+        value: vlog::Value::from(dbnt.value.clone()),
+        deltas: vec![],
+    };
+    let block = vec![EntryV1::MZ { key: 10, fpos: 200 }, zz];
+    let data = util::into_cbor_bytes(block).unwrap();
+
+    let entries = Entry::<u64, u64, u64>::decode_block(&data, 0).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0], Entry::new_mz(10, 200));
+    assert_eq!(entries[1].to_expiry(), None);
+    assert_eq!(db::Entry::try_from(entries[1].clone()).unwrap(), dbnt);
+    // exactly one layout is decoded, as per the format version.
+    assert!(Entry::<u64, u64, u64>::decode_block(&data, 1).is_err());
+
+    let data = util::into_cbor_bytes(vec![Entry::<u64, u64, u64>::from(dbnt)]).unwrap();
+    assert_eq!(Entry::<u64, u64, u64>::decode_block(&data, 1).unwrap().len(), 1);
+    assert!(Entry::<u64, u64, u64>::decode_block(&data, 0).is_err());
+}
@@ -2,7 +2,7 @@ use fs2::FileExt;
 use log::{info, trace};
 use mkit::thread;
 
-use std::{convert::TryFrom, ffi, fs, mem, path};
+use std::{cmp, convert::TryFrom, ffi, fs, mem, path, thread as std_thread, time};
 
 use crate::{Error, Result};
 
@@ -10,6 +10,8 @@ pub enum Flusher {
     File {
         file: ffi::OsString,
         fpos: u64,
+        // shared with flusher thread, to sync the file.
+        fd: fs::File,
         th: Option<thread::Thread<Vec<u8>, u64, Result<u64>>>,
         tx: Option<thread::Tx<Vec<u8>, u64>>,
     },
@@ -26,24 +28,35 @@ impl Drop for Flusher {
 }
 
 impl Flusher {
-    pub fn new(file: &ffi::OsStr, create: bool, chan_size: usize) -> Result<Flusher> {
+    /// Create a flusher thread for `file`, writes are throttled to `rate`
+    /// bytes per second, if supplied.
+    pub fn new(
+        file: &ffi::OsStr,
+        create: bool,
+        chan_size: usize,
+        rate: Option<u64>,
+    ) -> Result<Flusher> {
         let (fd, fpos) = if create {
             (create_file_a(file)?, 0)
         } else {
-            let fpos = err_at!(IOError, fs::metadata(file))?.len().saturating_sub(1);
+            let fpos = err_at!(IOError, fs::metadata(file))?.len();
             (open_file_a(file)?, fpos)
         };
+        let sync_fd = err_at!(IOError, fd.try_clone())?;
 
         let ffpp = file.to_os_string();
         let (th, tx) = thread::Thread::new_sync(
             "flusher",
             chan_size,
-            move |rx: thread::Rx<Vec<u8>, u64>| move || thread_flush(ffpp, fd, rx, fpos),
+            move |rx: thread::Rx<Vec<u8>, u64>| {
+                move || thread_flush(ffpp, fd, rx, fpos, rate)
+            },
         );
 
         let val = Flusher::File {
             file: file.to_os_string(),
             fpos,
+            fd: sync_fd,
             th: Some(th),
             tx: Some(tx),
         };
@@ -72,6 +85,16 @@ impl Flusher {
         Ok(())
     }
 
+    /// Sync data flushed so far to disk.
+    pub fn sync(&self) -> Result<()> {
+        match self {
+            Flusher::File { file, fd, .. } => {
+                err_at!(IOError, fd.sync_data(), "fail sync_data {:?}", file)
+            }
+            Flusher::None => Ok(()),
+        }
+    }
+
     pub fn close(&mut self) -> Result<u64> {
         match self {
             Flusher::File { tx, th, .. } => {
@@ -88,15 +111,20 @@ fn thread_flush(
     mut fd: fs::File,
     rx: thread::Rx<Vec<u8>, u64>,
     mut fpos: u64,
+    rate: Option<u64>,
 ) -> Result<u64> {
     info!(target: "robt-flush", "starting {:?} @ fpos {}", file, fpos);
 
     err_at!(IOError, fd.lock_shared(), "fail read lock for {:?}", file)?;
 
+    let mut throttle = rate.map(Throttle::new);
     for (data, res_tx) in rx {
         write_file!(fd, &data, &file, "flushing file")?;
 
         fpos += u64::try_from(data.len()).unwrap();
+        if let Some(throttle) = throttle.as_mut() {
+            throttle.consume(u64::try_from(data.len()).unwrap());
+        }
         trace!(target: "robt", "flusher {:?} {} {}", file, data.len(), fpos);
         res_tx.map(|tx| tx.send(fpos).ok());
     }
@@ -107,6 +135,43 @@ fn thread_flush(
     Ok(fpos)
 }
 
+// Token bucket, to throttle writes to `rate` bytes per second. Tokens
+// accrue at `rate` upto a burst of one second worth of writes, so that an
+// idle flusher does not bank unbounded credit.
+struct Throttle {
+    rate: f64,
+    tokens: f64,
+    last: time::Instant,
+}
+
+impl Throttle {
+    fn new(rate: u64) -> Throttle {
+        let rate = rate.max(1) as f64;
+        Throttle { rate, tokens: rate, last: time::Instant::now() }
+    }
+
+    // consume `n_bytes` worth of tokens, sleep off the deficit if any.
+    fn consume(&mut self, n_bytes: u64) {
+        let wait = self.consume_at(time::Instant::now(), n_bytes);
+        if wait > time::Duration::default() {
+            std_thread::sleep(wait)
+        }
+    }
+
+    // consume `n_bytes` worth of tokens at `now`, return the time to wait
+    // for the deficit, if any, to accrue.
+    fn consume_at(&mut self, now: time::Instant, n_bytes: u64) -> time::Duration {
+        let accrued = now.saturating_duration_since(self.last).as_secs_f64() * self.rate;
+        self.tokens = (self.tokens + accrued).min(self.rate) - (n_bytes as f64);
+        self.last = cmp::max(self.last, now);
+        if self.tokens < 0.0 {
+            time::Duration::from_secs_f64(-self.tokens / self.rate)
+        } else {
+            time::Duration::default()
+        }
+    }
+}
+
 // create a file in append mode for writing.
 fn create_file_a(file: &ffi::OsStr) -> Result<fs::File> {
     let os_file = {
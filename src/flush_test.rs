@@ -24,7 +24,7 @@ fn test_flush() {
 
         let create = true;
         let chan_size: usize = cmp::min(uns.arbitrary().unwrap(), 12);
-        Flusher::new(file.as_ref(), create, chan_size).unwrap()
+        Flusher::new(file.as_ref(), create, chan_size, None).unwrap()
     };
 
     let mut fpos = 0;
@@ -50,3 +50,57 @@ fn test_flush() {
 
     assert_eq!(flushed_data, filedata);
 }
+
+#[test]
+fn test_flush_append() {
+    let dir = std::env::temp_dir().join("test_flush_append");
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("test-flusher.data");
+    fs::write(&file, vec![0xab; 100]).unwrap();
+
+    // appending to an existing file starts at its length.
+    let mut flusher = Flusher::new(file.as_ref(), false, 1, None).unwrap();
+    assert_eq!(flusher.to_fpos(), Some(100));
+    flusher.flush(vec![0xcd; 50]).unwrap();
+    assert_eq!(flusher.to_fpos(), Some(150));
+    assert_eq!(flusher.close().unwrap(), 150);
+
+    let mut data = vec![0xab; 100];
+    data.extend_from_slice(&[0xcd; 50]);
+    assert_eq!(fs::read(&file).unwrap(), data);
+
+    // an empty file is appended from the start.
+    fs::write(&file, b"").unwrap();
+    let mut flusher = Flusher::new(file.as_ref(), false, 1, None).unwrap();
+    assert_eq!(flusher.to_fpos(), Some(0));
+    assert_eq!(flusher.close().unwrap(), 0);
+}
+
+#[test]
+fn test_throttle() {
+    use std::time::Duration;
+
+    // wait time, rounded to milliseconds.
+    let ms = |wait: Duration| (wait.as_secs_f64() * 1000.0).round() as u64;
+
+    let mut throttle = Throttle::new(1000);
+    let t0 = throttle.last;
+    let at = |n: u64| t0 + Duration::from_millis(n);
+
+    // burst upto one second of rate.
+    assert_eq!(ms(throttle.consume_at(at(0), 1000)), 0);
+
+    // deficit is waited off at rate.
+    assert_eq!(ms(throttle.consume_at(at(0), 200)), 200);
+    // tokens accrued while waiting repay the deficit.
+    assert_eq!(ms(throttle.consume_at(at(200), 100)), 100);
+
+    // idle period does not accrue more than the burst.
+    assert_eq!(ms(throttle.consume_at(at(1800), 1500)), 500);
+
+    // clock going backwards accrues nothing.
+    let mut throttle = Throttle::new(1000);
+    let t0 = throttle.last;
+    assert_eq!(ms(throttle.consume_at(t0 + Duration::from_millis(100), 1000)), 0);
+    assert_eq!(ms(throttle.consume_at(t0, 100)), 100);
+}
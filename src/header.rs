@@ -0,0 +1,92 @@
+//! Module implement the header persisted at offset 0 of index file.
+//!
+//! Header identifies the file as a robt index file and carries the on-disk
+//! format version. Same version is also persisted in the meta-block. Index
+//! files built before [FORMAT_VERSION] 1 don't have a header, they are
+//! treated as format version 0.
+//!
+//! Header is padded to a multiple of `gcd(z_blocksize, m_blocksize)`, so
+//! that every block in the index file continue to start at a multiple of
+//! the same.
+
+use std::{
+    convert::{TryFrom, TryInto},
+    fs, io,
+};
+
+use crate::{util, Error, Result};
+
+/// On-disk format version for index file, written by this version of
+/// the package. Index files with a newer format version are rejected
+/// while opening.
+///
+/// * 0, index file without header and with positional meta-items.
+/// * 1, index file with header and named meta-sections.
+pub const FORMAT_VERSION: u64 = 1;
+
+/// Magic bytes at offset 0 of index file.
+const MAGIC: [u8; 8] = *b"robt\x00fmt";
+
+/// Magic, followed by version as u64, followed by header length as u64.
+const HEADER_SIZE: u64 = 24;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Header {
+    pub version: u64,
+    /// Header length in bytes, including the padding.
+    pub length: u64,
+}
+
+impl Header {
+    pub fn new(z_blocksize: usize, m_blocksize: usize) -> Header {
+        let (z, m) = (z_blocksize as u64, m_blocksize as u64);
+        let n = util::gcd(z, m).max(1);
+        let length = ((HEADER_SIZE + n - 1) / n) * n;
+        Header { version: FORMAT_VERSION, length }
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut data = vec![0; err_at!(Fatal, usize::try_from(self.length))?];
+        data[..8].copy_from_slice(&MAGIC);
+        data[8..16].copy_from_slice(&self.version.to_be_bytes());
+        data[16..24].copy_from_slice(&self.length.to_be_bytes());
+        Ok(data)
+    }
+
+    /// Read header from index file, return None if the file does not
+    /// start with a header.
+    pub fn read(index: &mut fs::File) -> Result<Option<Header>> {
+        let file_len = err_at!(IOError, index.metadata())?.len();
+        if file_len < HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let seek = io::SeekFrom::Start(0);
+        let data = read_file!(index, seek, HEADER_SIZE, "reading header from index")?;
+        if data[..8] != MAGIC {
+            return Ok(None);
+        }
+        let version = u64::from_be_bytes(err_at!(InvalidFile, data[8..16].try_into())?);
+        let length = u64::from_be_bytes(err_at!(InvalidFile, data[16..24].try_into())?);
+        if length < HEADER_SIZE || length > file_len {
+            err_at!(InvalidFile, msg: "bad header length {}", length)?
+        }
+
+        Ok(Some(Header { version, length }))
+    }
+}
+
+/// Check whether index file's format version is supported.
+pub(crate) fn check_version(version: u64) -> Result<()> {
+    if version > FORMAT_VERSION {
+        let n = FORMAT_VERSION;
+        let msg = "unsupported format";
+        err_at!(InvalidFile, msg: "{} version {}, expected <= {}", msg, version, n)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[path = "header_test.rs"]
+mod header_test;
@@ -0,0 +1,56 @@
+use std::io::Write;
+
+use super::*;
+
+#[test]
+fn test_header() {
+    assert_eq!(Header::new(4096, 4096).length, 4096);
+    assert_eq!(Header::new(4096, 1024).length, 1024);
+    assert_eq!(Header::new(12, 8).length, 24);
+    assert_eq!(Header::new(10, 15).length, 25);
+
+    let dir = std::env::temp_dir().join("test_header");
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("test_header-robt.indx");
+
+    let header = Header::new(1024, 1024);
+    let data = header.to_bytes().unwrap();
+    assert_eq!(data.len(), 1024);
+    {
+        let mut fd = fs::File::create(&file).unwrap();
+        fd.write_all(&data).unwrap();
+    }
+    let mut fd = fs::File::open(&file).unwrap();
+    assert_eq!(Header::read(&mut fd).unwrap(), Some(header));
+
+    // older index files don't have header.
+    {
+        let mut fd = fs::File::create(&file).unwrap();
+        fd.write_all(&[0; 1024]).unwrap();
+    }
+    let mut fd = fs::File::open(&file).unwrap();
+    assert_eq!(Header::read(&mut fd).unwrap(), None);
+
+    // header length beyond the file.
+    {
+        let mut data = Header::new(1024, 1024).to_bytes().unwrap();
+        data.truncate(512);
+        let mut fd = fs::File::create(&file).unwrap();
+        fd.write_all(&data).unwrap();
+    }
+    let mut fd = fs::File::open(&file).unwrap();
+    match Header::read(&mut fd) {
+        Err(Error::InvalidFile(_, _)) => (),
+        res => panic!("unexpected {:?}", res),
+    }
+}
+
+#[test]
+fn test_check_version() {
+    check_version(0).unwrap();
+    check_version(FORMAT_VERSION).unwrap();
+    match check_version(FORMAT_VERSION + 1) {
+        Err(Error::InvalidFile(_, _)) => (),
+        res => panic!("unexpected {:?}", res),
+    }
+}
@@ -26,6 +26,44 @@
 //! * API `iter_version()` and `reverse_version()` operation similar to
 //!   iter/reverse but also fetches older versions for a entry. Note that
 //!   iter/reverse do not fetch the older versions.
+//! * Optional per-entry expiry, computed while building the index. Expired
+//!   entries can be treated as missing while reading and are purged
+//!   during compaction.
+//! * Range tombstones, to delete a range of keys, are persisted in the
+//!   meta-block and honoured by read APIs and compaction.
+//! * Keys are ordered using `Ord` by default, optionally a custom
+//!   [Comparator] can be used to collate keys.
+//! * Index persists a [Schema] finger-print, with an optional stable
+//!   schema-id supplied by application that can be verified after opening
+//!   the index. Opening an index with mismatching types is an error.
+//! * Index can be opened without knowing its types, using [Dyn] as
+//!   key-type, value-type and delta-type, refer to [db::DynIndex].
+//! * Index file carries its on-disk [FORMAT_VERSION], in a header at offset
+//!   0 and in the meta-block. Files with a newer format version are rejected
+//!   while opening, older files can be rewritten using [db::Index::upgrade].
+//! * Optional merkle tree over index blocks, to compare replicas and find
+//!   the key ranges that differ, refer to [Config::set_merkle].
+//! * Key-level changes between two snapshots, as an ordered stream of
+//!   added, removed and changed entries, refer to [db::diff].
+//! * Parallel build, leaf blocks are encoded on worker threads from
+//!   partitioned input, refer to [db::Builder::build_index_par].
+//! * Build from unsorted input, larger than memory, using an external sort,
+//!   refer to [db::Builder::from_unsorted].
+//! * Build from several sorted inputs, like memtable shards, merged in a
+//!   single pass, refer to [db::Builder::build_index_merged].
+//! * Concatenate indexes with disjoint key ranges, by copying their leaf
+//!   blocks and value-logs, refer to [db::Builder::concat].
+//! * Split an index into key-range shards, refer to [db::Index::split_at].
+//! * Incremental build that reuses unchanged leaf blocks from the previous
+//!   snapshot, refer to [db::Builder::build_index_patch].
+//! * Observe build progress and cancel an on-going build, refer to
+//!   [db::Builder::set_observer] and [db::Builder::set_cancel]. Disk
+//!   bandwidth used by the build can be limited, refer to
+//!   [Config::set_flush_rate].
+//! * Resume a build that died midway from its last checkpoint, refer to
+//!   [Config::set_checkpoint] and [db::Builder::resume].
+//! * Leaf blocks and intermediate blocks can be partially filled, to trade
+//!   space for cheaper edits, refer to [Config::set_fill_factor].
 //!
 //! **Value-log file**
 //!
@@ -212,19 +250,33 @@ macro_rules! read_file {
             io::{Read, Seek},
         };
 
-        match $fd.seek($seek) {
-            Ok(_) => {
-                let mut buf = vec![0; usize::try_from($n).unwrap()];
-                match $fd.read(&mut buf) {
-                    Ok(n) if buf.len() == n => Ok(buf),
-                    Ok(n) => {
-                        let m = buf.len();
-                        err_at!(Fatal, msg: concat!($msg, " {}/{} at {:?}"), m, n, $seek)
+        // length may come from a corrupted file, hence buffer is grown as
+        // data is read, instead of pre-allocating `$n` bytes upfront.
+        match ($fd.seek($seek), usize::try_from($n)) {
+            (Ok(_), Ok(m)) => {
+                let mut buf: Vec<u8> = vec![];
+                loop {
+                    let n = buf.len();
+                    if n == m {
+                        break Ok(buf);
+                    }
+                    buf.resize(n + std::cmp::min(m - n, 1024 * 1024), 0);
+                    match $fd.read(&mut buf[n..]) {
+                        Ok(0) => {
+                            break err_at!(
+                                Fatal, msg: concat!($msg, " {}/{} at {:?}"), m, n, $seek
+                            );
+                        }
+                        Ok(k) => buf.truncate(n + k),
+                        Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {
+                            buf.truncate(n)
+                        }
+                        Err(err) => break err_at!(IOError, Err(err)),
                     }
-                    Err(err) => err_at!(IOError, Err(err)),
                 }
             }
-            Err(err) => err_at!(IOError, Err(err)),
+            (Ok(_), Err(err)) => err_at!(FailConvert, Err(err)),
+            (Err(err), _) => err_at!(IOError, Err(err)),
         }
     }};
 }
@@ -297,22 +349,59 @@ macro_rules! err_at {
 }
 
 mod build;
+mod checkpoint;
+mod comparator;
+mod concat;
 mod config;
+mod diff;
+mod dynamic;
 mod entry;
 mod files;
 mod flush;
+mod header;
 mod marker;
+mod merge;
+mod merkle;
+mod meta;
+mod parallel;
+mod patch;
 mod reader;
 mod robt;
+mod salvage;
 mod scans;
+mod schema;
+mod sort;
+mod split;
+mod tombstone;
 mod util;
+mod validate;
 mod vlog;
 
-pub use config::{Config, Stats, FLUSH_QUEUE_SIZE, MBLOCKSIZE, VBLOCKSIZE, ZBLOCKSIZE};
+pub use build::Progress;
+pub use comparator::{Comparator, Natural};
+pub use config::{
+    Config, Stats, FLUSH_QUEUE_SIZE, MBLOCKSIZE, MIN_BLOCKSIZE, VBLOCKSIZE, ZBLOCKSIZE,
+};
+pub use dynamic::Dyn;
+pub use header::FORMAT_VERSION;
+pub use merkle::HASH_SIZE;
+pub use parallel::SEGMENT_SIZE;
+pub use salvage::{LostRange, SalvageReport};
+pub use schema::Schema;
+pub use validate::{Problem, ValidateReport};
 /// Module implement [Builder] and [Index] type parametrised over
 /// delta-type and bitmap-type.
 pub mod db {
+    pub use crate::diff::{diff, Change, DiffIter};
     pub use crate::robt::{Builder, Index};
+    pub use crate::salvage::{salvage, salvage_by};
+    pub use crate::tombstone::{
+        is_covered, is_covered_by, Limit, RangeTombstone, TombstoneScan,
+    };
+
+    /// Type-erased index, to read an index without knowing its key-type,
+    /// value-type and delta-type. Refer to [Dyn][crate::Dyn] for details.
+    pub type DynIndex<B> = Index<crate::Dyn, crate::Dyn, crate::Dyn, B>;
 }
 
 /// Type alias for [db::Builder] without version control for value-type.
@@ -338,6 +427,8 @@ pub enum Error {
     InvalidFile(String, String),
     KeyNotFound(String, String),
     Retry(String, String),
+    TypeMismatch(String, String),
+    Cancelled(String, String),
 }
 
 impl fmt::Display for Error {
@@ -355,6 +446,8 @@ impl fmt::Display for Error {
             InvalidFile(p, msg) => write!(f, "{} InvalidFile: {}", p, msg),
             KeyNotFound(p, msg) => write!(f, "{} KeyNotFound: {}", p, msg),
             Retry(p, msg) => write!(f, "{} Retry: {}", p, msg),
+            TypeMismatch(p, msg) => write!(f, "{} TypeMismatch: {}", p, msg),
+            Cancelled(p, msg) => write!(f, "{} Cancelled: {}", p, msg),
         }
     }
 }
@@ -0,0 +1,177 @@
+//! Module implement k-way merge of sorted iterators, to build index.
+//!
+//! Each input iterator must yield entries in sort order with unique keys,
+//! for example a memtable shard. Entries across inputs are merged by key
+//! and duplicate keys are resolved by seqno, entry with the higher seqno
+//! wins. Optionally older versions can be folded into the winning entry's
+//! deltas, refer to [fold_versions].
+
+use mkit::{data::Diff, db};
+
+use std::{cmp, collections::BinaryHeap, marker};
+
+use crate::{comparator::Comparator, Error, Result};
+
+// Fold an older entry, for the same key, into the newer entry.
+pub type FoldFn<K, V, D> =
+    fn(db::Entry<K, V, D>, db::Entry<K, V, D>) -> db::Entry<K, V, D>;
+
+// head entry from each source, ordered for a max-heap such that smaller
+// key and, for the same key, higher seqno is popped first.
+pub struct Head<K, V, D, C> {
+    pub entry: db::Entry<K, V, D>,
+    pub src: usize,
+
+    _cmp: marker::PhantomData<C>,
+}
+
+impl<K, V, D, C> Head<K, V, D, C> {
+    pub fn new(entry: db::Entry<K, V, D>, src: usize) -> Self {
+        Head { entry, src, _cmp: marker::PhantomData }
+    }
+}
+
+impl<K, V, D, C> Ord for Head<K, V, D, C>
+where
+    C: Comparator<K>,
+{
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        let (a, b) = (&self.entry, &other.entry);
+        C::compare(&b.key, &a.key).then(a.to_seqno().cmp(&b.to_seqno()))
+    }
+}
+
+impl<K, V, D, C> PartialOrd for Head<K, V, D, C>
+where
+    C: Comparator<K>,
+{
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K, V, D, C> PartialEq for Head<K, V, D, C>
+where
+    C: Comparator<K>,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == cmp::Ordering::Equal
+    }
+}
+
+impl<K, V, D, C> Eq for Head<K, V, D, C> where C: Comparator<K> {}
+
+/// Iterator over entries merged from several sorted iterators. Fails with
+/// [Error::Invalid] if any of the input iterator is not sorted.
+pub struct MergeScan<K, V, D, C, I> {
+    sources: Vec<I>,
+    heap: BinaryHeap<Head<K, V, D, C>>,
+    fold: Option<FoldFn<K, V, D>>,
+    last_key: Option<K>,
+}
+
+impl<K, V, D, C, I> MergeScan<K, V, D, C, I>
+where
+    C: Comparator<K>,
+    I: Iterator<Item = db::Entry<K, V, D>>,
+{
+    /// Merge `sources`, for duplicate keys only the entry with the highest
+    /// seqno is retained, unless `fold` is supplied.
+    pub fn new(sources: Vec<I>, fold: Option<FoldFn<K, V, D>>) -> Self {
+        let heap = BinaryHeap::new();
+        let mut iter = MergeScan { sources, heap, fold, last_key: None };
+        for src in 0..iter.sources.len() {
+            iter.fill(src);
+        }
+        iter
+    }
+
+    fn fill(&mut self, src: usize) {
+        if let Some(entry) = self.sources[src].next() {
+            self.heap.push(Head::new(entry, src));
+        }
+    }
+}
+
+impl<K, V, D, C, I> Iterator for MergeScan<K, V, D, C, I>
+where
+    K: Clone,
+    C: Comparator<K>,
+    I: Iterator<Item = db::Entry<K, V, D>>,
+{
+    type Item = Result<db::Entry<K, V, D>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let head = self.heap.pop()?;
+        self.fill(head.src);
+
+        if let Some(last_key) = self.last_key.as_ref() {
+            if C::compare(last_key, &head.entry.key) != cmp::Ordering::Less {
+                return Some(err_at!(Invalid, msg: "unsorted input {}", head.src));
+            }
+        }
+
+        // older versions of the same key, from other sources.
+        let mut entry = head.entry;
+        while let Some(next) = self.heap.peek() {
+            match C::compare(&next.entry.key, &entry.key) {
+                cmp::Ordering::Equal => {
+                    let older = self.heap.pop().unwrap();
+                    self.fill(older.src);
+                    if let Some(fold) = self.fold {
+                        entry = fold(entry, older.entry);
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        self.last_key = Some(entry.key.clone());
+        Some(Ok(entry))
+    }
+}
+
+/// Fold `older` entry into `newer` entry's deltas. Older value is encoded
+/// as delta relative to the oldest version of `newer`, followed by older
+/// entry's own deltas.
+pub fn fold_versions<K, V, D>(
+    newer: db::Entry<K, V, D>,
+    older: db::Entry<K, V, D>,
+) -> db::Entry<K, V, D>
+where
+    V: Clone + Diff<Delta = D> + From<D>,
+    D: Clone + From<V>,
+{
+    // reconstruct the oldest version of the newer entry.
+    let mut base = match &newer.value {
+        db::Value::U { value, .. } => Some(value.clone()),
+        db::Value::D { .. } => None,
+    };
+    for delta in newer.deltas.iter() {
+        base = match (delta, base) {
+            (db::Delta::U { delta, .. }, Some(value)) => Some(value.merge(delta)),
+            (db::Delta::U { delta, .. }, None) => Some(V::from(delta.clone())),
+            (db::Delta::D { .. }, _) => None,
+        };
+    }
+
+    let delta = match older.value {
+        db::Value::U { value, seqno } => {
+            let delta = match base {
+                Some(base) => base.diff(&value),
+                None => D::from(value),
+            };
+            db::Delta::U { delta, seqno }
+        }
+        db::Value::D { seqno } => db::Delta::D { seqno },
+    };
+
+    let mut entry = newer;
+    entry.deltas.push(delta);
+    entry.deltas.extend(older.deltas);
+    entry
+}
+
+#[cfg(test)]
+#[path = "merge_test.rs"]
+mod merge_test;
@@ -0,0 +1,171 @@
+use mkit::nobitmap::NoBitmap;
+use rand::{prelude::random, rngs::StdRnd, Rng, SeedableRng};
+
+use std::{collections::BTreeMap, ops::Bound};
+
+use super::*;
+use crate::{
+    comparator::Natural,
+    db::{Builder, Index},
+    util, Config,
+};
+
+type Entry = db::Entry<u16, u64, u64>;
+
+// distribute entries across `n` sorted shards, along with an older version
+// for some of the keys in a different shard.
+fn to_shards(rng: &mut StdRnd, entries: Vec<Entry>, n: usize) -> Vec<Vec<Entry>> {
+    let mut shards: Vec<BTreeMap<u16, Entry>> = vec![BTreeMap::new(); n];
+    for entry in entries.into_iter() {
+        let shard = rng.gen::<usize>() % n;
+        let seqno = entry.to_seqno();
+        if n > 1 && seqno > 1 && rng.gen::<u8>() % 4 == 0 {
+            let older = Entry {
+                key: entry.key,
+                value: db::Value::U { value: rng.gen(), seqno: seqno - 1 },
+                deltas: vec![],
+            };
+            shards[(shard + 1) % n].insert(entry.key, older);
+        }
+        shards[shard].insert(entry.key, entry);
+    }
+    shards.into_iter().map(|s| s.into_iter().map(|(_, e)| e).collect()).collect()
+}
+
+#[test]
+fn test_merge_scan() {
+    let seed: u128 = random();
+    println!("test_merge_scan {}", seed);
+    let mut rng = StdRnd::from_seed(seed.to_le_bytes());
+
+    let n = 1 + (rng.gen::<usize>() % 8);
+    let mut shards: Vec<Vec<Entry>> = vec![];
+    let mut refs: BTreeMap<u16, Vec<u64>> = BTreeMap::new();
+    for _ in 0..n {
+        let mut shard: BTreeMap<u16, Entry> = BTreeMap::new();
+        for _ in 0..1_000 {
+            let (key, value) = (rng.gen::<u16>() % 2_000, rng.gen::<u64>());
+            let seqno = rng.gen::<u64>() % 1_000_000;
+            let value = db::Value::U { value, seqno };
+            shard.insert(key, Entry { key, value, deltas: vec![] });
+        }
+        for (key, entry) in shard.iter() {
+            refs.entry(*key).or_insert_with(Vec::new).push(entry.to_seqno());
+        }
+        shards.push(shard.into_iter().map(|(_, e)| e).collect());
+    }
+    refs.values_mut().for_each(|seqnos| seqnos.sort_by(|a, b| b.cmp(a)));
+
+    // older versions are dropped.
+    let iters: Vec<_> = shards.clone().into_iter().map(|s| s.into_iter()).collect();
+    let iter = MergeScan::<u16, u64, u64, Natural, _>::new(iters, None);
+    let entries: Vec<Entry> = iter.map(|e| e.unwrap()).collect();
+    assert_eq!(entries.len(), refs.len());
+    for (entry, (key, seqnos)) in entries.into_iter().zip(refs.iter()) {
+        assert_eq!(entry.key, *key);
+        assert_eq!(entry.to_seqno(), seqnos[0]);
+        assert!(entry.deltas.is_empty());
+    }
+
+    // older versions are folded into deltas.
+    let iters: Vec<_> = shards.clone().into_iter().map(|s| s.into_iter()).collect();
+    let fold: FoldFn<u16, u64, u64> = fold_versions;
+    let iter = MergeScan::<u16, u64, u64, Natural, _>::new(iters, Some(fold));
+    let entries: Vec<Entry> = iter.map(|e| e.unwrap()).collect();
+    assert_eq!(entries.len(), refs.len());
+    for (entry, (key, seqnos)) in entries.into_iter().zip(refs.iter()) {
+        assert_eq!(entry.key, *key);
+        assert_eq!(entry.to_seqno(), seqnos[0]);
+        let deltas: Vec<u64> = entry
+            .deltas
+            .iter()
+            .map(|d| match d {
+                db::Delta::U { seqno, .. } | db::Delta::D { seqno } => *seqno,
+            })
+            .collect();
+        assert_eq!(deltas, seqnos[1..].to_vec());
+    }
+
+    // unsorted input.
+    let mut shard = shards.remove(0);
+    shard.reverse();
+    let n_entries = shard.len();
+    let iter = MergeScan::<u16, u64, u64, Natural, _>::new(vec![shard.into_iter()], None);
+    match iter.collect::<Result<Vec<Entry>>>() {
+        Err(Error::Invalid(_, _)) if n_entries > 1 => (),
+        Ok(_) if n_entries < 2 => (),
+        res => panic!("unexpected {:?}", res),
+    }
+}
+
+#[test]
+fn test_build_index_merged() {
+    let seed: u128 = random();
+    println!("test_build_index_merged {}", seed);
+    let mut rng = StdRnd::from_seed(seed.to_le_bytes());
+
+    let dir = std::env::temp_dir().join("test_build_index_merged");
+    let name = "test_build_index_merged";
+    let mut config = Config::new(dir.as_os_str(), name);
+    config
+        .set_blocksize(1024, 1024, 1024)
+        .set_value_log(rng.gen())
+        .set_delta(rng.gen());
+    println!("vlog:{} delta:{}", config.value_in_vlog, config.delta_ok);
+
+    let mdb = util::load_index(seed, 5_000, 1_000, 500, 100, None);
+    let n = 1 + (rng.gen::<usize>() % 8);
+    let shards = to_shards(&mut rng, mdb.iter().unwrap().collect(), n);
+
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    let iters: Vec<_> = shards.clone().into_iter().map(|s| s.into_iter()).collect();
+    build.build_index_merged(iters, NoBitmap, None).unwrap();
+
+    let mut index = Index::<u16, u64, u64, NoBitmap>::open(&config.dir, name).unwrap();
+    let stats = index.validate().unwrap();
+    assert_eq!(stats.n_count, mdb.len() as u64);
+    assert_eq!(stats.n_deleted, mdb.deleted_count());
+    assert_eq!(stats.seqno, mdb.to_seqno());
+
+    let r = (Bound::<u16>::Unbounded, Bound::<u16>::Unbounded);
+    let mut iter = index.iter_versions(r).unwrap();
+    for mut e1 in mdb.iter().unwrap() {
+        if !config.delta_ok {
+            e1.deltas = vec![];
+        }
+        assert_eq!(e1, iter.next().unwrap().unwrap());
+    }
+    assert!(iter.next().is_none());
+
+    // older versions preserved as deltas.
+    config.name = "test_build_index_merged-versions".to_string();
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    let iters: Vec<_> = shards.into_iter().map(|s| s.into_iter()).collect();
+    build.build_index_merged_versions(iters, NoBitmap, None).unwrap();
+
+    let mut index =
+        Index::<u16, u64, u64, NoBitmap>::open(&config.dir, &config.name).unwrap();
+    index.validate().unwrap();
+    assert_eq!(index.len(), mdb.len());
+    let mut iter = index.iter_versions(r).unwrap();
+    for e1 in mdb.iter().unwrap() {
+        let e2 = iter.next().unwrap().unwrap();
+        assert_eq!(e1.key, e2.key);
+        assert_eq!(e1.to_seqno(), e2.to_seqno());
+        if config.delta_ok {
+            assert!(e2.deltas.len() >= e1.deltas.len());
+        }
+    }
+    assert!(iter.next().is_none());
+
+    // unsorted input fails the build, and partial files are removed.
+    config.name = "test_build_index_merged-unsorted".to_string();
+    let mut entries: Vec<Entry> = mdb.iter().unwrap().collect();
+    entries.push(entries[0].clone());
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    match build.build_index_merged(vec![entries.into_iter()], NoBitmap, None) {
+        Err(Error::Invalid(_, _)) => (),
+        res => panic!("unexpected {:?}", res),
+    }
+    assert!(Index::<u16, u64, u64, NoBitmap>::open(&config.dir, &config.name).is_err());
+}
@@ -0,0 +1,279 @@
+//! Module implement a merkle tree over index blocks.
+//!
+//! When enabled via [Config::set_merkle][crate::Config::set_merkle], every
+//! leaf block is hashed along with the values and deltas it serialized into
+//! value-log file, and every intermediate block is hashed over its child
+//! hashes, all the way up to the root block. Root hash is persisted in the
+//! meta-block, block hashes are persisted as a separate region in the index
+//! file, just before the meta-block, and read only when the tree is
+//! descended.
+//!
+//! Two indexes holding the same snapshot, built with the same configuration,
+//! shall have the same merkle root. When they differ, mismatching subtrees
+//! are descended to find the key ranges that differ.
+
+use mkit::{cbor::FromCbor, Cborize};
+use sha2::{Digest, Sha256};
+
+use std::{
+    cmp,
+    collections::{BTreeMap, HashSet},
+    convert::TryInto,
+    io,
+    ops::Bound,
+};
+
+use crate::{
+    comparator::Comparator,
+    entry::Entry,
+    reader::{Reader, MAX_DEPTH},
+    Error, Result,
+};
+
+const REGION_VER1: u32 = 0x0001;
+
+/// Size of block hash, in bytes.
+pub const HASH_SIZE: usize = 32;
+
+pub(crate) type Hash = [u8; HASH_SIZE];
+
+// hash a leaf block, along with its values and deltas from value-log.
+pub(crate) fn leaf_hash(zblock: &[u8], vblock: &[u8]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(&[0]);
+    hasher.update(zblock);
+    hasher.update(vblock);
+    to_hash(&hasher.finalize())
+}
+
+// hash an intermediate block over the hashes of its children.
+pub(crate) fn node_hash(children: &[Hash]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(&[1]);
+    for child in children.iter() {
+        hasher.update(child);
+    }
+    to_hash(&hasher.finalize())
+}
+
+fn to_hash(data: &[u8]) -> Hash {
+    let mut hash = [0; HASH_SIZE];
+    hash.copy_from_slice(data);
+    hash
+}
+
+/// Block hashes, indexed by the block's file-position.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Hashes {
+    hashes: BTreeMap<u64, Hash>,
+}
+
+impl Hashes {
+    pub fn insert(&mut self, fpos: u64, hash: Hash) {
+        self.hashes.insert(fpos, hash);
+    }
+
+    pub fn get(&self, fpos: u64) -> Result<Hash> {
+        match self.hashes.get(&fpos) {
+            Some(hash) => Ok(*hash),
+            None => err_at!(InvalidFile, msg: "missing block hash at {}", fpos),
+        }
+    }
+
+    // serialize as a packed array of {fpos, hash}.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.hashes.len() * (8 + HASH_SIZE));
+        for (fpos, hash) in self.hashes.iter() {
+            data.extend_from_slice(&fpos.to_be_bytes());
+            data.extend_from_slice(hash);
+        }
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Hashes> {
+        if data.len() % (8 + HASH_SIZE) != 0 {
+            err_at!(InvalidFile, msg: "bad merkle hashes, length {}", data.len())?
+        }
+
+        let mut hashes = BTreeMap::new();
+        for item in data.chunks(8 + HASH_SIZE) {
+            let fpos = u64::from_be_bytes(err_at!(InvalidFile, item[..8].try_into())?);
+            hashes.insert(fpos, to_hash(&item[8..]));
+        }
+
+        Ok(Hashes { hashes })
+    }
+}
+
+/// Location of block hashes in index file, persisted in the meta-block
+/// under [MERKLE_HASHES][crate::meta::MERKLE_HASHES].
+#[derive(Clone, Debug, Default, Eq, PartialEq, Cborize)]
+pub(crate) struct HashesRegion {
+    /// File-position where block hashes start.
+    pub fpos: u64,
+    /// Length of block hashes, in bytes.
+    pub length: u64,
+}
+
+impl HashesRegion {
+    const ID: u32 = REGION_VER1;
+}
+
+// subtree in the frontier, while descending mismatched subtrees.
+struct Node<K> {
+    start: Bound<K>,
+    end: Bound<K>,
+    fpos: u64,
+    leaf: bool,
+    hash: Hash,
+}
+
+/// Find key ranges that differ between two indexes, by descending
+/// mismatched subtrees.
+pub(crate) fn diff_ranges<K, V, D, C>(
+    a: (&mut Reader<K, V, D, C>, &Hashes, u64),
+    b: (&mut Reader<K, V, D, C>, &Hashes, u64),
+) -> Result<Vec<(Bound<K>, Bound<K>)>>
+where
+    K: Clone + FromCbor,
+    V: FromCbor,
+    D: FromCbor,
+    C: Comparator<K>,
+{
+    let (ra, ha, root_a) = a;
+    let (rb, hb, root_b) = b;
+
+    let root = |fpos: u64, hashes: &Hashes| -> Result<Node<K>> {
+        let (start, end) = (Bound::Unbounded, Bound::Unbounded);
+        let hash = hashes.get(fpos)?;
+        Ok(Node { start, end, fpos, leaf: false, hash })
+    };
+    let mut xs = vec![root(root_a, ha)?];
+    let mut ys = vec![root(root_b, hb)?];
+
+    for _ in 0..MAX_DEPTH {
+        // subtrees with the same hash hold the same entries.
+        let xh: HashSet<Hash> = xs.iter().map(|n| n.hash).collect();
+        let yh: HashSet<Hash> = ys.iter().map(|n| n.hash).collect();
+        xs.retain(|n| !yh.contains(&n.hash));
+        ys.retain(|n| !xh.contains(&n.hash));
+
+        if xs.iter().chain(ys.iter()).all(|n| n.leaf) {
+            let iter = xs.into_iter().chain(ys);
+            let ranges = iter.map(|n| (n.start, n.end)).collect();
+            return Ok(merge_ranges::<K, C>(ranges));
+        }
+
+        xs = expand(ra, ha, xs)?;
+        ys = expand(rb, hb, ys)?;
+    }
+
+    err_at!(InvalidFile, msg: "depth > {}", MAX_DEPTH)
+}
+
+// replace intermediate nodes with their children, leaf nodes are retained.
+fn expand<K, V, D, C>(
+    reader: &mut Reader<K, V, D, C>,
+    hashes: &Hashes,
+    nodes: Vec<Node<K>>,
+) -> Result<Vec<Node<K>>>
+where
+    K: Clone + FromCbor,
+    V: FromCbor,
+    D: FromCbor,
+{
+    let mut items = vec![];
+    for node in nodes.into_iter() {
+        if node.leaf {
+            items.push(node);
+            continue;
+        }
+
+        let fd = &mut reader.index;
+        let seek = io::SeekFrom::Start(node.fpos);
+        let block = read_file!(fd, seek, reader.m_blocksize, "read block")?;
+        let entries = Entry::<K, V, D>::decode_block(&block, reader.format)?;
+
+        let mut children = vec![];
+        for entry in entries.into_iter() {
+            match entry {
+                Entry::MM { key, fpos } => children.push((key, fpos, false)),
+                Entry::MZ { key, fpos } => children.push((key, fpos, true)),
+                Entry::ZZ { .. } => {
+                    err_at!(InvalidFile, msg: "leaf entry in m-block {}", node.fpos)?
+                }
+            }
+        }
+
+        let n = children.len();
+        let keys: Vec<K> = children.iter().map(|c| c.0.clone()).collect();
+        for (i, (key, fpos, leaf)) in children.into_iter().enumerate() {
+            let start = match i {
+                0 => node.start.clone(),
+                _ => Bound::Included(key),
+            };
+            let end = match i + 1 {
+                j if j == n => node.end.clone(),
+                j => Bound::Excluded(keys[j].clone()),
+            };
+            let hash = hashes.get(fpos)?;
+            items.push(Node { start, end, fpos, leaf, hash });
+        }
+    }
+
+    Ok(items)
+}
+
+// sort and merge overlapping, and adjacent, ranges. Lower bounds are either
+// unbounded or included, upper bounds are either unbounded or excluded.
+fn merge_ranges<K, C>(mut ranges: Vec<(Bound<K>, Bound<K>)>) -> Vec<(Bound<K>, Bound<K>)>
+where
+    C: Comparator<K>,
+{
+    use std::cmp::Ordering::{Greater, Less};
+
+    // unbounded lower bound is negative infinity.
+    ranges.sort_by(|a, b| match (bound_key(&a.0), bound_key(&b.0)) {
+        (None, None) => cmp::Ordering::Equal,
+        (None, Some(_)) => Less,
+        (Some(_), None) => Greater,
+        (Some(x), Some(y)) => C::compare(x, y),
+    });
+
+    let mut merged: Vec<(Bound<K>, Bound<K>)> = vec![];
+    for (start, end) in ranges.into_iter() {
+        if let Some(last) = merged.last_mut() {
+            // unbounded upper bound is positive infinity.
+            let (overlap, extend) = match (bound_key(&last.1), bound_key(&end)) {
+                (None, _) => (true, false),
+                (Some(e), y) => {
+                    let overlap = match bound_key(&start) {
+                        Some(s) => C::compare(s, e) != Greater,
+                        None => true,
+                    };
+                    (overlap, y.map(|y| C::compare(y, e) == Greater).unwrap_or(true))
+                }
+            };
+            if overlap {
+                if extend {
+                    last.1 = end;
+                }
+                continue;
+            }
+        }
+        merged.push((start, end));
+    }
+
+    merged
+}
+
+fn bound_key<K>(bound: &Bound<K>) -> Option<&K> {
+    match bound {
+        Bound::Included(key) | Bound::Excluded(key) => Some(key),
+        Bound::Unbounded => None,
+    }
+}
+
+#[cfg(test)]
+#[path = "merkle_test.rs"]
+mod merkle_test;
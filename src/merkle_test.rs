@@ -0,0 +1,127 @@
+use mkit::{
+    db::{self, BuildIndex},
+    nobitmap::NoBitmap,
+};
+use ppom::mdb::OMap;
+use rand::{prelude::random, rngs::StdRnd, Rng, SeedableRng};
+
+use std::ops::RangeBounds;
+
+use super::*;
+use crate::{
+    comparator::Natural,
+    db::{Builder, Index},
+    Config,
+};
+
+#[test]
+fn test_merkle_hashes() {
+    let mut hashes = Hashes::default();
+    hashes.insert(4096, leaf_hash(&[1, 2, 3], &[]));
+    hashes.insert(0, leaf_hash(&[1, 2, 3], &[4]));
+    let children = [hashes.get(0).unwrap(), hashes.get(4096).unwrap()];
+    hashes.insert(8192, node_hash(&children));
+    assert_ne!(hashes.get(0).unwrap(), hashes.get(4096).unwrap());
+    assert!(hashes.get(1024).is_err());
+
+    let data = hashes.to_bytes();
+    assert_eq!(data.len(), 3 * (8 + HASH_SIZE));
+    let other = Hashes::from_bytes(&data).unwrap();
+    assert_eq!(other.hashes, hashes.hashes);
+
+    match Hashes::from_bytes(&data[1..]) {
+        Err(Error::InvalidFile(_, _)) => (),
+        res => panic!("unexpected {:?}", res),
+    }
+}
+
+#[test]
+fn test_merge_ranges() {
+    use std::ops::Bound::{Excluded, Included, Unbounded};
+
+    let ranges = vec![
+        (Included(30_u16), Excluded(40)),
+        (Unbounded, Excluded(10)),
+        (Included(10), Excluded(20)),
+        (Included(50), Unbounded),
+        (Included(35), Excluded(38)),
+    ];
+    let refs = vec![
+        (Unbounded, Excluded(20)),
+        (Included(30), Excluded(40)),
+        (Included(50), Unbounded),
+    ];
+    assert_eq!(merge_ranges::<u16, Natural>(ranges), refs);
+
+    let ranges = vec![(Included(30_u16), Unbounded), (Included(35), Excluded(38))];
+    let refs = vec![(Included(30_u16), Unbounded)];
+    assert_eq!(merge_ranges::<u16, Natural>(ranges), refs);
+}
+
+#[test]
+fn test_merkle_diff() {
+    let seed: u128 = random();
+    println!("test_merkle_diff {}", seed);
+    let mut rng = StdRnd::from_seed(seed.to_le_bytes());
+
+    let dir = std::env::temp_dir().join("test_merkle_diff");
+    let mut config = Config::new(dir.as_os_str(), "test_merkle_diff");
+    config.set_blocksize(1024, 1024, 1024).set_value_log(true).set_merkle(true);
+
+    let build = |config: &Config, name: &str, mdb: &OMap<u16, u64>| {
+        let mut config = config.clone();
+        config.name = name.to_string();
+        let mut b = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+        b.build_index(mdb.iter().unwrap(), NoBitmap, None).unwrap();
+        Index::<u16, u64, u64, NoBitmap>::open(&config.dir, name).unwrap()
+    };
+
+    let mdb = util::load_index(seed, 5_000, 0, 0, 0, None);
+    let mut a = build(&config, "test_merkle_diff-a", &mdb);
+    let mut b = build(&config, "test_merkle_diff-b", &mdb);
+    assert!(a.to_merkle_root().is_some());
+    assert_eq!(a.to_merkle_root(), b.to_merkle_root());
+    assert_eq!(a.diff_ranges(&mut b).unwrap(), vec![]);
+
+    // only the location of block hashes is persisted in meta-block.
+    let mut fd = std::fs::File::open(a.to_index_file_location()).unwrap();
+    let (metas, meta_fpos) = crate::robt::read_meta_block(&mut fd).unwrap();
+    assert_eq!(metas.to_version(crate::meta::MERKLE_HASHES), Some(2));
+    let region: HashesRegion = metas.decode(crate::meta::MERKLE_HASHES).unwrap().unwrap();
+    assert_eq!(region.fpos + region.length, meta_fpos);
+    assert_eq!(region.length % (8 + HASH_SIZE) as u64, 0);
+
+    for _ in 0..3 {
+        mdb.set(rng.gen::<u16>(), rng.gen::<u64>()).ok();
+    }
+    let mut c = build(&config, "test_merkle_diff-c", &mdb);
+    assert_ne!(a.to_merkle_root(), c.to_merkle_root());
+
+    let ranges = a.diff_ranges(&mut c).unwrap();
+    println!("test_merkle_diff ranges {}", ranges.len());
+    assert!(!ranges.is_empty());
+
+    let r = (Bound::<u16>::Unbounded, Bound::<u16>::Unbounded);
+    let xs: Vec<db::Entry<u16, u64, u64>> =
+        a.iter_versions(r).unwrap().map(|e| e.unwrap()).collect();
+    let ys: Vec<db::Entry<u16, u64, u64>> =
+        c.iter_versions(r).unwrap().map(|e| e.unwrap()).collect();
+    let covered = |key: &u16| ranges.iter().any(|r| r.contains(key));
+    for x in xs.iter() {
+        match ys.iter().find(|y| y.key == x.key) {
+            Some(y) if y == x => (),
+            _ => assert!(covered(&x.key), "{}", x.key),
+        }
+    }
+    for y in ys.iter() {
+        if !xs.iter().any(|x| x.key == y.key) {
+            assert!(covered(&y.key), "{}", y.key);
+        }
+    }
+
+    // index without merkle tree.
+    config.set_merkle(false);
+    let mut d = build(&config, "test_merkle_diff-d", &mdb);
+    assert_eq!(d.to_merkle_root(), None);
+    assert!(a.diff_ranges(&mut d).is_err());
+}
@@ -0,0 +1,230 @@
+//! Module implement the meta-block persisted at the tip of index file.
+//!
+//! Meta-block is a versioned list of named sections. Each section carries
+//! its own version and opaque data, readers lookup sections by name and
+//! skip sections they don't know about. This allows new features to add
+//! sections to the meta-block without breaking older readers.
+//!
+//! Index files built before this layout persist a positional list of
+//! [MetaItem], such files are still readable via [Metas::from_bytes].
+
+use mkit::{cbor::FromCbor, Cborize};
+
+use crate::{util, Error, Result};
+
+/// Current version of meta-block layout. Bump this only when the layout
+/// changes in an incompatible way, adding new sections does not require
+/// a version bump.
+pub(crate) const META_VERSION: u64 = 1;
+
+/// Section name for application supplied metadata.
+pub(crate) const APP_METADATA: &str = "app_metadata";
+/// Section name prefix for named application metadata, refer to
+/// [crate::db::Builder::put_app_metadata].
+pub(crate) const APP_METADATA_ITEM: &str = "app_metadata/";
+/// Section name for on-disk format version, refer to
+/// [crate::FORMAT_VERSION].
+pub(crate) const FORMAT: &str = "format_version";
+/// Section name for merkle root, refer to [crate::Config::set_merkle].
+pub(crate) const MERKLE_ROOT: &str = "merkle_root";
+/// Section name for block hashes in merkle tree. From version 2, section
+/// only locates the hashes, persisted in the index file just before the
+/// meta-block, refer to [crate::merkle::HashesRegion].
+pub(crate) const MERKLE_HASHES: &str = "merkle_hashes";
+/// Section name for index statistics, refer to [crate::Stats].
+pub(crate) const STATS: &str = "stats";
+/// Section name for bloom-filter.
+pub(crate) const BITMAP: &str = "bitmap";
+/// Section name for file-position of root block.
+pub(crate) const ROOT: &str = "root";
+/// Section name for robt finger-print.
+pub(crate) const MARKER: &str = "marker";
+/// Section name for range tombstones, refer to [crate::db::RangeTombstone].
+pub(crate) const RANGE_TOMBSTONES: &str = "range_tombstones";
+/// Section name for schema, refer to [crate::Schema].
+pub(crate) const SCHEMA: &str = "schema";
+
+/// Return the current version of data format for section `name`, None if
+/// section is not known to this version of `robt`. Bump a section's
+/// version when its data format changes in an incompatible way, readers
+/// reject known sections that are newer than what they understand.
+pub(crate) fn to_section_version(name: &str) -> Option<u64> {
+    match name {
+        FORMAT | APP_METADATA | STATS | BITMAP | ROOT | MARKER => Some(1),
+        RANGE_TOMBSTONES | SCHEMA | MERKLE_ROOT => Some(1),
+        MERKLE_HASHES => Some(2),
+        name if name.starts_with(APP_METADATA_ITEM) => Some(1),
+        _ => None,
+    }
+}
+
+/// Enumeration of meta items stored in older [Robt] index, where the
+/// meta-block is a positional list of meta items.
+///
+/// [Robt]: crate::db::Index
+#[derive(Clone, Debug, Cborize)]
+pub enum MetaItem {
+    /// Application supplied metadata, typically serialized and opaque to `robt`.
+    AppMetadata(Vec<u8>),
+    /// Contains index-statistics along with configuration values.
+    Stats(Vec<u8>),
+    /// Bloom-filter.
+    Bitmap(Vec<u8>),
+    /// File-position where the root block for the Btree starts.
+    Root(u64),
+    /// Finger print for robt.
+    Marker(Vec<u8>),
+    /// List of range tombstones, refer to [crate::db::RangeTombstone].
+    RangeTombstones(Vec<u8>),
+    /// Finger print for key-type, value-type and delta-type.
+    Schema(Vec<u8>),
+}
+
+impl MetaItem {
+    const ID: &'static str = "robt/metaitem/0.0.1";
+}
+
+/// Named section in meta-block.
+#[derive(Clone, Debug, Eq, PartialEq, Cborize)]
+pub struct MetaSection {
+    /// Name of the section, readers skip sections they don't know about.
+    pub name: String,
+    /// Version of the section's data format, sections converted from the
+    /// older positional layout are at version 0.
+    pub version: u64,
+    /// Section data, interpretation depends on the section.
+    pub data: Vec<u8>,
+}
+
+impl MetaSection {
+    const ID: &'static str = "robt/metasection/0.0.1";
+
+    /// Create a section at its current version, refer to
+    /// [to_section_version].
+    pub fn new(name: &str, data: Vec<u8>) -> MetaSection {
+        let version = to_section_version(name).unwrap_or(1);
+        MetaSection { name: name.to_string(), version, data }
+    }
+
+    fn new_legacy(name: &str, data: Vec<u8>) -> MetaSection {
+        MetaSection { name: name.to_string(), version: 0, data }
+    }
+
+    // fail if this section is newer than what this reader understands,
+    // unknown sections are skipped by readers.
+    fn check_version(&self) -> Result<()> {
+        match to_section_version(&self.name) {
+            Some(version) if self.version > version => {
+                let (name, v) = (&self.name, self.version);
+                err_at!(InvalidFile, msg: "unsupported section {} version {}", name, v)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Cborize)]
+struct MetaBlock {
+    version: u64,
+    sections: Vec<MetaSection>,
+}
+
+impl MetaBlock {
+    const ID: &'static str = "robt/metablock/0.0.1";
+}
+
+/// List of named sections, as read from, or to be written into, the
+/// meta-block.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Metas {
+    pub sections: Vec<MetaSection>,
+}
+
+impl Metas {
+    pub fn new(sections: Vec<MetaSection>) -> Metas {
+        Metas { sections }
+    }
+
+    /// Decode meta-block, in current layout or in the older positional
+    /// layout.
+    pub fn from_bytes(data: &[u8]) -> Result<Metas> {
+        if let Ok((block, _)) = util::from_cbor_bytes::<MetaBlock>(data) {
+            if block.version > META_VERSION {
+                let v = block.version;
+                err_at!(InvalidFile, msg: "unsupported meta-block version {}", v)?
+            }
+            for section in block.sections.iter() {
+                section.check_version()?
+            }
+            return Ok(Metas { sections: block.sections });
+        }
+
+        let items: Vec<MetaItem> = util::from_cbor_bytes(data)?.0;
+        let mut sections = vec![];
+        for item in items.into_iter() {
+            let section = match item {
+                MetaItem::AppMetadata(data) => {
+                    MetaSection::new_legacy(APP_METADATA, data)
+                }
+                MetaItem::Stats(data) => MetaSection::new_legacy(STATS, data),
+                MetaItem::Bitmap(data) => MetaSection::new_legacy(BITMAP, data),
+                MetaItem::Root(root) => {
+                    MetaSection::new_legacy(ROOT, util::into_cbor_bytes(root)?)
+                }
+                MetaItem::Marker(data) => MetaSection::new_legacy(MARKER, data),
+                MetaItem::RangeTombstones(data) => {
+                    MetaSection::new_legacy(RANGE_TOMBSTONES, data)
+                }
+                MetaItem::Schema(data) => MetaSection::new_legacy(SCHEMA, data),
+            };
+            sections.push(section);
+        }
+        Ok(Metas { sections })
+    }
+
+    /// Encode into meta-block, always in current layout.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let block = MetaBlock {
+            version: META_VERSION,
+            sections: self.sections.clone(),
+        };
+        util::into_cbor_bytes(block)
+    }
+
+    /// Return data for section `name`, None if section is missing.
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        let mut iter = self.sections.iter();
+        iter.find(|s| s.name == name).map(|s| s.data.as_slice())
+    }
+
+    /// Return version of section `name`, None if section is missing.
+    pub fn to_version(&self, name: &str) -> Option<u64> {
+        let mut iter = self.sections.iter();
+        iter.find(|s| s.name == name).map(|s| s.version)
+    }
+
+    /// Return named application metadata, as `(name, data)`, in the order
+    /// they were persisted.
+    pub fn to_app_metadata_items(&self) -> Vec<(String, Vec<u8>)> {
+        let iter = self.sections.iter().filter_map(|s| {
+            let name = s.name.strip_prefix(APP_METADATA_ITEM)?;
+            Some((name.to_string(), s.data.clone()))
+        });
+        iter.collect()
+    }
+
+    /// Decode section `name` as `T`, None if section is missing.
+    pub fn decode<T>(&self, name: &str) -> Result<Option<T>>
+    where
+        T: FromCbor,
+    {
+        match self.get(name) {
+            Some(data) => Ok(Some(util::from_cbor_bytes(data)?.0)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "meta_test.rs"]
+mod meta_test;
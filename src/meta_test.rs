@@ -0,0 +1,74 @@
+use super::*;
+
+#[test]
+fn test_metas() {
+    let sections = vec![
+        MetaSection::new(APP_METADATA, vec![1, 2, 3]),
+        MetaSection::new(ROOT, util::into_cbor_bytes(4096_u64).unwrap()),
+        MetaSection::new(MARKER, vec![]),
+    ];
+    let metas = Metas::from_bytes(&Metas::new(sections.clone()).to_bytes().unwrap());
+    let metas = metas.unwrap();
+
+    assert_eq!(metas.sections, sections);
+    assert_eq!(metas.get(APP_METADATA), Some(&[1, 2, 3][..]));
+    assert!(metas.get(MARKER).unwrap().is_empty());
+    assert_eq!(metas.decode::<u64>(ROOT).unwrap(), Some(4096));
+    assert_eq!(metas.get(SCHEMA), None);
+    assert_eq!(metas.decode::<u64>(SCHEMA).unwrap(), None);
+}
+
+#[test]
+fn test_metas_unknown() {
+    // sections added by newer writers are skipped.
+    let mut section = MetaSection::new("checksums", vec![0xff; 32]);
+    section.version = 10;
+    let sections = vec![section, MetaSection::new(APP_METADATA, vec![1, 2, 3])];
+    let data = Metas::new(sections).to_bytes().unwrap();
+
+    let metas = Metas::from_bytes(&data).unwrap();
+    assert_eq!(metas.get(APP_METADATA), Some(&[1, 2, 3][..]));
+
+    // known section, newer than what this reader understands, is rejected.
+    let mut section = MetaSection::new(STATS, vec![4, 5]);
+    assert_eq!(section.version, to_section_version(STATS).unwrap());
+    section.version += 1;
+    let data = Metas::new(vec![section]).to_bytes().unwrap();
+    match Metas::from_bytes(&data) {
+        Err(Error::InvalidFile(_, _)) => (),
+        res => panic!("unexpected {:?}", res),
+    }
+
+    // incompatible layout is rejected.
+    let block = MetaBlock { version: META_VERSION + 1, sections: vec![] };
+    let data = util::into_cbor_bytes(block).unwrap();
+    match Metas::from_bytes(&data) {
+        Err(Error::InvalidFile(_, _)) => (),
+        res => panic!("unexpected {:?}", res),
+    }
+}
+
+#[test]
+fn test_metas_legacy() {
+    let items = vec![
+        MetaItem::AppMetadata(vec![1, 2, 3]),
+        MetaItem::Stats(vec![4, 5]),
+        MetaItem::Bitmap(vec![]),
+        MetaItem::Root(4096),
+        MetaItem::Marker(vec![6]),
+    ];
+    let data = util::into_cbor_bytes(items).unwrap();
+
+    let metas = Metas::from_bytes(&data).unwrap();
+    assert_eq!(metas.get(APP_METADATA), Some(&[1, 2, 3][..]));
+    assert_eq!(metas.get(STATS), Some(&[4, 5][..]));
+    assert!(metas.get(BITMAP).unwrap().is_empty());
+    assert_eq!(metas.decode::<u64>(ROOT).unwrap(), Some(4096));
+    assert_eq!(metas.get(MARKER), Some(&[6][..]));
+    assert_eq!(metas.get(RANGE_TOMBSTONES), None);
+    assert_eq!(metas.get(SCHEMA), None);
+    assert!(metas.sections.iter().all(|s| s.version == 0));
+
+    let data = util::into_cbor_bytes(vec![1_u64, 2]).unwrap();
+    assert!(Metas::from_bytes(&data).is_err());
+}
@@ -0,0 +1,408 @@
+//! Module implement parallel build of leaf blocks.
+//!
+//! Input is supplied as segments of sorted entries, each segment carrying
+//! its position in the final key order. Worker threads encode segments
+//! into leaf blocks, along with their values and deltas, into temporary
+//! files, and gather the first key of every leaf block and the bitmap of
+//! keys. Subsequently the main thread stitches the leaf blocks in segment
+//! order into the index file and value-log file, while intermediate blocks
+//! are built on top of them, refer to [StitchZZ]. Leaf blocks are decoded
+//! on the main thread only when their value-log references are rebased.
+
+use mkit::{
+    cbor::{self, Cbor, FromCbor, IntoCbor},
+    db::{self, Bloom},
+};
+
+use std::{
+    cell::RefCell, cmp, collections::VecDeque, convert::TryFrom, ffi, fs, hash, io,
+    marker, mem, rc::Rc,
+};
+
+use crate::{
+    build::{BuildZZ, HashesRef, LeafIter},
+    comparator::Comparator,
+    config::Config,
+    entry::Entry,
+    flush::Flusher,
+    merkle,
+    scans::{BitmappedScan, BuildScan},
+    util, Error, Result, FORMAT_VERSION,
+};
+
+// Value-log references from worker threads are encoded relative to this
+// placeholder and rebased while stitching. File positions beyond 2^32 are
+// always encoded in 9 bytes, hence rebasing never grows a leaf block.
+const VLOG_BASE: u64 = 1 << 62;
+
+/// Number of entries in each segment, when a single input iterator is
+/// split across worker threads.
+pub const SEGMENT_SIZE: usize = 10_000;
+
+// Leaf block encoded by a worker thread.
+#[derive(Clone)]
+struct Leaf<K> {
+    // first key in the z-block.
+    key: K,
+    // number of entries in the z-block.
+    n_entries: u64,
+    // length of v-block for the z-block.
+    vlen: u64,
+}
+
+// Sequence of leaf blocks, for a segment of input entries.
+#[derive(Clone)]
+pub struct Segment<K> {
+    id: usize,
+    part: usize,
+    // file position of the first z-block in part's temporary index file.
+    zpos: u64,
+    // file position of the first v-block in part's temporary value-log file.
+    vpos: u64,
+    // leaf blocks are already encoded with final value-log references.
+    exact: bool,
+    leaves: VecDeque<Leaf<K>>,
+    // last key in the segment.
+    last_key: Option<K>,
+}
+
+// Output of a worker thread.
+pub struct Part<K, B> {
+    index_file: ffi::OsString,
+    vlog_file: Option<ffi::OsString>,
+    segments: Vec<Segment<K>>,
+    // bitmap of keys across all segments in this part.
+    pub bitmap: B,
+    pub seqno: u64,
+    pub n_count: u64,
+    pub n_deleted: u64,
+}
+
+impl<K, B> Part<K, B> {
+    // remove temporary files, ignore errors.
+    pub fn purge(&self) {
+        fs::remove_file(&self.index_file).ok();
+        if let Some(vlog_file) = self.vlog_file.as_ref() {
+            fs::remove_file(vlog_file).ok();
+        }
+    }
+}
+
+/// Encode segments of sorted entries into leaf blocks, into temporary
+/// files, adding their keys into `bitmap`. Segment with id 0 is encoded
+/// with final value-log references, starting from `vbase`.
+pub fn build_part<K, V, D, B, S, I>(
+    config: Config,
+    part: usize,
+    vbase: u64,
+    bitmap: B,
+    segments: S,
+) -> Result<Part<K, B>>
+where
+    K: Clone + hash::Hash + IntoCbor,
+    V: Clone + IntoCbor,
+    D: Clone + IntoCbor,
+    B: Bloom + Default,
+    S: Iterator<Item = (usize, I)>,
+    I: Iterator<Item = db::Entry<K, V, D>>,
+{
+    let (queue_size, rate) = (config.flush_queue_size, config.flush_rate);
+    let index_file = to_part_file(config.to_index_file_location(), part);
+    let vlog_file = match config.value_in_vlog || config.delta_ok {
+        true => Some(to_part_file(config.to_vlog_file_location(), part)),
+        false => None,
+    };
+
+    let iflush = {
+        let flusher = Flusher::new(&index_file, true, queue_size, rate)?;
+        Rc::new(RefCell::new(flusher))
+    };
+    let vflush = match vlog_file.as_ref() {
+        Some(file) => Rc::new(RefCell::new(Flusher::new(file, true, queue_size, rate)?)),
+        None => Rc::new(RefCell::new(Flusher::empty())),
+    };
+
+    let mut val = Part {
+        index_file,
+        vlog_file,
+        segments: vec![],
+        bitmap,
+        seqno: 0,
+        n_count: 0,
+        n_deleted: 0,
+    };
+
+    let flushers = (iflush, vflush);
+    let res = build_segments(&config, &mut val, part, vbase, segments, flushers);
+    if res.is_err() {
+        val.purge();
+    }
+    res.map(|_| val)
+}
+
+fn build_segments<K, V, D, B, S, I>(
+    config: &Config,
+    val: &mut Part<K, B>,
+    part: usize,
+    vbase: u64,
+    segments: S,
+    (iflush, vflush): (Rc<RefCell<Flusher>>, Rc<RefCell<Flusher>>),
+) -> Result<()>
+where
+    K: Clone + hash::Hash + IntoCbor,
+    V: Clone + IntoCbor,
+    D: Clone + IntoCbor,
+    B: Bloom + Default,
+    S: Iterator<Item = (usize, I)>,
+    I: Iterator<Item = db::Entry<K, V, D>>,
+{
+    for (id, iter) in segments {
+        let zpos = iflush.borrow().to_fpos().unwrap_or(0);
+        // without value-log, leaf blocks don't carry references.
+        let (exact, vpos, vshift) = match (id, vflush.borrow().to_fpos()) {
+            (_, None) => (true, 0, 0),
+            (0, Some(vpos)) => {
+                let vshift = err_at!(Fatal, vbase.checked_sub(vpos).ok_or("vshift"))?;
+                (true, vpos, vshift)
+            }
+            (_, Some(vpos)) => (false, vpos, VLOG_BASE - vpos),
+        };
+
+        let mut last_key = None;
+        let iter = iter.inspect(|entry| last_key = Some(entry.key.clone()));
+        let iter = {
+            let bitmap = mem::take(&mut val.bitmap);
+            let iter = BitmappedScan::<K, V, D, B, _>::new(iter, bitmap);
+            Rc::new(RefCell::new(BuildScan::new(iter, 0)))
+        };
+        let mut zz = {
+            let (iflush, vflush) = (Rc::clone(&iflush), Rc::clone(&vflush));
+            BuildZZ::new(config, iflush, vflush, Rc::clone(&iter), None, None)
+        };
+        zz.set_vshift(vshift);
+
+        let mut leaves = VecDeque::new();
+        loop {
+            let vstart = vflush.borrow().to_fpos().unwrap_or(0);
+            let n_start = zz.to_entries();
+            match zz.next() {
+                Some(Ok((key, _))) => {
+                    let vlen = vflush.borrow().to_fpos().unwrap_or(0) - vstart;
+                    let n_entries = zz.to_entries() - n_start;
+                    leaves.push_back(Leaf { key, n_entries, vlen });
+                }
+                Some(Err(err)) => return Err(err),
+                None => break,
+            }
+        }
+        mem::drop(zz);
+
+        let iter = Rc::try_unwrap(iter).ok().unwrap().into_inner();
+        let (_, seqno, n_count, n_deleted, _, iter) = iter.unwrap()?;
+        val.bitmap = iter.unwrap()?.0;
+        val.seqno = cmp::max(val.seqno, seqno);
+        val.n_count += n_count;
+        val.n_deleted += n_deleted;
+
+        let seg = Segment { id, part, zpos, vpos, exact, leaves, last_key };
+        val.segments.push(seg);
+    }
+
+    iflush.borrow_mut().close()?;
+    vflush.borrow_mut().close()?;
+
+    Ok(())
+}
+
+fn to_part_file(file: ffi::OsString, part: usize) -> ffi::OsString {
+    let mut file = file;
+    file.push(format!(".part-{}", part));
+    file
+}
+
+/// Iterator over leaf blocks from worker threads, in segment order. Each
+/// leaf block, along with its v-block, is copied into the final index file
+/// and value-log file, yielding the first key and file position of each
+/// z-block, same as [BuildZZ]. Leaf blocks are copied as is, unless their
+/// value-log references are to be rebased.
+pub struct StitchZZ<K, V, D, C> {
+    z_blocksize: usize,
+    iflush: Rc<RefCell<Flusher>>,
+    vflush: Rc<RefCell<Flusher>>,
+    hashes: HashesRef,
+
+    files: Vec<(fs::File, Option<fs::File>)>,
+    segments: VecDeque<Segment<K>>,
+    // value-log position in the final file, for the current segment.
+    vfinal: Option<u64>,
+    last_key: Option<K>,
+    n_entries: u64,
+
+    _val: marker::PhantomData<V>,
+    _dff: marker::PhantomData<D>,
+    _cmp: marker::PhantomData<C>,
+}
+
+impl<K, V, D, C> StitchZZ<K, V, D, C>
+where
+    K: Clone,
+{
+    pub fn new<B>(
+        config: &Config,
+        iflush: Rc<RefCell<Flusher>>,
+        vflush: Rc<RefCell<Flusher>>,
+        hashes: HashesRef,
+        parts: &[Part<K, B>],
+    ) -> Result<Self> {
+        let mut files = vec![];
+        let mut segments = vec![];
+        for part in parts.iter() {
+            let index = err_at!(IOError, fs::File::open(&part.index_file))?;
+            let vlog = match part.vlog_file.as_ref() {
+                Some(file) => Some(err_at!(IOError, fs::File::open(file))?),
+                None => None,
+            };
+            files.push((index, vlog));
+            segments.extend(part.segments.iter().cloned());
+        }
+        segments.sort_by_key(|seg| seg.id);
+
+        let val = StitchZZ {
+            z_blocksize: config.z_blocksize,
+            iflush,
+            vflush,
+            hashes,
+
+            files,
+            segments: segments.into_iter().collect(),
+            vfinal: None,
+            last_key: None,
+            n_entries: 0,
+
+            _val: marker::PhantomData,
+            _dff: marker::PhantomData,
+            _cmp: marker::PhantomData,
+        };
+
+        Ok(val)
+    }
+}
+
+impl<K, V, D, C> StitchZZ<K, V, D, C>
+where
+    K: Clone + FromCbor + IntoCbor,
+    V: FromCbor + IntoCbor,
+    D: FromCbor + IntoCbor,
+    C: Comparator<K>,
+{
+    fn stitch(&mut self) -> Result<Option<(K, u64)>> {
+        loop {
+            match self.segments.front() {
+                Some(seg) if seg.leaves.is_empty() => {
+                    self.segments.pop_front();
+                    self.vfinal = None;
+                }
+                Some(_) => break,
+                None => return Ok(None),
+            }
+        }
+        // references within a segment are relative to its first v-block.
+        let vfinal = match self.vfinal {
+            Some(vfinal) => vfinal,
+            None => {
+                let seg = self.segments.front().unwrap();
+                let first_key = &seg.leaves.front().unwrap().key;
+                if let Some(last_key) = self.last_key.as_ref() {
+                    if C::compare(last_key, first_key) != cmp::Ordering::Less {
+                        let id = seg.id;
+                        err_at!(Invalid, msg: "segment {} overlaps previous segment", id)?
+                    }
+                }
+                self.last_key = seg.last_key.clone();
+
+                let vfinal = self.vflush.borrow().to_fpos().unwrap_or(0);
+                self.vfinal = Some(vfinal);
+                vfinal
+            }
+        };
+        let seg = self.segments.front_mut().unwrap();
+        let leaf = seg.leaves.pop_front().unwrap();
+
+        let (index, vlog) = &mut self.files[seg.part];
+        let zblock = {
+            let seek = io::SeekFrom::Start(seg.zpos);
+            read_file!(index, seek, self.z_blocksize, "read part z-block")?
+        };
+        let vblock = match vlog {
+            Some(vlog) => {
+                let seek = io::SeekFrom::Start(seg.vpos);
+                read_file!(vlog, seek, leaf.vlen, "read part v-block")?
+            }
+            None => vec![],
+        };
+        seg.zpos += err_at!(FailConvert, u64::try_from(self.z_blocksize))?;
+        seg.vpos += leaf.vlen;
+        self.n_entries += leaf.n_entries;
+
+        let zblock = match seg.exact {
+            true => zblock,
+            false => {
+                let entries = Entry::<K, V, D>::decode_block(&zblock, FORMAT_VERSION)?;
+                let mut zblock = Vec::with_capacity(self.z_blocksize);
+                Cbor::Major4(cbor::Info::Indefinite, vec![]).encode(&mut zblock)?;
+                for entry in entries.into_iter() {
+                    let entry = entry.rebase(VLOG_BASE, vfinal);
+                    zblock.extend_from_slice(&util::into_cbor_bytes(entry)?);
+                }
+                let brk = util::into_cbor_bytes(cbor::SimpleValue::Break)?;
+                zblock.extend_from_slice(&brk);
+                if zblock.len() > self.z_blocksize {
+                    err_at!(Fatal, msg: "rebased z-block {}", zblock.len())?
+                }
+                zblock.resize(self.z_blocksize, 0);
+                zblock
+            }
+        };
+
+        let fpos = self.iflush.borrow().to_fpos().unwrap_or(0);
+        if let Some(hashes) = self.hashes.as_ref() {
+            let hash = merkle::leaf_hash(&zblock, &vblock);
+            hashes.borrow_mut().insert(fpos, hash);
+        }
+
+        self.vflush.borrow_mut().flush(vblock)?;
+        self.iflush.borrow_mut().flush(zblock)?;
+
+        Ok(Some((leaf.key, fpos)))
+    }
+}
+
+impl<K, V, D, C> Iterator for StitchZZ<K, V, D, C>
+where
+    K: Clone + FromCbor + IntoCbor,
+    V: FromCbor + IntoCbor,
+    D: FromCbor + IntoCbor,
+    C: Comparator<K>,
+{
+    type Item = Result<(K, u64)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.stitch().transpose()
+    }
+}
+
+impl<K, V, D, C> LeafIter<K> for StitchZZ<K, V, D, C>
+where
+    K: Clone + FromCbor + IntoCbor,
+    V: FromCbor + IntoCbor,
+    D: FromCbor + IntoCbor,
+    C: Comparator<K>,
+{
+    fn to_entries(&self) -> u64 {
+        self.n_entries
+    }
+}
+
+#[cfg(test)]
+#[path = "parallel_test.rs"]
+mod parallel_test;
@@ -0,0 +1,169 @@
+use mkit::{db::BuildIndex, nobitmap::NoBitmap};
+use ppom::mdb::OMap;
+use rand::{prelude::random, rngs::StdRnd, Rng, SeedableRng};
+
+use std::ops::Bound;
+
+use super::*;
+use crate::{
+    db::{Builder, Index},
+    util,
+};
+
+// split entries from `mdb` into `n` partitions of disjoint key ranges.
+fn to_partitions(mdb: &OMap<u16, u64>, n: usize) -> Vec<Vec<db::Entry<u16, u64, u64>>> {
+    let entries: Vec<db::Entry<u16, u64, u64>> = mdb.iter().unwrap().collect();
+    let size = (entries.len() / n) + 1;
+    let mut partitions: Vec<Vec<db::Entry<u16, u64, u64>>> =
+        entries.chunks(size).map(|c| c.to_vec()).collect();
+    partitions.resize(n, vec![]);
+    partitions
+}
+
+fn check_index(
+    index: &mut Index<u16, u64, u64, NoBitmap>,
+    mdb: &OMap<u16, u64>,
+    config: &Config,
+) {
+    let stats = index.validate().unwrap();
+    assert_eq!(stats.n_count, mdb.len() as u64);
+    assert_eq!(stats.n_deleted, mdb.deleted_count());
+    assert_eq!(stats.seqno, mdb.to_seqno());
+
+    let r = (Bound::<u16>::Unbounded, Bound::<u16>::Unbounded);
+    let mut iter = index.iter_versions(r).unwrap();
+    for mut e1 in mdb.iter().unwrap() {
+        if !config.delta_ok {
+            e1.deltas = vec![];
+        }
+        assert_eq!(e1, iter.next().unwrap().unwrap());
+    }
+    assert!(iter.next().is_none());
+}
+
+#[test]
+fn test_build_index_par() {
+    let seed: u128 = random();
+    println!("test_build_index_par {}", seed);
+    let mut rng = StdRnd::from_seed(seed.to_le_bytes());
+
+    let dir = std::env::temp_dir().join("test_build_index_par");
+    let name = "test_build_index_par";
+    let mut config = Config::new(dir.as_os_str(), name);
+    config
+        .set_blocksize(1024, 1024, 1024)
+        .set_value_log(rng.gen())
+        .set_delta(rng.gen())
+        .set_merkle(true)
+        .set_epoch(1);
+    println!("vlog:{} delta:{}", config.value_in_vlog, config.delta_ok);
+
+    let mdb = util::load_index(seed, 5_000, 1_000, 500, 100, None);
+    let n = 1 + (rng.gen::<usize>() % 8);
+
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    let partitions: Vec<_> =
+        to_partitions(&mdb, n).into_iter().map(|p| p.into_iter()).collect();
+    build.build_index_par(partitions, NoBitmap, None).unwrap();
+
+    let mut index = Index::<u16, u64, u64, NoBitmap>::open(&config.dir, name).unwrap();
+    check_index(&mut index, &mdb, &config);
+    assert!(index.to_merkle_root().is_some());
+
+    // temporary files are removed.
+    for part in 0..n {
+        let file = to_part_file(config.to_index_file_location(), part);
+        assert!(fs::metadata(&file).is_err(), "{:?}", file);
+    }
+
+    // incremental build, appending values to existing value-log.
+    if config.value_in_vlog || config.delta_ok {
+        let vlog = index.to_vlog_file_location();
+        let mut config = config.clone();
+        config.name = "test_build_index_par-incr".to_string();
+        let mut build =
+            Builder::<u16, u64, u64>::incremental(config.clone(), vlog, vec![]).unwrap();
+        let partitions: Vec<_> =
+            to_partitions(&mdb, n).into_iter().map(|p| p.into_iter()).collect();
+        build.build_index_par(partitions, NoBitmap, None).unwrap();
+
+        let mut index =
+            Index::<u16, u64, u64, NoBitmap>::open(&config.dir, &config.name).unwrap();
+        check_index(&mut index, &mdb, &config);
+    }
+
+    // partitions out of order.
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    let mut partitions = to_partitions(&mdb, 2);
+    partitions.reverse();
+    let partitions: Vec<_> = partitions.into_iter().map(|p| p.into_iter()).collect();
+    match build.build_index_par(partitions, NoBitmap, None) {
+        Err(Error::Invalid(_, _)) => (),
+        res => panic!("unexpected {:?}", res),
+    }
+}
+
+#[test]
+fn test_build_index_split() {
+    let seed: u128 = random();
+    println!("test_build_index_split {}", seed);
+    let mut rng = StdRnd::from_seed(seed.to_le_bytes());
+
+    let dir = std::env::temp_dir().join("test_build_index_split");
+    let name = "test_build_index_split";
+    let mut config = Config::new(dir.as_os_str(), name);
+    config
+        .set_blocksize(1024, 1024, 1024)
+        .set_value_log(rng.gen())
+        .set_delta(rng.gen());
+    println!("vlog:{} delta:{}", config.value_in_vlog, config.delta_ok);
+
+    let mdb = util::load_index(seed, 30_000, 10_000, 1_000, 1_000, None);
+    assert!(mdb.len() > SEGMENT_SIZE);
+    let n_threads = 1 + (rng.gen::<usize>() % 4);
+
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    build.build_index_split(mdb.iter().unwrap(), n_threads, NoBitmap, None).unwrap();
+
+    let mut index = Index::<u16, u64, u64, NoBitmap>::open(&config.dir, name).unwrap();
+    check_index(&mut index, &mdb, &config);
+
+    // same entries as a sequential build.
+    config.name = "test_build_index_split-seq".to_string();
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    build.build_index(mdb.iter().unwrap(), NoBitmap, None).unwrap();
+    let mut seq =
+        Index::<u16, u64, u64, NoBitmap>::open(&config.dir, &config.name).unwrap();
+
+    let r = (Bound::<u16>::Unbounded, Bound::<u16>::Unbounded);
+    let xs: Vec<_> = index.iter_versions(r).unwrap().map(|e| e.unwrap()).collect();
+    let ys: Vec<_> = seq.iter_versions(r).unwrap().map(|e| e.unwrap()).collect();
+    assert_eq!(xs, ys);
+}
+
+#[test]
+fn test_build_index_split_bitmap() {
+    use xorfilter::{BuildHasherDefault, Xor8};
+
+    let seed: u128 = random();
+    println!("test_build_index_split_bitmap {}", seed);
+
+    let dir = std::env::temp_dir().join("test_build_index_split_bitmap");
+    let name = "test_build_index_split_bitmap";
+    let mut config = Config::new(dir.as_os_str(), name);
+    config.set_blocksize(1024, 1024, 1024).set_value_log(true);
+
+    let mdb = util::load_index(seed, 30_000, 0, 0, 0, None);
+    assert!(mdb.len() > SEGMENT_SIZE);
+
+    // keys are added into per-thread bitmaps and merged while stitching.
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    let bitmap = Xor8::<BuildHasherDefault>::new();
+    build.build_index_split(mdb.iter().unwrap(), 3, bitmap, None).unwrap();
+
+    type B = Xor8<BuildHasherDefault>;
+    let index = Index::<u16, u64, u64, B>::open(&config.dir, name).unwrap();
+    for entry in mdb.iter().unwrap() {
+        assert!(index.as_bitmap().contains(&entry.key), "{}", entry.key);
+    }
+}
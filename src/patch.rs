@@ -0,0 +1,433 @@
+//! Module implement incremental build, reusing unchanged leaf blocks.
+//!
+//! Leaf blocks from the previous snapshot, whose key range does not
+//! overlap any of the changed keys, are copied verbatim into the new index
+//! file. Leaf blocks that overlap changed keys are merged with the changes
+//! and re-encoded, while new values and deltas are appended, in place, to
+//! the previous snapshot's value-log file. Intermediate blocks are built
+//! fresh, refer to [PatchZZ].
+
+use mkit::{
+    cbor::{self, Cbor, FromCbor, IntoCbor},
+    db::{self, Bloom},
+};
+
+use std::{
+    cell::RefCell, cmp, collections::VecDeque, convert::TryFrom, hash, io, iter, marker,
+    rc::Rc,
+};
+
+use crate::{
+    build::{ExpiryFn, HashesRef, LeafIter},
+    comparator::Comparator,
+    concat::{self, Source},
+    config::Config,
+    entry::Entry,
+    flush::Flusher,
+    merge::FoldFn,
+    merkle::{self, Hashes},
+    split, util, vlog, Error, Result,
+};
+
+// entry to be encoded into a re-encoded leaf block.
+enum Item<K, V, D> {
+    // entry from previous snapshot, encoded as is.
+    Old(Entry<K, V, D>),
+    // changed entry.
+    New(db::Entry<K, V, D>),
+}
+
+/// Iterator over leaf blocks for the new snapshot, merging leaf blocks from
+/// the previous snapshot with a sorted iterator of changes. Yields the first
+/// key and file position of each z-block, same as
+/// [BuildZZ][crate::build::BuildZZ].
+pub struct PatchZZ<K, V, D, B, C, I>
+where
+    I: Iterator<Item = db::Entry<K, V, D>>,
+{
+    z_blocksize: usize,
+    z_limit: usize,
+    v_blocksize: usize,
+    value_in_vlog: bool,
+    delta_ok: bool,
+    iflush: Rc<RefCell<Flusher>>,
+    vflush: Rc<RefCell<Flusher>>,
+    bitmap: Rc<RefCell<B>>,
+    hashes: HashesRef,
+    expiry: Option<ExpiryFn<K, V, D>>,
+    // fold replaced entry into the changed entry's deltas.
+    fold: Option<FoldFn<K, V, D>>,
+
+    source: Source,
+    // leaf hashes from previous snapshot, if it was built with merkle tree.
+    prev_hashes: Option<Hashes>,
+    leaves: VecDeque<(K, u64)>,
+    changes: iter::Peekable<I>,
+    items: VecDeque<Item<K, V, D>>,
+    last_change: Option<K>,
+
+    pub seqno: u64,
+    pub n_count: u64,
+    pub n_deleted: usize,
+    pub n_reused: usize,
+    n_entries: u64,
+
+    _cmp: marker::PhantomData<C>,
+}
+
+impl<K, V, D, B, C, I> PatchZZ<K, V, D, B, C, I>
+where
+    K: FromCbor,
+    V: FromCbor,
+    D: FromCbor,
+    I: Iterator<Item = db::Entry<K, V, D>>,
+{
+    /// Stats are initialized from previous snapshot, and adjusted for every
+    /// change that is merged.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        config: &Config,
+        (iflush, vflush): (Rc<RefCell<Flusher>>, Rc<RefCell<Flusher>>),
+        bitmap: Rc<RefCell<B>>,
+        hashes: HashesRef,
+        (expiry, fold): (Option<ExpiryFn<K, V, D>>, Option<FoldFn<K, V, D>>),
+        mut source: Source,
+        prev: (Option<Hashes>, u64, u64, usize),
+        changes: I,
+    ) -> Result<Self> {
+        let leaves = concat::to_leaves::<K, V, D>(&mut source)?;
+        let (prev_hashes, seqno, n_count, n_deleted) = prev;
+
+        // reused leaf blocks refer to values and deltas by their position in
+        // the previous value-log, new ones are appended to it in place.
+        if let Some(vlog) = source.vlog.as_ref() {
+            let len = err_at!(IOError, vlog.metadata())?.len();
+            if vflush.borrow().to_fpos() != Some(len) {
+                err_at!(Invalid, msg: "not appending to previous value-log")?
+            }
+        }
+
+        let val = PatchZZ {
+            z_blocksize: config.z_blocksize,
+            z_limit: config.to_z_limit(),
+            v_blocksize: config.v_blocksize,
+            value_in_vlog: config.value_in_vlog,
+            delta_ok: config.delta_ok,
+            iflush,
+            vflush,
+            bitmap,
+            hashes,
+            expiry,
+            fold,
+
+            source,
+            prev_hashes,
+            leaves,
+            changes: changes.peekable(),
+            items: VecDeque::new(),
+            last_change: None,
+
+            seqno,
+            n_count,
+            n_deleted,
+            n_reused: 0,
+            n_entries: 0,
+
+            _cmp: marker::PhantomData,
+        };
+
+        Ok(val)
+    }
+}
+
+impl<K, V, D, B, C, I> PatchZZ<K, V, D, B, C, I>
+where
+    K: Clone + hash::Hash + FromCbor + IntoCbor,
+    V: Clone + FromCbor + IntoCbor,
+    D: Clone + FromCbor + IntoCbor,
+    B: Bloom,
+    C: Comparator<K>,
+    I: Iterator<Item = db::Entry<K, V, D>>,
+{
+    fn patch(&mut self) -> Result<Option<(K, u64)>> {
+        if !self.items.is_empty() {
+            return self.encode().map(Some);
+        }
+
+        let (_, fpos) = match self.leaves.pop_front() {
+            Some(leaf) => leaf,
+            None => return Ok(None),
+        };
+        let zblock = {
+            let seek = io::SeekFrom::Start(fpos);
+            let index = &mut self.source.index;
+            read_file!(index, seek, self.z_blocksize, "read leaf block")?
+        };
+        let entries = Entry::<K, V, D>::decode_block(&zblock, self.source.format)?;
+
+        if self.is_touched() {
+            self.merge(entries)?;
+            self.encode().map(Some)
+        } else {
+            let first_key = match entries.first() {
+                Some(entry) => entry.to_key(),
+                None => err_at!(InvalidFile, msg: "empty z-block at {}", fpos)?,
+            };
+            {
+                let mut bitmap = self.bitmap.borrow_mut();
+                entries.iter().for_each(|e| bitmap.add_key(e.as_key()));
+            }
+
+            let new_fpos = self.iflush.borrow().to_fpos().unwrap_or(0);
+            if let Some(hashes) = self.hashes.clone() {
+                let hash = match self.prev_hashes.as_ref() {
+                    Some(prev) => prev.get(fpos)?,
+                    None => {
+                        let mut vbytes = vec![];
+                        for entry in entries.iter() {
+                            vbytes.extend_from_slice(&self.to_vbytes(entry)?);
+                        }
+                        merkle::leaf_hash(&zblock, &vbytes)
+                    }
+                };
+                hashes.borrow_mut().insert(new_fpos, hash);
+            }
+            self.iflush.borrow_mut().flush(zblock)?;
+            self.n_reused += 1;
+            self.n_entries += err_at!(FailConvert, u64::try_from(entries.len()))?;
+
+            Ok(Some((first_key, new_fpos)))
+        }
+    }
+
+    // whether next change sorts before the next leaf block, such changes are
+    // merged into the current leaf block.
+    fn is_touched(&mut self) -> bool {
+        match (self.changes.peek(), self.leaves.front()) {
+            (Some(change), Some((next, _))) => {
+                C::compare(&change.key, next) == cmp::Ordering::Less
+            }
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+
+    // merge entries from previous snapshot's leaf block with changes that
+    // sort before the next leaf block, for duplicate keys the entry with
+    // higher seqno wins.
+    fn merge(&mut self, entries: Vec<Entry<K, V, D>>) -> Result<()> {
+        let mut entries: VecDeque<Entry<K, V, D>> = entries.into_iter().collect();
+        loop {
+            let change = match self.is_touched() {
+                true => {
+                    let change = self.changes.next().unwrap();
+                    if let Some(last) = self.last_change.as_ref() {
+                        if C::compare(last, &change.key) != cmp::Ordering::Less {
+                            err_at!(Invalid, msg: "changes not sorted")?
+                        }
+                    }
+                    self.last_change = Some(change.key.clone());
+                    change
+                }
+                false => break,
+            };
+
+            while let Some(entry) = entries.front() {
+                match C::compare(entry.as_key(), &change.key) {
+                    cmp::Ordering::Less => {
+                        let entry = entries.pop_front().unwrap();
+                        self.push_old(entry);
+                    }
+                    _ => break,
+                }
+            }
+
+            let old = match entries.front() {
+                Some(entry) => match C::compare(entry.as_key(), &change.key) {
+                    cmp::Ordering::Equal => entries.pop_front(),
+                    _ => None,
+                },
+                None => None,
+            };
+            match old {
+                Some(old) => {
+                    let (deleted, seqno) = self.to_state(&old)?;
+                    if change.to_seqno() >= seqno {
+                        let n = usize::from(deleted);
+                        self.n_deleted = self.n_deleted.saturating_sub(n);
+                        let change = self.fold_old(change, old)?;
+                        self.push_new(change, false);
+                    } else {
+                        self.push_old(old);
+                    }
+                }
+                None => self.push_new(change, true),
+            }
+        }
+        entries.into_iter().for_each(|entry| self.push_old(entry));
+
+        Ok(())
+    }
+
+    // fold replaced entry from previous snapshot into the change, if
+    // configured, else the replaced entry is dropped.
+    fn fold_old(
+        &mut self,
+        change: db::Entry<K, V, D>,
+        old: Entry<K, V, D>,
+    ) -> Result<db::Entry<K, V, D>> {
+        match (self.fold, self.delta_ok) {
+            (Some(fold), true) => {
+                let old = match self.source.vlog.as_mut() {
+                    Some(vlog) => old.into_native(vlog, true)?,
+                    None => old,
+                };
+                Ok(fold(change, db::Entry::try_from(old)?))
+            }
+            (_, _) => Ok(change),
+        }
+    }
+
+    fn push_old(&mut self, entry: Entry<K, V, D>) {
+        self.bitmap.borrow_mut().add_key(entry.as_key());
+        self.items.push_back(Item::Old(entry));
+    }
+
+    fn push_new(&mut self, entry: db::Entry<K, V, D>, added: bool) {
+        self.bitmap.borrow_mut().add_key(&entry.key);
+        self.seqno = cmp::max(self.seqno, entry.to_seqno());
+        self.n_count += u64::from(added);
+        self.n_deleted += usize::from(entry.is_deleted());
+        self.items.push_back(Item::New(entry));
+    }
+
+    // whether entry from previous snapshot is deleted, along with its seqno.
+    fn to_state(&mut self, entry: &Entry<K, V, D>) -> Result<(bool, u64)> {
+        match entry {
+            Entry::ZZ { value: vlog::Value::N { value }, .. } => {
+                Ok(split::to_state(value))
+            }
+            Entry::ZZ { value: vlog::Value::R { fpos, length }, .. } => {
+                let data = match self.source.vlog.as_mut() {
+                    Some(vlog) => {
+                        let seek = io::SeekFrom::Start(*fpos);
+                        read_file!(vlog, seek, *length, "read value-log")?
+                    }
+                    None => err_at!(InvalidFile, msg: "value-log reference")?,
+                };
+                let value: db::Value<V> = util::from_cbor_bytes(&data)?.0;
+                Ok(split::to_state(&value))
+            }
+            _ => err_at!(InvalidFile, msg: "intermediate entry in z-block"),
+        }
+    }
+
+    // values and deltas referred by entry from previous value-log, to compute
+    // the leaf hash.
+    fn to_vbytes(&mut self, entry: &Entry<K, V, D>) -> Result<Vec<u8>> {
+        match (self.hashes.as_ref(), self.source.vlog.as_mut()) {
+            (Some(_), Some(vlog)) => concat::to_vbytes(vlog, entry),
+            (_, _) => Ok(vec![]),
+        }
+    }
+
+    // encode merged items into a leaf block.
+    fn encode(&mut self) -> Result<(K, u64)> {
+        let mut zblock = Vec::with_capacity(self.z_blocksize);
+        let mut vblock = Vec::with_capacity(self.v_blocksize);
+        let mut hbytes = vec![];
+        let block_size = self.z_blocksize.saturating_sub(1);
+
+        let mut first_key: Option<K> = None;
+
+        Cbor::Major4(cbor::Info::Indefinite, vec![]).encode(&mut zblock)?;
+
+        let mut vfpos = self.vflush.borrow().to_fpos().unwrap_or(0);
+        while let Some(item) = self.items.pop_front() {
+            let (key, ibytes, vbytes, old) = match &item {
+                Item::Old(entry) => {
+                    let ibytes = util::into_cbor_bytes(entry.clone())?;
+                    (entry.to_key(), ibytes, vec![], self.to_vbytes(entry)?)
+                }
+                Item::New(entry) => {
+                    let mut entry = entry.clone();
+                    if !self.delta_ok {
+                        entry.drain_deltas()
+                    }
+                    let mut e = Entry::<K, V, D>::from(entry.clone());
+                    e.set_expiry(self.expiry.as_ref().and_then(|f| f(&entry)));
+                    let (e, vbytes) = e.into_reference(vfpos, self.value_in_vlog)?;
+                    (entry.key, util::into_cbor_bytes(e)?, vbytes, vec![])
+                }
+            };
+
+            let limit = match first_key {
+                Some(_) => self.z_limit,
+                None => block_size,
+            };
+            if (zblock.len() + ibytes.len()) > limit {
+                if first_key.is_none() {
+                    err_at!(Invalid, msg: "entry {} exceeds z-block", ibytes.len())?
+                }
+                self.items.push_front(item);
+                break;
+            }
+            first_key.get_or_insert(key);
+            self.n_entries += 1;
+            zblock.extend_from_slice(&ibytes);
+            hbytes.extend_from_slice(&old);
+            hbytes.extend_from_slice(&vbytes);
+            vblock.extend_from_slice(&vbytes);
+            vfpos += err_at!(FailConvert, u64::try_from(vbytes.len()))?;
+        }
+
+        let brk = util::into_cbor_bytes(cbor::SimpleValue::Break)?;
+        zblock.extend_from_slice(&brk);
+        zblock.resize(self.z_blocksize, 0);
+
+        let fpos = self.iflush.borrow().to_fpos().unwrap_or(0);
+        if let Some(hashes) = self.hashes.as_ref() {
+            let hash = merkle::leaf_hash(&zblock, &hbytes);
+            hashes.borrow_mut().insert(fpos, hash);
+        }
+
+        self.vflush.borrow_mut().flush(vblock)?;
+        self.iflush.borrow_mut().flush(zblock)?;
+
+        Ok((first_key.unwrap(), fpos))
+    }
+}
+
+impl<K, V, D, B, C, I> Iterator for PatchZZ<K, V, D, B, C, I>
+where
+    K: Clone + hash::Hash + FromCbor + IntoCbor,
+    V: Clone + FromCbor + IntoCbor,
+    D: Clone + FromCbor + IntoCbor,
+    B: Bloom,
+    C: Comparator<K>,
+    I: Iterator<Item = db::Entry<K, V, D>>,
+{
+    type Item = Result<(K, u64)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.patch().transpose()
+    }
+}
+
+impl<K, V, D, B, C, I> LeafIter<K> for PatchZZ<K, V, D, B, C, I>
+where
+    K: Clone + hash::Hash + FromCbor + IntoCbor,
+    V: Clone + FromCbor + IntoCbor,
+    D: Clone + FromCbor + IntoCbor,
+    B: Bloom,
+    C: Comparator<K>,
+    I: Iterator<Item = db::Entry<K, V, D>>,
+{
+    fn to_entries(&self) -> u64 {
+        self.n_entries
+    }
+}
+
+#[cfg(test)]
+#[path = "patch_test.rs"]
+mod patch_test;
@@ -0,0 +1,187 @@
+use mkit::{db::BuildIndex, nobitmap::NoBitmap};
+use rand::{prelude::random, rngs::StdRnd, Rng, SeedableRng};
+
+use std::ops::Bound;
+
+use super::*;
+use crate::{
+    db::{Builder, Index},
+    Config,
+};
+
+#[test]
+fn test_build_index_patch() {
+    let seed: u128 = random();
+    println!("test_build_index_patch {}", seed);
+    let mut rng = StdRnd::from_seed(seed.to_le_bytes());
+
+    let dir = std::env::temp_dir().join("test_build_index_patch");
+    let mut config = Config::new(dir.as_os_str(), "test_build_index_patch");
+    config
+        .set_blocksize(1024, 1024, 1024)
+        .set_value_log(rng.gen())
+        .set_delta(rng.gen())
+        .set_merkle(rng.gen());
+    println!(
+        "vlog:{} delta:{} merkle:{}",
+        config.value_in_vlog, config.delta_ok, config.merkle
+    );
+
+    let mdb = crate::util::load_index(seed, 5_000, 1_000, 500, 100, None);
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    build.build_index(mdb.iter().unwrap(), NoBitmap, None).unwrap();
+    let mut prev =
+        Index::<u16, u64, u64, NoBitmap>::open(&config.dir, &config.name).unwrap();
+    let r = (Bound::<u16>::Unbounded, Bound::<u16>::Unbounded);
+    let olds: Vec<db::Entry<u16, u64, u64>> =
+        prev.iter_versions(r).unwrap().map(|e| e.unwrap()).collect();
+
+    // changes are updates and deletes, along with new keys.
+    let seqno = mdb.to_seqno();
+    let n_changes = rng.gen::<usize>() % 200;
+    for _ in 0..n_changes {
+        let key: u16 = rng.gen();
+        match rng.gen::<u8>() % 2 {
+            0 => {
+                mdb.set(key, rng.gen()).ok();
+            }
+            _ => {
+                mdb.delete(&key).ok();
+            }
+        }
+    }
+    let changes: Vec<db::Entry<u16, u64, u64>> =
+        mdb.iter().unwrap().filter(|e| e.to_seqno() > seqno).collect();
+
+    // new values and deltas are appended to previous value-log, in place.
+    let mut config = config.clone();
+    config.name = "test_build_index_patch-new".to_string();
+    let vlog = prev.to_vlog_file_location();
+    let vlen = vlog.as_ref().map(|vlog| std::fs::metadata(vlog).unwrap().len());
+    let mut build = {
+        let (config, vlog) = (config.clone(), vlog.clone());
+        Builder::<u16, u64, u64>::incremental(config, vlog, vec![]).unwrap()
+    };
+    build
+        .build_index_patch(&prev, changes.clone().into_iter(), NoBitmap, None)
+        .unwrap();
+
+    // previous snapshot is intact.
+    let name = "test_build_index_patch";
+    let mut prev = Index::<u16, u64, u64, NoBitmap>::open(dir.as_os_str(), name).unwrap();
+    prev.validate().unwrap();
+    let entries: Vec<db::Entry<u16, u64, u64>> =
+        prev.iter_versions(r).unwrap().map(|e| e.unwrap()).collect();
+    assert_eq!(entries, olds);
+
+    let mut index =
+        Index::<u16, u64, u64, NoBitmap>::open(&config.dir, &config.name).unwrap();
+    let stats = index.validate().unwrap();
+    assert_eq!(stats.n_count, mdb.len() as u64);
+    assert_eq!(stats.n_deleted, mdb.deleted_count());
+    assert_eq!(stats.seqno, mdb.to_seqno());
+    assert_eq!(index.to_merkle_root().is_some(), config.merkle);
+    assert_eq!(index.to_vlog_file_location(), vlog);
+    assert_eq!(index.to_stats().n_abytes, vlen.unwrap_or(0));
+
+    let mut iter = index.iter_versions(r).unwrap();
+    for mut e1 in mdb.iter().unwrap() {
+        if !config.delta_ok {
+            e1.deltas = vec![];
+        }
+        assert_eq!(e1, iter.next().unwrap().unwrap());
+    }
+    assert!(iter.next().is_none());
+
+    // unsorted changes.
+    if changes.len() > 1 {
+        config.name = "test_build_index_patch-unsorted".to_string();
+        let vlog = index.to_vlog_file_location();
+        let mut build =
+            Builder::<u16, u64, u64>::incremental(config.clone(), vlog, vec![]).unwrap();
+        let iter = changes.into_iter().rev();
+        match build.build_index_patch(&index, iter, NoBitmap, None) {
+            Err(Error::Invalid(_, _)) => (),
+            res => panic!("unexpected {:?}", res),
+        }
+    }
+
+    // value-log other than the previous one.
+    if config.value_in_vlog || config.delta_ok {
+        config.name = "test_build_index_patch-fresh".to_string();
+        let mut build =
+            Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+        match build.build_index_patch(&index, vec![].into_iter(), NoBitmap, None) {
+            Err(Error::Invalid(_, _)) => (),
+            res => panic!("unexpected {:?}", res),
+        }
+    }
+
+    // mismatched block size.
+    config.name = "test_build_index_patch-blocksize".to_string();
+    config.set_blocksize(2048, 1024, 1024);
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    match build.build_index_patch(&index, vec![].into_iter(), NoBitmap, None) {
+        Err(Error::Invalid(_, _)) => (),
+        res => panic!("unexpected {:?}", res),
+    }
+}
+
+#[test]
+fn test_build_index_patch_versions() {
+    let seed: u128 = random();
+    println!("test_build_index_patch_versions {}", seed);
+    let mut rng = StdRnd::from_seed(seed.to_le_bytes());
+
+    let dir = std::env::temp_dir().join("test_build_index_patch_versions");
+    let mut config = Config::new(dir.as_os_str(), "test_build_index_patch_versions");
+    config.set_blocksize(1024, 1024, 1024).set_value_log(rng.gen()).set_delta(true);
+    println!("vlog:{}", config.value_in_vlog);
+
+    let mdb = crate::util::load_index(seed, 5_000, 1_000, 500, 100, None);
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    build.build_index(mdb.iter().unwrap(), NoBitmap, None).unwrap();
+    let mut prev =
+        Index::<u16, u64, u64, NoBitmap>::open(&config.dir, &config.name).unwrap();
+
+    // changes without history, replacing existing keys.
+    let seqno = mdb.to_seqno();
+    let keys: Vec<u16> = mdb.iter().unwrap().map(|e| e.key).collect();
+    for _ in 0..100 {
+        let key = keys[rng.gen::<usize>() % keys.len()];
+        mdb.set(key, rng.gen()).ok();
+    }
+    let changes: Vec<db::Entry<u16, u64, u64>> = mdb
+        .iter()
+        .unwrap()
+        .filter(|e| e.to_seqno() > seqno)
+        .map(|mut e| {
+            e.deltas = vec![];
+            e
+        })
+        .collect();
+
+    config.name = "test_build_index_patch_versions-new".to_string();
+    let vlog = prev.to_vlog_file_location();
+    let mut build =
+        Builder::<u16, u64, u64>::incremental(config.clone(), vlog, vec![]).unwrap();
+    let iter = changes.clone().into_iter();
+    build.build_index_patch_versions(&prev, iter, NoBitmap, None).unwrap();
+
+    // replaced entries are folded into deltas.
+    let mut index =
+        Index::<u16, u64, u64, NoBitmap>::open(&config.dir, &config.name).unwrap();
+    index.validate().unwrap();
+    for change in changes.into_iter() {
+        let old = prev.get_versions(&change.key).unwrap();
+        let new = index.get_versions(&change.key).unwrap();
+        assert_eq!(new.to_seqno(), change.to_seqno());
+        assert_eq!(new.deltas.len(), old.deltas.len() + 1);
+        match &new.deltas[0] {
+            db::Delta::U { seqno, .. } | db::Delta::D { seqno } => {
+                assert_eq!(*seqno, old.to_seqno())
+            }
+        }
+        assert_eq!(new.deltas[1..].to_vec(), old.deltas);
+    }
+}
@@ -4,22 +4,42 @@ use mkit::{cbor::FromCbor, db};
 
 use std::{
     borrow::Borrow,
-    cmp, fmt, fs, io, marker,
+    cmp,
+    convert::TryFrom,
+    fmt, fs, io, marker,
     ops::{Bound, RangeBounds},
+    sync::Arc,
 };
 
-use crate::{config::Stats, entry::Entry, util, Error, Result};
+use crate::{
+    comparator::{self, Comparator, Natural},
+    config::Stats,
+    entry::Entry,
+    tombstone::{self, RangeTombstone},
+    Error, Result,
+};
+
+// btree is built with at most 30 levels, descending deeper than this implies
+// a corrupted index file, like a cycle in child references.
+pub(crate) const MAX_DEPTH: usize = 32;
 
-pub struct Reader<K, V, D> {
+pub struct Reader<K, V, D, C = Natural> {
     pub m_blocksize: usize,
     pub z_blocksize: usize,
+    /// On-disk format version of the index file, refer to
+    /// [FORMAT_VERSION][crate::FORMAT_VERSION].
+    pub format: u64,
     pub root: Vec<Entry<K, V, D>>,
+    pub expiry_clock: Option<u64>,
+    pub tombstones: Arc<Vec<RangeTombstone<K>>>,
 
     pub index: fs::File,
     pub vlog: Option<fs::File>,
+
+    _cmp: marker::PhantomData<C>,
 }
 
-impl<K, V, D> Drop for Reader<K, V, D> {
+impl<K, V, D, C> Drop for Reader<K, V, D, C> {
     fn drop(&mut self) {
         if let Err(err) = self.index.unlock() {
             error!( target: "robt", "fail to unlock reader lock for index: {}", err)
@@ -32,7 +52,7 @@ impl<K, V, D> Drop for Reader<K, V, D> {
     }
 }
 
-impl<K, V, D> Reader<K, V, D>
+impl<K, V, D, C> Reader<K, V, D, C>
 where
     K: FromCbor,
     V: FromCbor,
@@ -40,14 +60,14 @@ where
 {
     pub fn from_root(
         root: u64,
-        stats: &Stats,
+        (stats, format): (&Stats, u64),
         mut index: fs::File,
         vlog: Option<fs::File>,
     ) -> Result<Self> {
         let root: Vec<Entry<K, V, D>> = {
             let fpos = io::SeekFrom::Start(root);
             let block = read_file!(&mut index, fpos, stats.m_blocksize, "read block")?;
-            util::from_cbor_bytes(&block)?.0
+            Entry::decode_block(&block, format)?
         };
 
         err_at!(IOError, index.lock_shared())?;
@@ -58,27 +78,40 @@ where
         Ok(Reader {
             m_blocksize: stats.m_blocksize,
             z_blocksize: stats.z_blocksize,
+            format,
             root,
+            expiry_clock: None,
+            tombstones: Arc::new(vec![]),
 
             index,
             vlog,
+
+            _cmp: marker::PhantomData,
         })
     }
 
-    pub fn get<Q>(&mut self, ukey: &Q, versions: bool) -> Result<Entry<K, V, D>>
+    pub fn get<Q>(&mut self, ukey: &Q, versions: bool) -> Result<db::Entry<K, V, D>>
     where
         K: Clone + Borrow<Q>,
         V: Clone,
         D: Clone,
         Q: Ord,
+        C: Comparator<Q>,
     {
         let m_blocksize = self.m_blocksize;
         let z_blocksize = self.z_blocksize;
+        let format = self.format;
+        let expiry_clock = self.expiry_clock;
         let fd = &mut self.index;
 
         let mut es = self.root.clone();
+        let mut depth = 0;
         loop {
-            let off = match es.binary_search_by(|e| e.borrow_key().cmp(ukey)) {
+            depth += 1;
+            if depth > MAX_DEPTH {
+                break err_at!(InvalidFile, msg: "btree deeper than {}", MAX_DEPTH);
+            }
+            let off = match es.binary_search_by(|e| C::compare(e.borrow_key(), ukey)) {
                 Ok(off) => off,
                 Err(off) if off == 0 => break err_at!(KeyNotFound, msg: "missing key"),
                 Err(off) => off - 1,
@@ -87,16 +120,21 @@ where
                 Entry::MM { fpos, .. } => {
                     let fpos = io::SeekFrom::Start(fpos);
                     let block = read_file!(fd, fpos, m_blocksize, "read mm-block")?;
-                    util::from_cbor_bytes::<Vec<Entry<K, V, D>>>(&block)?.0
+                    Entry::<K, V, D>::decode_block(&block, format)?
                 }
                 Entry::MZ { fpos, .. } => {
                     let fpos = io::SeekFrom::Start(fpos);
                     let block = read_file!(fd, fpos, z_blocksize, "read mz-block")?;
-                    util::from_cbor_bytes::<Vec<Entry<K, V, D>>>(&block)?.0
+                    Entry::<K, V, D>::decode_block(&block, format)?
+                }
+                entry @ Entry::ZZ { .. } if entry.is_expired(expiry_clock) => {
+                    break err_at!(KeyNotFound, msg: "expired key");
                 }
-                Entry::ZZ { key, value, deltas } if key.borrow() == ukey => {
+                Entry::ZZ { key, value, deltas, expiry }
+                    if C::compare(key.borrow(), ukey) == cmp::Ordering::Equal =>
+                {
                     let deltas = if versions { deltas } else { Vec::default() };
-                    let mut entry = Entry::ZZ { key, value, deltas };
+                    let mut entry = Entry::ZZ { key, value, deltas, expiry };
                     let entry = match &mut self.vlog {
                         Some(fd) => entry.into_native(fd, versions)?,
                         None => {
@@ -104,6 +142,11 @@ where
                             entry
                         }
                     };
+                    let entry = db::Entry::try_from(entry)?;
+                    let tombstones = &self.tombstones;
+                    if tombstone::is_covered_by::<K, V, D, Q, C>(tombstones, &entry) {
+                        break err_at!(KeyNotFound, msg: "range deleted key");
+                    }
                     break Ok(entry);
                 }
                 _ => break err_at!(KeyNotFound, msg: "missing key"),
@@ -116,16 +159,17 @@ where
         range: R,
         reverse: bool,
         versions: bool,
-    ) -> Result<Iter<K, V, D>>
+    ) -> Result<Iter<K, V, D, C>>
     where
         K: Clone + Ord + Borrow<Q>,
         V: Clone,
         D: Clone,
         Q: Ord + ToOwned<Owned = K>,
         R: RangeBounds<Q>,
+        C: Comparator<Q> + Comparator<K>,
     {
         let (stack, bound) = if reverse {
-            let stack = self.rwd_stack(range.end_bound(), self.root.clone())?;
+            let stack = self.rwd_stack(range.end_bound(), self.root.clone(), 1)?;
             let bound: Bound<K> = match range.start_bound() {
                 Bound::Unbounded => Bound::Unbounded,
                 Bound::Included(q) => Bound::Included(q.to_owned()),
@@ -133,7 +177,7 @@ where
             };
             (stack, bound)
         } else {
-            let stack = self.fwd_stack(range.start_bound(), self.root.clone())?;
+            let stack = self.fwd_stack(range.start_bound(), self.root.clone(), 1)?;
             let bound: Bound<K> = match range.end_bound() {
                 Bound::Unbounded => Bound::Unbounded,
                 Bound::Included(q) => Bound::Included(q.to_owned()),
@@ -143,28 +187,20 @@ where
         };
         let mut iter = Iter::new(self, bound, stack, reverse, versions);
 
-        while let Some(item) = iter.next() {
+        while let Some(item) = iter.next_item() {
             match item {
-                Ok(entry) if reverse => {
-                    let key = entry.borrow_key();
-                    match range.end_bound() {
-                        Bound::Included(ekey) if key.gt(ekey) => (),
-                        Bound::Excluded(ekey) if key.ge(ekey) => (),
-                        _ => {
-                            iter.push(entry);
-                            break;
-                        }
+                Ok(item) if reverse => {
+                    let key = item.0.borrow_key();
+                    if comparator::before_end::<Q, C>(key, range.end_bound()) {
+                        iter.push(item);
+                        break;
                     }
                 }
-                Ok(entry) => {
-                    let key = entry.borrow_key();
-                    match range.start_bound() {
-                        Bound::Included(skey) if key.lt(skey) => (),
-                        Bound::Excluded(skey) if key.le(skey) => (),
-                        _ => {
-                            iter.push(entry);
-                            break;
-                        }
+                Ok(item) => {
+                    let key = item.0.borrow_key();
+                    if comparator::after_start::<Q, C>(key, range.start_bound()) {
+                        iter.push(item);
+                        break;
                     }
                 }
                 Err(err) => return Err(err),
@@ -178,22 +214,29 @@ where
         &mut self,
         sk: Bound<&Q>,
         block: Vec<Entry<K, V, D>>,
+        depth: usize,
     ) -> Result<Vec<Vec<Entry<K, V, D>>>>
     where
         K: Clone + Borrow<Q>,
         V: Clone,
         D: Clone,
         Q: Ord,
+        C: Comparator<Q>,
     {
+        if depth > MAX_DEPTH {
+            err_at!(InvalidFile, msg: "btree deeper than {}", MAX_DEPTH)?
+        }
+
+        let fcmp = |e: &Entry<K, V, D>| comparator::fcmp::<Q, C>(e.borrow_key(), sk);
         let (entry, rem) = match block.first().map(|e| e.is_zblock()) {
-            Some(false) => match block.binary_search_by(|e| fcmp(e.borrow_key(), sk)) {
+            Some(false) => match block.binary_search_by(fcmp) {
                 Ok(off) => (block[off].clone(), block[off + 1..].to_vec()),
                 Err(off) => {
                     let off = off.saturating_sub(1);
                     (block[off].clone(), block[off + 1..].to_vec())
                 }
             },
-            Some(true) => match block.binary_search_by(|e| fcmp(e.borrow_key(), sk)) {
+            Some(true) => match block.binary_search_by(fcmp) {
                 Ok(off) | Err(off) => {
                     return Ok(vec![block[off..].to_vec()]);
                 }
@@ -212,11 +255,11 @@ where
             Entry::MZ { fpos, .. } => {
                 read_file!(fd, io::SeekFrom::Start(fpos), z_blocksize, "read mz-block")?
             }
-            _ => unreachable!(),
+            Entry::ZZ { .. } => err_at!(InvalidFile, msg: "leaf entry in m-block")?,
         };
 
-        let block = util::from_cbor_bytes::<Vec<Entry<K, V, D>>>(&block)?.0;
-        let mut stack = self.fwd_stack(sk, block)?;
+        let block = Entry::<K, V, D>::decode_block(&block, self.format)?;
+        let mut stack = self.fwd_stack(sk, block, depth + 1)?;
         stack.insert(0, rem);
         Ok(stack)
     }
@@ -225,22 +268,29 @@ where
         &mut self,
         ek: Bound<&Q>,
         block: Vec<Entry<K, V, D>>,
+        depth: usize,
     ) -> Result<Vec<Vec<Entry<K, V, D>>>>
     where
         K: Clone + Borrow<Q>,
         V: Clone,
         D: Clone,
         Q: Ord,
+        C: Comparator<Q>,
     {
+        if depth > MAX_DEPTH {
+            err_at!(InvalidFile, msg: "btree deeper than {}", MAX_DEPTH)?
+        }
+
+        let rcmp = |e: &Entry<K, V, D>| comparator::rcmp::<Q, C>(e.borrow_key(), ek);
         let (entry, mut rem) = match block.first().map(|e| e.is_zblock()) {
-            Some(false) => match block.binary_search_by(|e| rcmp(e.borrow_key(), ek)) {
+            Some(false) => match block.binary_search_by(rcmp) {
                 Ok(off) => (block[off].clone(), block[..off].to_vec()),
                 Err(off) => {
                     let off = off.saturating_sub(1);
                     (block[off].clone(), block[..off].to_vec())
                 }
             },
-            Some(true) => match block.binary_search_by(|e| rcmp(e.borrow_key(), ek)) {
+            Some(true) => match block.binary_search_by(rcmp) {
                 Ok(off) | Err(off) => {
                     let off = cmp::min(off + 1, block.len());
                     let mut rem = block[..off].to_vec();
@@ -263,11 +313,11 @@ where
             Entry::MZ { fpos, .. } => {
                 read_file!(fd, io::SeekFrom::Start(fpos), z_blocksize, "read mz-block")?
             }
-            _ => unreachable!(),
+            Entry::ZZ { .. } => err_at!(InvalidFile, msg: "leaf entry in m-block")?,
         };
 
-        let block = util::from_cbor_bytes::<Vec<Entry<K, V, D>>>(&block)?.0;
-        let mut stack = self.rwd_stack(ek, block)?;
+        let block = Entry::<K, V, D>::decode_block(&block, self.format)?;
+        let mut stack = self.rwd_stack(ek, block, depth + 1)?;
         stack.insert(0, rem);
         Ok(stack)
     }
@@ -285,21 +335,21 @@ where
     }
 }
 
-pub struct Iter<'a, K, V, D> {
-    reader: &'a mut Reader<K, V, D>,
+pub struct Iter<'a, K, V, D, C = Natural> {
+    reader: &'a mut Reader<K, V, D, C>,
     stack: Vec<Vec<Entry<K, V, D>>>,
     reverse: bool,
     versions: bool,
-    entry: Option<db::Entry<K, V, D>>,
+    entry: Option<(db::Entry<K, V, D>, Option<u64>)>,
     bound: Bound<K>,
 
     _key: marker::PhantomData<K>,
     _val: marker::PhantomData<V>,
 }
 
-impl<'a, K, V, D> Iter<'a, K, V, D> {
+impl<'a, K, V, D, C> Iter<'a, K, V, D, C> {
     fn new(
-        r: &'a mut Reader<K, V, D>,
+        r: &'a mut Reader<K, V, D, C>,
         bound: Bound<K>,
         stack: Vec<Vec<Entry<K, V, D>>>,
         reverse: bool,
@@ -318,37 +368,30 @@ impl<'a, K, V, D> Iter<'a, K, V, D> {
         }
     }
 
-    fn push(&mut self, entry: db::Entry<K, V, D>) {
-        self.entry = Some(entry);
+    fn push(&mut self, item: (db::Entry<K, V, D>, Option<u64>)) {
+        self.entry = Some(item);
     }
 
-    fn till(&mut self, e: db::Entry<K, V, D>) -> Option<Result<db::Entry<K, V, D>>>
+    fn till(&mut self, key: &K) -> bool
     where
-        K: Ord,
+        C: Comparator<K>,
     {
-        let key = &e.key;
-
-        if self.reverse {
-            match &self.bound {
-                Bound::Unbounded => Some(Ok(e)),
-                Bound::Included(till) if key.ge(till) => Some(Ok(e)),
-                Bound::Excluded(till) if key.gt(till) => Some(Ok(e)),
-                _ => {
-                    self.stack.drain(..);
-                    None
-                }
-            }
+        let ok = if self.reverse {
+            comparator::after_start::<K, C>(key, self.bound.as_ref())
         } else {
-            match &self.bound {
-                Bound::Unbounded => Some(Ok(e)),
-                Bound::Included(till) if key.le(till) => Some(Ok(e)),
-                Bound::Excluded(till) if key.lt(till) => Some(Ok(e)),
-                _ => {
-                    self.stack.drain(..);
-                    None
-                }
-            }
+            comparator::before_end::<K, C>(key, self.bound.as_ref())
+        };
+
+        if !ok {
+            self.stack.drain(..);
         }
+        ok
+    }
+
+    // iterate over entries along with their expiry, used by compaction to
+    // carry the expiry over to the new snapshot.
+    pub(crate) fn with_expiry(self) -> ExpiryIter<'a, K, V, D, C> {
+        ExpiryIter { iter: self }
     }
 
     fn fetchzz(&mut self, mut entry: Entry<K, V, D>) -> Result<Entry<K, V, D>>
@@ -370,66 +413,100 @@ impl<'a, K, V, D> Iter<'a, K, V, D> {
     }
 }
 
-impl<'a, K, V, D> Iterator for Iter<'a, K, V, D>
+impl<'a, K, V, D, C> Iter<'a, K, V, D, C>
 where
     K: Ord + FromCbor,
     V: FromCbor,
     D: FromCbor,
+    C: Comparator<K>,
 {
-    type Item = Result<db::Entry<K, V, D>>;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        if let Some(entry) = self.entry.take() {
-            return Some(Ok(entry));
+    fn next_item(&mut self) -> Option<Result<(db::Entry<K, V, D>, Option<u64>)>> {
+        if let Some(item) = self.entry.take() {
+            return Some(Ok(item));
         }
 
-        let fd = &mut self.reader.index;
         let m_blocksize = self.reader.m_blocksize;
+        let format = self.reader.format;
+        let expiry_clock = self.reader.expiry_clock;
 
-        match self.stack.pop() {
-            Some(block) if block.is_empty() => self.next(),
-            Some(mut block) => match block.remove(0) {
-                entry @ Entry::ZZ { .. } => {
-                    self.stack.push(block);
-                    let entry = iter_result!(self.fetchzz(entry));
-                    self.till(entry.into())
-                }
-                Entry::MM { fpos, .. } | Entry::MZ { fpos, .. } => {
-                    self.stack.push(block);
-
-                    let mut entries = iter_result!(|| -> Result<Vec<Entry<K, V, D>>> {
-                        let fpos = io::SeekFrom::Start(fpos);
-                        let block = read_file!(fd, fpos, m_blocksize, "read mm-block")?;
-                        Ok(util::from_cbor_bytes(&block)?.0)
-                    }());
-                    if self.reverse {
-                        entries.reverse();
+        loop {
+            let fd = &mut self.reader.index;
+
+            match self.stack.pop() {
+                Some(block) if block.is_empty() => (),
+                Some(mut block) => match block.remove(0) {
+                    entry @ Entry::ZZ { .. } => {
+                        self.stack.push(block);
+                        if !self.till(entry.as_key()) {
+                            break None;
+                        } else if entry.is_expired(expiry_clock) {
+                            continue;
+                        }
+                        let expiry = entry.to_expiry();
+                        let entry = iter_result!(self.fetchzz(entry));
+                        let entry = iter_result!(db::Entry::try_from(entry));
+                        let tombstones = &self.reader.tombstones;
+                        if tombstone::is_covered_by::<K, V, D, K, C>(tombstones, &entry) {
+                            continue;
+                        }
+                        break Some(Ok((entry, expiry)));
                     }
-                    self.stack.push(entries);
-                    self.next()
-                }
-            },
-            None => None,
+                    Entry::MM { fpos, .. } | Entry::MZ { fpos, .. } => {
+                        self.stack.push(block);
+                        if self.stack.len() >= MAX_DEPTH {
+                            self.stack.drain(..);
+                            let err = err_at!(InvalidFile, msg: "depth > {}", MAX_DEPTH);
+                            break Some(err);
+                        }
+
+                        let mut entries =
+                            iter_result!(|| -> Result<Vec<Entry<K, V, D>>> {
+                                let fpos = io::SeekFrom::Start(fpos);
+                                let block =
+                                    read_file!(fd, fpos, m_blocksize, "read mm-block")?;
+                                Entry::decode_block(&block, format)
+                            }());
+                        if self.reverse {
+                            entries.reverse();
+                        }
+                        self.stack.push(entries);
+                    }
+                },
+                None => break None,
+            }
         }
     }
 }
 
-fn fcmp<Q>(key: &Q, skey: Bound<&Q>) -> cmp::Ordering
+impl<'a, K, V, D, C> Iterator for Iter<'a, K, V, D, C>
 where
-    Q: Ord,
+    K: Ord + FromCbor,
+    V: FromCbor,
+    D: FromCbor,
+    C: Comparator<K>,
 {
-    match skey {
-        Bound::Unbounded => cmp::Ordering::Greater,
-        Bound::Included(skey) | Bound::Excluded(skey) => key.cmp(skey),
+    type Item = Result<db::Entry<K, V, D>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.next_item()?.map(|(entry, _)| entry))
     }
 }
 
-fn rcmp<Q>(key: &Q, ekey: Bound<&Q>) -> cmp::Ordering
+// Iterator over entries along with their expiry, refer to Iter::with_expiry.
+pub struct ExpiryIter<'a, K, V, D, C = Natural> {
+    iter: Iter<'a, K, V, D, C>,
+}
+
+impl<'a, K, V, D, C> Iterator for ExpiryIter<'a, K, V, D, C>
 where
-    Q: Ord,
+    K: Ord + FromCbor,
+    V: FromCbor,
+    D: FromCbor,
+    C: Comparator<K>,
 {
-    match ekey {
-        Bound::Unbounded => cmp::Ordering::Less,
-        Bound::Included(ekey) | Bound::Excluded(ekey) => key.cmp(ekey),
+    type Item = Result<(db::Entry<K, V, D>, Option<u64>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next_item()
     }
 }
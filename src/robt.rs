@@ -3,15 +3,16 @@ use log::debug;
 use mkit::{
     self,
     cbor::{FromCbor, IntoCbor},
+    data::Diff,
     db::Bloom,
     db::{self, BuildIndex},
-    Cborize,
 };
 
 use std::{
     borrow::Borrow,
     cell::RefCell,
     cmp,
+    collections::{BTreeMap, VecDeque},
     convert::{TryFrom, TryInto},
     ffi, fmt, fs,
     hash::Hash,
@@ -19,18 +20,34 @@ use std::{
     ops::{Bound, RangeBounds},
     path,
     rc::Rc,
-    sync::Arc,
+    sync::{atomic::AtomicBool, mpsc, Arc},
+    thread, time,
 };
 
 use crate::{
-    build,
-    config::{to_index_file, to_vlog_file, Config, Stats},
+    build::{self, Progress},
+    checkpoint::{self, Checkpointer},
+    comparator::{Comparator, Natural},
+    concat,
+    config::{to_checkpoint_file, to_index_file, to_vlog_file, Config, Stats},
     files::{IndexFileName, VlogFileName},
     flush::Flusher,
+    header::{self, Header, FORMAT_VERSION},
     marker::ROOT_MARKER,
+    merge::{self, MergeScan},
+    merkle::{self, Hashes, HASH_SIZE},
+    meta::{self, MetaSection, Metas},
+    parallel::{self, SEGMENT_SIZE},
+    patch,
     reader::{Iter, Reader},
-    scans::{BitmappedScan, BuildScan, CompactScan},
-    util, Error, Result,
+    scans::{BitmappedScan, BuildScan, CompactScan, FailScan, ScanItem},
+    schema::Schema,
+    sort::Sorter,
+    split,
+    tombstone::RangeTombstone,
+    util,
+    validate::{DeepValidator, ValidateReport},
+    Error, Result,
 };
 
 /// Marker block size, not to be tampered with.
@@ -39,7 +56,7 @@ const MARKER_BLOCK_SIZE: usize = 1024 * 4;
 /// Build an immutable read-only btree index from an iterator.
 ///
 /// Refer to package documentation for typical work-flow.
-pub struct Builder<K, V, D> {
+pub struct Builder<K, V, D, C = Natural> {
     // configuration
     config: Config,
     // active values
@@ -47,48 +64,75 @@ pub struct Builder<K, V, D> {
     vflush: Rc<RefCell<Flusher>>,
     // final result to be persisted
     app_meta: Vec<u8>,
+    app_items: BTreeMap<String, Vec<u8>>,
     stats: Stats,
     root: u64,
+    expiry: Option<build::ExpiryFn<K, V, D>>,
+    tombstones: Vec<RangeTombstone<K>>,
+    schema: Schema,
+    hashes: build::HashesRef,
+    observer: Option<build::ObserverFn>,
+    cancel: Option<Arc<AtomicBool>>,
+    checkpoint: Option<build::CheckpointRef<K>>,
+    // last checkpoint, to complete a resumed build.
+    resume: Option<checkpoint::Checkpoint<K>>,
 
     _key: marker::PhantomData<K>,
     _val: marker::PhantomData<V>,
     _dff: marker::PhantomData<D>,
+    _cmp: marker::PhantomData<C>,
 }
 
-impl<K, V, D> Builder<K, V, D> {
+impl<K, V, D> Builder<K, V, D>
+where
+    K: Ord,
+{
     /// Build a fresh index, using configuration and snapshot specific
     /// meta-data.
     pub fn initial(config: Config, meta: Vec<u8>) -> Result<Self> {
-        let queue_size = config.flush_queue_size;
-        let iflush = {
-            let file_path = to_index_file(&config.dir, &config.name);
-            Rc::new(RefCell::new(Flusher::new(&file_path, true, queue_size)?))
-        };
+        Self::initial_by(config, meta)
+    }
+
+    /// Build an incremental index on top of an existing index. Refer
+    /// to [Builder::incremental_by] for details.
+    pub fn incremental(
+        config: Config,
+        vlog: Option<ffi::OsString>,
+        meta: Vec<u8>,
+    ) -> Result<Self> {
+        Self::incremental_by(config, vlog, meta)
+    }
+}
+
+impl<K, V, D, C> Builder<K, V, D, C>
+where
+    C: Comparator<K>,
+{
+    /// Same as [Builder::initial], but order keys using comparator `C`.
+    /// Entries from the iterator are expected to be sorted using the
+    /// same comparator.
+    pub fn initial_by(config: Config, meta: Vec<u8>) -> Result<Self> {
+        config.validate()?;
+
+        let (queue_size, rate) = (config.flush_queue_size, config.flush_rate);
+        let iflush = new_index_flusher(&config)?;
         let (vflush, vlog_file) = if config.value_in_vlog || config.delta_ok {
             let file_path = to_vlog_file(&config.dir, &config.name);
-            (
-                Rc::new(RefCell::new(Flusher::new(&file_path, true, queue_size)?)),
-                Some(file_path),
-            )
+            (Flusher::new(&file_path, true, queue_size, rate)?, Some(file_path))
         } else {
-            (Rc::new(RefCell::new(Flusher::empty())), None)
+            (Flusher::empty(), None)
         };
 
-        let mut stats: Stats = config.clone().into();
-        stats.vlog_file = vlog_file;
-
-        let val = Builder {
+        let mut val = Self::from_flushers(
             config,
-            iflush,
-            vflush,
-
-            app_meta: meta,
-            stats,
-            root: u64::default(),
-
-            _key: marker::PhantomData,
-            _val: marker::PhantomData,
-            _dff: marker::PhantomData,
+            meta,
+            (iflush, vflush),
+            vlog_file,
+            Hashes::default(),
+        );
+        val.checkpoint = {
+            let n_abytes = val.vflush.borrow().to_fpos().unwrap_or(0);
+            new_checkpoint(&val.config, (val.stats.vlog_file.clone(), n_abytes))?
         };
 
         Ok(val)
@@ -100,48 +144,208 @@ impl<K, V, D> Builder<K, V, D> {
     /// form the iterator can hold reference, as `{fpos, length}` to values
     /// and deltas within a value-log file. Instead of creating a fresh
     /// value-log file, incremental build will serialize values and deltas
-    /// into supplied `vlog` file in append only fashion.
-    pub fn incremental(
+    /// into supplied `vlog` file in append only fashion. If `vlog` is the
+    /// value-log location for this index, refer to
+    /// [Config::to_vlog_file_location], it is created afresh.
+    pub fn incremental_by(
         config: Config,
         vlog: Option<ffi::OsString>,
         meta: Vec<u8>,
     ) -> Result<Self> {
-        let queue_size = config.flush_queue_size;
+        config.validate_incremental()?;
+
+        let (queue_size, rate) = (config.flush_queue_size, config.flush_rate);
+        let iflush = new_index_flusher(&config)?;
+        let (vflush, vlog_file) = match vlog {
+            Some(vlog) if config.value_in_vlog || config.delta_ok => {
+                let own = to_vlog_file(&config.dir, &config.name);
+                let create = vlog == own || fs::metadata(&vlog).is_err();
+                (Flusher::new(&vlog, create, queue_size, rate)?, Some(vlog))
+            }
+            Some(_) => err_at!(Invalid, msg: "vlog not required")?,
+            None => (Flusher::empty(), None),
+        };
+
+        let mut val = Self::from_flushers(
+            config,
+            meta,
+            (iflush, vflush),
+            vlog_file,
+            Hashes::default(),
+        );
+        val.checkpoint = {
+            let n_abytes = val.vflush.borrow().to_fpos().unwrap_or(0);
+            new_checkpoint(&val.config, (val.stats.vlog_file.clone(), n_abytes))?
+        };
+
+        Ok(val)
+    }
+
+    /// Resume a build that died midway, from its last checkpoint, refer
+    /// to [Config::set_checkpoint]. `config` and `meta` shall be the same
+    /// as that of the interrupted build. Index file and value-log file are
+    /// truncated to the last checkpoint, and the build shall be completed
+    /// using [Builder::build_index_resume].
+    ///
+    /// Named application metadata, range tombstones and schema-id are
+    /// restored from the checkpoint. Expiry function, observer and cancel
+    /// token are not persisted, callers shall set them again, if any.
+    pub fn resume(config: Config, meta: Vec<u8>) -> Result<Self>
+    where
+        K: FromCbor,
+    {
+        config.validate_incremental()?;
+
+        let (checkpointer, mut last) = {
+            let file = to_checkpoint_file(&config.dir, &config.name);
+            let every = config.checkpoint.unwrap_or(usize::MAX);
+            Checkpointer::<K>::load(&file, every)?
+        };
+
+        let (queue_size, rate) = (config.flush_queue_size, config.flush_rate);
         let iflush = {
             let file_path = to_index_file(&config.dir, &config.name);
-            Rc::new(RefCell::new(Flusher::new(&file_path, true, queue_size)?))
+            truncate_file(&file_path, last.ipos)?;
+            Flusher::new(&file_path, false, queue_size, rate)?
         };
-        let (vflush, vlog_file) = match vlog {
-            Some(vlog) if config.value_in_vlog || config.delta_ok => (
-                Rc::new(RefCell::new(Flusher::new(&vlog, true, queue_size)?)),
-                Some(vlog),
-            ),
-            Some(_) => err_at!(Invalid, msg: "vlog not required")?,
-            None => (Rc::new(RefCell::new(Flusher::empty())), None),
+        let vflush = match last.vlog_file.as_ref() {
+            Some(vlog) => {
+                truncate_file(vlog, last.vpos)?;
+                Flusher::new(vlog, false, queue_size, rate)?
+            }
+            None => Flusher::empty(),
         };
 
+        let (flushers, vlog_file) = ((iflush, vflush), last.vlog_file.clone());
+        let hashes = match config.merkle {
+            true => Hashes::from_bytes(&last.hashes)?,
+            false => Hashes::default(),
+        };
+        let mut val = Self::from_flushers(config, meta, flushers, vlog_file, hashes);
+        val.stats.n_abytes = last.n_abytes;
+        let items = mem::take(&mut last.app_items).into_iter();
+        val.app_items = items.map(|s| (s.name, s.data)).collect();
+        val.tombstones = mem::take(&mut last.tombstones);
+        val.schema.id = last.schema_id.take();
+        val.checkpoint = Some(Rc::new(RefCell::new(checkpointer)));
+        val.resume = Some(last);
+
+        Ok(val)
+    }
+
+    // common construction for initial, incremental and resumed builds,
+    // checkpoint, if any, is set by the caller.
+    fn from_flushers(
+        config: Config,
+        meta: Vec<u8>,
+        (iflush, vflush): (Flusher, Flusher),
+        vlog_file: Option<ffi::OsString>,
+        hashes: Hashes,
+    ) -> Self {
         let mut stats: Stats = config.clone().into();
         stats.vlog_file = vlog_file;
+        stats.comparator = C::NAME.to_string();
+        let hashes = match config.merkle {
+            true => Some(Rc::new(RefCell::new(hashes))),
+            false => None,
+        };
 
-        let val = Builder {
+        Builder {
             config,
-            iflush,
-            vflush,
+            iflush: Rc::new(RefCell::new(iflush)),
+            vflush: Rc::new(RefCell::new(vflush)),
 
             app_meta: meta,
+            app_items: BTreeMap::new(),
             stats,
             root: u64::default(),
+            expiry: None,
+            tombstones: Vec::default(),
+            schema: Schema::new::<K, V, D>(),
+            hashes,
+            observer: None,
+            cancel: None,
+            checkpoint: None,
+            resume: None,
 
             _key: marker::PhantomData,
             _val: marker::PhantomData,
             _dff: marker::PhantomData,
-        };
+            _cmp: marker::PhantomData,
+        }
+    }
 
-        Ok(val)
+    /// Set a function to compute expiry for each entry, as nanoseconds
+    /// from UNIX EPOCH. Computed expiry is persisted along with the entry
+    /// in leaf-node. Refer to [Index::set_expiry_clock] for details.
+    pub fn set_expiry<F>(&mut self, expiry: F) -> &mut Self
+    where
+        F: 'static + Fn(&db::Entry<K, V, D>) -> Option<u64>,
+    {
+        self.expiry = Some(Rc::new(expiry));
+        self
+    }
+
+    /// Set a function to observe the build's progress, called after every
+    /// leaf block is flushed, refer to [Progress] for details.
+    pub fn set_observer<F>(&mut self, observer: F) -> &mut Self
+    where
+        F: 'static + Fn(&Progress),
+    {
+        self.observer = Some(Rc::new(observer));
+        self
+    }
+
+    /// Set a token to cancel the build. Token is checked between leaf
+    /// blocks, and once it is set, build fails with `Cancelled` error and
+    /// removes the partially built index file, along with the value-log
+    /// file created by this builder.
+    pub fn set_cancel(&mut self, cancel: Arc<AtomicBool>) -> &mut Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Delete all entries within `range`, whose seqno is less than or equal
+    /// to `seqno`. Range tombstones are persisted in the meta-block and
+    /// apply to entries in this index, as well as older snapshots.
+    pub fn delete_range<R>(&mut self, range: R, seqno: u64) -> &mut Self
+    where
+        K: Clone,
+        R: RangeBounds<K>,
+    {
+        self.tombstones.push(RangeTombstone::new(range, seqno));
+        self
+    }
+
+    /// Persist a named application metadata along with the index, in
+    /// addition to the opaque meta-data supplied while creating the builder.
+    /// Putting the same name again shall replace the earlier value. Refer
+    /// to [Index::get_app_metadata] for details.
+    pub fn put_app_metadata<T>(&mut self, name: &str, value: T) -> Result<&mut Self>
+    where
+        T: IntoCbor,
+    {
+        let data = util::into_cbor_bytes(value)?;
+        self.app_items.insert(name.to_string(), data);
+        Ok(self)
+    }
+
+    // carry over named application metadata, as serialized bytes.
+    pub(crate) fn set_app_metadata_items(&mut self, items: Vec<(String, Vec<u8>)>) {
+        self.app_items.extend(items)
+    }
+
+    /// Set an application specific, stable, schema-id for key-type,
+    /// value-type and delta-type. Schema-id is persisted along with the
+    /// index, use [Index::verify_schema_id] to verify it after opening the
+    /// index.
+    pub fn set_schema_id(&mut self, id: &str) -> &mut Self {
+        self.schema.id = Some(id.to_string());
+        self
     }
 }
 
-impl<K, V, D, B> BuildIndex<K, V, D, B> for Builder<K, V, D>
+impl<K, V, D, B, C> BuildIndex<K, V, D, B> for Builder<K, V, D, C>
 where
     K: Clone + Hash + IntoCbor,
     V: Clone + IntoCbor,
@@ -153,6 +357,72 @@ where
     fn build_index<I>(&mut self, iter: I, bitmap: B, seqno: Option<u64>) -> Result<()>
     where
         I: Iterator<Item = db::Entry<K, V, D>>,
+    {
+        self.build_items(iter, bitmap, seqno)
+    }
+}
+
+impl<K, V, D, C> Builder<K, V, D, C>
+where
+    K: Clone + Hash + IntoCbor,
+    V: Clone + IntoCbor,
+    D: Clone + IntoCbor,
+{
+    // same as build_index, except that items can carry their own expiry,
+    // refer to [ScanItem].
+    pub(crate) fn build_items<I, B>(
+        &mut self,
+        iter: I,
+        bitmap: B,
+        seqno: Option<u64>,
+    ) -> Result<()>
+    where
+        I: Iterator,
+        I::Item: ScanItem<K, V, D>,
+        B: Bloom,
+    {
+        let bitmap = self.build_scan(iter, bitmap)?;
+
+        self.build_flush(err_at!(Fatal, bitmap.to_bytes())?, seqno)?;
+
+        Ok(())
+    }
+
+    // same as build_items, except that items are supplied via [FailScan],
+    // whose error, if any, is checked before finalizing the index. On error
+    // partially built files are removed.
+    pub(crate) fn build_checked<I, B>(
+        &mut self,
+        iter: I,
+        failed: Rc<RefCell<Option<Error>>>,
+        bitmap: B,
+        seqno: Option<u64>,
+    ) -> Result<()>
+    where
+        I: Iterator,
+        I::Item: ScanItem<K, V, D>,
+        B: Bloom,
+    {
+        let res = self.build_scan(iter, bitmap);
+        let failed = failed.borrow_mut().take();
+        match (res, failed) {
+            (_, Some(err)) | (Err(err), None) => {
+                self.purge();
+                Err(err)
+            }
+            (Ok(bitmap), None) => {
+                self.build_flush(err_at!(Fatal, bitmap.to_bytes())?, seqno)?;
+                Ok(())
+            }
+        }
+    }
+
+    // build leaf blocks and intermediate blocks, return the bitmap.
+    fn build_scan<I, B>(&mut self, iter: I, bitmap: B) -> Result<B>
+    where
+        I: Iterator,
+        I::Item: ScanItem<K, V, D>,
+        B: Bloom,
     {
         let iter = {
             let iter = BitmappedScan::<K, V, D, B, I>::new(iter, bitmap);
@@ -163,20 +433,614 @@ where
 
         let (bitmap, _) = iter.unwrap()?;
 
+        Ok(bitmap)
+    }
+}
+
+impl<K, V, D, C> Builder<K, V, D, C>
+where
+    K: 'static + Send + Clone + Hash + FromCbor + IntoCbor,
+    V: 'static + Send + Clone + FromCbor + IntoCbor,
+    D: 'static + Send + Clone + FromCbor + IntoCbor,
+    C: Comparator<K>,
+{
+    /// Build index in parallel, from disjoint partitions of sorted entries.
+    /// Leaf blocks for each partition are encoded on its own thread, while
+    /// intermediate blocks are built on the calling thread. Partitions must
+    /// be supplied in key order, that is, every key in a partition must sort
+    /// before keys in the next partition.
+    ///
+    /// Resulting index is same as the one built using
+    /// [build_index][BuildIndex::build_index], except for the layout of
+    /// blocks. Expiry function, refer to [Builder::set_expiry], and
+    /// checkpoints, refer to [Config::set_checkpoint], are not supported
+    /// for parallel builds. Each worker thread adds its keys into
+    /// its own bitmap, which are then merged into `bitmap`.
+    pub fn build_index_par<I, B>(
+        &mut self,
+        partitions: Vec<I>,
+        bitmap: B,
+        seqno: Option<u64>,
+    ) -> Result<()>
+    where
+        I: 'static + Send + Iterator<Item = db::Entry<K, V, D>>,
+        B: 'static + Send + Default + Bloom,
+    {
+        let start = time::SystemTime::now();
+        let vbase = self.check_parallel()?;
+
+        let mut threads = vec![];
+        for (part, iter) in partitions.into_iter().enumerate() {
+            let config = self.config.clone();
+            let segments = std::iter::once((part, iter));
+            threads.push(thread::spawn(move || {
+                parallel::build_part(config, part, vbase, B::default(), segments)
+            }));
+        }
+
+        self.build_from_parts((start, vbase), threads, bitmap, seqno)
+    }
+
+    /// Same as [Builder::build_index_par], but split a single iterator of
+    /// sorted entries into segments of [SEGMENT_SIZE] entries, distributed
+    /// across `n_threads` worker threads.
+    pub fn build_index_split<I, B>(
+        &mut self,
+        iter: I,
+        n_threads: usize,
+        bitmap: B,
+        seqno: Option<u64>,
+    ) -> Result<()>
+    where
+        I: Iterator<Item = db::Entry<K, V, D>>,
+        B: 'static + Send + Default + Bloom,
+    {
+        let start = time::SystemTime::now();
+        let vbase = self.check_parallel()?;
+
+        let n_threads = cmp::max(n_threads, 1);
+        let (mut threads, mut txs) = (vec![], vec![]);
+        for part in 0..n_threads {
+            let (tx, rx) = mpsc::sync_channel::<(usize, Vec<db::Entry<K, V, D>>)>(2);
+            let config = self.config.clone();
+            threads.push(thread::spawn(move || {
+                let segments = rx.into_iter().map(|(id, seg)| (id, seg.into_iter()));
+                parallel::build_part(config, part, vbase, B::default(), segments)
+            }));
+            txs.push(tx);
+        }
+
+        let mut id = 0;
+        let mut segment = Vec::with_capacity(SEGMENT_SIZE);
+        for entry in iter {
+            segment.push(entry);
+            if segment.len() == SEGMENT_SIZE {
+                let next = Vec::with_capacity(SEGMENT_SIZE);
+                let segment = mem::replace(&mut segment, next);
+                // on failure, worker's error is returned while joining.
+                if txs[id % n_threads].send((id, segment)).is_err() {
+                    break;
+                }
+                id += 1;
+            }
+        }
+        if !segment.is_empty() {
+            txs[id % n_threads].send((id, segment)).ok();
+        }
+        mem::drop(txs);
+
+        self.build_from_parts((start, vbase), threads, bitmap, seqno)
+    }
+
+    // return the value-log position to start with.
+    fn check_parallel(&self) -> Result<u64> {
+        if self.checkpoint.is_some() {
+            err_at!(Invalid, msg: "checkpoint not supported for parallel build")?
+        }
+        match self.expiry {
+            Some(_) => err_at!(Invalid, msg: "expiry not supported for parallel build"),
+            None => Ok(self.vflush.borrow().to_fpos().unwrap_or(0)),
+        }
+    }
+
+    fn build_from_parts<B>(
+        &mut self,
+        (start, vbase): (time::SystemTime, u64),
+        threads: Vec<thread::JoinHandle<Result<parallel::Part<K, B>>>>,
+        bitmap: B,
+        seqno: Option<u64>,
+    ) -> Result<()>
+    where
+        B: Bloom,
+    {
+        let mut parts = vec![];
+        let mut res = Ok(());
+        for th in threads.into_iter() {
+            match th.join() {
+                Ok(Ok(part)) => parts.push(part),
+                Ok(Err(err)) if res.is_ok() => res = Err(err),
+                Err(err) if res.is_ok() => res = err_at!(ThreadFail, msg: "{:?}", err),
+                _ => (),
+            }
+        }
+
+        let res = res.and_then(|_| self.build_stitch(&parts, bitmap));
+        parts.iter().for_each(|part| part.purge());
+        let (bitmap, root) = res?;
+
+        self.root = root;
+        self.stats.n_abytes = vbase;
+        self.stats.n_count = parts.iter().map(|p| p.n_count).sum();
+        let n_deleted: u64 = parts.iter().map(|p| p.n_deleted).sum();
+        self.stats.n_deleted = err_at!(FailConvert, usize::try_from(n_deleted))?;
+        self.stats.seqno = parts.iter().map(|p| p.seqno).max().unwrap_or(0);
+        self.set_build_time(start)?;
+
         self.build_flush(err_at!(Fatal, bitmap.to_bytes())?, seqno)?;
 
         Ok(())
     }
+
+    fn build_stitch<B>(
+        &self,
+        parts: &[parallel::Part<K, B>],
+        bitmap: B,
+    ) -> Result<(B, u64)>
+    where
+        B: Bloom,
+    {
+        let mut bitmap = bitmap;
+        for part in parts.iter() {
+            bitmap = err_at!(Fatal, bitmap.or(&part.bitmap))?;
+        }
+
+        let zz = parallel::StitchZZ::<K, V, D, C>::new(
+            &self.config,
+            Rc::clone(&self.iflush),
+            Rc::clone(&self.vflush),
+            self.hashes.clone(),
+            parts,
+        )?;
+        let root = self.build_levels(zz, vec![])?;
+
+        Ok((bitmap, root))
+    }
 }
 
-impl<K, V, D> Builder<K, V, D>
+impl<K, V, D, C> Builder<K, V, D, C>
+where
+    K: Clone + Hash + FromCbor + IntoCbor,
+    V: Clone + FromCbor + IntoCbor,
+    D: Clone + FromCbor + IntoCbor,
+    C: Comparator<K>,
+{
+    /// Concatenate `indexes` into a single index. Indexes must be supplied
+    /// in key order, that is, every key in an index must sort before keys in
+    /// the next index. Value-log files are copied verbatim. Leaf blocks are
+    /// decoded to verify the key order and to populate the bitmap, and are
+    /// copied as is only when their value-log references need no rebasing,
+    /// otherwise they are re-encoded with rebased references. Intermediate
+    /// blocks are built fresh.
+    ///
+    /// Source indexes must have been built with the same z-blocksize and the
+    /// same value-log and delta settings as this builder. Indexes with range
+    /// tombstones and the expiry function, refer to [Builder::set_expiry],
+    /// are not supported, compact the indexes before concatenating them.
+    /// Checkpoints, refer to [Config::set_checkpoint], are not supported.
+    pub fn concat<B, B2>(
+        &mut self,
+        indexes: &[Index<K, V, D, B2, C>],
+        bitmap: B,
+        seqno: Option<u64>,
+    ) -> Result<()>
+    where
+        B: Bloom,
+    {
+        let start = time::SystemTime::now();
+        if self.expiry.is_some() {
+            err_at!(Invalid, msg: "expiry not supported for concat")?
+        }
+        if self.checkpoint.is_some() {
+            err_at!(Invalid, msg: "checkpoint not supported for concat")?
+        }
+
+        let mut sources = vec![];
+        for index in indexes.iter() {
+            self.check_layout(index)?;
+            if !index.reader.tombstones.is_empty() {
+                err_at!(Invalid, msg: "{} has range tombstones", index.name)?
+            }
+            sources.push(index.to_source()?);
+        }
+
+        self.stats.n_abytes = self.vflush.borrow().to_fpos().unwrap_or(0);
+
+        let bitmap = Rc::new(RefCell::new(bitmap));
+        let zz = concat::ConcatZZ::<K, V, D, B, C>::new(
+            &self.config,
+            Rc::clone(&self.iflush),
+            Rc::clone(&self.vflush),
+            Rc::clone(&bitmap),
+            self.hashes.clone(),
+            sources,
+        )?;
+        self.root = self.build_levels(zz, vec![])?;
+        let bitmap = Rc::try_unwrap(bitmap).ok().unwrap().into_inner();
+
+        self.stats.n_count = indexes.iter().map(|i| i.stats.n_count).sum();
+        self.stats.n_deleted = indexes.iter().map(|i| i.stats.n_deleted).sum();
+        self.stats.seqno = indexes.iter().map(|i| i.stats.seqno).max().unwrap_or(0);
+        self.set_build_time(start)?;
+
+        self.build_flush(err_at!(Fatal, bitmap.to_bytes())?, seqno)?;
+
+        Ok(())
+    }
+
+    /// Build index from the previous snapshot `prev` and a sorted iterator
+    /// of `changes`. Leaf blocks from `prev` that don't overlap any of the
+    /// changed keys are copied verbatim, while the rest are merged with the
+    /// changes and re-encoded. For duplicate keys, entry with the higher
+    /// seqno wins. Intermediate blocks are built fresh.
+    ///
+    /// Reused leaf blocks refer to values and deltas by their position in
+    /// the previous value-log, hence new values and deltas are appended, in
+    /// place, to the previous value-log. Builder must be created using
+    /// [Builder::incremental] with `prev`'s value-log, and configured with
+    /// the same z-blocksize and value-log settings as `prev`. Range
+    /// tombstones from `prev` are carried over, and expiry function, refer
+    /// to [Builder::set_expiry], applies only to the changed entries.
+    /// Checkpoints, refer to [Config::set_checkpoint], are not supported.
+    ///
+    /// When a changed entry replaces an entry from `prev`, older value and
+    /// deltas of the replaced entry are dropped, refer to
+    /// [build_index_patch_versions][Builder::build_index_patch_versions] to
+    /// preserve them as deltas.
+    pub fn build_index_patch<I, B, B2>(
+        &mut self,
+        prev: &Index<K, V, D, B2, C>,
+        changes: I,
+        bitmap: B,
+        seqno: Option<u64>,
+    ) -> Result<()>
+    where
+        I: Iterator<Item = db::Entry<K, V, D>>,
+        B: Bloom,
+    {
+        self.build_patch(prev, changes, bitmap, seqno, None)
+    }
+
+    /// Same as [build_index_patch][Builder::build_index_patch], except that
+    /// when a changed entry replaces an entry from `prev`, the replaced entry
+    /// is folded into the changed entry's deltas, using the
+    /// [Diff][mkit::data::Diff] mechanics. Applicable only when the builder
+    /// is configured with deltas, refer to [Config::set_delta].
+    pub fn build_index_patch_versions<I, B, B2>(
+        &mut self,
+        prev: &Index<K, V, D, B2, C>,
+        changes: I,
+        bitmap: B,
+        seqno: Option<u64>,
+    ) -> Result<()>
+    where
+        V: Diff<Delta = D> + From<D>,
+        D: From<V>,
+        I: Iterator<Item = db::Entry<K, V, D>>,
+        B: Bloom,
+    {
+        let fold: merge::FoldFn<K, V, D> = merge::fold_versions;
+        self.build_patch(prev, changes, bitmap, seqno, Some(fold))
+    }
+
+    fn build_patch<I, B, B2>(
+        &mut self,
+        prev: &Index<K, V, D, B2, C>,
+        changes: I,
+        bitmap: B,
+        seqno: Option<u64>,
+        fold: Option<merge::FoldFn<K, V, D>>,
+    ) -> Result<()>
+    where
+        I: Iterator<Item = db::Entry<K, V, D>>,
+        B: Bloom,
+    {
+        let start = time::SystemTime::now();
+        if self.checkpoint.is_some() {
+            err_at!(Invalid, msg: "checkpoint not supported for patch")?
+        }
+        self.check_layout(prev)?;
+        if let Some(vlog) = prev.to_vlog_file_location() {
+            if self.stats.vlog_file.as_ref() != Some(&vlog) {
+                let name = &self.config.name;
+                err_at!(Invalid, msg: "{} shall append to value-log {:?}", name, vlog)?
+            }
+        }
+        self.tombstones.extend(prev.to_range_tombstones());
+
+        let prev_hashes = match prev.to_merkle_root() {
+            Some(_) => Some(prev.to_merkle_hashes()?),
+            None => None,
+        };
+        let ps = &prev.stats;
+        let state = (prev_hashes, ps.seqno, ps.n_count, ps.n_deleted);
+
+        let bitmap = Rc::new(RefCell::new(bitmap));
+        let mut zz = patch::PatchZZ::<K, V, D, B, C, I>::new(
+            &self.config,
+            (Rc::clone(&self.iflush), Rc::clone(&self.vflush)),
+            Rc::clone(&bitmap),
+            self.hashes.clone(),
+            (self.expiry.clone(), fold),
+            prev.to_source()?,
+            state,
+            changes,
+        )?;
+        self.stats.n_abytes = self.vflush.borrow().to_fpos().unwrap_or(0);
+        self.root = self.build_levels(&mut zz, vec![])?;
+        self.stats.n_count = zz.n_count;
+        self.stats.n_deleted = zz.n_deleted;
+        self.stats.seqno = zz.seqno;
+        debug!(target: "robt", "{} reused {} leaf blocks", self.config.name, zz.n_reused);
+        mem::drop(zz);
+        self.set_build_time(start)?;
+
+        let bitmap = Rc::try_unwrap(bitmap).ok().unwrap().into_inner();
+        self.build_flush(err_at!(Fatal, bitmap.to_bytes())?, seqno)?;
+
+        Ok(())
+    }
+
+    // leaf blocks from `index` can be copied as is, only if it was built
+    // with the same z-blocksize and value-log settings.
+    fn check_layout<B>(&self, index: &Index<K, V, D, B, C>) -> Result<()> {
+        let stats = &index.stats;
+        if stats.z_blocksize != self.config.z_blocksize {
+            let (x, y) = (stats.z_blocksize, self.config.z_blocksize);
+            err_at!(Invalid, msg: "{} z_blocksize {} != {}", index.name, x, y)?
+        }
+        let vlog = (stats.value_in_vlog, stats.delta_ok);
+        if vlog != (self.config.value_in_vlog, self.config.delta_ok) {
+            err_at!(Invalid, msg: "{} value-log settings {:?}", index.name, vlog)?
+        }
+        Ok(())
+    }
+
+    // build shard holding entries from `iter` that sort before `end`.
+    fn build_split<B>(
+        &mut self,
+        iter: &mut split::RawIter<K, V, D, C>,
+        end: Option<K>,
+        bitmap: B,
+    ) -> Result<()>
+    where
+        B: Bloom,
+    {
+        let start = time::SystemTime::now();
+        if self.checkpoint.is_some() {
+            err_at!(Invalid, msg: "checkpoint not supported for split")?
+        }
+        self.stats.n_abytes = self.vflush.borrow().to_fpos().unwrap_or(0);
+
+        let bitmap = Rc::new(RefCell::new(bitmap));
+        let mut zz = split::SplitZZ::new(
+            &self.config,
+            (Rc::clone(&self.iflush), Rc::clone(&self.vflush)),
+            Rc::clone(&bitmap),
+            self.hashes.clone(),
+            iter,
+            end,
+        );
+        self.root = self.build_levels(&mut zz, vec![])?;
+        self.stats.n_count = zz.n_count;
+        self.stats.n_deleted = zz.n_deleted;
+        self.stats.seqno = zz.seqno;
+        mem::drop(zz);
+        self.set_build_time(start)?;
+
+        let bitmap = Rc::try_unwrap(bitmap).ok().unwrap().into_inner();
+        self.build_flush(err_at!(Fatal, bitmap.to_bytes())?, None)?;
+
+        Ok(())
+    }
+}
+
+impl<K, V, D, C> Builder<K, V, D, C>
+where
+    K: Clone + Hash + FromCbor + IntoCbor,
+    V: Clone + FromCbor + IntoCbor,
+    D: Clone + FromCbor + IntoCbor,
+    C: Comparator<K>,
+{
+    /// Build index from an unsorted iterator, using an external sort that
+    /// buffers upto `mem_budget` bytes of serialized entries in memory.
+    /// When the budget is exhausted, buffered entries are spilled as a sorted
+    /// run into a temporary file under `config.dir`. Sorted runs are k-way
+    /// merged and fed into [build_index][BuildIndex::build_index]. For
+    /// duplicate keys, entry with the higher seqno is retained. If sorting
+    /// fails, build is aborted and partially built files are removed.
+    pub fn from_unsorted<I, B>(
+        &mut self,
+        iter: I,
+        mem_budget: usize,
+        bitmap: B,
+        seqno: Option<u64>,
+    ) -> Result<()>
+    where
+        I: Iterator<Item = db::Entry<K, V, D>>,
+        B: Bloom,
+    {
+        self.try_from_unsorted(iter.map(Ok), mem_budget, bitmap, seqno)
+    }
+
+    /// Same as [Builder::from_unsorted], but entries are fallible, like
+    /// records parsed from a file. Input is fully sorted before building
+    /// the index, hence the first error from `iter` aborts the build before
+    /// any entry is flushed, partially built files are removed and the
+    /// error is returned.
+    pub fn try_from_unsorted<I, B>(
+        &mut self,
+        iter: I,
+        mem_budget: usize,
+        bitmap: B,
+        seqno: Option<u64>,
+    ) -> Result<()>
+    where
+        I: Iterator<Item = Result<db::Entry<K, V, D>>>,
+        B: Bloom,
+    {
+        let mut sorter = Sorter::<K, C>::new(&self.config, mem_budget);
+        let res: Result<()> = iter.map(|entry| sorter.add(entry?)).collect();
+        let scan = match res.and_then(|_| sorter.into_scan::<V, D>()) {
+            Ok(scan) => scan,
+            Err(err) => {
+                self.purge();
+                return Err(err);
+            }
+        };
+
+        let iter = FailScan::new(scan);
+        let failed = iter.to_failed();
+        self.build_checked(iter, failed, bitmap, seqno)
+    }
+
+    /// Complete a build resumed from its last checkpoint, refer to
+    /// [Builder::resume]. `iter_from_key` is called with the last key
+    /// flushed before the checkpoint, and shall return the remaining
+    /// entries in sort order, entries upto and including that key are
+    /// skipped. Bitmap over keys flushed before the checkpoint is restored
+    /// from the checkpoint, and merged with `bitmap`.
+    pub fn build_index_resume<F, I, B>(
+        &mut self,
+        iter_from_key: F,
+        bitmap: B,
+        seqno: Option<u64>,
+    ) -> Result<()>
+    where
+        F: FnOnce(&K) -> I,
+        I: Iterator<Item = db::Entry<K, V, D>>,
+        B: Bloom,
+    {
+        let start = time::SystemTime::now();
+        let last = match self.resume.take() {
+            Some(last) => last,
+            None => err_at!(Invalid, msg: "{} is not resumed", self.config.name)?,
+        };
+
+        let bitmap = {
+            let (restored, _) = err_at!(InvalidFile, B::from_bytes(&last.bitmap))?;
+            err_at!(Fatal, restored.or(&bitmap))?
+        };
+
+        let last_key = last.last_key.clone();
+        let iter = iter_from_key(&last.last_key)
+            .skip_while(move |e| C::compare(&e.key, &last_key) != cmp::Ordering::Greater);
+        let iter = {
+            let iter = BitmappedScan::<K, V, D, B, _>::new(iter, bitmap);
+            Rc::new(RefCell::new(BuildScan::new(iter, 0 /*seqno*/)))
+        };
+
+        let mut zz = build::BuildZZ::new(
+            &self.config,
+            Rc::clone(&self.iflush),
+            Rc::clone(&self.vflush),
+            Rc::clone(&iter),
+            self.expiry.clone(),
+            self.hashes.clone(),
+        );
+        if let Some(checkpoint) = self.checkpoint.as_ref() {
+            self.set_checkpoint_metadata(checkpoint);
+            zz.set_checkpoint(Rc::clone(checkpoint));
+        }
+        let leaf = (last.leaf.key.clone(), last.leaf.fpos);
+        let zz = checkpoint::ReplayZZ::new(leaf, last.n_count, zz);
+        self.root = self.build_levels(zz, last.to_levels())?;
+
+        let iter = Rc::try_unwrap(iter).ok().unwrap().into_inner();
+        let (_, iseqno, n_count, n_deleted, _, iter) = iter.unwrap()?;
+        let n_deleted = last.n_deleted + n_deleted;
+        self.stats.n_count = last.n_count + n_count;
+        self.stats.n_deleted = err_at!(FailConvert, usize::try_from(n_deleted))?;
+        self.stats.seqno = cmp::max(last.seqno, iseqno);
+        self.set_build_time(start)?;
+
+        let (bitmap, _) = iter.unwrap()?;
+        self.build_flush(err_at!(Fatal, bitmap.to_bytes())?, seqno)?;
+
+        Ok(())
+    }
+}
+
+impl<K, V, D, C> Builder<K, V, D, C>
+where
+    K: Clone + Hash + IntoCbor,
+    V: Clone + IntoCbor,
+    D: Clone + IntoCbor,
+    C: Comparator<K>,
+{
+    /// Build index from several sorted iterators, like memtable shards,
+    /// merged by key in a single pass. Each iterator must yield entries in
+    /// sort order. For duplicate keys, entry with the highest seqno is
+    /// retained and older entries are dropped, refer to
+    /// [build_index_merged_versions][Builder::build_index_merged_versions]
+    /// to preserve them as deltas.
+    pub fn build_index_merged<I, B>(
+        &mut self,
+        iters: Vec<I>,
+        bitmap: B,
+        seqno: Option<u64>,
+    ) -> Result<()>
+    where
+        I: Iterator<Item = db::Entry<K, V, D>>,
+        B: Bloom,
+    {
+        self.build_merge(MergeScan::<K, V, D, C, I>::new(iters, None), bitmap, seqno)
+    }
+
+    /// Same as [build_index_merged][Builder::build_index_merged], except
+    /// that older entries for duplicate keys are folded into the newest
+    /// entry's deltas, using the [Diff][mkit::data::Diff] mechanics.
+    pub fn build_index_merged_versions<I, B>(
+        &mut self,
+        iters: Vec<I>,
+        bitmap: B,
+        seqno: Option<u64>,
+    ) -> Result<()>
+    where
+        V: Diff<Delta = D> + From<D>,
+        D: From<V>,
+        I: Iterator<Item = db::Entry<K, V, D>>,
+        B: Bloom,
+    {
+        let fold: merge::FoldFn<K, V, D> = merge::fold_versions;
+        let iter = MergeScan::<K, V, D, C, I>::new(iters, Some(fold));
+        self.build_merge(iter, bitmap, seqno)
+    }
+
+    fn build_merge<I, B>(
+        &mut self,
+        iter: MergeScan<K, V, D, C, I>,
+        bitmap: B,
+        seqno: Option<u64>,
+    ) -> Result<()>
+    where
+        I: Iterator<Item = db::Entry<K, V, D>>,
+        B: Bloom,
+    {
+        let iter = FailScan::new(iter);
+        let failed = iter.to_failed();
+        self.build_checked(iter, failed, bitmap, seqno)
+    }
+}
+
+impl<K, V, D, C> Builder<K, V, D, C>
 where
     K: Clone + IntoCbor,
     V: Clone + IntoCbor,
 {
     fn build_from_iter<I>(&mut self, iter: BuildScan<K, V, D, I>) -> Result<I>
     where
-        I: Iterator<Item = db::Entry<K, V, D>>,
+        I: Iterator,
+        I::Item: ScanItem<K, V, D>,
         D: Clone + IntoCbor,
     {
         self.stats.n_abytes = self.vflush.as_ref().borrow().to_fpos().unwrap_or(0);
@@ -186,6 +1050,11 @@ where
         self.root = root;
         self.stats.n_count = n_count;
         self.stats.n_deleted = n_deleted.try_into().unwrap();
+        // wall-clock is not used for deterministic builds.
+        let (build_time, epoch) = match self.config.epoch {
+            Some(epoch) => (0, epoch),
+            None => (build_time, epoch),
+        };
         self.stats.build_time = build_time;
         self.stats.epoch = epoch;
         self.stats.seqno = seqno;
@@ -198,56 +1067,181 @@ where
         iter: BuildScan<K, V, D, I>,
     ) -> Result<(BuildScan<K, V, D, I>, u64)>
     where
-        I: Iterator<Item = db::Entry<K, V, D>>,
+        I: Iterator,
+        I::Item: ScanItem<K, V, D>,
         D: Clone + IntoCbor,
     {
         let iter = Rc::new(RefCell::new(iter));
 
-        let zz = build::BuildZZ::new(
+        let mut zz = build::BuildZZ::new(
             &self.config,
             Rc::clone(&self.iflush),
             Rc::clone(&self.vflush),
             Rc::clone(&iter),
+            self.expiry.clone(),
+            self.hashes.clone(),
         );
-        let mz = build::BuildMZ::new(&self.config, Rc::clone(&self.iflush), zz);
-        let mut build = (0..28).fold(build::BuildIter::from(mz), |build, _| {
-            build::BuildMM::new(&self.config, Rc::clone(&self.iflush), build).into()
+        if let Some(checkpoint) = self.checkpoint.as_ref() {
+            self.set_checkpoint_metadata(checkpoint);
+            zz.set_checkpoint(Rc::clone(checkpoint));
+        }
+        let root = self.build_levels(zz, vec![])?;
+
+        Ok((Rc::try_unwrap(iter).ok().unwrap().into_inner(), root))
+    }
+
+    // build intermediate blocks over leaf blocks from `zz`, return the
+    // file-position of root block. `levels` carry the open intermediate
+    // blocks, level by level, restored from checkpoint.
+    fn build_levels<I>(&self, zz: I, levels: Vec<VecDeque<(K, u64)>>) -> Result<u64>
+    where
+        I: build::LeafIter<K>,
+        D: Clone + IntoCbor,
+    {
+        let zz = build::Watch::new(
+            zz,
+            (Rc::clone(&self.iflush), Rc::clone(&self.vflush)),
+            self.observer.clone(),
+            self.cancel.clone(),
+        );
+
+        let mut levels = levels.into_iter();
+        let (iflush, hashes) = (Rc::clone(&self.iflush), self.hashes.clone());
+        let mut mz = build::BuildMZ::<K, V, D, _>::new(&self.config, iflush, zz, hashes);
+        if let Some(checkpoint) = self.checkpoint.as_ref() {
+            let children = levels.next().unwrap_or_default();
+            mz.set_checkpoint(Rc::clone(checkpoint), children);
+        }
+        let mut build = (1..29).fold(build::BuildIter::from(mz), |build, level| {
+            let (iflush, hashes) = (Rc::clone(&self.iflush), self.hashes.clone());
+            let mut mm = build::BuildMM::new(&self.config, iflush, build, hashes);
+            if let Some(checkpoint) = self.checkpoint.as_ref() {
+                let children = levels.next().unwrap_or_default();
+                mm.set_checkpoint(Rc::clone(checkpoint), level, children);
+            }
+            mm.into()
         });
 
         let root = match build.next() {
             Some(Ok((_, root))) => root,
+            Some(Err(err @ Error::Cancelled(_, _))) => {
+                self.purge();
+                return Err(err);
+            }
             Some(Err(err)) => return Err(err),
             None => err_at!(Invalid, msg: "empty iterator")?,
         };
-        mem::drop(build);
 
-        Ok((Rc::try_unwrap(iter).ok().unwrap().into_inner(), root))
+        Ok(root)
+    }
+
+    // remove partially built index file, and value-log file created by this
+    // builder, after the build is cancelled or failed.
+    fn purge(&self) {
+        self.iflush.borrow_mut().close().ok();
+        self.vflush.borrow_mut().close().ok();
+        if let Some(checkpoint) = self.checkpoint.as_ref() {
+            checkpoint.borrow().purge();
+        }
+
+        let (dir, name) = (&self.config.dir, &self.config.name);
+        fs::remove_file(to_index_file(dir, name)).ok();
+        let vlog_file = to_vlog_file(dir, name);
+        if self.stats.vlog_file.as_ref() == Some(&vlog_file) {
+            fs::remove_file(vlog_file).ok();
+        }
+    }
+
+    // persist builder's metadata with every checkpoint, to be restored
+    // while resuming, refer to [Builder::resume].
+    fn set_checkpoint_metadata(&self, checkpoint: &build::CheckpointRef<K>) {
+        let items = self.app_items.iter();
+        let items = items.map(|(name, data)| MetaSection::new(name, data.clone()));
+        checkpoint.borrow_mut().set_metadata(
+            items.collect(),
+            self.tombstones.clone(),
+            self.schema.id.clone(),
+        );
+    }
+
+    // wall-clock is not used for deterministic builds.
+    fn set_build_time(&mut self, start: time::SystemTime) -> Result<()> {
+        let (build_time, epoch) = match self.config.epoch {
+            Some(epoch) => (0, epoch),
+            None => {
+                let elapsed = err_at!(Fatal, start.elapsed())?;
+                let build_time = err_at!(FailConvert, u64::try_from(elapsed.as_nanos()))?;
+                let elapsed = err_at!(Fatal, time::UNIX_EPOCH.elapsed())?;
+                (build_time, err_at!(FailConvert, u64::try_from(elapsed.as_nanos()))?)
+            }
+        };
+        self.stats.build_time = build_time;
+        self.stats.epoch = epoch;
+
+        Ok(())
     }
 
     fn build_flush(&mut self, bitmap: Vec<u8>, seqno: Option<u64>) -> Result<(u64, u64)> {
-        let block = self.meta_blocks(bitmap, seqno)?;
+        // block hashes can be large, they are flushed as a separate region
+        // just before the meta-block, and read on demand.
+        let region = match self.hashes.as_ref() {
+            Some(hashes) => {
+                let data = hashes.borrow().to_bytes();
+                let fpos = self.iflush.borrow().to_fpos().unwrap_or(0);
+                let length = err_at!(Fatal, u64::try_from(data.len()))?;
+                self.iflush.borrow_mut().flush(data)?;
+                Some(merkle::HashesRegion { fpos, length })
+            }
+            None => None,
+        };
+        let block = self.meta_blocks(bitmap, seqno, region)?;
 
         self.iflush.borrow_mut().flush(block)?;
 
         let len1 = self.iflush.borrow_mut().close()?;
         let len2 = self.vflush.borrow_mut().close()?;
+        // build is complete, checkpoints are no longer required.
+        if let Some(checkpoint) = self.checkpoint.as_ref() {
+            checkpoint.borrow().purge();
+        }
 
         Ok((len1, len2))
     }
 
-    fn meta_blocks(&mut self, bitmap: Vec<u8>, seqno: Option<u64>) -> Result<Vec<u8>> {
+    fn meta_blocks(
+        &mut self,
+        bitmap: Vec<u8>,
+        seqno: Option<u64>,
+        region: Option<merkle::HashesRegion>,
+    ) -> Result<Vec<u8>> {
         self.stats.seqno = seqno.unwrap_or(self.stats.seqno);
         let stats = util::into_cbor_bytes(self.stats.clone())?;
-
-        let metas = vec![
-            MetaItem::AppMetadata(self.app_meta.clone()),
-            MetaItem::Stats(stats),
-            MetaItem::Bitmap(bitmap),
-            MetaItem::Root(self.root),
-            MetaItem::Marker(ROOT_MARKER.clone()),
+        let tombstones = util::into_cbor_bytes(self.tombstones.clone())?;
+        let schema = util::into_cbor_bytes(self.schema.clone())?;
+
+        let mut sections = vec![
+            MetaSection::new(meta::FORMAT, util::into_cbor_bytes(FORMAT_VERSION)?),
+            MetaSection::new(meta::APP_METADATA, self.app_meta.clone()),
+            MetaSection::new(meta::STATS, stats),
+            MetaSection::new(meta::BITMAP, bitmap),
+            MetaSection::new(meta::ROOT, util::into_cbor_bytes(self.root)?),
+            MetaSection::new(meta::MARKER, ROOT_MARKER.clone()),
+            MetaSection::new(meta::RANGE_TOMBSTONES, tombstones),
+            MetaSection::new(meta::SCHEMA, schema),
         ];
+        if let (Some(hashes), Some(region)) = (self.hashes.as_ref(), region) {
+            let root = hashes.borrow().get(self.root)?.to_vec();
+            sections.push(MetaSection::new(meta::MERKLE_ROOT, root));
+            let region = util::into_cbor_bytes(region)?;
+            sections.push(MetaSection::new(meta::MERKLE_HASHES, region));
+        }
+        for (name, data) in self.app_items.iter() {
+            let name = format!("{}{}", meta::APP_METADATA_ITEM, name);
+            sections.push(MetaSection::new(&name, data.clone()));
+        }
+        let metas = Metas::new(sections);
 
-        let mut block = util::into_cbor_bytes(metas)?;
+        let mut block = metas.to_bytes()?;
         let len = err_at!(Fatal, u64::try_from(block.len()))?;
         let m = Self::compute_root_block(block.len() + 16);
         block.resize(m, 0);
@@ -268,49 +1262,28 @@ where
     }
 }
 
-/// Enumeration of meta items stored in [Robt] index.
-///
-/// [Robt] index is a fully packed immutable [Btree] index. To interpret
-/// the index a list of meta items are appended to the tip of index-file.
-///
-/// [Btree]: https://en.wikipedia.org/wiki/B-tree
-#[derive(Clone, Debug, Cborize)]
-pub enum MetaItem {
-    /// Application supplied metadata, typically serialized and opaque to `robt`.
-    AppMetadata(Vec<u8>),
-    /// Contains index-statistics along with configuration values.
-    Stats(Vec<u8>),
-    /// Bloom-filter.
-    Bitmap(Vec<u8>),
-    /// File-position where the root block for the Btree starts.
-    Root(u64),
-    /// Finger print for robt.
-    Marker(Vec<u8>),
-}
-
-impl MetaItem {
-    const ID: &'static str = "robt/metaitem/0.0.1";
-}
-
 /// Index type, immutable, durable, fully-packed and lockless reads.
-pub struct Index<K, V, D, B> {
+pub struct Index<K, V, D, B, C = Natural> {
     dir: ffi::OsString,
     name: String,
 
-    reader: Reader<K, V, D>,
-    metas: Arc<Vec<MetaItem>>,
+    reader: Reader<K, V, D, C>,
+    metas: Arc<Metas>,
+    root: u64,
     stats: Stats,
     bitmap: Arc<B>,
 }
 
-impl<K, V, D, B> Index<K, V, D, B> {
-    /// Open an existing index for read-only.
-    pub fn open(dir: &ffi::OsStr, name: &str) -> Result<Index<K, V, D, B>>
+impl<K, V, D, B, C> Index<K, V, D, B, C> {
+    /// Open an existing index for read-only. Index must have been built
+    /// using the same comparator `C`.
+    pub fn open(dir: &ffi::OsStr, name: &str) -> Result<Self>
     where
         K: FromCbor,
         V: FromCbor,
         D: FromCbor,
         B: Bloom,
+        C: Comparator<K>,
     {
         match find_index_file(dir, name) {
             Some(file) => Self::open_file(&file),
@@ -320,12 +1293,13 @@ impl<K, V, D, B> Index<K, V, D, B> {
 
     /// Open an existing index for read-only, from index file. file must be
     /// supplied along with full-path.
-    pub fn open_file(file: &ffi::OsStr) -> Result<Index<K, V, D, B>>
+    pub fn open_file(file: &ffi::OsStr) -> Result<Self>
     where
         K: FromCbor,
         V: FromCbor,
         D: FromCbor,
         B: Bloom,
+        C: Comparator<K>,
     {
         let dir = match path::Path::new(file).parent() {
             Some(dir) => dir.as_os_str().to_os_string(),
@@ -335,41 +1309,64 @@ impl<K, V, D, B> Index<K, V, D, B> {
 
         let mut index = err_at!(IOError, fs::OpenOptions::new().read(true).open(&file))?;
 
-        let metas: Vec<MetaItem> = {
-            let off = {
-                let seek = io::SeekFrom::End(-16);
-                let data = read_file!(index, seek, 8, "reading meta-off from index")?;
-                i64::from_be_bytes(data.try_into().unwrap())
-            };
-            let len = {
-                let seek = io::SeekFrom::End(-8);
-                let data = read_file!(index, seek, 8, "reading meta-len from index")?;
-                u64::from_be_bytes(data.try_into().unwrap())
-            };
-            let seek = io::SeekFrom::End(-off);
-            let block = read_file!(index, seek, len, "reading meta-data from index")?;
-            util::from_cbor_bytes(&block)?.0
-        };
+        // older index files don't have header, and are in format version 0.
+        let header = Header::read(&mut index)?;
+        if let Some(header) = header.as_ref() {
+            header::check_version(header.version)?
+        }
+
+        let (metas, _) = read_meta_block(&mut index)?;
+
+        let version: u64 = metas.decode(meta::FORMAT)?.unwrap_or(0);
+        match header {
+            Some(header) if header.version != version => {
+                let (x, y) = (header.version, version);
+                err_at!(InvalidFile, msg: "format version header:{} meta:{}", x, y)?
+            }
+            Some(_) => (),
+            None if version > 0 => {
+                err_at!(InvalidFile, msg: "missing header, format version {}", version)?
+            }
+            None => (),
+        }
 
-        let stats: Stats = match &metas[1] {
-            MetaItem::Stats(stats) => util::from_cbor_bytes(stats)?.0,
-            _ => unreachable!(),
+        let stats = match metas.get(meta::STATS) {
+            Some(data) => Stats::from_bytes(data)?,
+            None => err_at!(InvalidFile, msg: "missing stats in {:?}", file)?,
         };
+        if stats.m_blocksize == 0 || stats.z_blocksize == 0 {
+            let (m, z) = (stats.m_blocksize, stats.z_blocksize);
+            err_at!(InvalidFile, msg: "bad block size m:{} z:{}", m, z)?
+        }
+
+        if metas.get(meta::APP_METADATA).is_none() {
+            err_at!(InvalidFile, msg: "missing app-metadata in {:?}", file)?
+        }
+
+        // older index files don't have schema.
+        if let Some(schema) = metas.decode::<Schema>(meta::SCHEMA)? {
+            schema.verify_types::<K, V, D>()?
+        }
+
+        if stats.comparator != C::NAME {
+            let (x, y) = (&stats.comparator, C::NAME);
+            err_at!(Invalid, msg: "comparator mismatch, index:{} given:{}", x, y)?
+        }
 
-        let bitmap = match &metas[2] {
-            MetaItem::Bitmap(data) => err_at!(Fatal, B::from_bytes(&data))?.0,
-            _ => unreachable!(),
+        let bitmap = match metas.get(meta::BITMAP) {
+            Some(data) => err_at!(InvalidFile, B::from_bytes(data))?.0,
+            None => err_at!(InvalidFile, msg: "missing bitmap in {:?}", file)?,
         };
 
-        let root = match &metas[3] {
-            MetaItem::Root(root) => *root,
-            _ => unreachable!(),
+        let root: u64 = match metas.decode(meta::ROOT)? {
+            Some(root) => root,
+            None => err_at!(InvalidFile, msg: "missing root in {:?}", file)?,
         };
 
-        if let MetaItem::Marker(mrkr) = &metas[4] {
-            if mrkr.ne(ROOT_MARKER.as_slice()) {
-                err_at!(Invalid, msg: "invalid marker {:?}", mrkr)?
-            }
+        match metas.get(meta::MARKER) {
+            Some(mrkr) if mrkr.eq(ROOT_MARKER.as_slice()) => (),
+            Some(mrkr) => err_at!(InvalidFile, msg: "invalid marker {:?}", mrkr)?,
+            None => err_at!(InvalidFile, msg: "missing marker in {:?}", file)?,
         }
 
         let vlog = match stats.value_in_vlog || stats.delta_ok {
@@ -386,7 +1383,12 @@ impl<K, V, D, B> Index<K, V, D, B> {
             false => None,
         };
 
-        let reader = Reader::from_root(root, &stats, index, vlog)?;
+        // older index files don't have range tombstones.
+        let tombstones: Vec<RangeTombstone<K>> =
+            metas.decode(meta::RANGE_TOMBSTONES)?.unwrap_or_default();
+
+        let mut reader = Reader::from_root(root, (&stats, version), index, vlog)?;
+        reader.tombstones = Arc::new(tombstones);
 
         let val = Index {
             dir,
@@ -394,6 +1396,7 @@ impl<K, V, D, B> Index<K, V, D, B> {
 
             reader,
             metas: Arc::new(metas),
+            root,
             stats,
             bitmap: Arc::new(bitmap),
         };
@@ -407,6 +1410,15 @@ impl<K, V, D, B> Index<K, V, D, B> {
         self.bitmap = Arc::new(bitmap)
     }
 
+    /// Set the reference clock, as nanoseconds from UNIX EPOCH, to check for
+    /// expired entries. When set, `get` and `iter` APIs shall treat entries
+    /// whose expiry is less than or equal to `clock` as missing. Typically
+    /// the build's `epoch` from [Stats] can be used as the reference clock
+    /// for the snapshot. Pass None to ignore expiry, which is the default.
+    pub fn set_expiry_clock(&mut self, clock: Option<u64>) {
+        self.reader.expiry_clock = clock
+    }
+
     /// Clone this index instance, with its underlying meta-data `shared`
     /// across index instances. Note that file-descriptors are not `shared`.
     pub fn try_clone(&self) -> Result<Self>
@@ -433,12 +1445,10 @@ impl<K, V, D, B> Index<K, V, D, B> {
             false => None,
         };
 
-        let root = match &self.metas[3] {
-            MetaItem::Root(root) => *root,
-            _ => unreachable!(),
-        };
-
-        let reader = Reader::from_root(root, &self.stats, index, vlog)?;
+        let mut reader =
+            Reader::from_root(self.root, (&self.stats, self.reader.format), index, vlog)?;
+        reader.expiry_clock = self.reader.expiry_clock;
+        reader.tombstones = Arc::clone(&self.reader.tombstones);
 
         let val = Index {
             dir: self.dir.clone(),
@@ -446,6 +1456,7 @@ impl<K, V, D, B> Index<K, V, D, B> {
 
             reader,
             metas: Arc::clone(&self.metas),
+            root: self.root,
             stats: self.stats.clone(),
             bitmap: Arc::clone(&self.bitmap),
         };
@@ -456,6 +1467,11 @@ impl<K, V, D, B> Index<K, V, D, B> {
     /// Compact this index into a new index specified by [Config].
     /// The `bitmap` argument carry same meaning as that of `build_index`
     /// method. Refer to package documentation to know more about `Cutoff`.
+    ///
+    /// Expired entries are purged, with reference to the clock set via
+    /// [Index::set_expiry_clock], or the snapshot's `epoch` if not set.
+    /// Entries covered by range tombstones are purged, and tombstones
+    /// themselves are retained only if they are newer than `cutoff`.
     pub fn compact(
         mut self,
         config: Config,
@@ -463,23 +1479,149 @@ impl<K, V, D, B> Index<K, V, D, B> {
         cutoff: db::Cutoff,
     ) -> Result<Self>
     where
-        K: Clone + Ord + Hash + FromCbor + IntoCbor,
-        V: Clone + FromCbor + IntoCbor,
-        D: Clone + FromCbor + IntoCbor,
+        K: 'static + Clone + Ord + Hash + FromCbor + IntoCbor,
+        V: 'static + Clone + FromCbor + IntoCbor,
+        D: 'static + Clone + FromCbor + IntoCbor,
         B: Bloom,
+        C: Comparator<K>,
     {
-        let mut builder = {
-            let app_meta = self.to_app_metadata();
-            Builder::<K, V, D>::initial(config.clone(), app_meta)?
-        };
+        let mut builder = self.to_builder(&config)?;
+        for tomb in self.to_range_tombstones().into_iter() {
+            if let Some(tomb) = tomb.purge(cutoff) {
+                builder.delete_range(tomb.to_range(), tomb.to_seqno());
+            }
+        }
+
+        let clock = self.reader.expiry_clock.unwrap_or(self.stats.epoch);
+        self.set_expiry_clock(Some(clock));
+
+        // carry the expiry of each entry over to the new snapshot.
         let r = (Bound::<K>::Unbounded, Bound::<K>::Unbounded);
-        let iter = CompactScan::new(self.iter(r)?.map(|e| e.unwrap()), cutoff);
+        let iter = FailScan::new(self.iter(r)?.with_expiry());
 
-        builder.build_index(iter, bitmap, None)?;
+        // stop at the first error, and fail the compaction.
+        let failed = iter.to_failed();
+        let iter = CompactScan::new(iter, cutoff);
+
+        builder.build_checked(iter, failed, bitmap, None)?;
 
         Index::open(&config.dir, &config.name)
     }
 
+    /// Rewrite this index into a new index specified by [Config], in the
+    /// current on-disk format, refer to [FORMAT_VERSION]. Unlike compact,
+    /// all entries along with their older versions, expiry and range
+    /// tombstones are carried over as they are.
+    pub fn upgrade(mut self, config: Config, bitmap: B) -> Result<Self>
+    where
+        K: 'static + Clone + Ord + Hash + FromCbor + IntoCbor,
+        V: 'static + Clone + FromCbor + IntoCbor,
+        D: 'static + Clone + FromCbor + IntoCbor,
+        B: Bloom,
+        C: Comparator<K>,
+    {
+        let mut builder = self.to_builder(&config)?;
+        for tomb in self.to_range_tombstones().into_iter() {
+            builder.delete_range(tomb.to_range(), tomb.to_seqno());
+        }
+
+        // copy expired and range-deleted entries as well.
+        self.set_expiry_clock(None);
+        self.reader.tombstones = Arc::new(vec![]);
+        let seqno = self.to_seqno();
+
+        let r = (Bound::<K>::Unbounded, Bound::<K>::Unbounded);
+        let iter = FailScan::new(self.iter_versions(r)?.with_expiry());
+
+        // stop at the first error, and fail the upgrade.
+        let failed = iter.to_failed();
+        builder.build_checked(iter, failed, bitmap, Some(seqno))?;
+
+        Index::open(&config.dir, &config.name)
+    }
+
+    /// Split this index at `keys` into `keys.len() + 1` new indexes, one for
+    /// each `(config, bitmap)` in `shards`. Shard `i` holds entries whose key
+    /// sort at or after `keys[i-1]` and before `keys[i]`, hence `keys` must
+    /// be sorted and every shard must hold at least one entry. If building
+    /// a shard fails, shards built so far are removed.
+    ///
+    /// Entries are carried over along with their older versions and expiry,
+    /// values and deltas in value-log are copied as raw bytes. Application
+    /// metadata, schema-id and range tombstones are carried over to every
+    /// shard, while stats and bitmap are computed afresh for each shard.
+    /// Shards cannot be configured with checkpoints, refer to
+    /// [Config::set_checkpoint].
+    pub fn split_at(&self, keys: &[K], shards: Vec<(Config, B)>) -> Result<Vec<Self>>
+    where
+        K: Clone + Hash + FromCbor + IntoCbor,
+        V: Clone + FromCbor + IntoCbor,
+        D: Clone + FromCbor + IntoCbor,
+        B: Bloom,
+        C: Comparator<K>,
+    {
+        if keys.len() + 1 != shards.len() {
+            let (x, y) = (keys.len(), shards.len());
+            err_at!(Invalid, msg: "split keys:{} shards:{}", x, y)?
+        }
+        for (i, pair) in keys.windows(2).enumerate() {
+            if C::compare(&pair[0], &pair[1]) != cmp::Ordering::Less {
+                err_at!(Invalid, msg: "split key {} not sorted", i + 1)?
+            }
+        }
+
+        let source = self.to_source()?;
+        let mut iter = split::RawIter::<K, V, D, C>::new(source, self.stats.z_blocksize)?;
+
+        let mut ends: Vec<Option<K>> = keys.iter().cloned().map(Some).collect();
+        ends.push(None);
+
+        let mut configs: Vec<Config> = vec![];
+        for ((config, bitmap), end) in shards.into_iter().zip(ends.into_iter()) {
+            let res = self.to_builder(&config).and_then(|mut builder| {
+                builder.tombstones = self.to_range_tombstones();
+                let res = builder.build_split(&mut iter, end, bitmap);
+                if res.is_err() {
+                    builder.purge();
+                }
+                res
+            });
+            // remove shards that are already built.
+            if let Err(err) = res {
+                for config in configs.iter() {
+                    fs::remove_file(to_index_file(&config.dir, &config.name)).ok();
+                    if config.value_in_vlog || config.delta_ok {
+                        fs::remove_file(to_vlog_file(&config.dir, &config.name)).ok();
+                    }
+                }
+                return Err(err);
+            }
+            configs.push(config);
+        }
+
+        let mut indexes = vec![];
+        for config in configs.into_iter() {
+            indexes.push(Index::open(&config.dir, &config.name)?);
+        }
+
+        Ok(indexes)
+    }
+
+    // new builder carrying over application metadata and schema-id from
+    // this index.
+    fn to_builder(&self, config: &Config) -> Result<Builder<K, V, D, C>>
+    where
+        C: Comparator<K>,
+    {
+        let app_meta = self.to_app_metadata();
+        let mut builder = Builder::<K, V, D, C>::initial_by(config.clone(), app_meta)?;
+        builder.set_app_metadata_items(self.metas.to_app_metadata_items());
+        if let Some(id) = self.to_schema().and_then(|s| s.id) {
+            builder.set_schema_id(&id);
+        }
+        Ok(builder)
+    }
+
     /// Close this index, releasing OS resources. To purge, call `purge()`
     /// method.
     pub fn close(self) -> Result<()> {
@@ -498,15 +1640,79 @@ impl<K, V, D, B> Index<K, V, D, B> {
     }
 }
 
-impl<K, V, D, B> Index<K, V, D, B> {
+impl<K, V, D, B, C> Index<K, V, D, B, C> {
     pub fn to_name(&self) -> String {
         self.name.clone()
     }
 
     pub fn to_app_metadata(&self) -> Vec<u8> {
-        match &self.metas[0] {
-            MetaItem::AppMetadata(data) => data.clone(),
-            _ => unreachable!(),
+        self.metas.get(meta::APP_METADATA).unwrap_or_default().to_vec()
+    }
+
+    /// Return named application metadata, decoded as `T`. Return None if
+    /// `name` was not put while building the index, refer to
+    /// [Builder::put_app_metadata].
+    pub fn get_app_metadata<T>(&self, name: &str) -> Result<Option<T>>
+    where
+        T: FromCbor,
+    {
+        let name = format!("{}{}", meta::APP_METADATA_ITEM, name);
+        self.metas.decode(&name)
+    }
+
+    /// Return the list of names for application metadata persisted with
+    /// the index.
+    pub fn to_app_metadata_names(&self) -> Vec<String> {
+        let items = self.metas.to_app_metadata_items();
+        items.into_iter().map(|(name, _)| name).collect()
+    }
+
+    /// Return the on-disk format version of the index file, refer to
+    /// [FORMAT_VERSION] for details.
+    pub fn to_format_version(&self) -> u64 {
+        self.metas.decode(meta::FORMAT).ok().flatten().unwrap_or(0)
+    }
+
+    /// Return the merkle root for this index, None if the index was built
+    /// without merkle tree, refer to [Config::set_merkle].
+    pub fn to_merkle_root(&self) -> Option<[u8; HASH_SIZE]> {
+        let data = self.metas.get(meta::MERKLE_ROOT)?;
+        data.try_into().ok()
+    }
+
+    // source to copy leaf blocks and values from, file descriptors are
+    // shared with this index.
+    fn to_source(&self) -> Result<concat::Source> {
+        let vlog = match self.reader.vlog.as_ref() {
+            Some(vlog) => Some(err_at!(IOError, vlog.try_clone())?),
+            None => None,
+        };
+        let source = concat::Source {
+            index: err_at!(IOError, self.reader.index.try_clone())?,
+            vlog,
+            root: self.root,
+            m_blocksize: self.stats.m_blocksize,
+            format: self.reader.format,
+        };
+
+        Ok(source)
+    }
+
+    // block hashes are read on demand, older index files persisted them
+    // within the meta-block.
+    fn to_merkle_hashes(&self) -> Result<Hashes> {
+        let version = self.metas.to_version(meta::MERKLE_HASHES);
+        match (self.metas.get(meta::MERKLE_HASHES), version) {
+            (Some(data), Some(1)) => Hashes::from_bytes(data),
+            (Some(_), _) => {
+                let region: merkle::HashesRegion =
+                    self.metas.decode(meta::MERKLE_HASHES)?.unwrap_or_default();
+                let mut fd = err_at!(IOError, self.reader.index.try_clone())?;
+                let seek = io::SeekFrom::Start(region.fpos);
+                let data = read_file!(fd, seek, region.length, "read merkle hashes")?;
+                Hashes::from_bytes(&data)
+            }
+            (None, _) => err_at!(Invalid, msg: "{} built without merkle tree", self.name),
         }
     }
 
@@ -514,6 +1720,21 @@ impl<K, V, D, B> Index<K, V, D, B> {
         self.stats.clone()
     }
 
+    /// Return the schema persisted with the index. Older index files
+    /// may not have a schema.
+    pub fn to_schema(&self) -> Option<Schema> {
+        self.metas.decode(meta::SCHEMA).ok().flatten()
+    }
+
+    /// Verify application supplied schema-id, refer to
+    /// [Builder::set_schema_id] for details.
+    pub fn verify_schema_id(&self, id: &str) -> Result<()> {
+        match self.to_schema() {
+            Some(schema) => schema.verify_id(id),
+            None => err_at!(TypeMismatch, msg: "schema missing, expected {}", id),
+        }
+    }
+
     pub fn as_bitmap(&self) -> &B {
         self.bitmap.as_ref()
     }
@@ -526,16 +1747,24 @@ impl<K, V, D, B> Index<K, V, D, B> {
     }
 
     pub fn to_root(&self) -> u64 {
-        match &self.metas[3] {
-            MetaItem::Root(root) => *root,
-            _ => unreachable!(),
-        }
+        self.root
     }
 
     pub fn to_seqno(&self) -> u64 {
         self.stats.seqno
     }
 
+    pub fn to_expiry_clock(&self) -> Option<u64> {
+        self.reader.expiry_clock
+    }
+
+    pub fn to_range_tombstones(&self) -> Vec<RangeTombstone<K>>
+    where
+        K: Clone,
+    {
+        self.reader.tombstones.as_ref().clone()
+    }
+
     pub fn is_compacted(&self) -> bool {
         self.stats.n_abytes == 0
     }
@@ -572,9 +1801,10 @@ impl<K, V, D, B> Index<K, V, D, B> {
         V: Clone + FromCbor,
         D: Clone + FromCbor,
         Q: Ord,
+        C: Comparator<Q>,
     {
         let versions = false;
-        Ok(self.reader.get(key, versions)?.into())
+        self.reader.get(key, versions)
     }
 
     pub fn get_versions<Q>(&mut self, key: &Q) -> Result<db::Entry<K, V, D>>
@@ -583,64 +1813,127 @@ impl<K, V, D, B> Index<K, V, D, B> {
         V: Clone + FromCbor,
         D: Clone + FromCbor,
         Q: Ord,
+        C: Comparator<Q>,
     {
         let versions = true;
-        Ok(self.reader.get(key, versions)?.into())
+        self.reader.get(key, versions)
     }
 
-    pub fn iter<Q, R>(&mut self, range: R) -> Result<Iter<K, V, D>>
+    pub fn iter<Q, R>(&mut self, range: R) -> Result<Iter<K, V, D, C>>
     where
         K: Clone + Ord + Borrow<Q> + FromCbor,
         V: Clone + FromCbor,
         D: Clone + FromCbor,
         Q: Ord + ToOwned<Owned = K>,
         R: RangeBounds<Q>,
+        C: Comparator<Q> + Comparator<K>,
     {
         let (reverse, versions) = (false, false);
         self.reader.iter(range, reverse, versions)
     }
 
-    pub fn reverse<Q, R>(&mut self, range: R) -> Result<Iter<K, V, D>>
+    pub fn reverse<Q, R>(&mut self, range: R) -> Result<Iter<K, V, D, C>>
     where
         K: Clone + Ord + Borrow<Q> + FromCbor,
         V: Clone + FromCbor,
         D: Clone + FromCbor,
         Q: Ord + ToOwned<Owned = K>,
         R: RangeBounds<Q>,
+        C: Comparator<Q> + Comparator<K>,
     {
         let (reverse, versions) = (true, false);
         self.reader.iter(range, reverse, versions)
     }
 
-    pub fn iter_versions<Q, R>(&mut self, range: R) -> Result<Iter<K, V, D>>
+    pub fn iter_versions<Q, R>(&mut self, range: R) -> Result<Iter<K, V, D, C>>
     where
         K: Clone + Ord + Borrow<Q> + FromCbor,
         V: Clone + FromCbor,
         D: Clone + FromCbor,
         Q: Ord + ToOwned<Owned = K>,
         R: RangeBounds<Q>,
+        C: Comparator<Q> + Comparator<K>,
     {
         let (reverse, versions) = (false, true);
         self.reader.iter(range, reverse, versions)
     }
 
-    pub fn reverse_versions<Q, R>(&mut self, range: R) -> Result<Iter<K, V, D>>
+    pub fn reverse_versions<Q, R>(&mut self, range: R) -> Result<Iter<K, V, D, C>>
     where
         K: Clone + Ord + Borrow<Q> + FromCbor,
         V: Clone + FromCbor,
         D: Clone + FromCbor,
         Q: Ord + ToOwned<Owned = K>,
         R: RangeBounds<Q>,
+        C: Comparator<Q> + Comparator<K>,
     {
         let (reverse, versions) = (true, true);
         self.reader.iter(range, reverse, versions)
     }
 
+    /// Find key ranges that differ between this index and `other`, by
+    /// descending mismatched subtrees of their merkle trees. Return an
+    /// empty list if both indexes hold the same snapshot. Both indexes
+    /// must have been built with merkle tree and the same configuration,
+    /// refer to [Config::set_merkle].
+    ///
+    /// Returned ranges are sorted and disjoint. Note that a range may also
+    /// include keys that are identical in both indexes.
+    pub fn diff_ranges<B2>(
+        &mut self,
+        other: &mut Index<K, V, D, B2, C>,
+    ) -> Result<Vec<(Bound<K>, Bound<K>)>>
+    where
+        K: Clone + FromCbor,
+        V: FromCbor,
+        D: FromCbor,
+        C: Comparator<K>,
+    {
+        let (ha, hb) = (self.to_merkle_hashes()?, other.to_merkle_hashes()?);
+        let a = (&mut self.reader, &ha, self.root);
+        let b = (&mut other.reader, &hb, other.root);
+        merkle::diff_ranges(a, b)
+    }
+
     pub fn validate(&mut self) -> Result<Stats>
     where
         K: Clone + PartialOrd + Ord + fmt::Debug + FromCbor,
         V: Clone + FromCbor,
         D: Clone + FromCbor,
+        C: Comparator<K>,
+    {
+        // count expired and range-deleted entries as well, stats include them.
+        let clock = self.reader.expiry_clock.take();
+        let tombstones = mem::take(&mut self.reader.tombstones);
+        let res = self.do_validate();
+        self.reader.expiry_clock = clock;
+        self.reader.tombstones = tombstones;
+        res
+    }
+
+    /// Walk every block in the index, and check that the btree is
+    /// structurally sound. Unlike [Index::validate], which stops at the
+    /// first error, this method collects all problems found into the
+    /// returned [ValidateReport]. Refer to [Problem][crate::Problem] for the
+    /// list of checks.
+    pub fn validate_deep(&mut self) -> Result<ValidateReport>
+    where
+        K: Clone + fmt::Debug + FromCbor,
+        V: Clone + FromCbor,
+        D: Clone + FromCbor,
+        C: Comparator<K>,
+    {
+        let root = self.to_root();
+        let validator = DeepValidator::new(&mut self.reader, root)?;
+        Ok(validator.validate(&self.stats))
+    }
+
+    fn do_validate(&mut self) -> Result<Stats>
+    where
+        K: Clone + PartialOrd + Ord + fmt::Debug + FromCbor,
+        V: Clone + FromCbor,
+        D: Clone + FromCbor,
+        C: Comparator<K>,
     {
         let iter = self.iter((Bound::<K>::Unbounded, Bound::<K>::Unbounded))?;
 
@@ -657,9 +1950,9 @@ impl<K, V, D, B> Index<K, V, D, B> {
 
             seqno = cmp::max(seqno, entry.to_seqno());
 
-            match prev_key.as_ref().map(|pk| pk.lt(&entry.key)) {
-                Some(true) | None => (),
-                Some(false) => err_at!(Fatal, msg: "{:?} >= {:?}", prev_key, entry.key)?,
+            match prev_key.as_ref().map(|pk| C::compare(pk, &entry.key)) {
+                Some(cmp::Ordering::Less) | None => (),
+                Some(_) => err_at!(Fatal, msg: "{:?} >= {:?}", prev_key, entry.key)?,
             }
 
             for d in entry.deltas.iter() {
@@ -694,6 +1987,7 @@ impl<K, V, D, B> Index<K, V, D, B> {
         println!("app_meta_data     : {}", self.to_app_metadata().len());
         println!("root block at     : {}", self.to_root());
         println!("sequence num. at  : {}", self.to_seqno());
+        println!("range tombstones  : {}", self.reader.tombstones.len());
         let stats = self.to_stats();
         println!("stats         :");
         println!("  z_blocksize  : {}", stats.z_blocksize);
@@ -702,6 +1996,7 @@ impl<K, V, D, B> Index<K, V, D, B> {
         println!("  delta_ok     : {}", stats.delta_ok);
         println!("  vlog_file    : {:?}", stats.vlog_file);
         println!("  value_in_vlog: {}", stats.value_in_vlog);
+        println!("  comparator   : {}", stats.comparator);
         println!("  n_count      : {}", stats.n_count);
         println!("  n_deleted    : {}", stats.n_deleted);
         println!("  seqno        : {}", stats.seqno);
@@ -713,6 +2008,31 @@ impl<K, V, D, B> Index<K, V, D, B> {
     }
 }
 
+// read the meta-block from the tail of index file, return the meta sections
+// and the file-position where the meta-block starts.
+pub(crate) fn read_meta_block(index: &mut fs::File) -> Result<(Metas, u64)> {
+    let file_len = err_at!(IOError, index.metadata())?.len();
+    if file_len < 16 {
+        err_at!(InvalidFile, msg: "index file too short {}", file_len)?
+    }
+    let off = {
+        let seek = io::SeekFrom::End(-16);
+        let data = read_file!(index, seek, 8, "reading meta-off from index")?;
+        u64::from_be_bytes(err_at!(InvalidFile, data.as_slice().try_into())?)
+    };
+    let len = {
+        let seek = io::SeekFrom::End(-8);
+        let data = read_file!(index, seek, 8, "reading meta-len from index")?;
+        u64::from_be_bytes(err_at!(InvalidFile, data.as_slice().try_into())?)
+    };
+    if off < 16 || off > file_len || len > (off - 16) {
+        err_at!(InvalidFile, msg: "bad meta-block off:{} len:{}", off, len)?
+    }
+    let fpos = file_len - off;
+    let block = read_file!(index, io::SeekFrom::Start(fpos), len, "reading meta-data")?;
+    Ok((Metas::from_bytes(&block)?, fpos))
+}
+
 fn find_index_file(dir: &ffi::OsStr, name: &str) -> Option<ffi::OsString> {
     let iter = fs::read_dir(dir).ok()?;
     let entry = iter.filter_map(|entry| entry.ok()).find(|entry| {
@@ -744,6 +2064,38 @@ fn purge_file(file: ffi::OsString) -> Result<()> {
     }
 }
 
+// create index file afresh, starting with the file header.
+fn new_index_flusher(config: &Config) -> Result<Flusher> {
+    let (queue_size, rate) = (config.flush_queue_size, config.flush_rate);
+    let file_path = to_index_file(&config.dir, &config.name);
+    let mut flusher = Flusher::new(&file_path, true, queue_size, rate)?;
+    let header = Header::new(config.z_blocksize, config.m_blocksize);
+    flusher.flush(header.to_bytes()?)?;
+    Ok(flusher)
+}
+
+// create checkpoint file, if checkpoints are enabled for the build.
+fn new_checkpoint<K>(
+    config: &Config,
+    vlog: (Option<ffi::OsString>, u64),
+) -> Result<Option<build::CheckpointRef<K>>> {
+    match config.checkpoint {
+        Some(every) => {
+            let file = to_checkpoint_file(&config.dir, &config.name);
+            let checkpointer = Checkpointer::create(&file, every, vlog)?;
+            Ok(Some(Rc::new(RefCell::new(checkpointer))))
+        }
+        None => Ok(None),
+    }
+}
+
+// truncate file to `len` bytes, discarding data flushed after checkpoint.
+fn truncate_file(file: &ffi::OsStr, len: u64) -> Result<()> {
+    let fd = err_at!(IOError, fs::OpenOptions::new().write(true).open(file))?;
+    err_at!(IOError, fd.set_len(len), "fail truncate {:?}", file)?;
+    err_at!(IOError, fd.sync_all(), "fail sync {:?}", file)
+}
+
 fn open_file_r(file: &ffi::OsStr) -> Result<fs::File> {
     let os_file = path::Path::new(file);
     Ok(err_at!(IOError, fs::OpenOptions::new().read(true).open(os_file))?)
@@ -33,6 +33,12 @@ fn test_robt_read() {
         delta_ok: false,
         value_in_vlog: false,
         flush_queue_size: 32,
+        flush_rate: None,
+        epoch: None,
+        merkle: false,
+        checkpoint: None,
+        z_fill_factor: 1.0,
+        m_fill_factor: 1.0,
     };
     println!("test_robt_read index file {:?}", config.to_index_file_location());
 
@@ -327,6 +333,596 @@ fn test_compact_tombstone() {
     println!("test_compact {}", seed);
 }
 
+#[test]
+fn test_robt_expiry() {
+    let seed: u128 = random();
+    println!("test_robt_expiry {}", seed);
+
+    let dir = std::env::temp_dir().join("test_robt_expiry");
+    let name = "test_robt_expiry";
+    let config = Config::new(dir.as_os_str(), name);
+
+    let mdb = util::load_index(seed, 10_000, 0, 0, 0, None);
+    let clock = mdb.to_seqno() / 2;
+    let expired = |e: &db::Entry<u16, u64, u64>| e.key % 2 == 0 && e.to_seqno() <= clock;
+
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    build.set_expiry(|e| if e.key % 2 == 0 { Some(e.to_seqno()) } else { None });
+    build.build_index(mdb.iter().unwrap(), NoBitmap, None).unwrap();
+
+    let mut index = Index::<u16, u64, u64, NoBitmap>::open(&config.dir, name).unwrap();
+    index.set_expiry_clock(Some(clock));
+    assert_eq!(index.to_expiry_clock(), Some(clock));
+
+    for e in mdb.iter().unwrap() {
+        match index.get(&e.key) {
+            Ok(_) => assert!(!expired(&e), "{}", e.key),
+            Err(Error::KeyNotFound(_, _)) => assert!(expired(&e), "{}", e.key),
+            Err(err) => panic!("{}", err),
+        }
+    }
+    let n = mdb.iter().unwrap().filter(|e| !expired(e)).count();
+    let r = (Bound::<u16>::Unbounded, Bound::<u16>::Unbounded);
+    assert_eq!(index.iter(r).unwrap().count(), n);
+    assert_eq!(index.reverse(r).unwrap().count(), n);
+    index.validate().unwrap();
+
+    let config = Config::new(dir.as_os_str(), "test_robt_expiry-compact");
+    let mut index = index.compact(config, NoBitmap, db::Cutoff::Mono).unwrap();
+    assert_eq!(index.len(), n);
+
+    index.set_expiry_clock(Some(u64::MAX));
+    let n = mdb.iter().unwrap().filter(|e| e.key % 2 == 1).count();
+    assert_eq!(index.iter(r).unwrap().count(), n);
+}
+
+#[test]
+fn test_robt_range_tombstone() {
+    let seed: u128 = random();
+    println!("test_robt_range_tombstone {}", seed);
+
+    let dir = std::env::temp_dir().join("test_robt_range_tombstone");
+    let name = "test_robt_range_tombstone";
+    let config = Config::new(dir.as_os_str(), name);
+
+    let mdb = util::load_index(seed, 10_000, 0, 0, 0, None);
+    let seqno = mdb.to_seqno() / 2;
+    let tombs = vec![
+        RangeTombstone::new(1000..2000, seqno),
+        RangeTombstone::new(30000.., mdb.to_seqno()),
+    ];
+
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    for tomb in tombs.iter() {
+        build.delete_range(tomb.to_range(), tomb.to_seqno());
+    }
+    build.build_index(mdb.iter().unwrap(), NoBitmap, None).unwrap();
+
+    let mut index = Index::<u16, u64, u64, NoBitmap>::open(&config.dir, name).unwrap();
+    assert_eq!(index.to_range_tombstones(), tombs);
+
+    let covered = |e: &db::Entry<u16, u64, u64>| {
+        crate::tombstone::is_covered::<_, _, _, u16>(&tombs, e)
+    };
+    for e in mdb.iter().unwrap() {
+        match index.get(&e.key) {
+            Ok(_) => assert!(!covered(&e), "{}", e.key),
+            Err(Error::KeyNotFound(_, _)) => assert!(covered(&e), "{}", e.key),
+            Err(err) => panic!("{}", err),
+        }
+    }
+    let refs: Vec<u16> =
+        mdb.iter().unwrap().filter(|e| !covered(e)).map(|e| e.key).collect();
+    let r = (Bound::<u16>::Unbounded, Bound::<u16>::Unbounded);
+    let keys: Vec<u16> = index.iter(r).unwrap().map(|e| e.unwrap().key).collect();
+    assert_eq!(keys, refs);
+    index.validate().unwrap();
+
+    let config = Config::new(dir.as_os_str(), "test_robt_range_tombstone-compact");
+    let cutoff = db::Cutoff::Lsm(Bound::Included(seqno));
+    let mut index = index.compact(config, NoBitmap, cutoff).unwrap();
+    assert_eq!(index.len(), refs.len());
+    assert_eq!(index.to_range_tombstones(), tombs[1..].to_vec());
+    let keys: Vec<u16> = index.iter(r).unwrap().map(|e| e.unwrap().key).collect();
+    assert_eq!(keys, refs);
+}
+
+#[test]
+fn test_robt_comparator() {
+    struct Descending;
+
+    impl Comparator<u16> for Descending {
+        const NAME: &'static str = "descending";
+
+        fn compare(a: &u16, b: &u16) -> cmp::Ordering {
+            b.cmp(a)
+        }
+    }
+
+    let seed: u128 = random();
+    println!("test_robt_comparator {}", seed);
+
+    let dir = std::env::temp_dir().join("test_robt_comparator");
+    let name = "test_robt_comparator";
+    let config = Config::new(dir.as_os_str(), name);
+
+    let mdb = util::load_index(seed, 10_000, 0, 0, 0, None);
+    let mut entries: Vec<db::Entry<u16, u64, u64>> = mdb.iter().unwrap().collect();
+    entries.iter_mut().for_each(|e| e.deltas = vec![]);
+    entries.reverse();
+
+    let mut build =
+        Builder::<u16, u64, u64, Descending>::initial_by(config.clone(), vec![]).unwrap();
+    build.build_index(entries.clone().into_iter(), NoBitmap, None).unwrap();
+
+    let mut index =
+        Index::<u16, u64, u64, NoBitmap, Descending>::open(&config.dir, name).unwrap();
+    assert_eq!(index.to_stats().comparator, "descending");
+    index.validate().unwrap();
+
+    for e in entries.iter() {
+        assert_eq!(&index.get(&e.key).unwrap(), e);
+    }
+    let r = (Bound::<u16>::Unbounded, Bound::<u16>::Unbounded);
+    let items: Vec<db::Entry<u16, u64, u64>> =
+        index.iter(r).unwrap().map(|e| e.unwrap()).collect();
+    assert_eq!(items, entries);
+
+    match Index::<u16, u64, u64, NoBitmap>::open(&config.dir, name) {
+        Err(Error::Invalid(_, _)) => (),
+        Err(err) => panic!("unexpected {}", err),
+        Ok(_) => panic!("expected comparator mismatch"),
+    }
+}
+
+#[test]
+fn test_robt_schema() {
+    let seed: u128 = random();
+    println!("test_robt_schema {}", seed);
+
+    let dir = std::env::temp_dir().join("test_robt_schema");
+    let name = "test_robt_schema";
+    let config = Config::new(dir.as_os_str(), name);
+
+    let mdb = util::load_index(seed, 1_000, 0, 0, 0, None);
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    build.build_index(mdb.iter().unwrap(), NoBitmap, None).unwrap();
+
+    let index = Index::<u16, u64, u64, NoBitmap>::open(&config.dir, name).unwrap();
+    assert_eq!(index.to_schema(), Some(Schema::new::<u16, u64, u64>()));
+    assert!(index.verify_schema_id("test_robt_schema/v1").is_err());
+    match Index::<u64, u64, u64, NoBitmap>::open(&config.dir, name) {
+        Err(Error::TypeMismatch(_, _)) => (),
+        Err(err) => panic!("unexpected {}", err),
+        Ok(_) => panic!("unexpected open with mismatching key-type"),
+    }
+
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    build.set_schema_id("test_robt_schema/v1");
+    build.build_index(mdb.iter().unwrap(), NoBitmap, None).unwrap();
+
+    let index = Index::<u16, u64, u64, NoBitmap>::open(&config.dir, name).unwrap();
+    index.verify_schema_id("test_robt_schema/v1").unwrap();
+    assert!(index.verify_schema_id("test_robt_schema/v2").is_err());
+}
+
+#[test]
+fn test_robt_app_metadata() {
+    let seed: u128 = random();
+    println!("test_robt_app_metadata {}", seed);
+
+    let dir = std::env::temp_dir().join("test_robt_app_metadata");
+    let name = "test_robt_app_metadata";
+    let config = Config::new(dir.as_os_str(), name);
+
+    let mdb = util::load_index(seed, 1_000, 0, 0, 0, None);
+    let app_meta = vec![1, 2];
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), app_meta).unwrap();
+    build.put_app_metadata("lsm", 10_u64).unwrap();
+    build.put_app_metadata("replication", "node-1".to_string()).unwrap();
+    build.put_app_metadata("lsm", 20_u64).unwrap();
+    build.build_index(mdb.iter().unwrap(), NoBitmap, None).unwrap();
+
+    let index = Index::<u16, u64, u64, NoBitmap>::open(&config.dir, name).unwrap();
+    assert_eq!(index.to_app_metadata(), vec![1, 2]);
+    assert_eq!(index.to_app_metadata_names(), vec!["lsm", "replication"]);
+    assert_eq!(index.get_app_metadata::<u64>("lsm").unwrap(), Some(20));
+    let val = index.get_app_metadata::<String>("replication").unwrap();
+    assert_eq!(val, Some("node-1".to_string()));
+    assert_eq!(index.get_app_metadata::<u64>("schema").unwrap(), None);
+    assert!(index.get_app_metadata::<u64>("replication").is_err());
+
+    let mut config = config;
+    config.name = "test_robt_app_metadata-compact".to_string();
+    let index = index.compact(config, NoBitmap, db::Cutoff::Mono).unwrap();
+    assert_eq!(index.to_app_metadata(), vec![1, 2]);
+    assert_eq!(index.to_app_metadata_names(), vec!["lsm", "replication"]);
+    assert_eq!(index.get_app_metadata::<u64>("lsm").unwrap(), Some(20));
+    let val = index.get_app_metadata::<String>("replication").unwrap();
+    assert_eq!(val, Some("node-1".to_string()));
+}
+
+#[test]
+fn test_robt_upgrade() {
+    use std::io::{Seek, SeekFrom, Write};
+
+    let seed: u128 = random();
+    println!("test_robt_upgrade {}", seed);
+
+    let dir = std::env::temp_dir().join("test_robt_upgrade");
+    let name = "test_robt_upgrade";
+    let mut config = Config::new(dir.as_os_str(), name);
+    config.set_blocksize(1024, 1024, 1024).set_value_log(true);
+
+    let mdb = util::load_index(seed, 5_000, 1_000, 500, 100, None);
+    let clock = mdb.to_seqno() / 2;
+    let tomb = RangeTombstone::new(1000..2000, clock);
+
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![1]).unwrap();
+    build.set_expiry(|e| if e.key % 2 == 0 { Some(e.to_seqno()) } else { None });
+    build.delete_range(tomb.to_range(), tomb.to_seqno());
+    build.put_app_metadata("lsm", 10_u64).unwrap();
+    build.build_index(mdb.iter().unwrap(), NoBitmap, None).unwrap();
+
+    let mut index = Index::<u16, u64, u64, NoBitmap>::open(&config.dir, name).unwrap();
+    assert_eq!(index.to_format_version(), crate::FORMAT_VERSION);
+
+    let mut new_config = config.clone();
+    new_config.name = "test_robt_upgrade-upgraded".to_string();
+    let mut upgraded = index.try_clone().unwrap().upgrade(new_config, NoBitmap).unwrap();
+
+    assert_eq!(upgraded.to_format_version(), crate::FORMAT_VERSION);
+    assert_eq!(upgraded.to_seqno(), index.to_seqno());
+    assert_eq!(upgraded.len(), index.len());
+    assert_eq!(upgraded.to_app_metadata(), vec![1]);
+    assert_eq!(upgraded.get_app_metadata::<u64>("lsm").unwrap(), Some(10));
+    assert_eq!(upgraded.to_range_tombstones(), vec![tomb]);
+    upgraded.validate().unwrap();
+
+    let r = (Bound::<u16>::Unbounded, Bound::<u16>::Unbounded);
+    for clock in [None, Some(clock)].iter() {
+        index.set_expiry_clock(*clock);
+        upgraded.set_expiry_clock(*clock);
+        let e1: Vec<db::Entry<u16, u64, u64>> =
+            index.iter_versions(r).unwrap().map(|e| e.unwrap()).collect();
+        let e2: Vec<db::Entry<u16, u64, u64>> =
+            upgraded.iter_versions(r).unwrap().map(|e| e.unwrap()).collect();
+        assert_eq!(e1, e2);
+    }
+
+    // unsupported format version.
+    let file = config.to_index_file_location();
+    {
+        let mut fd = fs::OpenOptions::new().write(true).open(&file).unwrap();
+        fd.seek(SeekFrom::Start(8)).unwrap();
+        fd.write_all(&(crate::FORMAT_VERSION + 1).to_be_bytes()).unwrap();
+    }
+    match Index::<u16, u64, u64, NoBitmap>::open(&config.dir, name) {
+        Err(Error::InvalidFile(_, msg)) => {
+            assert!(msg.contains("unsupported"), "{}", msg)
+        }
+        Err(err) => panic!("unexpected {}", err),
+        Ok(_) => panic!("expected unsupported format version"),
+    }
+    // header missing.
+    {
+        let mut fd = fs::OpenOptions::new().write(true).open(&file).unwrap();
+        fd.write_all(&[0; 8]).unwrap();
+    }
+    match Index::<u16, u64, u64, NoBitmap>::open(&config.dir, name) {
+        Err(Error::InvalidFile(_, _)) => (),
+        Err(err) => panic!("unexpected {}", err),
+        Ok(_) => panic!("expected missing header"),
+    }
+}
+
+#[test]
+fn test_robt_format0() {
+    let seed: u128 = random();
+    println!("test_robt_format0 {}", seed);
+
+    let dir = std::env::temp_dir().join("test_robt_format0");
+    let name = "test_robt_format0";
+    let config = Config::new(dir.as_os_str(), name);
+
+    let mdb = util::load_index(seed, 1_000, 200, 100, 100, None);
+    let mut entries: Vec<db::Entry<u16, u64, u64>> = mdb.iter().unwrap().collect();
+    entries.iter_mut().for_each(|e| e.deltas = vec![]);
+    write_format0(&config, &entries);
+
+    let mut index = Index::<u16, u64, u64, NoBitmap>::open(&config.dir, name).unwrap();
+    assert_eq!(index.to_format_version(), 0);
+    assert_eq!(index.to_stats().comparator, Natural::NAME);
+    assert_eq!(index.to_stats().n_count, entries.len() as u64);
+    assert_eq!(index.to_schema(), None);
+    index.validate().unwrap();
+
+    let r = (Bound::<u16>::Unbounded, Bound::<u16>::Unbounded);
+    let items: Vec<db::Entry<u16, u64, u64>> =
+        index.iter_versions(r).unwrap().map(|e| e.unwrap()).collect();
+    assert_eq!(items, entries);
+    for e in entries.iter() {
+        assert_eq!(&index.get_versions(&e.key).unwrap(), e);
+    }
+
+    let mut new_config = config.clone();
+    new_config.name = "test_robt_format0-upgraded".to_string();
+    let mut upgraded = index.upgrade(new_config, NoBitmap).unwrap();
+    assert_eq!(upgraded.to_format_version(), crate::FORMAT_VERSION);
+    assert_eq!(upgraded.len(), entries.len());
+    upgraded.validate().unwrap();
+    let items: Vec<db::Entry<u16, u64, u64>> =
+        upgraded.iter_versions(r).unwrap().map(|e| e.unwrap()).collect();
+    assert_eq!(items, entries);
+}
+
+// write index file in format-0, the layout used before FORMAT_VERSION was
+// introduced: no header, leaf blocks of version-1 entries, a single root
+// block and a positional list of meta-items. Entries are held in leaf
+// blocks, without value-log.
+fn write_format0(config: &Config, entries: &[db::Entry<u16, u64, u64>]) {
+    use crate::{config::StatsV1, entry::EntryV1, meta::MetaItem, vlog};
+    use mkit::cbor::{self, Cbor};
+
+    fs::create_dir_all(&config.dir).unwrap();
+
+    let to_block = |items: Vec<EntryV1<u16, u64, u64>>, size: usize| -> Vec<u8> {
+        let mut block = vec![];
+        Cbor::Major4(cbor::Info::Indefinite, vec![]).encode(&mut block).unwrap();
+        for item in items.into_iter() {
+            block.extend_from_slice(&util::into_cbor_bytes(item).unwrap());
+        }
+        let brk = util::into_cbor_bytes(cbor::SimpleValue::Break).unwrap();
+        block.extend_from_slice(&brk);
+        assert!(block.len() < size, "{}", block.len());
+        block.resize(size, 0);
+        block
+    };
+
+    let mut data = vec![];
+    let mut mz = vec![];
+    for chunk in entries.chunks(50) {
+        let fpos = data.len() as u64;
+        mz.push(EntryV1::MZ { key: chunk[0].key, fpos });
+        let items = chunk.iter().map(|e| EntryV1::ZZ {
+            key: e.key,
+            value: vlog::Value::from(e.value.clone()),
+            deltas: vec![],
+        });
+        data.extend_from_slice(&to_block(items.collect(), config.z_blocksize));
+    }
+    let root = data.len() as u64;
+    data.extend_from_slice(&to_block(mz, config.m_blocksize));
+
+    let stats = StatsV1 {
+        name: config.name.clone(),
+        z_blocksize: config.z_blocksize,
+        m_blocksize: config.m_blocksize,
+        v_blocksize: config.v_blocksize,
+        n_count: entries.len() as u64,
+        n_deleted: entries.iter().filter(|e| e.is_deleted()).count(),
+        seqno: entries.iter().map(|e| e.to_seqno()).max().unwrap(),
+        ..StatsV1::default()
+    };
+    let metas = vec![
+        MetaItem::AppMetadata(vec![]),
+        MetaItem::Stats(util::into_cbor_bytes(stats).unwrap()),
+        MetaItem::Bitmap(NoBitmap.to_bytes().unwrap()),
+        MetaItem::Root(root),
+        MetaItem::Marker(ROOT_MARKER.clone()),
+    ];
+    let mut block = util::into_cbor_bytes(metas).unwrap();
+    let len = block.len() as u64;
+    let m = Builder::<u16, u64, u64>::compute_root_block(block.len() + 16);
+    block.resize(m, 0);
+    block[m - 16..m - 8].copy_from_slice(&(m as u64).to_be_bytes());
+    block[m - 8..m].copy_from_slice(&len.to_be_bytes());
+    data.extend_from_slice(&block);
+
+    fs::write(config.to_index_file_location(), &data).unwrap();
+}
+
+#[test]
+fn test_robt_deterministic() {
+    let seed: u128 = random();
+    println!("test_robt_deterministic {}", seed);
+
+    let dir = std::env::temp_dir().join("test_robt_deterministic");
+    let name = "test_robt_deterministic";
+    let mut config = Config::new(dir.as_os_str(), name);
+    config.set_blocksize(1024, 1024, 1024).set_value_log(true).set_epoch(1000);
+
+    let mdb = util::load_index(seed, 5_000, 1_000, 500, 100, None);
+    let tomb = RangeTombstone::new(1000..2000, mdb.to_seqno() / 2);
+    let (index_file, vlog_file) =
+        (config.to_index_file_location(), config.to_vlog_file_location());
+    let build = || -> (Vec<u8>, Vec<u8>) {
+        let config = config.clone();
+        let mut build = Builder::<u16, u64, u64>::initial(config, vec![]).unwrap();
+        build.set_expiry(|e| if e.key % 2 == 0 { Some(e.to_seqno()) } else { None });
+        build.delete_range(tomb.to_range(), tomb.to_seqno());
+        build.put_app_metadata("lsm", 10_u64).unwrap();
+        build.build_index(mdb.iter().unwrap(), NoBitmap, None).unwrap();
+
+        (fs::read(&index_file).unwrap(), fs::read(&vlog_file).unwrap())
+    };
+
+    let (index1, vlog1) = build();
+    let (index2, vlog2) = build();
+    assert!(index1 == index2, "index files differ");
+    assert!(vlog1 == vlog2, "vlog files differ");
+
+    let index = Index::<u16, u64, u64, NoBitmap>::open(&config.dir, name).unwrap();
+    let stats = index.to_stats();
+    assert_eq!(stats.epoch, 1000);
+    assert_eq!(stats.build_time, 0);
+}
+
+#[test]
+fn test_robt_observer() {
+    use std::sync::atomic::Ordering::SeqCst;
+
+    let seed: u128 = random();
+    println!("test_robt_observer {}", seed);
+
+    let dir = std::env::temp_dir().join("test_robt_observer");
+    let name = "test_robt_observer";
+    let mut config = Config::new(dir.as_os_str(), name);
+    config
+        .set_blocksize(1024, 1024, 1024)
+        .set_value_log(true)
+        .set_flush_rate(100 * 1024 * 1024);
+
+    let mdb = util::load_index(seed, 5_000, 1_000, 500, 100, None);
+    let progress: Rc<RefCell<Vec<Progress>>> = Rc::new(RefCell::new(vec![]));
+
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    let sink = Rc::clone(&progress);
+    build.set_observer(move |p| sink.borrow_mut().push(p.clone()));
+    build.build_index(mdb.iter().unwrap(), NoBitmap, None).unwrap();
+
+    let progress = progress.borrow();
+    assert!(!progress.is_empty());
+    for (i, ps) in progress.windows(2).enumerate() {
+        assert!(ps[0].n_entries < ps[1].n_entries, "entries at {}", i);
+        assert!(ps[0].n_ibytes < ps[1].n_ibytes, "ibytes at {}", i);
+        assert!(ps[0].n_vbytes <= ps[1].n_vbytes, "vbytes at {}", i);
+    }
+    let last = progress.last().unwrap();
+    assert_eq!(last.n_entries, mdb.len() as u64);
+    assert_eq!(last.n_blocks, progress.len() as u64);
+    assert!(last.n_vbytes > 0);
+
+    // cancel the build after a few leaf blocks.
+    let cancel = Arc::new(AtomicBool::new(false));
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    let token = Arc::clone(&cancel);
+    build
+        .set_observer(move |p| token.store(p.n_blocks >= 2, SeqCst))
+        .set_cancel(Arc::clone(&cancel));
+    match build.build_index(mdb.iter().unwrap(), NoBitmap, None) {
+        Err(Error::Cancelled(_, _)) => (),
+        res => panic!("unexpected {:?}", res),
+    }
+    assert!(cancel.load(SeqCst));
+    assert!(!path::Path::new(&config.to_index_file_location()).exists());
+    assert!(!path::Path::new(&config.to_vlog_file_location()).exists());
+}
+
+#[test]
+fn test_robt_dynamic() {
+    use crate::Dyn;
+
+    let seed: u128 = random();
+    println!("test_robt_dynamic {}", seed);
+
+    let dir = std::env::temp_dir().join("test_robt_dynamic");
+    let name = "test_robt_dynamic";
+    let mut config = Config::new(dir.as_os_str(), name);
+    config.set_value_log(true);
+
+    let mdb = util::load_index(seed, 1_000, 1_000, 100, 100, None);
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    build.build_index(mdb.iter().unwrap(), NoBitmap, None).unwrap();
+
+    let mut index = crate::db::DynIndex::<NoBitmap>::open(&config.dir, name).unwrap();
+    index.validate().unwrap();
+
+    let r = (Bound::<Dyn>::Unbounded, Bound::<Dyn>::Unbounded);
+    let mut iter = index.iter_versions(r).unwrap();
+    for e1 in mdb.iter().unwrap() {
+        let e2 = iter.next().unwrap().unwrap();
+        assert_eq!(u64::try_from(e2.key.clone()).unwrap(), u64::from(e1.key));
+        assert_eq!(e2.to_seqno(), e1.to_seqno());
+        assert_eq!(e2.is_deleted(), e1.is_deleted());
+        assert_eq!(e2.deltas.len(), e1.deltas.len());
+    }
+    assert!(iter.next().is_none());
+
+    for e1 in mdb.iter().unwrap() {
+        let e2 = index.get(&Dyn::from(u64::from(e1.key))).unwrap();
+        assert_eq!(e2.to_seqno(), e1.to_seqno());
+    }
+}
+
+#[test]
+fn test_robt_corrupted() {
+    // shipped corpus of malformed files.
+    let corpus = path::Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/corrupt");
+    let mut n = 0;
+    for entry in fs::read_dir(&corpus).unwrap() {
+        let file = entry.unwrap().path();
+        if file.extension().and_then(|x| x.to_str()) != Some("indx") {
+            continue;
+        }
+        println!("test_robt_corrupted {:?}", file);
+        match Index::<u16, u64, u64, NoBitmap>::open_file(file.as_os_str()) {
+            Err(Error::InvalidFile(_, _)) | Err(Error::Fatal(_, _)) => (),
+            Err(err) => panic!("{:?} unexpected {}", file, err),
+            Ok(_) => panic!("{:?} expected error", file),
+        }
+        assert!(crate::db::DynIndex::<NoBitmap>::open_file(file.as_os_str()).is_err());
+        n += 1;
+    }
+    assert!(n > 0);
+
+    // corrupt a valid index, by truncating and by flipping bytes.
+    let seed: u128 = random();
+    println!("test_robt_corrupted {}", seed);
+    let mut rng = StdRnd::from_seed(seed.to_le_bytes());
+
+    let dir = std::env::temp_dir().join("test_robt_corrupted");
+    let name = "test_robt_corrupted";
+    let mut config = Config::new(dir.as_os_str(), name);
+    config.set_blocksize(1024, 1024, 1024).set_value_log(true);
+
+    let mdb = util::load_index(seed, 2_000, 500, 200, 100, None);
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    build.build_index(mdb.iter().unwrap(), NoBitmap, None).unwrap();
+    let data = fs::read(config.to_index_file_location()).unwrap();
+
+    // every mutation is written into the same scratch file.
+    let file = dir.join(format!("{}-scratch-robt.indx", name));
+    for i in 0..200 {
+        let mut bad = data.clone();
+        match i % 4 {
+            0 => bad.truncate(rng.gen::<usize>() % data.len()),
+            1 => {
+                // tail carries the meta-block offset and length.
+                let off = data.len() - 1 - (rng.gen::<usize>() % 16);
+                bad[off] ^= 1 << (rng.gen::<u8>() % 8);
+            }
+            _ => {
+                for _ in 0..(1 + i % 8) {
+                    let off = rng.gen::<usize>() % data.len();
+                    bad[off] ^= 1 << (rng.gen::<u8>() % 8);
+                }
+            }
+        }
+        fs::write(&file, &bad).unwrap();
+
+        let res = Index::<u16, u64, u64, NoBitmap>::open_file(file.as_os_str());
+        let mut index = match res {
+            Ok(index) => index,
+            Err(_) => continue,
+        };
+        index.validate().ok();
+        index.validate_deep().ok();
+        let r = (Bound::<u16>::Unbounded, Bound::<u16>::Unbounded);
+        let n = mdb.len() * 2;
+        if let Ok(iter) = index.iter_versions(r) {
+            iter.take(n).count();
+        }
+        if let Ok(iter) = index.reverse_versions(r) {
+            iter.take(n).count();
+        }
+        for e in mdb.iter().unwrap().take(100) {
+            index.get_versions(&e.key).ok();
+        }
+    }
+    fs::remove_file(&file).unwrap();
+}
+
 fn validate_stats(stats: &Stats, config: &Config, mdb: &OMap<u16, u64>, n_abytes: u64) {
     assert_eq!(stats.name, config.name);
     assert_eq!(stats.z_blocksize, config.z_blocksize);
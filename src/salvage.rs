@@ -0,0 +1,420 @@
+use mkit::{
+    cbor::{FromCbor, IntoCbor},
+    db,
+    nobitmap::NoBitmap,
+};
+
+use std::{cmp, convert::TryFrom, ffi, fmt, fs, hash::Hash, io, marker, path, vec};
+
+use crate::{
+    comparator::{Comparator, Natural},
+    config::{to_vlog_file, Config, Stats},
+    entry::Entry,
+    files::IndexFileName,
+    header::Header,
+    merkle::HashesRegion,
+    meta::{self, Metas},
+    robt::{read_meta_block, Builder},
+    schema::Schema,
+    tombstone::RangeTombstone,
+    util, Error, Result,
+};
+
+/// Report returned by [salvage][crate::db::salvage].
+#[derive(Clone, Debug, Default)]
+pub struct SalvageReport {
+    /// Whether the meta-block, at the tail of the index file, was readable.
+    pub meta_ok: bool,
+    /// Number of entries in the damaged index, as per its statistics,
+    /// available only when the meta-block is readable.
+    pub expected: Option<u64>,
+    /// Number of leaf blocks recovered.
+    pub n_zblocks: usize,
+    /// Number of intermediate blocks skipped.
+    pub n_mblocks: usize,
+    /// Number of entries recovered into the new index.
+    pub n_count: u64,
+    /// Number of entries dropped because their value could not be
+    /// resolved from value-log file.
+    pub n_lost_entries: u64,
+    /// Number of older versions dropped because they could not be
+    /// resolved from value-log file.
+    pub n_lost_deltas: u64,
+    /// Keys for entries dropped, in their `Debug` format.
+    pub lost_keys: Vec<String>,
+    /// Regions of index file that could not be decoded.
+    pub lost_ranges: Vec<LostRange>,
+}
+
+/// Region of index file that could not be decoded, during salvage. Since
+/// leaf blocks are flushed in sort order, keys lost in this region lie
+/// between `after` and `before`, which are in their `Debug` format.
+///
+/// Note that a region may only have held intermediate blocks, in which case
+/// no entries were lost.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LostRange {
+    /// File position where the region starts.
+    pub fpos: u64,
+    /// Length of the region in bytes.
+    pub length: u64,
+    /// Last key recovered before this region, None if region is at the start.
+    pub after: Option<String>,
+    /// First key recovered after this region, None if region is at the end.
+    pub before: Option<String>,
+}
+
+enum Block<K, V, D> {
+    Z(Vec<Entry<K, V, D>>),
+    M,
+}
+
+/// Recover entries from a damaged index file, and build a fresh index
+/// using `config`.
+///
+/// Index file is scanned from the first block, block by block, and every leaf
+/// block that can be decoded is recovered, without relying on the
+/// intermediate blocks or the meta-block. Values and older versions,
+/// persisted in value-log file, are resolved when possible. If `vlog` is
+/// None, value-log file is located in the same directory as the index file.
+/// Recovered entries are streamed into the new index, entries that are out
+/// of sort order, with respect to previously recovered entries, are dropped
+/// and reported as lost.
+///
+/// Block sizes are picked from the meta-block when it is readable,
+/// otherwise `config.z_blocksize` and `config.m_blocksize` shall be used
+/// to scan the damaged file. Application metadata, including named items,
+/// schema-id and range tombstones are carried over to the new index when
+/// the meta-block is readable.
+pub fn salvage<K, V, D>(
+    file: &ffi::OsStr,
+    vlog: Option<&ffi::OsStr>,
+    config: Config,
+) -> Result<SalvageReport>
+where
+    K: Clone + Ord + Hash + fmt::Debug + FromCbor + IntoCbor,
+    V: Clone + FromCbor + IntoCbor,
+    D: Clone + FromCbor + IntoCbor,
+{
+    salvage_by::<K, V, D, Natural>(file, vlog, config)
+}
+
+/// Same as [salvage], but for index files whose keys are ordered using
+/// comparator `C`.
+pub fn salvage_by<K, V, D, C>(
+    file: &ffi::OsStr,
+    vlog: Option<&ffi::OsStr>,
+    config: Config,
+) -> Result<SalvageReport>
+where
+    K: Clone + Hash + fmt::Debug + FromCbor + IntoCbor,
+    V: Clone + FromCbor + IntoCbor,
+    D: Clone + FromCbor + IntoCbor,
+    C: Comparator<K>,
+{
+    let mut index = err_at!(IOError, fs::OpenOptions::new().read(true).open(file))?;
+    let mut report = SalvageReport::default();
+
+    let (metas, end) = match read_meta_block(&mut index) {
+        Ok((metas, fpos)) => (metas, fpos),
+        Err(_) => (Metas::default(), err_at!(IOError, index.metadata())?.len()),
+    };
+    // block hashes, if any, sit between the last block and the meta-block.
+    let end = match metas.to_version(meta::MERKLE_HASHES) {
+        Some(version) if version > 1 => {
+            let region = metas.decode::<HashesRegion>(meta::MERKLE_HASHES).ok().flatten();
+            region.map(|r| cmp::min(r.fpos, end)).unwrap_or(end)
+        }
+        _ => end,
+    };
+    let stats = metas.get(meta::STATS).and_then(|data| Stats::from_bytes(data).ok());
+    report.meta_ok = stats.is_some();
+    report.expected = stats.as_ref().map(|s| s.n_count);
+
+    let (z_blocksize, m_blocksize) = match stats.as_ref() {
+        Some(s) if s.z_blocksize > 0 && s.m_blocksize > 0 => {
+            (s.z_blocksize, s.m_blocksize)
+        }
+        _ => (config.z_blocksize, config.m_blocksize),
+    };
+    if z_blocksize == 0 || m_blocksize == 0 {
+        err_at!(Invalid, msg: "bad block size m:{} z:{}", m_blocksize, z_blocksize)?
+    }
+
+    let vlog = match vlog {
+        Some(vlog) => {
+            let vlog = err_at!(IOError, fs::OpenOptions::new().read(true).open(vlog))?;
+            Some(vlog)
+        }
+        None => open_vlog(file, stats.as_ref()),
+    };
+
+    // older index files don't have header, blocks start at offset 0 and are
+    // in format version 0.
+    let (fpos, format) = match Header::read(&mut index) {
+        Ok(Some(header)) => (header.length, header.version),
+        _ => (0, metas.decode(meta::FORMAT).ok().flatten().unwrap_or(0)),
+    };
+
+    let app_meta = metas.get(meta::APP_METADATA).unwrap_or_default().to_vec();
+    let tombstones: Vec<RangeTombstone<K>> =
+        metas.decode(meta::RANGE_TOMBSTONES).ok().flatten().unwrap_or_default();
+    let schema: Option<Schema> = metas.decode(meta::SCHEMA).ok().flatten();
+
+    let mut iter = Recover::<K, V, D, C> {
+        index,
+        vlog,
+        fpos,
+        end,
+        format,
+        z_blocksize,
+        m_blocksize,
+        // z-blocks and m-blocks are interleaved in the index file.
+        stride: util::gcd(z_blocksize as u64, m_blocksize as u64),
+        bad: None,
+        last_key: None,
+        prev_key: None,
+        entries: vec![].into_iter(),
+        report: &mut report,
+
+        _cmp: marker::PhantomData,
+    }
+    .peekable();
+    if iter.peek().is_none() {
+        err_at!(InvalidFile, msg: "no entries recovered from {:?}", file)?
+    }
+
+    let mut builder = Builder::<K, V, D, C>::initial_by(config, app_meta)?;
+    builder.set_app_metadata_items(metas.to_app_metadata_items());
+    if let Some(id) = schema.and_then(|s| s.id) {
+        builder.set_schema_id(&id);
+    }
+    for tomb in tombstones.into_iter() {
+        builder.delete_range(tomb.to_range(), tomb.to_seqno());
+    }
+    builder.build_items(iter, NoBitmap, None)?;
+
+    Ok(report)
+}
+
+// Iterator over entries recovered from leaf blocks, in file order, yielded
+// along with their expiry. Updates the salvage report as it goes.
+struct Recover<'a, K, V, D, C> {
+    index: fs::File,
+    vlog: Option<fs::File>,
+    fpos: u64,
+    end: u64,
+    format: u64,
+    z_blocksize: usize,
+    m_blocksize: usize,
+    stride: u64,
+    // start of the region that could not be decoded.
+    bad: Option<u64>,
+    // last key in the last recovered leaf block.
+    last_key: Option<K>,
+    // last key yielded by this iterator.
+    prev_key: Option<K>,
+    entries: vec::IntoIter<Entry<K, V, D>>,
+    report: &'a mut SalvageReport,
+
+    _cmp: marker::PhantomData<C>,
+}
+
+impl<'a, K, V, D, C> Recover<'a, K, V, D, C>
+where
+    K: Clone + fmt::Debug + FromCbor,
+    V: FromCbor,
+    D: FromCbor,
+    C: Comparator<K>,
+{
+    // scan for the next leaf block that can be decoded.
+    fn next_zblock(&mut self) -> Option<Vec<Entry<K, V, D>>> {
+        let (z_blocksize, m_blocksize) = (self.z_blocksize, self.m_blocksize);
+
+        while self.fpos < self.end {
+            let (fpos, end) = (self.fpos, self.end);
+            let n = cmp::min(cmp::max(z_blocksize, m_blocksize) as u64, end - fpos);
+            let block = (self.fpos, n, self.format);
+            let zz = match read_block::<K, V, D, C>(&mut self.index, block) {
+                Some(Block::M) => {
+                    self.report.n_mblocks += 1;
+                    self.fpos += m_blocksize as u64;
+                    continue;
+                }
+                Some(Block::Z(zz)) => zz,
+                None => {
+                    self.bad.get_or_insert(self.fpos);
+                    self.fpos += self.stride;
+                    continue;
+                }
+            };
+
+            if let Some(start) = self.bad.take() {
+                self.report.lost_ranges.push(LostRange {
+                    fpos: start,
+                    length: self.fpos - start,
+                    after: self.last_key.as_ref().map(|k| format!("{:?}", k)),
+                    before: zz.first().map(|e| format!("{:?}", e.as_key())),
+                });
+            }
+            self.report.n_zblocks += 1;
+            self.fpos += z_blocksize as u64;
+            self.last_key = zz.last().map(|e| e.to_key());
+            return Some(zz);
+        }
+
+        if let Some(start) = self.bad.take() {
+            let after = self.last_key.as_ref().map(|k| format!("{:?}", k));
+            let (fpos, length, before) = (start, self.end - start, None);
+            self.report.lost_ranges.push(LostRange { fpos, length, after, before });
+        }
+        None
+    }
+
+    fn lost_entry(&mut self, key: &K) {
+        self.report.n_lost_entries += 1;
+        self.report.lost_keys.push(format!("{:?}", key));
+    }
+}
+
+impl<'a, K, V, D, C> Iterator for Recover<'a, K, V, D, C>
+where
+    K: Clone + fmt::Debug + FromCbor,
+    V: Clone + FromCbor,
+    D: Clone + FromCbor,
+    C: Comparator<K>,
+{
+    type Item = (db::Entry<K, V, D>, Option<u64>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = match self.entries.next() {
+                Some(entry) => entry,
+                None => {
+                    self.entries = self.next_zblock()?.into_iter();
+                    continue;
+                }
+            };
+
+            // leaf blocks are in sort order, unless the file is badly damaged.
+            let key = entry.to_key();
+            let ordered = match self.prev_key.as_ref() {
+                Some(prev) => C::compare(&key, prev) == cmp::Ordering::Greater,
+                None => true,
+            };
+            if !ordered {
+                self.lost_entry(&key);
+                continue;
+            }
+
+            let expiry = entry.to_expiry();
+            match resolve(entry, self.vlog.as_mut()) {
+                Some((entry, n_deltas)) => {
+                    self.report.n_lost_deltas += n_deltas;
+                    self.report.n_count += 1;
+                    self.prev_key = Some(key);
+                    break Some((entry, expiry));
+                }
+                None => self.lost_entry(&key),
+            }
+        }
+    }
+}
+
+// read and decode block at `fpos`, return None if block is not decodable.
+fn read_block<K, V, D, C>(
+    index: &mut fs::File,
+    (fpos, n, format): (u64, u64, u64),
+) -> Option<Block<K, V, D>>
+where
+    K: FromCbor,
+    V: FromCbor,
+    D: FromCbor,
+    C: Comparator<K>,
+{
+    let block = read_file!(index, io::SeekFrom::Start(fpos), n, "read block").ok()?;
+    let entries = Entry::<K, V, D>::decode_block(&block, format).ok()?;
+
+    let sorted = entries
+        .windows(2)
+        .all(|w| C::compare(w[0].as_key(), w[1].as_key()) == cmp::Ordering::Less);
+    match entries.first() {
+        _ if !sorted => None,
+        Some(Entry::ZZ { .. }) if entries.iter().all(|e| e.is_zblock()) => {
+            Some(Block::Z(entries))
+        }
+        Some(Entry::MM { .. }) | Some(Entry::MZ { .. }) => {
+            match entries.iter().any(|e| e.is_zblock()) {
+                true => None,
+                false => Some(Block::M),
+            }
+        }
+        _ => None,
+    }
+}
+
+// resolve value and deltas from value-log file, return the entry along
+// with the number of older versions that were dropped. Return None if the
+// value itself cannot be resolved.
+fn resolve<K, V, D>(
+    entry: Entry<K, V, D>,
+    vlog: Option<&mut fs::File>,
+) -> Option<(db::Entry<K, V, D>, u64)>
+where
+    K: Clone,
+    V: Clone + FromCbor,
+    D: Clone + FromCbor,
+{
+    let n_deltas = match &entry {
+        Entry::ZZ { deltas, .. } => deltas.len() as u64,
+        _ => 0,
+    };
+
+    let mut vlog = vlog;
+    match to_native(entry.clone(), vlog.as_deref_mut(), true) {
+        Ok(entry) => Some((entry, 0)),
+        Err(_) => {
+            let mut entry = entry;
+            entry.drain_deltas();
+            to_native(entry, vlog, false).ok().map(|entry| (entry, n_deltas))
+        }
+    }
+}
+
+fn to_native<K, V, D>(
+    entry: Entry<K, V, D>,
+    vlog: Option<&mut fs::File>,
+    versions: bool,
+) -> Result<db::Entry<K, V, D>>
+where
+    V: FromCbor,
+    D: FromCbor,
+{
+    let entry = match vlog {
+        Some(vlog) => entry.into_native(vlog, versions)?,
+        None => entry,
+    };
+    db::Entry::try_from(entry)
+}
+
+fn open_vlog(file: &ffi::OsStr, stats: Option<&Stats>) -> Option<fs::File> {
+    let file = path::Path::new(file);
+    let dir = file.parent()?.as_os_str();
+    let vlog_file = match stats.and_then(|s| s.vlog_file.as_ref()) {
+        Some(vlog_file) => {
+            let file_name = path::Path::new(vlog_file).file_name()?;
+            let vp: path::PathBuf = [dir, file_name].iter().collect();
+            vp.into_os_string()
+        }
+        None => {
+            let file_name = IndexFileName(file.as_os_str().to_os_string());
+            let name = String::try_from(file_name).ok()?;
+            to_vlog_file(dir, &name)
+        }
+    };
+    fs::OpenOptions::new().read(true).open(vlog_file).ok()
+}
+
+#[cfg(test)]
+#[path = "salvage_test.rs"]
+mod salvage_test;
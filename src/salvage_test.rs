@@ -0,0 +1,149 @@
+use mkit::nobitmap::NoBitmap;
+use rand::prelude::random;
+
+use std::{
+    fs,
+    io::{Seek, SeekFrom, Write},
+    ops::Bound,
+};
+
+use super::*;
+use crate::{db::Index, header::Header};
+
+fn build_index(seed: u128, name: &str) -> (Config, Vec<db::Entry<u16, u64, u64>>) {
+    let dir = std::env::temp_dir().join("test_salvage");
+    let mut config = Config::new(dir.as_os_str(), name);
+    config.set_blocksize(1024, 1024, 1024).set_value_log(true);
+
+    let mdb = util::load_index(seed, 5_000, 1_000, 500, 100, None);
+    let meta = vec![1, 2];
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), meta).unwrap();
+    build.build_index(mdb.iter().unwrap(), NoBitmap, None).unwrap();
+
+    let mut index = open_index(&config.dir, name);
+    let r = (Bound::<u16>::Unbounded, Bound::<u16>::Unbounded);
+    let entries = index.iter_versions(r).unwrap().map(|e| e.unwrap()).collect();
+    (config, entries)
+}
+
+fn open_index(dir: &ffi::OsStr, name: &str) -> Index<u16, u64, u64, NoBitmap> {
+    Index::open(dir, name).unwrap()
+}
+
+fn salvaged_entries(config: &Config) -> Vec<db::Entry<u16, u64, u64>> {
+    let mut index = open_index(&config.dir, &config.name);
+    index.validate().unwrap();
+    let r = (Bound::<u16>::Unbounded, Bound::<u16>::Unbounded);
+    index.iter_versions(r).unwrap().map(|e| e.unwrap()).collect()
+}
+
+#[test]
+fn test_salvage_meta() {
+    let seed: u128 = random();
+    println!("test_salvage_meta {}", seed);
+
+    let (config, entries) = build_index(seed, "test_salvage_meta");
+    let file = config.to_index_file_location();
+    {
+        // wipe out the meta-block at the tail.
+        let len = fs::metadata(&file).unwrap().len();
+        let fd = fs::OpenOptions::new().write(true).open(&file).unwrap();
+        fd.set_len(len - 100).unwrap();
+    }
+    assert!(Index::<u16, u64, u64, NoBitmap>::open_file(&file).is_err());
+
+    let mut new_config = config.clone();
+    new_config.name = "test_salvage_meta-salvaged".to_string();
+    let report = salvage::<u16, u64, u64>(&file, None, new_config.clone()).unwrap();
+    println!("{:?}", report);
+
+    assert!(!report.meta_ok);
+    assert_eq!(report.expected, None);
+    assert_eq!(report.n_count, entries.len() as u64);
+    assert_eq!(report.n_lost_entries, 0);
+    assert_eq!(report.n_lost_deltas, 0);
+    assert_eq!(salvaged_entries(&new_config), entries);
+}
+
+#[test]
+fn test_salvage_block() {
+    let seed: u128 = random();
+    println!("test_salvage_block {}", seed);
+
+    let (config, entries) = build_index(seed, "test_salvage_block");
+    let file = config.to_index_file_location();
+    let fpos = Header::new(1024, 1024).length;
+    {
+        // first block, after the header, is a leaf block.
+        let mut fd = fs::OpenOptions::new().write(true).open(&file).unwrap();
+        fd.seek(SeekFrom::Start(fpos)).unwrap();
+        fd.write_all(&[0xff; 64]).unwrap();
+    }
+
+    let mut new_config = config.clone();
+    new_config.name = "test_salvage_block-salvaged".to_string();
+    let report = salvage::<u16, u64, u64>(&file, None, new_config.clone()).unwrap();
+    println!("{:?}", report);
+
+    assert!(report.meta_ok);
+    assert_eq!(report.expected, Some(entries.len() as u64));
+    assert!(report.n_count < entries.len() as u64);
+    assert_eq!(report.lost_ranges[0].fpos, fpos);
+    assert_eq!(report.lost_ranges[0].after, None);
+
+    let salvaged = salvaged_entries(&new_config);
+    assert_eq!(salvaged.len() as u64, report.n_count);
+    assert_eq!(&entries[entries.len() - salvaged.len()..], salvaged.as_slice());
+
+    let index = Index::<u16, u64, u64, NoBitmap>::open_file(&file).unwrap();
+    let salvaged = open_index(&new_config.dir, &new_config.name);
+    assert_eq!(salvaged.to_app_metadata(), index.to_app_metadata());
+}
+
+#[test]
+fn test_salvage_by() {
+    struct Descending;
+
+    impl Comparator<u16> for Descending {
+        const NAME: &'static str = "descending";
+
+        fn compare(a: &u16, b: &u16) -> cmp::Ordering {
+            b.cmp(a)
+        }
+    }
+
+    let seed: u128 = random();
+    println!("test_salvage_by {}", seed);
+
+    let dir = std::env::temp_dir().join("test_salvage");
+    let mut config = Config::new(dir.as_os_str(), "test_salvage_by");
+    config.set_blocksize(1024, 1024, 1024).set_value_log(true);
+
+    let mdb = util::load_index(seed, 5_000, 0, 0, 0, None);
+    let mut entries: Vec<db::Entry<u16, u64, u64>> = mdb.iter().unwrap().collect();
+    entries.iter_mut().for_each(|e| e.deltas = vec![]);
+    entries.reverse();
+
+    let mut build =
+        Builder::<u16, u64, u64, Descending>::initial_by(config.clone(), vec![]).unwrap();
+    build.set_schema_id("test_salvage_by/v1");
+    build.build_index(entries.clone().into_iter(), NoBitmap, None).unwrap();
+
+    let file = config.to_index_file_location();
+    let mut new_config = config.clone();
+    new_config.name = "test_salvage_by-salvaged".to_string();
+    let report =
+        salvage_by::<u16, u64, u64, Descending>(&file, None, new_config.clone()).unwrap();
+    assert_eq!(report.n_count, entries.len() as u64);
+    assert_eq!(report.n_lost_entries, 0);
+
+    let (dir, name) = (&new_config.dir, &new_config.name);
+    let mut index =
+        Index::<u16, u64, u64, NoBitmap, Descending>::open(dir, name).unwrap();
+    index.validate().unwrap();
+    index.verify_schema_id("test_salvage_by/v1").unwrap();
+    let r = (Bound::<u16>::Unbounded, Bound::<u16>::Unbounded);
+    let items: Vec<db::Entry<u16, u64, u64>> =
+        index.iter(r).unwrap().map(|e| e.unwrap()).collect();
+    assert_eq!(items, entries);
+}
@@ -3,15 +3,61 @@ use mkit::{
     db::{self, Bloom},
 };
 
-use std::{cmp, convert::TryFrom, hash, marker, time};
+use std::{cell::RefCell, cmp, convert::TryFrom, hash, marker, rc::Rc, time};
 
 use crate::{Error, Result};
 
+// Items scanned while building an index, entries are optionally paired with
+// their expiry, refer to [Entry][crate::entry::Entry].
+pub trait ScanItem<K, V, D>: Sized {
+    fn as_entry(&self) -> &db::Entry<K, V, D>;
+
+    fn into_parts(self) -> (db::Entry<K, V, D>, Option<u64>);
+
+    fn purge(self, cutoff: db::Cutoff) -> Option<Self>;
+}
+
+impl<K, V, D> ScanItem<K, V, D> for db::Entry<K, V, D> {
+    fn as_entry(&self) -> &db::Entry<K, V, D> {
+        self
+    }
+
+    fn into_parts(self) -> (db::Entry<K, V, D>, Option<u64>) {
+        (self, None)
+    }
+
+    fn purge(self, cutoff: db::Cutoff) -> Option<Self> {
+        db::Entry::purge(self, cutoff)
+    }
+}
+
+impl<K, V, D> ScanItem<K, V, D> for (db::Entry<K, V, D>, Option<u64>) {
+    fn as_entry(&self) -> &db::Entry<K, V, D> {
+        &self.0
+    }
+
+    fn into_parts(self) -> (db::Entry<K, V, D>, Option<u64>) {
+        self
+    }
+
+    fn purge(self, cutoff: db::Cutoff) -> Option<Self> {
+        let (entry, expiry) = self;
+        Some((entry.purge(cutoff)?, expiry))
+    }
+}
+
+// Scans that compute a bitmap over the keys scanned so far. Bitmap's state
+// is persisted in checkpoints, refer to [Checkpointer][crate::checkpoint::Checkpointer].
+pub trait ToBitmap {
+    fn to_bitmap(&self) -> Result<Vec<u8>>;
+}
+
 // Iterator wrapper, to wrap full-table scanners and count seqno,
-// index-items, deleted items and epoch.
+// index-items, deleted items and epoch. Yields each entry along with its
+// expiry, if any.
 pub struct BuildScan<K, V, D, I> {
     iter: I,
-    entry: Option<db::Entry<K, V, D>>,
+    entry: Option<(db::Entry<K, V, D>, Option<u64>)>,
 
     start: time::SystemTime,
     seqno: u64,
@@ -38,9 +84,9 @@ impl<K, V, D, I> BuildScan<K, V, D, I> {
         }
     }
 
-    pub fn push(&mut self, entry: db::Entry<K, V, D>) {
+    pub fn push(&mut self, item: (db::Entry<K, V, D>, Option<u64>)) {
         self.entry = match &self.entry {
-            None => Some(entry),
+            None => Some(item),
             Some(_) => unreachable!(),
         }
     }
@@ -61,27 +107,37 @@ impl<K, V, D, I> BuildScan<K, V, D, I> {
 
 impl<K, V, D, I> Iterator for BuildScan<K, V, D, I>
 where
-    I: Iterator<Item = db::Entry<K, V, D>>,
+    I: Iterator,
+    I::Item: ScanItem<K, V, D>,
 {
-    type Item = db::Entry<K, V, D>;
+    type Item = (db::Entry<K, V, D>, Option<u64>);
 
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
         match self.entry.take() {
-            Some(entry) => Some(entry),
+            Some(item) => Some(item),
             None => {
-                let entry = self.iter.next()?;
+                let (entry, expiry) = self.iter.next()?.into_parts();
                 self.seqno = cmp::max(self.seqno, entry.to_seqno());
                 self.n_count += 1;
                 if entry.is_deleted() {
                     self.n_deleted += 1;
                 }
-                Some(entry)
+                Some((entry, expiry))
             }
         }
     }
 }
 
+impl<K, V, D, I> ToBitmap for BuildScan<K, V, D, I>
+where
+    I: ToBitmap,
+{
+    fn to_bitmap(&self) -> Result<Vec<u8>> {
+        self.iter.to_bitmap()
+    }
+}
+
 // Iterator wrapper, to wrap full-table scanners and generate bitmap index.
 //
 // Computes a bitmap of all keys iterated over the index `I`. Bitmap type
@@ -117,15 +173,25 @@ impl<K, V, D, B, I> Iterator for BitmappedScan<K, V, D, B, I>
 where
     K: hash::Hash,
     B: Bloom,
-    I: Iterator<Item = db::Entry<K, V, D>>,
+    I: Iterator,
+    I::Item: ScanItem<K, V, D>,
 {
-    type Item = db::Entry<K, V, D>;
+    type Item = I::Item;
 
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
-        let entry = self.iter.next()?;
-        self.bitmap.add_key(&entry.key);
-        Some(entry)
+        let item = self.iter.next()?;
+        self.bitmap.add_key(&item.as_entry().key);
+        Some(item)
+    }
+}
+
+impl<K, V, D, B, I> ToBitmap for BitmappedScan<K, V, D, B, I>
+where
+    B: Bloom,
+{
+    fn to_bitmap(&self) -> Result<Vec<u8>> {
+        err_at!(Fatal, self.bitmap.to_bytes())
     }
 }
 
@@ -155,15 +221,55 @@ impl<K, V, D, I> CompactScan<K, V, D, I> {
 
 impl<K, V, D, I> Iterator for CompactScan<K, V, D, I>
 where
-    I: Iterator<Item = db::Entry<K, V, D>>,
+    I: Iterator,
+    I::Item: ScanItem<K, V, D>,
 {
-    type Item = db::Entry<K, V, D>;
+    type Item = I::Item;
 
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            if let Some(entry) = self.iter.next()?.purge(self.cutoff) {
-                break Some(entry);
+            if let Some(item) = self.iter.next()?.purge(self.cutoff) {
+                break Some(item);
+            }
+        }
+    }
+}
+
+// Iterator wrapper, to unwrap fallible items. Iteration stops at the first
+// error, which is held in a shared slot, so that the build can fail instead
+// of finalizing an index over a truncated stream.
+pub struct FailScan<I> {
+    iter: I,
+    failed: Rc<RefCell<Option<Error>>>,
+}
+
+impl<I> FailScan<I> {
+    pub fn new(iter: I) -> FailScan<I> {
+        FailScan { iter, failed: Rc::new(RefCell::new(None)) }
+    }
+
+    pub fn to_failed(&self) -> Rc<RefCell<Option<Error>>> {
+        Rc::clone(&self.failed)
+    }
+}
+
+impl<I, T> Iterator for FailScan<I>
+where
+    I: Iterator<Item = Result<T>>,
+{
+    type Item = T;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed.borrow().is_some() {
+            return None;
+        }
+        match self.iter.next()? {
+            Ok(item) => Some(item),
+            Err(err) => {
+                self.failed.borrow_mut().replace(err);
+                None
             }
         }
     }
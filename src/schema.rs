@@ -0,0 +1,75 @@
+use mkit::Cborize;
+
+use std::any;
+
+use crate::{dynamic::Dyn, Error, Result};
+
+const SCHEMA_VER1: u32 = 0x0001;
+
+/// Schema finger-print for index's key-type, value-type and delta-type.
+///
+/// Schema is persisted along with the index. Applications can supply a
+/// stable schema-id via [crate::db::Builder::set_schema_id] and verify it
+/// using [crate::db::Index::verify_schema_id]. Type-names, as reported by
+/// [std::any::type_name], are verified while opening the index and a
+/// mismatch fails with `TypeMismatch`. Note that type-names are not
+/// guaranteed to be stable across compiler versions, use [Dyn] to open
+/// an index irrespective of its types.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Cborize)]
+pub struct Schema {
+    /// Type name for key.
+    pub key: String,
+    /// Type name for value.
+    pub value: String,
+    /// Type name for delta.
+    pub delta: String,
+    /// Optional application supplied, stable, schema-id.
+    pub id: Option<String>,
+}
+
+impl Schema {
+    const ID: u32 = SCHEMA_VER1;
+
+    /// Create a schema from type-names of `K`, `V` and `D`.
+    pub fn new<K, V, D>() -> Schema {
+        Schema {
+            key: any::type_name::<K>().to_string(),
+            value: any::type_name::<V>().to_string(),
+            delta: any::type_name::<D>().to_string(),
+            id: None,
+        }
+    }
+
+    /// Verify that this schema, typically loaded from the index, matches
+    /// the types `K`, `V` and `D`. Type-erased [Dyn] matches any type, both
+    /// while reading an index and when the index was built with [Dyn].
+    pub fn verify_types<K, V, D>(&self) -> Result<()> {
+        let other = Schema::new::<K, V, D>();
+        let dyn_name = any::type_name::<Dyn>();
+        let ok = |x: &str, y: &str| x == y || x == dyn_name || y == dyn_name;
+
+        if !ok(&self.key, &other.key) {
+            err_at!(TypeMismatch, msg: "key-type {} != {}", self.key, other.key)
+        } else if !ok(&self.value, &other.value) {
+            err_at!(TypeMismatch, msg: "value-type {} != {}", self.value, other.value)
+        } else if !ok(&self.delta, &other.delta) {
+            err_at!(TypeMismatch, msg: "delta-type {} != {}", self.delta, other.delta)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Verify that this schema, typically loaded from the index, matches
+    /// the application supplied schema-id.
+    pub fn verify_id(&self, id: &str) -> Result<()> {
+        match self.id.as_ref() {
+            Some(sid) if sid == id => Ok(()),
+            Some(sid) => err_at!(TypeMismatch, msg: "schema-id {} != {}", sid, id),
+            None => err_at!(TypeMismatch, msg: "schema-id missing, expected {}", id),
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "schema_test.rs"]
+mod schema_test;
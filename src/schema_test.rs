@@ -0,0 +1,31 @@
+use super::*;
+
+#[test]
+fn test_schema() {
+    let schema = Schema::new::<u64, String, u64>();
+    assert_eq!(schema.key, "u64");
+    assert_eq!(schema.value, "alloc::string::String");
+    assert_eq!(schema.id, None);
+
+    schema.verify_types::<u64, String, u64>().unwrap();
+    match schema.verify_types::<String, String, u64>() {
+        Err(Error::TypeMismatch(_, _)) => (),
+        res => panic!("unexpected {:?}", res),
+    }
+    match schema.verify_types::<u64, u64, u64>() {
+        Err(Error::TypeMismatch(_, _)) => (),
+        res => panic!("unexpected {:?}", res),
+    }
+    assert!(schema.verify_id("movies/v1").is_err());
+    schema.verify_types::<Dyn, Dyn, Dyn>().unwrap();
+    schema.verify_types::<u64, Dyn, u64>().unwrap();
+
+    let schema = Schema::new::<u64, String, Dyn>();
+    schema.verify_types::<u64, String, u64>().unwrap();
+    assert!(schema.verify_types::<u64, u64, u64>().is_err());
+
+    let mut schema = schema;
+    schema.id = Some("movies/v1".to_string());
+    schema.verify_id("movies/v1").unwrap();
+    assert!(schema.verify_id("movies/v2").is_err());
+}
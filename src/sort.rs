@@ -0,0 +1,271 @@
+//! Module implement external sort, to build index from unsorted input.
+//!
+//! Entries are buffered in memory, in serialized form, until the memory
+//! budget is exhausted. Buffered entries are then sorted and spilled as a
+//! sorted run into a temporary file under `config.dir`. Sorted runs, along
+//! with the last buffer, are k-way merged into a single sorted stream.
+//! Duplicate keys are resolved by seqno, entry with the higher seqno wins.
+
+use mkit::{
+    cbor::{Cbor, FromCbor, IntoCbor},
+    db,
+};
+
+use std::{
+    cmp,
+    collections::BinaryHeap,
+    convert::TryFrom,
+    ffi, fs,
+    io::{self, BufRead, Write},
+    marker, mem, vec,
+};
+
+use crate::{
+    comparator::Comparator, config::Config, entry::Entry, merge::Head, util, Error,
+    Result,
+};
+
+// Approximate overhead for each buffered entry, in bytes.
+const ENTRY_OVERHEAD: usize = 64;
+// Maximum number of sorted runs to merge at once, bounds open files.
+const MERGE_FANIN: usize = 64;
+
+// buffered entry as {key, seqno, serialized-entry}.
+type Item<K> = (K, u64, Vec<u8>);
+
+/// Sort entries from an unsorted iterator, within a memory budget.
+pub struct Sorter<K, C> {
+    prefix: ffi::OsString,
+    mem_budget: usize,
+    items: Vec<Item<K>>,
+    footprint: usize,
+    runs: Vec<ffi::OsString>,
+    n_files: usize,
+
+    _cmp: marker::PhantomData<C>,
+}
+
+impl<K, C> Drop for Sorter<K, C> {
+    fn drop(&mut self) {
+        for file in self.runs.iter() {
+            fs::remove_file(file).ok(); // NOTE: ignore remove errors.
+        }
+    }
+}
+
+impl<K, C> Sorter<K, C>
+where
+    C: Comparator<K>,
+{
+    pub fn new(config: &Config, mem_budget: usize) -> Sorter<K, C> {
+        let mut prefix = config.to_index_file_location();
+        prefix.push(".sort");
+
+        Sorter {
+            prefix,
+            mem_budget,
+            items: vec![],
+            footprint: 0,
+            runs: vec![],
+            n_files: 0,
+
+            _cmp: marker::PhantomData,
+        }
+    }
+
+    pub fn add<V, D>(&mut self, entry: db::Entry<K, V, D>) -> Result<()>
+    where
+        K: Clone,
+        V: IntoCbor,
+        D: IntoCbor,
+    {
+        let (key, seqno) = (entry.key.clone(), entry.to_seqno());
+        let data = util::into_cbor_bytes(Entry::from(entry))?;
+
+        self.footprint += data.len() + ENTRY_OVERHEAD;
+        self.items.push((key, seqno, data));
+        if self.footprint > self.mem_budget {
+            self.spill()?;
+        }
+
+        Ok(())
+    }
+
+    // sort buffered items, drop older duplicates and write them as a run.
+    fn spill(&mut self) -> Result<()> {
+        let items = self.sort_items();
+        self.write_run(items.into_iter().map(|(_, _, data)| Ok(data)))
+    }
+
+    fn write_run<I>(&mut self, iter: I) -> Result<()>
+    where
+        I: Iterator<Item = Result<Vec<u8>>>,
+    {
+        let file = {
+            let mut file = self.prefix.clone();
+            file.push(format!("-{}", self.n_files));
+            file
+        };
+        let fd = err_at!(IOError, fs::File::create(&file))?;
+        self.runs.push(file.clone());
+        self.n_files += 1;
+
+        let mut fd = io::BufWriter::new(fd);
+        for data in iter {
+            err_at!(IOError, fd.write_all(&data?), "spill {:?}", file)?;
+        }
+        err_at!(IOError, fd.flush(), "spill {:?}", file)?;
+
+        Ok(())
+    }
+
+    // merge sorted runs, in multiple passes, until they can be merged at once.
+    fn merge_runs<V, D>(&mut self) -> Result<()>
+    where
+        K: FromCbor,
+        V: FromCbor + IntoCbor,
+        D: FromCbor + IntoCbor,
+    {
+        while self.runs.len() > MERGE_FANIN {
+            let runs: Vec<ffi::OsString> = self.runs.drain(..MERGE_FANIN).collect();
+            let iter = SortScan::<K, V, D, C>::new(vec![], runs)?;
+            self.write_run(iter.map(|e| util::into_cbor_bytes(Entry::from(e?))))?;
+        }
+        Ok(())
+    }
+
+    fn sort_items(&mut self) -> Vec<Item<K>> {
+        let mut items = mem::take(&mut self.items);
+        self.footprint = 0;
+
+        // for the same key, higher seqno shall sort first.
+        items.sort_by(|a, b| C::compare(&a.0, &b.0).then(b.1.cmp(&a.1)));
+        items.dedup_by(|b, a| C::compare(&a.0, &b.0) == cmp::Ordering::Equal);
+        items
+    }
+
+    /// Return a sorted iterator over all entries added so far.
+    pub fn into_scan<V, D>(mut self) -> Result<SortScan<K, V, D, C>>
+    where
+        K: FromCbor,
+        V: FromCbor + IntoCbor,
+        D: FromCbor + IntoCbor,
+    {
+        self.merge_runs::<V, D>()?;
+        let items = self.sort_items();
+        SortScan::new(items, mem::take(&mut self.runs))
+    }
+}
+
+enum Source<K> {
+    Mem(vec::IntoIter<Item<K>>),
+    Run(io::BufReader<fs::File>),
+}
+
+impl<K> Source<K> {
+    fn next<V, D>(&mut self) -> Result<Option<db::Entry<K, V, D>>>
+    where
+        K: FromCbor,
+        V: FromCbor,
+        D: FromCbor,
+    {
+        let entry: Entry<K, V, D> = match self {
+            Source::Mem(iter) => match iter.next() {
+                Some((_, _, data)) => util::from_cbor_bytes(&data)?.0,
+                None => return Ok(None),
+            },
+            Source::Run(fd) => match err_at!(IOError, fd.fill_buf())?.len() {
+                0 => return Ok(None),
+                _ => {
+                    let (val, _) = err_at!(InvalidFile, Cbor::decode(fd))?;
+                    err_at!(InvalidFile, Entry::from_cbor(val))?
+                }
+            },
+        };
+
+        Ok(Some(db::Entry::try_from(entry)?))
+    }
+}
+
+/// Iterator over sorted entries, returned by [Sorter::into_scan]. Temporary
+/// files are removed when the iterator is dropped.
+pub struct SortScan<K, V, D, C> {
+    sources: Vec<Source<K>>,
+    heap: BinaryHeap<Head<K, V, D, C>>,
+    runs: Vec<ffi::OsString>,
+}
+
+impl<K, V, D, C> Drop for SortScan<K, V, D, C> {
+    fn drop(&mut self) {
+        for file in self.runs.iter() {
+            fs::remove_file(file).ok(); // NOTE: ignore remove errors.
+        }
+    }
+}
+
+impl<K, V, D, C> SortScan<K, V, D, C>
+where
+    K: FromCbor,
+    V: FromCbor,
+    D: FromCbor,
+    C: Comparator<K>,
+{
+    // merge buffered items and sorted runs, runs are removed on drop.
+    fn new(items: Vec<Item<K>>, runs: Vec<ffi::OsString>) -> Result<Self> {
+        let mut iter = SortScan {
+            sources: vec![Source::Mem(items.into_iter())],
+            heap: BinaryHeap::new(),
+            runs,
+        };
+        for file in iter.runs.iter() {
+            let fd = err_at!(IOError, fs::File::open(file))?;
+            iter.sources.push(Source::Run(io::BufReader::new(fd)));
+        }
+        for src in 0..iter.sources.len() {
+            iter.fill(src)?;
+        }
+
+        Ok(iter)
+    }
+
+    fn fill(&mut self, src: usize) -> Result<()> {
+        if let Some(entry) = self.sources[src].next()? {
+            self.heap.push(Head::new(entry, src));
+        }
+        Ok(())
+    }
+}
+
+impl<K, V, D, C> Iterator for SortScan<K, V, D, C>
+where
+    K: FromCbor,
+    V: FromCbor,
+    D: FromCbor,
+    C: Comparator<K>,
+{
+    type Item = Result<db::Entry<K, V, D>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let head = self.heap.pop()?;
+        iter_result!(self.fill(head.src));
+
+        // drop older duplicates from other runs.
+        loop {
+            let dup = match self.heap.peek() {
+                Some(next) => C::compare(&next.entry.key, &head.entry.key),
+                None => break Some(Ok(head.entry)),
+            };
+            match dup {
+                cmp::Ordering::Equal => {
+                    let src = self.heap.pop().unwrap().src;
+                    iter_result!(self.fill(src));
+                }
+                _ => break Some(Ok(head.entry)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "sort_test.rs"]
+mod sort_test;
@@ -0,0 +1,118 @@
+use mkit::nobitmap::NoBitmap;
+use rand::{prelude::random, rngs::StdRnd, seq::SliceRandom, Rng, SeedableRng};
+
+use std::{collections::BTreeMap, ops::Bound};
+
+use super::*;
+use crate::{
+    comparator::Natural,
+    db::{Builder, Index},
+};
+
+#[test]
+fn test_sorter() {
+    let seed: u128 = random();
+    println!("test_sorter {}", seed);
+    let mut rng = StdRnd::from_seed(seed.to_le_bytes());
+
+    let dir = std::env::temp_dir().join("test_sorter");
+    fs::create_dir_all(&dir).unwrap();
+    let config = Config::new(dir.as_os_str(), "test_sorter");
+
+    let mem_budget = [1024, 64 * 1024, 1024 * 1024][rng.gen::<usize>() % 3];
+    println!("test_sorter mem_budget:{}", mem_budget);
+
+    let mut refs: BTreeMap<u16, db::Entry<u16, u64, u64>> = BTreeMap::new();
+    let mut sorter = Sorter::<u16, Natural>::new(&config, mem_budget);
+    for _ in 0..20_000 {
+        let (key, value) = (rng.gen::<u16>() % 5_000, rng.gen::<u64>());
+        let seqno = rng.gen::<u64>() % 1_000_000;
+        let entry = db::Entry {
+            key,
+            value: db::Value::U { value, seqno },
+            deltas: vec![],
+        };
+        match refs.get(&key) {
+            Some(old) if old.to_seqno() >= seqno => (),
+            _ => {
+                refs.insert(key, entry.clone());
+            }
+        }
+        sorter.add(entry).unwrap();
+    }
+    println!("test_sorter runs:{}", sorter.runs.len());
+
+    let iter = sorter.into_scan::<u64, u64>().unwrap();
+    let entries: Vec<db::Entry<u16, u64, u64>> = iter.map(|e| e.unwrap()).collect();
+    assert_eq!(entries.len(), refs.len());
+    for (entry, (key, refe)) in entries.into_iter().zip(refs.into_iter()) {
+        assert_eq!(entry.key, key);
+        assert_eq!(entry.to_seqno(), refe.to_seqno());
+    }
+
+    // temporary files are removed.
+    for item in fs::read_dir(&dir).unwrap() {
+        let file = item.unwrap().file_name();
+        assert!(!file.to_str().unwrap().contains(".sort-"), "{:?}", file);
+    }
+}
+
+#[test]
+fn test_from_unsorted() {
+    let seed: u128 = random();
+    println!("test_from_unsorted {}", seed);
+    let mut rng = StdRnd::from_seed(seed.to_le_bytes());
+
+    let dir = std::env::temp_dir().join("test_from_unsorted");
+    let name = "test_from_unsorted";
+    let mut config = Config::new(dir.as_os_str(), name);
+    config
+        .set_blocksize(1024, 1024, 1024)
+        .set_value_log(rng.gen())
+        .set_delta(rng.gen());
+
+    let mdb = crate::util::load_index(seed, 5_000, 1_000, 500, 100, None);
+    let mut entries: Vec<db::Entry<u16, u64, u64>> = mdb.iter().unwrap().collect();
+    entries.shuffle(&mut rng);
+
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    build.from_unsorted(entries.into_iter(), 16 * 1024, NoBitmap, None).unwrap();
+
+    let mut index = Index::<u16, u64, u64, NoBitmap>::open(&config.dir, name).unwrap();
+    index.validate().unwrap();
+    assert_eq!(index.len(), mdb.len());
+
+    let r = (Bound::<u16>::Unbounded, Bound::<u16>::Unbounded);
+    let mut iter = index.iter_versions(r).unwrap();
+    for mut e1 in mdb.iter().unwrap() {
+        if !config.delta_ok {
+            e1.deltas = vec![];
+        }
+        assert_eq!(e1, iter.next().unwrap().unwrap());
+    }
+    assert!(iter.next().is_none());
+}
+
+#[test]
+fn test_try_from_unsorted() {
+    let seed: u128 = random();
+    println!("test_try_from_unsorted {}", seed);
+
+    let dir = std::env::temp_dir().join("test_try_from_unsorted");
+    let name = "test_try_from_unsorted";
+    let mut config = Config::new(dir.as_os_str(), name);
+    config.set_blocksize(1024, 1024, 1024);
+
+    let mdb = crate::util::load_index(seed, 5_000, 0, 0, 0, None);
+    let mut entries: Vec<Result<db::Entry<u16, u64, u64>>> =
+        mdb.iter().unwrap().map(Ok).collect();
+    entries.insert(entries.len() / 2, err_at!(Invalid, msg: "bad record"));
+
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    match build.try_from_unsorted(entries.into_iter(), 16 * 1024, NoBitmap, None) {
+        Err(Error::Invalid(_, msg)) => assert!(msg.contains("bad record"), "{}", msg),
+        res => panic!("unexpected {:?}", res),
+    }
+    assert!(!std::path::Path::new(&config.to_index_file_location()).exists());
+    assert!(Index::<u16, u64, u64, NoBitmap>::open(&config.dir, name).is_err());
+}
@@ -0,0 +1,332 @@
+//! Module implement split of an index into key-range shards.
+//!
+//! Entries are read from the source index's leaf blocks without fetching
+//! their values, and encoded into leaf blocks for each shard. Values and
+//! deltas referred from the source value-log are copied as raw bytes into
+//! the shard's value-log, without re-serializing them. Intermediate blocks
+//! for each shard are built fresh, refer to [SplitZZ].
+
+use mkit::{
+    cbor::{self, Cbor, FromCbor, IntoCbor},
+    db::{self, Bloom},
+};
+
+use std::{
+    cell::RefCell, cmp, collections::VecDeque, convert::TryFrom, hash, io, marker, rc::Rc,
+};
+
+use crate::{
+    build::{HashesRef, LeafIter},
+    comparator::Comparator,
+    concat::{self, Source},
+    config::Config,
+    dynamic::Dyn,
+    entry::Entry,
+    flush::Flusher,
+    merkle, util, vlog, Error, Result,
+};
+
+/// Iterator over source entries, in key order, as encoded in leaf blocks.
+pub struct RawIter<K, V, D, C> {
+    source: Source,
+    z_blocksize: usize,
+    leaves: VecDeque<u64>,
+    entries: VecDeque<Entry<K, V, D>>,
+
+    _cmp: marker::PhantomData<C>,
+}
+
+impl<K, V, D, C> RawIter<K, V, D, C>
+where
+    K: FromCbor,
+    V: FromCbor,
+    D: FromCbor,
+    C: Comparator<K>,
+{
+    pub fn new(mut source: Source, z_blocksize: usize) -> Result<Self> {
+        let leaves = concat::to_leaves::<K, V, D>(&mut source)?;
+        let val = RawIter {
+            source,
+            z_blocksize,
+            leaves: leaves.into_iter().map(|(_, fpos)| fpos).collect(),
+            entries: VecDeque::new(),
+
+            _cmp: marker::PhantomData,
+        };
+
+        Ok(val)
+    }
+
+    // return the next entry, only if it sorts before `end`.
+    fn next_before(&mut self, end: Option<&K>) -> Result<Option<Entry<K, V, D>>> {
+        while self.entries.is_empty() {
+            let fpos = match self.leaves.pop_front() {
+                Some(fpos) => fpos,
+                None => return Ok(None),
+            };
+            let seek = io::SeekFrom::Start(fpos);
+            let index = &mut self.source.index;
+            let zblock = read_file!(index, seek, self.z_blocksize, "read leaf block")?;
+            let entries = Entry::<K, V, D>::decode_block(&zblock, self.source.format)?;
+            self.entries.extend(entries);
+        }
+
+        match (self.entries.front(), end) {
+            (Some(entry), Some(end)) => match C::compare(entry.as_key(), end) {
+                cmp::Ordering::Less => Ok(self.entries.pop_front()),
+                _ => Ok(None),
+            },
+            (_, _) => Ok(self.entries.pop_front()),
+        }
+    }
+
+    fn push_front(&mut self, entry: Entry<K, V, D>) {
+        self.entries.push_front(entry)
+    }
+
+    fn read_vlog(&mut self, fpos: u64, length: u64) -> Result<Vec<u8>> {
+        match self.source.vlog.as_mut() {
+            Some(vlog) => {
+                let seek = io::SeekFrom::Start(fpos);
+                read_file!(vlog, seek, length, "read value-log")
+            }
+            None => err_at!(InvalidFile, msg: "value-log reference without value-log"),
+        }
+    }
+}
+
+/// Iterator over leaf blocks for a single shard, holding source entries
+/// that sort before `end`. Yields the first key and file position of each
+/// z-block, same as [BuildZZ][crate::build::BuildZZ].
+pub struct SplitZZ<'a, K, V, D, B, C> {
+    z_blocksize: usize,
+    z_limit: usize,
+    v_blocksize: usize,
+    value_in_vlog: bool,
+    delta_ok: bool,
+    iflush: Rc<RefCell<Flusher>>,
+    vflush: Rc<RefCell<Flusher>>,
+    bitmap: Rc<RefCell<B>>,
+    hashes: HashesRef,
+
+    iter: &'a mut RawIter<K, V, D, C>,
+    end: Option<K>,
+
+    pub seqno: u64,
+    pub n_count: u64,
+    pub n_deleted: usize,
+}
+
+impl<'a, K, V, D, B, C> SplitZZ<'a, K, V, D, B, C> {
+    pub fn new(
+        config: &Config,
+        (iflush, vflush): (Rc<RefCell<Flusher>>, Rc<RefCell<Flusher>>),
+        bitmap: Rc<RefCell<B>>,
+        hashes: HashesRef,
+        iter: &'a mut RawIter<K, V, D, C>,
+        end: Option<K>,
+    ) -> Self {
+        SplitZZ {
+            z_blocksize: config.z_blocksize,
+            z_limit: config.to_z_limit(),
+            v_blocksize: config.v_blocksize,
+            value_in_vlog: config.value_in_vlog,
+            delta_ok: config.delta_ok,
+            iflush,
+            vflush,
+            bitmap,
+            hashes,
+
+            iter,
+            end,
+
+            seqno: 0,
+            n_count: 0,
+            n_deleted: 0,
+        }
+    }
+}
+
+impl<'a, K, V, D, B, C> SplitZZ<'a, K, V, D, B, C>
+where
+    K: Clone + hash::Hash + FromCbor + IntoCbor,
+    V: Clone + FromCbor + IntoCbor,
+    D: Clone + FromCbor + IntoCbor,
+    B: Bloom,
+    C: Comparator<K>,
+{
+    fn split(&mut self) -> Result<Option<(K, u64)>> {
+        let mut zblock = Vec::with_capacity(self.z_blocksize);
+        let mut vblock = Vec::with_capacity(self.v_blocksize);
+        let block_size = self.z_blocksize.saturating_sub(1);
+
+        let mut first_key: Option<K> = None;
+
+        Cbor::Major4(cbor::Info::Indefinite, vec![]).encode(&mut zblock)?;
+
+        let vfpos = self.vflush.borrow().to_fpos().unwrap_or(0);
+        loop {
+            let entry = match self.iter.next_before(self.end.as_ref())? {
+                Some(entry) => entry,
+                None if first_key.is_some() => break,
+                None => return Ok(None),
+            };
+
+            let vpos = vfpos + err_at!(FailConvert, u64::try_from(vblock.len()))?;
+            let (e, vbytes, (deleted, seqno)) = self.copy_entry(entry.clone(), vpos)?;
+            let ibytes = util::into_cbor_bytes(e)?;
+
+            let limit = match first_key {
+                Some(_) => self.z_limit,
+                None => block_size,
+            };
+            if (zblock.len() + ibytes.len()) > limit {
+                if first_key.is_none() {
+                    err_at!(Invalid, msg: "entry {} exceeds z-block", ibytes.len())?
+                }
+                self.iter.push_front(entry);
+                break;
+            }
+            first_key.get_or_insert_with(|| entry.to_key());
+            self.bitmap.borrow_mut().add_key(entry.as_key());
+            zblock.extend_from_slice(&ibytes);
+            vblock.extend_from_slice(&vbytes);
+
+            self.seqno = cmp::max(self.seqno, seqno);
+            self.n_count += 1;
+            if deleted {
+                self.n_deleted += 1;
+            }
+        }
+
+        let brk = util::into_cbor_bytes(cbor::SimpleValue::Break)?;
+        zblock.extend_from_slice(&brk);
+        zblock.resize(self.z_blocksize, 0);
+
+        let fpos = self.iflush.borrow().to_fpos().unwrap_or(0);
+        if let Some(hashes) = self.hashes.as_ref() {
+            let hash = merkle::leaf_hash(&zblock, &vblock);
+            hashes.borrow_mut().insert(fpos, hash);
+        }
+
+        self.vflush.borrow_mut().flush(vblock)?;
+        self.iflush.borrow_mut().flush(zblock)?;
+
+        Ok(Some((first_key.unwrap(), fpos)))
+    }
+
+    // encode source entry for the shard, values and deltas referred from
+    // source value-log are copied as raw bytes into the shard's value-log
+    // starting at `vfpos`. Return the entry, its value-log bytes, and
+    // whether it is deleted along with its seqno.
+    #[allow(clippy::type_complexity)]
+    fn copy_entry(
+        &mut self,
+        entry: Entry<K, V, D>,
+        vfpos: u64,
+    ) -> Result<(Entry<K, V, D>, Vec<u8>, (bool, u64))> {
+        let (key, value, deltas, expiry) = match entry {
+            Entry::ZZ { key, value, deltas, expiry } => (key, value, deltas, expiry),
+            _ => err_at!(InvalidFile, msg: "intermediate entry in z-block")?,
+        };
+        let mut vblock = vec![];
+
+        let (value, state) = match value {
+            vlog::Value::N { value } => {
+                let state = to_state(&value);
+                match self.value_in_vlog {
+                    true => {
+                        let value = vlog::Value::N { value };
+                        let (value, data) = value.into_reference(vfpos)?;
+                        vblock.extend_from_slice(&data);
+                        (value, state)
+                    }
+                    false => (vlog::Value::N { value }, state),
+                }
+            }
+            vlog::Value::R { fpos, length } => {
+                let data = self.iter.read_vlog(fpos, length)?;
+                match self.value_in_vlog {
+                    // value is copied as raw bytes, decode only its envelope
+                    // without constructing the value.
+                    true => {
+                        let value: db::Value<Dyn> = util::from_cbor_bytes(&data)?.0;
+                        vblock.extend_from_slice(&data);
+                        (vlog::Value::R { fpos: vfpos, length }, to_state(&value))
+                    }
+                    false => {
+                        let value: db::Value<V> = util::from_cbor_bytes(&data)?.0;
+                        let state = to_state(&value);
+                        (vlog::Value::N { value }, state)
+                    }
+                }
+            }
+        };
+
+        let deltas = if self.delta_ok { deltas } else { vec![] };
+        let mut drefs = vec![];
+        if self.value_in_vlog || self.delta_ok {
+            Cbor::Major4(cbor::Info::Indefinite, vec![]).encode(&mut vblock)?;
+            for delta in deltas.into_iter() {
+                let dpos = vfpos + err_at!(FailConvert, u64::try_from(vblock.len()))?;
+                let delta = match delta {
+                    vlog::Delta::N { delta } => {
+                        let delta = vlog::Delta::N { delta };
+                        let (delta, data) = delta.into_reference(dpos)?;
+                        vblock.extend_from_slice(&data);
+                        delta
+                    }
+                    vlog::Delta::R { fpos, length } => {
+                        vblock.extend_from_slice(&self.iter.read_vlog(fpos, length)?);
+                        vlog::Delta::R { fpos: dpos, length }
+                    }
+                };
+                drefs.push(delta);
+            }
+            vblock.extend_from_slice(&util::into_cbor_bytes(cbor::SimpleValue::Break)?);
+        }
+
+        let entry = Entry::ZZ { key, value, deltas: drefs, expiry };
+        Ok((entry, vblock, state))
+    }
+}
+
+impl<'a, K, V, D, B, C> Iterator for SplitZZ<'a, K, V, D, B, C>
+where
+    K: Clone + hash::Hash + FromCbor + IntoCbor,
+    V: Clone + FromCbor + IntoCbor,
+    D: Clone + FromCbor + IntoCbor,
+    B: Bloom,
+    C: Comparator<K>,
+{
+    type Item = Result<(K, u64)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.split().transpose()
+    }
+}
+
+impl<'a, K, V, D, B, C> LeafIter<K> for SplitZZ<'a, K, V, D, B, C>
+where
+    K: Clone + hash::Hash + FromCbor + IntoCbor,
+    V: Clone + FromCbor + IntoCbor,
+    D: Clone + FromCbor + IntoCbor,
+    B: Bloom,
+    C: Comparator<K>,
+{
+    fn to_entries(&self) -> u64 {
+        self.n_count
+    }
+}
+
+// whether value is deleted, along with its seqno.
+pub fn to_state<V>(value: &db::Value<V>) -> (bool, u64) {
+    match value {
+        db::Value::U { seqno, .. } => (false, *seqno),
+        db::Value::D { seqno } => (true, *seqno),
+    }
+}
+
+#[cfg(test)]
+#[path = "split_test.rs"]
+mod split_test;
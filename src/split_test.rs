@@ -0,0 +1,121 @@
+use mkit::{db::BuildIndex, nobitmap::NoBitmap};
+use rand::{prelude::random, rngs::StdRnd, Rng, SeedableRng};
+
+use std::ops::Bound;
+
+use super::*;
+use crate::{
+    db::{Builder, Index},
+    Config,
+};
+
+#[test]
+fn test_split_at() {
+    let seed: u128 = random();
+    println!("test_split_at {}", seed);
+    let mut rng = StdRnd::from_seed(seed.to_le_bytes());
+
+    let dir = std::env::temp_dir().join("test_split_at");
+    let name = "test_split_at";
+    let mut config = Config::new(dir.as_os_str(), name);
+    config
+        .set_blocksize(1024, 1024, 1024)
+        .set_value_log(rng.gen())
+        .set_delta(rng.gen());
+    println!("vlog:{} delta:{}", config.value_in_vlog, config.delta_ok);
+
+    let mdb = crate::util::load_index(seed, 5_000, 1_000, 500, 100, None);
+    let entries: Vec<db::Entry<u16, u64, u64>> = mdb.iter().unwrap().collect();
+
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    build.put_app_metadata("tenant", 10_u64).unwrap();
+    build.build_index(entries.clone().into_iter(), NoBitmap, None).unwrap();
+    let index = Index::<u16, u64, u64, NoBitmap>::open(&config.dir, name).unwrap();
+
+    // split keys from entries, such that every shard holds an entry.
+    let n = 1 + (rng.gen::<usize>() % 4);
+    let keys: Vec<u16> = (1..n).map(|i| entries[i * entries.len() / n].key).collect();
+
+    let shards: Vec<(Config, NoBitmap)> = (0..n)
+        .map(|i| {
+            let mut config = Config::new(dir.as_os_str(), &format!("{}-{}", name, i));
+            config
+                .set_blocksize(1024, 1024, 1024)
+                .set_value_log(rng.gen())
+                .set_delta(rng.gen())
+                .set_merkle(rng.gen());
+            (config, NoBitmap)
+        })
+        .collect();
+    let configs: Vec<Config> = shards.iter().map(|(c, _)| c.clone()).collect();
+
+    let mut indexes = index.split_at(&keys, shards).unwrap();
+    assert_eq!(indexes.len(), n);
+
+    let mut refs = entries.clone().into_iter().peekable();
+    for (i, (shard, config)) in indexes.iter_mut().zip(configs.iter()).enumerate() {
+        let mut items = vec![];
+        while let Some(e) = refs.peek() {
+            match keys.get(i) {
+                Some(key) if e.key >= *key => break,
+                _ => items.push(refs.next().unwrap()),
+            }
+        }
+
+        let stats = shard.validate().unwrap();
+        assert_eq!(stats.n_count, items.len() as u64);
+        let n_deleted = items.iter().filter(|e| e.is_deleted()).count();
+        assert_eq!(stats.n_deleted, n_deleted);
+        let seqno = items.iter().map(|e| e.to_seqno()).max().unwrap();
+        assert_eq!(stats.seqno, seqno);
+        assert_eq!(shard.get_app_metadata::<u64>("tenant").unwrap(), Some(10));
+
+        let r = (Bound::<u16>::Unbounded, Bound::<u16>::Unbounded);
+        let mut iter = shard.iter_versions(r).unwrap();
+        for mut e1 in items.into_iter() {
+            if !index.to_stats().delta_ok || !config.delta_ok {
+                e1.deltas = vec![];
+            }
+            assert_eq!(e1, iter.next().unwrap().unwrap());
+        }
+        assert!(iter.next().is_none());
+    }
+    assert!(refs.next().is_none());
+
+    // unsorted split keys.
+    let shards: Vec<(Config, NoBitmap)> =
+        configs.iter().take(3).map(|c| (c.clone(), NoBitmap)).collect();
+    if shards.len() == 3 {
+        let keys = vec![keys[1], keys[0]];
+        match index.split_at(&keys, shards) {
+            Err(Error::Invalid(_, _)) => (),
+            res => panic!("unexpected {:?}", res.map(|_| ())),
+        }
+    }
+
+    // empty shard in the middle, shards already built are removed.
+    let i = (0..entries.len() - 1).find(|i| entries[i + 1].key > entries[*i].key + 1);
+    if let (Some(i), true) = (i, configs.len() > 2) {
+        let keys = vec![entries[i].key + 1, entries[i + 1].key];
+        let shards: Vec<(Config, NoBitmap)> =
+            configs.iter().take(3).map(|c| (c.clone(), NoBitmap)).collect();
+        match index.split_at(&keys, shards) {
+            Err(Error::Invalid(_, _)) => (),
+            res => panic!("unexpected {:?}", res.map(|_| ())),
+        }
+        for config in configs.iter().take(2) {
+            let res = Index::<u16, u64, u64, NoBitmap>::open(&config.dir, &config.name);
+            assert!(res.is_err());
+        }
+    }
+
+    // mismatched number of shards.
+    if configs.len() > 1 {
+        let shards: Vec<(Config, NoBitmap)> =
+            configs.iter().take(2).map(|c| (c.clone(), NoBitmap)).collect();
+        match index.split_at(&[], shards) {
+            Err(Error::Invalid(_, _)) => (),
+            res => panic!("unexpected {:?}", res.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use mkit::{db, Cborize};
+
+use std::{
+    borrow::Borrow,
+    marker,
+    ops::{Bound, RangeBounds},
+};
+
+use crate::{
+    comparator::{self, Comparator, Natural},
+    Result,
+};
+
+const LIMIT_VER1: u32 = 0x0001;
+const TOMBSTONE_VER1: u32 = 0x0001;
+
+/// Serializable variant of [Bound], used to persist range tombstones.
+#[derive(Clone, Debug, Eq, PartialEq, Cborize)]
+pub enum Limit<K> {
+    Unbounded,
+    Included(K),
+    Excluded(K),
+}
+
+impl<K> Limit<K> {
+    const ID: u32 = LIMIT_VER1;
+}
+
+impl<'a, K> From<Bound<&'a K>> for Limit<K>
+where
+    K: Clone,
+{
+    fn from(bound: Bound<&'a K>) -> Limit<K> {
+        match bound {
+            Bound::Unbounded => Limit::Unbounded,
+            Bound::Included(key) => Limit::Included(key.clone()),
+            Bound::Excluded(key) => Limit::Excluded(key.clone()),
+        }
+    }
+}
+
+impl<K> From<Limit<K>> for Bound<K> {
+    fn from(limit: Limit<K>) -> Bound<K> {
+        match limit {
+            Limit::Unbounded => Bound::Unbounded,
+            Limit::Included(key) => Bound::Included(key),
+            Limit::Excluded(key) => Bound::Excluded(key),
+        }
+    }
+}
+
+/// Range tombstone, delete all entries falling within the range
+/// `[start, end]` whose seqno is less than or equal to `seqno`.
+///
+/// Range tombstones are persisted in the meta-block of the index. They
+/// are honoured by `get` and `iter` APIs, applied on older snapshots via
+/// [TombstoneScan], and covered entries are purged during compaction.
+#[derive(Clone, Debug, Eq, PartialEq, Cborize)]
+pub struct RangeTombstone<K> {
+    pub start: Limit<K>,
+    pub end: Limit<K>,
+    pub seqno: u64,
+}
+
+impl<K> RangeTombstone<K> {
+    const ID: u32 = TOMBSTONE_VER1;
+
+    /// Create a new range tombstone for `range`, at `seqno`.
+    pub fn new<R>(range: R, seqno: u64) -> RangeTombstone<K>
+    where
+        K: Clone,
+        R: RangeBounds<K>,
+    {
+        RangeTombstone {
+            start: range.start_bound().into(),
+            end: range.end_bound().into(),
+            seqno,
+        }
+    }
+
+    pub fn to_seqno(&self) -> u64 {
+        self.seqno
+    }
+
+    /// Return the tombstone's range as `(start, end)` bounds.
+    pub fn to_range(&self) -> (Bound<K>, Bound<K>)
+    where
+        K: Clone,
+    {
+        (self.start.clone().into(), self.end.clone().into())
+    }
+
+    /// Return whether `key` fall within the tombstone's range.
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.contains_by::<Q, Natural>(key)
+    }
+
+    /// Same as contains(), but order keys using comparator `C`.
+    pub fn contains_by<Q, C>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        let start = match &self.start {
+            Limit::Unbounded => Bound::Unbounded,
+            Limit::Included(start) => Bound::Included(start.borrow()),
+            Limit::Excluded(start) => Bound::Excluded(start.borrow()),
+        };
+        let end = match &self.end {
+            Limit::Unbounded => Bound::Unbounded,
+            Limit::Included(end) => Bound::Included(end.borrow()),
+            Limit::Excluded(end) => Bound::Excluded(end.borrow()),
+        };
+        comparator::after_start::<Q, C>(key, start)
+            && comparator::before_end::<Q, C>(key, end)
+    }
+
+    /// Return whether `entry` is deleted by this tombstone.
+    pub fn covers<Q, V, D>(&self, entry: &db::Entry<K, V, D>) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.covers_by::<Q, V, D, Natural>(entry)
+    }
+
+    /// Same as covers(), but order keys using comparator `C`.
+    pub fn covers_by<Q, V, D, C>(&self, entry: &db::Entry<K, V, D>) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        entry.to_seqno() <= self.seqno && self.contains_by::<Q, C>(entry.key.borrow())
+    }
+
+    // Similar to db::Entry::purge(), return None if tombstone is no more
+    // needed after compacting upto `cutoff`.
+    pub(crate) fn purge(self, cutoff: db::Cutoff) -> Option<Self> {
+        let cutoff = match cutoff {
+            db::Cutoff::Mono => return None,
+            db::Cutoff::Lsm(cutoff) => cutoff,
+            db::Cutoff::Tombstone(cutoff) => cutoff,
+        };
+        match cutoff {
+            Bound::Included(cutoff) if self.seqno <= cutoff => None,
+            Bound::Excluded(cutoff) if self.seqno < cutoff => None,
+            _ => Some(self),
+        }
+    }
+}
+
+/// Return whether `entry` is deleted by any one of the `tombstones`.
+pub fn is_covered<K, V, D, Q>(
+    tombstones: &[RangeTombstone<K>],
+    entry: &db::Entry<K, V, D>,
+) -> bool
+where
+    K: Borrow<Q>,
+    Q: Ord + ?Sized,
+{
+    is_covered_by::<K, V, D, Q, Natural>(tombstones, entry)
+}
+
+/// Same as is_covered(), but order keys using comparator `C`.
+pub fn is_covered_by<K, V, D, Q, C>(
+    tombstones: &[RangeTombstone<K>],
+    entry: &db::Entry<K, V, D>,
+) -> bool
+where
+    K: Borrow<Q>,
+    Q: ?Sized,
+    C: Comparator<Q>,
+{
+    tombstones.iter().any(|t| t.covers_by::<Q, V, D, C>(entry))
+}
+
+/// Iterator wrapper, to apply range tombstones from a newer snapshot over
+/// entries from older snapshot(s), typically a merged iterator.
+///
+/// Entries covered by any of the tombstone are skipped. Keys are ordered
+/// using comparator `C`, which shall be the same as that of the snapshots.
+pub struct TombstoneScan<K, V, D, I, C = Natural> {
+    iter: I,
+    tombstones: Vec<RangeTombstone<K>>,
+
+    _val: marker::PhantomData<V>,
+    _dff: marker::PhantomData<D>,
+    _cmp: marker::PhantomData<C>,
+}
+
+impl<K, V, D, I> TombstoneScan<K, V, D, I> {
+    pub fn new(iter: I, tombstones: Vec<RangeTombstone<K>>) -> Self {
+        Self::new_by(iter, tombstones)
+    }
+}
+
+impl<K, V, D, I, C> TombstoneScan<K, V, D, I, C> {
+    /// Same as [TombstoneScan::new], but order keys using comparator `C`.
+    pub fn new_by(iter: I, tombstones: Vec<RangeTombstone<K>>) -> Self {
+        TombstoneScan {
+            iter,
+            tombstones,
+
+            _val: marker::PhantomData,
+            _dff: marker::PhantomData,
+            _cmp: marker::PhantomData,
+        }
+    }
+}
+
+impl<K, V, D, I, C> Iterator for TombstoneScan<K, V, D, I, C>
+where
+    C: Comparator<K>,
+    I: Iterator<Item = Result<db::Entry<K, V, D>>>,
+{
+    type Item = Result<db::Entry<K, V, D>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = iter_result!(self.iter.next()?);
+            if !is_covered_by::<K, V, D, K, C>(&self.tombstones, &entry) {
+                break Some(Ok(entry));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "tombstone_test.rs"]
+mod tombstone_test;
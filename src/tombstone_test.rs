@@ -0,0 +1,79 @@
+use super::*;
+
+#[test]
+fn test_range_tombstone() {
+    let tomb = RangeTombstone::<u64>::new(10..20, 5);
+    assert_eq!(tomb.to_seqno(), 5);
+    assert_eq!(tomb.to_range(), (Bound::Included(10), Bound::Excluded(20)));
+    assert!(!tomb.contains(&9));
+    assert!(tomb.contains(&10));
+    assert!(tomb.contains(&19));
+    assert!(!tomb.contains(&20));
+
+    let tomb = RangeTombstone::<u64>::new(.., 5);
+    assert!(tomb.contains(&0));
+    assert!(tomb.contains(&u64::MAX));
+
+    let tomb = RangeTombstone::<u64>::new(10..=20, 5);
+    let e = db::Entry::<u64, u64, u64>::new(20, 100, 5);
+    assert!(tomb.covers::<u64, u64, u64>(&e));
+    let e = db::Entry::<u64, u64, u64>::new(20, 100, 6);
+    assert!(!tomb.covers::<u64, u64, u64>(&e));
+    let e = db::Entry::<u64, u64, u64>::new(21, 100, 1);
+    assert!(!tomb.covers::<u64, u64, u64>(&e));
+
+    assert_eq!(tomb.clone().purge(db::Cutoff::Mono), None);
+    let cutoff = db::Cutoff::Lsm(Bound::Included(5));
+    assert_eq!(tomb.clone().purge(cutoff), None);
+    let cutoff = db::Cutoff::Lsm(Bound::Excluded(5));
+    assert_eq!(tomb.clone().purge(cutoff), Some(tomb.clone()));
+}
+
+#[test]
+fn test_tombstone_scan() {
+    let tombstones =
+        vec![RangeTombstone::<u64>::new(10..20, 5), RangeTombstone::<u64>::new(50.., 2)];
+    let entries: Vec<Result<db::Entry<u64, u64, u64>>> =
+        (0..100).map(|key| Ok(db::Entry::new(key, key, key % 10))).collect();
+
+    let keys: Vec<u64> = TombstoneScan::new(entries.into_iter(), tombstones)
+        .map(|e| e.unwrap().key)
+        .collect();
+
+    let refs: Vec<u64> = (0..100)
+        .filter(|key| !((10..20).contains(key) && (key % 10) <= 5))
+        .filter(|key| !((50..).contains(key) && (key % 10) <= 2))
+        .collect();
+    assert_eq!(keys, refs);
+}
+
+#[test]
+fn test_tombstone_scan_by() {
+    use std::cmp;
+
+    struct Descending;
+
+    impl Comparator<u64> for Descending {
+        const NAME: &'static str = "descending";
+
+        fn compare(a: &u64, b: &u64) -> cmp::Ordering {
+            b.cmp(a)
+        }
+    }
+
+    // under descending order, range starts from the larger key.
+    let tombstones = vec![RangeTombstone::<u64>::new(20..10, 5)];
+    let entries: Vec<Result<db::Entry<u64, u64, u64>>> =
+        (0..100).rev().map(|key| Ok(db::Entry::new(key, key, key % 10))).collect();
+
+    let keys: Vec<u64> =
+        TombstoneScan::<_, _, _, _, Descending>::new_by(entries.into_iter(), tombstones)
+            .map(|e| e.unwrap().key)
+            .collect();
+
+    let refs: Vec<u64> = (0..100)
+        .rev()
+        .filter(|key| !((11..=20).contains(key) && (key % 10) <= 5))
+        .collect();
+    assert_eq!(keys, refs);
+}
@@ -19,8 +19,18 @@ pub fn from_cbor_bytes<T>(mut data: &[u8]) -> Result<(T, usize)>
 where
     T: FromCbor,
 {
-    let (val, n) = Cbor::decode(&mut data)?;
-    Ok((T::from_cbor(val)?, n))
+    // data is always read from index/vlog file, failing to decode implies
+    // a malformed file.
+    let (val, n) = err_at!(InvalidFile, Cbor::decode(&mut data))?;
+    Ok((err_at!(InvalidFile, T::from_cbor(val))?, n))
+}
+
+// greatest common divisor, used for block alignment.
+pub fn gcd(a: u64, b: u64) -> u64 {
+    match b {
+        0 => a,
+        b => gcd(b, a % b),
+    }
 }
 
 #[cfg(test)]
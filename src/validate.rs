@@ -0,0 +1,422 @@
+use mkit::{cbor::FromCbor, db};
+
+use std::{cmp, convert::TryFrom, fmt, io};
+
+use crate::{
+    comparator::Comparator,
+    config::Stats,
+    entry::Entry,
+    reader::{Reader, MAX_DEPTH},
+    util,
+    vlog::{Delta, Value},
+    Result,
+};
+
+/// Report returned by [Index::validate_deep][crate::db::Index::validate_deep].
+///
+/// Unlike [Index::validate][crate::db::Index::validate], deep validation
+/// walks every block in the index and collects all problems found, instead
+/// of stopping at the first one.
+#[derive(Clone, Debug, Default)]
+pub struct ValidateReport {
+    /// Number of intermediate blocks visited, including the root block.
+    pub n_mblocks: usize,
+    /// Number of leaf blocks visited.
+    pub n_zblocks: usize,
+    /// Number of leaf entries visited.
+    pub n_count: u64,
+    /// Number of leaf entries marked as deleted.
+    pub n_deleted: usize,
+    /// Depth of the tree, counting the root block and the leaf blocks.
+    pub depth: usize,
+    /// List of problems found.
+    pub problems: Vec<Problem>,
+}
+
+impl ValidateReport {
+    /// Return true if no problems were found.
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Problem found by deep validation. Keys are reported in their `Debug`
+/// format and file positions are relative to index file, unless mentioned
+/// otherwise.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Problem {
+    /// Block at `fpos` could not be read or decoded.
+    BadBlock { fpos: u64, err: String },
+    /// Block at `fpos` is empty.
+    EmptyBlock { fpos: u64 },
+    /// Block at `fpos` holds entries that does not belong to its level.
+    MixedBlock { fpos: u64 },
+    /// Child `fpos` referred by block at `parent` is out of range or not
+    /// aligned to block size.
+    BadChild {
+        parent: u64,
+        fpos: u64,
+        reason: String,
+    },
+    /// Separator key in parent block does not match the first key of the
+    /// child block at `fpos`.
+    BadSeparator {
+        fpos: u64,
+        separator: String,
+        first: String,
+    },
+    /// Keys are not in strictly ascending order, `fpos` is the block
+    /// holding `key`.
+    KeyOrder {
+        fpos: u64,
+        prev: String,
+        key: String,
+    },
+    /// Leaf block at `fpos` found at a different depth from other leaves.
+    UnevenDepth {
+        fpos: u64,
+        depth: usize,
+        expected: usize,
+    },
+    /// Value or delta reference for `key` does not lie within value-log
+    /// file, or could not be decoded. `fpos` is relative to value-log file.
+    BadVlogRef {
+        key: String,
+        fpos: u64,
+        length: u64,
+        reason: String,
+    },
+    /// Older version is same or newer than the latest version for `key`.
+    DeltaSeqno {
+        key: String,
+        seqno: u64,
+        delta_seqno: u64,
+    },
+    /// Count from walking the tree does not match the persisted statistics.
+    StatsMismatch {
+        field: String,
+        stats: u64,
+        found: u64,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Problem::*;
+
+        match self {
+            BadBlock { fpos, err } => write!(f, "bad block at {}, {}", fpos, err),
+            EmptyBlock { fpos } => write!(f, "empty block at {}", fpos),
+            MixedBlock { fpos } => write!(f, "mixed entries in block at {}", fpos),
+            BadChild { parent, fpos, reason } => {
+                write!(f, "bad child {} in block at {}, {}", fpos, parent, reason)
+            }
+            BadSeparator { fpos, separator, first } => {
+                write!(f, "separator {} != first key {} at {}", separator, first, fpos)
+            }
+            KeyOrder { fpos, prev, key } => {
+                write!(f, "key {} not after {} in block at {}", key, prev, fpos)
+            }
+            UnevenDepth { fpos, depth, expected } => {
+                write!(f, "leaf at {} has depth {}, expected {}", fpos, depth, expected)
+            }
+            BadVlogRef { key, fpos, length, reason } => {
+                write!(f, "bad vlog ref {{{},{}}} for {}, {}", fpos, length, key, reason)
+            }
+            DeltaSeqno { key, seqno, delta_seqno } => {
+                write!(f, "delta seqno {} >= {} for {}", delta_seqno, seqno, key)
+            }
+            StatsMismatch { field, stats, found } => {
+                write!(f, "stats {} is {}, found {}", field, stats, found)
+            }
+        }
+    }
+}
+
+enum Level {
+    M,
+    Z,
+}
+
+pub(crate) struct DeepValidator<'a, K, V, D, C> {
+    reader: &'a mut Reader<K, V, D, C>,
+    root: u64,
+    align: u64,
+    index_len: u64,
+    vlog_len: Option<u64>,
+    prev_key: Option<K>,
+    report: ValidateReport,
+}
+
+impl<'a, K, V, D, C> DeepValidator<'a, K, V, D, C>
+where
+    K: Clone + fmt::Debug + FromCbor,
+    V: Clone + FromCbor,
+    D: Clone + FromCbor,
+    C: Comparator<K>,
+{
+    pub fn new(reader: &'a mut Reader<K, V, D, C>, root: u64) -> Result<Self> {
+        let index_len = err_at!(IOError, reader.index.metadata())?.len();
+        let vlog_len = match reader.vlog.as_ref() {
+            Some(vlog) => Some(err_at!(IOError, vlog.metadata())?.len()),
+            None => None,
+        };
+        // z-blocks and m-blocks are interleaved in the index file, hence
+        // every block starts at a multiple of gcd(z_blocksize, m_blocksize).
+        let align = util::gcd(reader.z_blocksize as u64, reader.m_blocksize as u64);
+
+        let val = DeepValidator {
+            reader,
+            root,
+            align,
+            index_len,
+            vlog_len,
+            prev_key: None,
+            report: ValidateReport::default(),
+        };
+        Ok(val)
+    }
+
+    pub fn validate(mut self, stats: &Stats) -> ValidateReport {
+        let (root, m_blocksize) = (self.root, self.reader.m_blocksize as u64);
+        match self.check_fpos(root, m_blocksize, self.index_len) {
+            Some(reason) => {
+                let (parent, fpos) = (root, root);
+                self.report.problems.push(Problem::BadChild { parent, fpos, reason });
+            }
+            None => self.visit(root, Level::M, 1, None),
+        }
+
+        let counts = [
+            ("n_count", stats.n_count, self.report.n_count),
+            ("n_deleted", stats.n_deleted as u64, self.report.n_deleted as u64),
+        ];
+        for (field, expected, found) in counts.iter() {
+            if expected != found {
+                let (field, stats, found) = (field.to_string(), *expected, *found);
+                self.report.problems.push(Problem::StatsMismatch { field, stats, found });
+            }
+        }
+
+        self.report
+    }
+
+    fn visit(&mut self, fpos: u64, level: Level, depth: usize, sep: Option<K>) {
+        let entries = match self.read_block(fpos, &level) {
+            Ok(entries) => entries,
+            Err(err) => {
+                let err = err.to_string();
+                self.report.problems.push(Problem::BadBlock { fpos, err });
+                return;
+            }
+        };
+
+        let first = match entries.first() {
+            Some(first) => first.to_key(),
+            None => {
+                self.report.problems.push(Problem::EmptyBlock { fpos });
+                return;
+            }
+        };
+        if let Some(sep) = sep {
+            if C::compare(&sep, &first) != cmp::Ordering::Equal {
+                let (separator, first) = (format!("{:?}", sep), format!("{:?}", first));
+                let problem = Problem::BadSeparator { fpos, separator, first };
+                self.report.problems.push(problem);
+            }
+        }
+
+        match level {
+            Level::M => {
+                self.report.n_mblocks += 1;
+                self.visit_mblock(fpos, entries, depth)
+            }
+            Level::Z => {
+                self.report.n_zblocks += 1;
+                self.visit_zblock(fpos, entries, depth)
+            }
+        }
+    }
+
+    fn visit_mblock(&mut self, fpos: u64, entries: Vec<Entry<K, V, D>>, depth: usize) {
+        let is_mm = |e: &Entry<K, V, D>| matches!(e, Entry::MM { .. });
+        let is_mz = |e: &Entry<K, V, D>| matches!(e, Entry::MZ { .. });
+        let mixed = match entries.first() {
+            Some(Entry::MM { .. }) => !entries.iter().all(is_mm),
+            Some(Entry::MZ { .. }) => !entries.iter().all(is_mz),
+            _ => true,
+        };
+        if mixed {
+            self.report.problems.push(Problem::MixedBlock { fpos });
+            return;
+        }
+
+        let mut prev: Option<&K> = None;
+        for entry in entries.iter() {
+            let key = entry.as_key();
+            match prev.map(|pk| C::compare(pk, key)) {
+                Some(cmp::Ordering::Less) | None => (),
+                Some(_) => {
+                    let prev = format!("{:?}", prev.unwrap());
+                    let key = format!("{:?}", key);
+                    self.report.problems.push(Problem::KeyOrder { fpos, prev, key });
+                }
+            }
+            prev = Some(key);
+        }
+
+        for entry in entries.into_iter() {
+            let (key, child, level, size) = match entry {
+                Entry::MM { key, fpos } => (key, fpos, Level::M, self.reader.m_blocksize),
+                Entry::MZ { key, fpos } => (key, fpos, Level::Z, self.reader.z_blocksize),
+                Entry::ZZ { .. } => unreachable!(),
+            };
+            // children are always flushed before their parent.
+            let reason = match self.check_fpos(child, size as u64, fpos) {
+                None if depth >= MAX_DEPTH => Some(format!("deeper than {}", MAX_DEPTH)),
+                reason => reason,
+            };
+            match reason {
+                Some(reason) => {
+                    let problem = Problem::BadChild { parent: fpos, fpos: child, reason };
+                    self.report.problems.push(problem);
+                }
+                None => self.visit(child, level, depth + 1, Some(key)),
+            }
+        }
+    }
+
+    fn visit_zblock(&mut self, fpos: u64, entries: Vec<Entry<K, V, D>>, depth: usize) {
+        match self.report.depth {
+            0 => self.report.depth = depth,
+            expected if expected != depth => {
+                let problem = Problem::UnevenDepth { fpos, depth, expected };
+                self.report.problems.push(problem);
+            }
+            _ => (),
+        }
+
+        if entries.iter().any(|e| !e.is_zblock()) {
+            self.report.problems.push(Problem::MixedBlock { fpos });
+            return;
+        }
+
+        for entry in entries.into_iter() {
+            let key = entry.to_key();
+            match self.prev_key.as_ref().map(|pk| C::compare(pk, &key)) {
+                Some(cmp::Ordering::Less) | None => (),
+                Some(_) => {
+                    let prev = format!("{:?}", self.prev_key.as_ref().unwrap());
+                    let key = format!("{:?}", key);
+                    self.report.problems.push(Problem::KeyOrder { fpos, prev, key });
+                }
+            }
+            self.prev_key = Some(key.clone());
+            self.report.n_count += 1;
+
+            if let Some(entry) = self.visit_entry(&key, entry) {
+                if entry.is_deleted() {
+                    self.report.n_deleted += 1;
+                }
+                let seqno = entry.to_seqno();
+                for delta in entry.deltas.iter() {
+                    if delta.to_seqno() >= seqno {
+                        let (key, delta_seqno) = (format!("{:?}", key), delta.to_seqno());
+                        let problem = Problem::DeltaSeqno { key, seqno, delta_seqno };
+                        self.report.problems.push(problem);
+                    }
+                }
+            }
+        }
+    }
+
+    // check value-log references and return the native entry.
+    fn visit_entry(
+        &mut self,
+        key: &K,
+        entry: Entry<K, V, D>,
+    ) -> Option<db::Entry<K, V, D>> {
+        let mut refs = vec![];
+        if let Entry::ZZ { value, deltas, .. } = &entry {
+            if let Value::R { fpos, length } = value {
+                refs.push((*fpos, *length))
+            }
+            for delta in deltas.iter() {
+                if let Delta::R { fpos, length } = delta {
+                    refs.push((*fpos, *length))
+                }
+            }
+        }
+
+        let mut ok = true;
+        for (fpos, length) in refs.into_iter() {
+            let reason = match self.vlog_len {
+                None => Some("missing value-log file".to_string()),
+                Some(vlog_len) => match fpos.checked_add(length) {
+                    Some(end) if end <= vlog_len => None,
+                    _ => Some(format!("beyond value-log file {}", vlog_len)),
+                },
+            };
+            if let Some(reason) = reason {
+                let key = format!("{:?}", key);
+                let problem = Problem::BadVlogRef { key, fpos, length, reason };
+                self.report.problems.push(problem);
+                ok = false;
+            }
+        }
+        if !ok {
+            return None;
+        }
+
+        let entry = match &mut self.reader.vlog {
+            Some(vlog) => match entry.into_native(vlog, true) {
+                Ok(entry) => entry,
+                Err(err) => {
+                    let (key, reason) = (format!("{:?}", key), err.to_string());
+                    let (fpos, length) = (0, 0);
+                    let problem = Problem::BadVlogRef { key, fpos, length, reason };
+                    self.report.problems.push(problem);
+                    return None;
+                }
+            },
+            None => entry,
+        };
+
+        match db::Entry::try_from(entry) {
+            Ok(entry) => Some(entry),
+            Err(err) => {
+                let (key, reason) = (format!("{:?}", key), err.to_string());
+                let (fpos, length) = (0, 0);
+                let problem = Problem::BadVlogRef { key, fpos, length, reason };
+                self.report.problems.push(problem);
+                None
+            }
+        }
+    }
+
+    fn read_block(&mut self, fpos: u64, level: &Level) -> Result<Vec<Entry<K, V, D>>> {
+        let n = match level {
+            Level::M => self.reader.m_blocksize,
+            Level::Z => self.reader.z_blocksize,
+        };
+        let fd = &mut self.reader.index;
+        let block = read_file!(fd, io::SeekFrom::Start(fpos), n, "read block")?;
+        Entry::<K, V, D>::decode_block(&block, self.reader.format)
+    }
+
+    // return the reason if block at `fpos` of `size` bytes does not lie
+    // before `limit` or is not aligned.
+    fn check_fpos(&self, fpos: u64, size: u64, limit: u64) -> Option<String> {
+        if self.align > 0 && fpos % self.align != 0 {
+            Some(format!("not aligned to {}", self.align))
+        } else if fpos.checked_add(size).map(|end| end > limit).unwrap_or(true) {
+            Some(format!("block of {} bytes beyond {}", size, limit))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "validate_test.rs"]
+mod validate_test;
@@ -0,0 +1,88 @@
+use mkit::{db::BuildIndex, nobitmap::NoBitmap};
+use rand::prelude::random;
+
+use std::{
+    fs,
+    io::{Seek, SeekFrom, Write},
+};
+
+use super::*;
+use crate::{db::Builder, db::Index, header::Header, Config};
+
+fn build_index(seed: u128, name: &str) -> Config {
+    let dir = std::env::temp_dir().join("test_validate_deep");
+    let mut config = Config::new(dir.as_os_str(), name);
+    config.set_blocksize(1024, 1024, 1024).set_value_log(true);
+
+    let mdb = util::load_index(seed, 5_000, 1_000, 500, 100, None);
+    let mut build = Builder::<u16, u64, u64>::initial(config.clone(), vec![]).unwrap();
+    build.build_index(mdb.iter().unwrap(), NoBitmap, None).unwrap();
+    config
+}
+
+fn open_index(config: &Config) -> Index<u16, u64, u64, NoBitmap> {
+    Index::open(&config.dir, &config.name).unwrap()
+}
+
+#[test]
+fn test_validate_deep() {
+    let seed: u128 = random();
+    println!("test_validate_deep {}", seed);
+
+    let config = build_index(seed, "test_validate_deep");
+    let mut index = open_index(&config);
+
+    let report = index.validate_deep().unwrap();
+    assert!(report.is_ok(), "{:?}", report.problems);
+    let stats = index.to_stats();
+    assert_eq!(report.n_count, stats.n_count);
+    assert_eq!(report.n_deleted, stats.n_deleted);
+    assert!(report.n_zblocks > 1, "{}", report.n_zblocks);
+    assert!(report.depth > 1, "{}", report.depth);
+}
+
+#[test]
+fn test_validate_deep_vlog() {
+    let seed: u128 = random();
+    println!("test_validate_deep_vlog {}", seed);
+
+    let config = build_index(seed, "test_validate_deep_vlog");
+    {
+        let vlog_file = config.to_vlog_file_location();
+        let len = fs::metadata(&vlog_file).unwrap().len();
+        let fd = fs::OpenOptions::new().write(true).open(&vlog_file).unwrap();
+        fd.set_len(len / 2).unwrap();
+    }
+    let mut index = open_index(&config);
+
+    let report = index.validate_deep().unwrap();
+    let problems = &report.problems;
+    assert!(problems.iter().any(|p| matches!(p, Problem::BadVlogRef { .. })));
+    assert!(!problems.iter().any(|p| matches!(p, Problem::BadBlock { .. })));
+    // every leaf entry is still visited.
+    assert_eq!(report.n_count, index.to_stats().n_count);
+}
+
+#[test]
+fn test_validate_deep_block() {
+    let seed: u128 = random();
+    println!("test_validate_deep_block {}", seed);
+
+    let config = build_index(seed, "test_validate_deep_block");
+    let fpos = Header::new(1024, 1024).length;
+    {
+        // first block, after the header, is a leaf block.
+        let index_file = config.to_index_file_location();
+        let mut fd = fs::OpenOptions::new().write(true).open(&index_file).unwrap();
+        fd.seek(SeekFrom::Start(fpos)).unwrap();
+        fd.write_all(&[0xff; 64]).unwrap();
+    }
+    let mut index = open_index(&config);
+
+    let report = index.validate_deep().unwrap();
+    let problems = &report.problems;
+    let bad = |p: &Problem| matches!(p, Problem::BadBlock { fpos: f, .. } if *f == fpos);
+    assert!(problems.iter().any(bad));
+    assert!(problems.iter().any(|p| matches!(p, Problem::StatsMismatch { .. })));
+    assert!(report.n_count < index.to_stats().n_count);
+}
@@ -27,11 +27,15 @@ impl<V> From<db::Value<V>> for Value<V> {
     }
 }
 
-impl<V> From<Value<V>> for db::Value<V> {
-    fn from(value: Value<V>) -> db::Value<V> {
+impl<V> TryFrom<Value<V>> for db::Value<V> {
+    type Error = Error;
+
+    fn try_from(value: Value<V>) -> Result<db::Value<V>> {
         match value {
-            Value::N { value } => value,
-            Value::R { .. } => unreachable!(),
+            Value::N { value } => Ok(value),
+            Value::R { fpos, length } => {
+                err_at!(InvalidFile, msg: "unresolved value ref {{{},{}}}", fpos, length)
+            }
         }
     }
 }
@@ -51,6 +55,16 @@ impl<V> Value<V> {
         }
     }
 
+    // shift reference at or beyond `from` in value-log, to start at `to`.
+    pub fn rebase(self, from: u64, to: u64) -> Self {
+        match self {
+            Value::R { fpos, length } if fpos >= from => {
+                Value::R { fpos: fpos - from + to, length }
+            }
+            val => val,
+        }
+    }
+
     #[allow(unused_imports)]
     pub fn into_native<F>(self, f: &mut F) -> Result<Self>
     where
@@ -85,11 +99,15 @@ impl<D> From<db::Delta<D>> for Delta<D> {
     }
 }
 
-impl<D> From<Delta<D>> for db::Delta<D> {
-    fn from(delta: Delta<D>) -> db::Delta<D> {
+impl<D> TryFrom<Delta<D>> for db::Delta<D> {
+    type Error = Error;
+
+    fn try_from(delta: Delta<D>) -> Result<db::Delta<D>> {
         match delta {
-            Delta::N { delta } => delta,
-            Delta::R { .. } => unreachable!(),
+            Delta::N { delta } => Ok(delta),
+            Delta::R { fpos, length } => {
+                err_at!(InvalidFile, msg: "unresolved delta ref {{{},{}}}", fpos, length)
+            }
         }
     }
 }
@@ -109,6 +127,16 @@ impl<D> Delta<D> {
         }
     }
 
+    // shift reference at or beyond `from` in value-log, to start at `to`.
+    pub fn rebase(self, from: u64, to: u64) -> Self {
+        match self {
+            Delta::R { fpos, length } if fpos >= from => {
+                Delta::R { fpos: fpos - from + to, length }
+            }
+            val => val,
+        }
+    }
+
     #[allow(unused_imports)]
     pub fn into_native<F>(self, f: &mut F) -> Result<Self>
     where
@@ -7,7 +7,7 @@ fn test_value() {
         db::Value::U { value, seqno }
     };
 
-    assert_eq!(dbval, db::Value::from(Value::from(dbval.clone())));
+    assert_eq!(dbval, db::Value::try_from(Value::from(dbval.clone())).unwrap());
 
     let value = Value::from(dbval.clone());
     let (value, data) = value.into_reference(1023).unwrap();
@@ -15,6 +15,12 @@ fn test_value() {
     buf.extend(&data);
     assert_eq!(value, Value::R { fpos: 1023, length: data.len() as u64 });
 
+    let length = data.len() as u64;
+    let rebased = value.clone().rebase(1024, 10);
+    assert_eq!(rebased, Value::R { fpos: 1023, length });
+    let rebased = value.clone().rebase(1000, 10);
+    assert_eq!(rebased, Value::R { fpos: 33, length });
+
     let mut buf = io::Cursor::new(buf);
     assert_eq!(value.into_native(&mut buf).unwrap(), Value::from(dbval));
 }
@@ -26,7 +32,7 @@ fn test_delta() {
         db::Delta::U { delta, seqno }
     };
 
-    assert_eq!(dbdelta, db::Delta::from(Delta::from(dbdelta.clone())));
+    assert_eq!(dbdelta, db::Delta::try_from(Delta::from(dbdelta.clone())).unwrap());
 
     let delta = Delta::from(dbdelta.clone());
     let (delta, data) = delta.into_reference(1023).unwrap();
@@ -34,6 +40,12 @@ fn test_delta() {
     buf.extend(&data);
     assert_eq!(delta, Delta::R { fpos: 1023, length: data.len() as u64 });
 
+    let length = data.len() as u64;
+    let rebased = delta.clone().rebase(1024, 10);
+    assert_eq!(rebased, Delta::R { fpos: 1023, length });
+    let rebased = delta.clone().rebase(1000, 10);
+    assert_eq!(rebased, Delta::R { fpos: 33, length });
+
     let mut buf = io::Cursor::new(buf);
     assert_eq!(delta.into_native(&mut buf).unwrap(), Delta::from(dbdelta));
 }